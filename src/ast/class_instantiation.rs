@@ -10,7 +10,9 @@ pub struct ClassInstantiation {
   pub class_name: String,
   pub generic_type_assignment: Option<Vec<TypeDeclaration>>,
   pub lifetime: String,
-  pub span: Span
+  pub span: Span,
+
+  pub mangled_accessor: RefCell<Option<String>>
 }
 
 impl ClassInstantiation {
@@ -27,6 +29,8 @@ impl ClassInstantiation {
 
 impl Visited for ClassInstantiation {
   fn accept<T: visitor::Visitor>(&self, visitor: &mut T) {
+    visitor.visit_class_instantiation(self);
+
     if let Some(generic_types) = &self.generic_type_assignment {
       visitor.visit_generic_class_instantiation(self);
 
@@ -60,10 +64,13 @@ impl Codegen for ClassInstantiation {
     let generic_variant_suffix =
       GenericContext::generic_variant_suffix_from_types(&stringified_types);
 
+    let mangled_accessor = self.mangled_accessor.borrow();
+    let class_name = mangled_accessor.as_ref().unwrap_or(&self.class_name);
+
     write!(
       f,
       "new {}{generic_variant_suffix} in {}",
-      self.class_name, self.lifetime
+      class_name, self.lifetime
     )
   }
 }
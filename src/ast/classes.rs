@@ -18,7 +18,13 @@ pub struct ClassDeclaration {
 
   pub span_name: Span,
 
-  pub context: Rc<RefCell<Context>>
+  pub context: Rc<RefCell<Context>>,
+
+  /// Set when the `parent_class_name` refers to a namespaced class
+  pub parent_class_mangled_accessor: RefCell<Option<String>>,
+
+  /// Set when the `extended_class_name` refers to a namespaced class
+  pub extended_class_mangled_accessor: RefCell<Option<String>>
 }
 
 impl Visited for ClassDeclaration {
@@ -70,7 +76,14 @@ fn emit_class(
 ) -> Result<(), std::io::Error> {
   use std::io::Write as IoWrite;
 
-  if let Some(mangled_accessor) = &context.mangled_accessor {
+  emit_span_marker(f, this.span_name)?;
+
+  let own_mangled_accessor = this.context.borrow().mangled_accessor.clone();
+
+  if let Some(mangled_accessor) = own_mangled_accessor
+    .as_ref()
+    .or(context.mangled_accessor.as_ref())
+  {
     write!(
       f,
       "{} {}{}",
@@ -85,10 +98,16 @@ fn emit_class(
   }
 
   if let Some(parent_class_name) = &this.parent_class_name {
+    let mangled_accessor = this.parent_class_mangled_accessor.borrow();
+    let parent_class_name = mangled_accessor.as_ref().unwrap_or(parent_class_name);
+
     write!(f, " in {parent_class_name}")?;
   }
 
   if let Some(extended_class_name) = &this.extended_class_name {
+    let mangled_accessor = this.extended_class_mangled_accessor.borrow();
+    let extended_class_name = mangled_accessor.as_ref().unwrap_or(extended_class_name);
+
     write!(f, " extends {extended_class_name}")?;
   }
 
@@ -102,6 +121,10 @@ fn emit_class(
         continue 'name_emitting;
       }
 
+      if let Some(span) = context.declaration_spans.get(name) {
+        emit_span_marker(f, *span)?;
+      }
+
       write!(f, "var {name}: ")?;
       declaration.type_declaration.emit(context, f)?;
       writeln!(f, ";")?;
@@ -3,7 +3,8 @@ use std::cell::{Ref, RefCell};
 use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 
-use crate::ast::TypedIdentifier;
+use crate::ast::{ImportStatement, Span, TypedIdentifier};
+use crate::utils::stable_hash;
 
 #[derive(Debug)]
 pub struct Context {
@@ -12,6 +13,10 @@ pub struct Context {
 
   pub context_type: ContextType,
 
+  /// The span of the name of the declaration the context was created for,
+  /// if there is one.
+  pub span: Option<Span>,
+
   pub identifiers: HashMap<String, String>,
 
   pub children_contexts: Vec<Rc<RefCell<Context>>>,
@@ -28,6 +33,22 @@ pub struct Context {
   /// at the start of the functions/classes/structs
   pub variable_declarations: Vec<Rc<TypedIdentifier>>,
 
+  /// The local variables that are emitted under another name, the variables
+  /// declared with the same name in different blocks of a function get unique
+  /// names. The keys are the span of the declaration or of the identifier that
+  /// uses the variable, and the name as it is written.
+  pub scoped_names: HashMap<(Span, String), String>,
+
+  /// The spans of the declarations without a value in the nested blocks of a
+  /// function, their variables get back the default value of their type each
+  /// time the declaration is run, like in the iterations of a loop.
+  pub resetting_declarations: HashSet<Span>,
+
+  /// The span of the declaration of the variables emitted at the start of the
+  /// function or of the class, by the name they are emitted with. The source
+  /// maps point the lines of the variables to them.
+  pub declaration_spans: HashMap<String, Span>,
+
   /// Stores for the identifiers (used as the keys) the infered types (used as
   /// values).
   ///
@@ -40,6 +61,11 @@ pub struct Context {
 
   pub local_parameters_inference: HashMap<String, String>,
 
+  /// The import statements written directly in this context, they make the
+  /// declarations of other namespaces visible to this context and its
+  /// children.
+  pub imports: Vec<ImportStatement>,
+
   /// A bool flag that will be used by identifiers matching with "this", so
   /// they know it should be replaced by the given string, it is used by the
   /// lambda expression to capture "this" expressions.
@@ -56,6 +82,7 @@ impl Context {
     Self {
       name: name.to_string(),
       context_type,
+      span: None,
       identifiers: HashMap::new(),
       children_contexts: Vec::new(),
       parent_context: None,
@@ -63,23 +90,70 @@ impl Context {
       is_library: false,
       mangled_accessor: None,
       variable_declarations: Vec::new(),
+      scoped_names: HashMap::new(),
+      resetting_declarations: HashSet::new(),
+      declaration_spans: HashMap::new(),
       local_variables_inference: HashMap::new(),
       local_parameters_inference: HashMap::new(),
+      imports: Vec::new(),
       replace_this_with_self: RefCell::new(None)
     }
   }
 
-  pub fn set_as_library(&mut self) {
+  /// Marks the context as coming from a library, the declarations it contains
+  /// get a mangled name derived from the seed so it stays the same between
+  /// compilations.
+  pub fn set_as_library(&mut self, seed: &str) {
     self.is_library = true;
-    self.mangled_accessor = Some(format!(
-      "wss{}",
-      uuid::Uuid::new_v4().to_string().replace("-", "")
-    ));
+    self.mangled_accessor = Some(format!("wss{}", stable_hash(&[seed, &self.name])));
+  }
+
+  /// Sets the mangled name of a declaration that is placed directly in a
+  /// namespace. Library declarations keep their own mangled names.
+  pub fn set_as_namespaced(&mut self, namespace_path: &[String], name: &str) {
+    if self.is_library {
+      return;
+    }
+
+    self.mangled_accessor = Some(Self::mangle_namespaced_name(namespace_path, name));
+  }
+
+  /// Returns the name the declarations in the given namespace are emitted with.
+  ///
+  /// The parts are joined with `__` and their own underscores are written
+  /// `_x`, so two different paths never give the same name.
+  pub fn mangle_namespaced_name(namespace_path: &[String], name: &str) -> String {
+    if namespace_path.is_empty() {
+      return name.to_string();
+    }
+
+    namespace_path
+      .iter()
+      .map(String::as_str)
+      .chain(std::iter::once(name))
+      .map(|part| part.replace('_', "_x"))
+      .collect::<Vec<_>>()
+      .join("__")
+  }
+
+  /// Returns the mangled name of the declaration if it was declared inside a
+  /// namespace.
+  pub fn get_namespaced_name(&self) -> Option<&String> {
+    if self.is_library {
+      None
+    } else {
+      self.mangled_accessor.as_ref()
+    }
   }
 
   pub fn get_class_name(&self) -> Option<String> {
     if self.name.starts_with("class: ") {
-      Some(self.name.replacen("class: ", "", 1))
+      Some(
+        self
+          .get_namespaced_name()
+          .cloned()
+          .unwrap_or_else(|| self.name.replacen("class: ", "", 1))
+      )
     } else {
       None
     }
@@ -87,7 +161,12 @@ impl Context {
 
   pub fn get_struct_name(&self) -> Option<String> {
     if self.name.starts_with("struct: ") {
-      Some(self.name.replacen("struct: ", "", 1))
+      Some(
+        self
+          .get_namespaced_name()
+          .cloned()
+          .unwrap_or_else(|| self.name.replacen("struct: ", "", 1))
+      )
     } else {
       None
     }
@@ -97,6 +176,17 @@ impl Context {
     self.get_class_name().or(self.get_struct_name())
   }
 
+  /// Returns the name the local variable is emitted with, the written name
+  /// unless the variable was renamed to not collide with the variable of
+  /// another block.
+  pub fn get_scoped_name<'a>(&'a self, span: Span, name: &'a str) -> &'a str {
+    self
+      .scoped_names
+      .get(&(span, name.to_string()))
+      .map(String::as_str)
+      .unwrap_or(name)
+  }
+
   pub fn get_variable_type_string(&self, variable_name: &str) -> Option<&String> {
     self
       .local_parameters_inference
@@ -104,6 +194,40 @@ impl Context {
       .or_else(|| self.local_variables_inference.get(variable_name))
   }
 
+  /// Returns the type of the variable, and looks in the parent contexts as well
+  /// to find the properties of the class a function is in and the global
+  /// variables. When the search starts from a class or a struct only its own
+  /// properties are considered.
+  pub fn find_variable_type(this: &Rc<RefCell<Context>>, variable_name: &str) -> Option<String> {
+    let context = Self::get_ref(this);
+
+    if let Some(variable_type) = context.get_variable_type_string(variable_name) {
+      return Some(variable_type.clone());
+    }
+
+    if let ContextType::ClassOrStruct
+    | ContextType::State {
+      parent_class_name: _
+    } = &context.context_type
+    {
+      return None;
+    }
+
+    let mut current = context.parent_context.clone();
+
+    while let Some(context) = current {
+      let context_ref = Self::get_ref(&context);
+
+      if let Some(variable_type) = context_ref.get_variable_type_string(variable_name) {
+        return Some(variable_type.clone());
+      }
+
+      current = context_ref.parent_context.clone();
+    }
+
+    None
+  }
+
   pub fn set_parent_context(this: &Rc<RefCell<Context>>, parent: &Rc<RefCell<Context>>) {
     if let Some(parent_context) = &Self::get_ref(this).parent_context {
       Self::remove_child(parent_context, &this);
@@ -112,8 +236,15 @@ impl Context {
     (*parent).borrow_mut().children_contexts.push(this.clone());
     (*this).borrow_mut().parent_context = Some(parent.clone());
 
-    if Self::get_ref(parent).is_library {
-      (*this).borrow_mut().set_as_library();
+    let parent_library_seed = Self::get_ref(parent).is_library.then(|| {
+      Self::get_ref(parent)
+        .mangled_accessor
+        .clone()
+        .unwrap_or_default()
+    });
+
+    if let Some(seed) = parent_library_seed {
+      (*this).borrow_mut().set_as_library(&seed);
     }
   }
 
@@ -140,16 +271,69 @@ impl Context {
 
   pub fn find_global_function_declaration(
     this: &Rc<RefCell<Context>>, name: &str
+  ) -> Option<Rc<RefCell<Context>>> {
+    Self::find_global_declaration(this, "function", name)
+  }
+
+  pub fn find_global_class_declaration(
+    this: &Rc<RefCell<Context>>, name: &str
+  ) -> Option<Rc<RefCell<Context>>> {
+    Self::find_global_declaration(this, "class", name)
+  }
+
+  pub fn find_global_struct_declaration(
+    this: &Rc<RefCell<Context>>, name: &str
+  ) -> Option<Rc<RefCell<Context>>> {
+    Self::find_global_declaration(this, "struct", name)
+  }
+
+  /// Finds the declaration of the given kind (`function`, `class`, `struct`)
+  /// the name refers to when used from `this` context. The lookup starts with
+  /// the namespaces `this` is in, from the innermost to the outermost, and the
+  /// imports of every context on the way. It then falls back to the
+  /// declarations from the global scope.
+  pub fn find_global_declaration(
+    this: &Rc<RefCell<Context>>, kind: &str, name: &str
   ) -> Option<Rc<RefCell<Context>>> {
     let program = Self::get_top_most_context(this);
-    let context_name = format!("function: {}", name);
+    let context_name = format!("{kind}: {name}");
+
+    let mut current = Some(this.clone());
+    while let Some(context) = current {
+      let context_ref = Self::get_ref(&context);
+
+      if let ContextType::Namespace { path: _ } = &context_ref.context_type {
+        let namespace_path = Self::get_namespace_path(&context);
+        let result = Self::find_namespaced_declaration(&program, &namespace_path, &context_name);
+
+        if result.is_some() {
+          return result;
+        }
+      }
+
+      for import in &context_ref.imports {
+        let (namespace_path, item) = import.namespace_and_item();
+
+        if item.is_some() && item.map(String::as_str) != Some(name) {
+          continue;
+        }
+
+        let result = Self::find_namespaced_declaration(&program, namespace_path, &context_name);
+
+        if result.is_some() {
+          return result;
+        }
+      }
+
+      current = context_ref.parent_context.clone();
+    }
 
     for file_context in &Self::get_ref(&program).children_contexts {
-      let result = Self::get_ref(&file_context)
+      let result = Self::get_ref(file_context)
         .children_contexts
         .iter()
         .find(|context| Self::get_ref(context).name == context_name)
-        .and_then(|c| Some(c.clone()));
+        .cloned();
 
       if result.is_some() {
         return result;
@@ -159,25 +343,100 @@ impl Context {
     None
   }
 
-  pub fn find_global_class_declaration(
-    this: &Rc<RefCell<Context>>, name: &str
+  /// Looks for the declaration with the given context name in every namespace
+  /// block of the program whose path is `namespace_path`.
+  fn find_namespaced_declaration(
+    program: &Rc<RefCell<Context>>, namespace_path: &[String], context_name: &str
   ) -> Option<Rc<RefCell<Context>>> {
-    let program = Self::get_top_most_context(this);
-    let context_name = format!("class: {}", name);
+    fn search(
+      context: &Rc<RefCell<Context>>, current_path: &[String], namespace_path: &[String],
+      context_name: &str
+    ) -> Option<Rc<RefCell<Context>>> {
+      for child in &Context::get_ref(context).children_contexts {
+        let child_ref = Context::get_ref(child);
+
+        let child_path = match &child_ref.context_type {
+          ContextType::Namespace { path } => [current_path, path.as_slice()].concat(),
+          // file contexts
+          ContextType::Global => current_path.to_vec(),
+          _ => continue
+        };
+
+        if !namespace_path.starts_with(&child_path) {
+          continue;
+        }
+
+        if child_path == namespace_path {
+          if let ContextType::Namespace { path: _ } = &child_ref.context_type {
+            let result = child_ref
+              .children_contexts
+              .iter()
+              .find(|context| Context::get_ref(context).name == context_name)
+              .cloned();
+
+            if result.is_some() {
+              return result;
+            }
+          }
+        }
+
+        let result = search(child, &child_path, namespace_path, context_name);
+        if result.is_some() {
+          return result;
+        }
+      }
 
-    for file_context in &Self::get_ref(&program).children_contexts {
-      let result = Self::get_ref(&file_context)
-        .children_contexts
-        .iter()
-        .find(|context| Self::get_ref(&context).name == context_name)
-        .and_then(|c| Some(c.clone()));
+      None
+    }
 
-      if result.is_some() {
-        return result;
+    search(program, &[], namespace_path, context_name)
+  }
+
+  /// Returns the full path of the namespace the context is in, including the
+  /// context itself if it is a namespace.
+  pub fn get_namespace_path(this: &Rc<RefCell<Context>>) -> Vec<String> {
+    let context = Self::get_ref(this);
+    let mut output = match &context.parent_context {
+      Some(parent) => Self::get_namespace_path(parent),
+      None => Vec::new()
+    };
+
+    if let ContextType::Namespace { path } = &context.context_type {
+      output.extend(path.iter().cloned());
+    }
+
+    output
+  }
+
+  /// Finds the context of the class or struct that is emitted as
+  /// `compound_name`, in the global scope or in any namespace.
+  pub fn find_compound_declaration(
+    this: &Rc<RefCell<Context>>, compound_name: &str
+  ) -> Option<Rc<RefCell<Context>>> {
+    fn search(context: &Rc<RefCell<Context>>, compound_name: &str) -> Option<Rc<RefCell<Context>>> {
+      for child in &Context::get_ref(context).children_contexts {
+        let child_ref = Context::get_ref(child);
+
+        match &child_ref.context_type {
+          ContextType::Global | ContextType::Namespace { path: _ } => {
+            let result = search(child, compound_name);
+
+            if result.is_some() {
+              return result;
+            }
+          }
+          _ => {
+            if child_ref.get_compound_name().as_deref() == Some(compound_name) {
+              return Some(child.clone());
+            }
+          }
+        };
       }
+
+      None
     }
 
-    None
+    search(&Self::get_top_most_context(this), compound_name)
   }
 
   /// Returns an optional mangled name the identifier should use to use the
@@ -236,7 +495,7 @@ impl Context {
     Ok(())
   }
 
-  pub fn get_ref(context: &Rc<RefCell<Context>>) -> Ref<Context> {
+  pub fn get_ref(context: &Rc<RefCell<Context>>) -> Ref<'_, Context> {
     let context: &RefCell<Context> = context.borrow();
 
     context.borrow()
@@ -254,6 +513,7 @@ impl Context {
 #[derive(Debug, Clone)]
 pub enum ContextType {
   Global,
+  Namespace { path: Vec<String> },
   ClassOrStruct,
   State { parent_class_name: String },
   Function
@@ -337,3 +597,27 @@ impl<'a> GenericContext {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::Context;
+
+  fn mangle(path: &[&str], name: &str) -> String {
+    let path: Vec<String> = path.iter().map(|part| part.to_string()).collect();
+
+    Context::mangle_namespaced_name(&path, name)
+  }
+
+  #[test]
+  fn mangled_names_join_the_path() {
+    assert_eq!(mangle(&["Mod", "Logging"], "log"), "Mod__Logging__log");
+    assert_eq!(mangle(&[], "log"), "log");
+  }
+
+  #[test]
+  fn mangled_names_do_not_collide_on_underscores() {
+    assert_ne!(mangle(&["A_B"], "c"), mangle(&["A"], "B_c"));
+    assert_ne!(mangle(&["A_"], "_B"), mangle(&["A"], "__B"));
+    assert_ne!(mangle(&["A"], "x_B"), mangle(&["A_x"], "B"));
+  }
+}
@@ -3,6 +3,21 @@ use std::ops::Deref;
 pub mod context;
 pub mod type_inference;
 
+/// The characters surrounding the span markers in the emitted code, they
+/// cannot appear in a source file.
+pub const SPAN_MARKER_START: char = '\u{1}';
+pub const SPAN_MARKER_END: char = '\u{2}';
+
+/// Writes a marker of the span in the output, the markers are removed from the
+/// emitted code once it is complete to build its source map.
+pub fn emit_span_marker(
+  output: &mut Vec<u8>, span: crate::ast::Span
+) -> Result<(), std::io::Error> {
+  use std::io::Write as IoWrite;
+
+  write!(output, "{SPAN_MARKER_START}{}{SPAN_MARKER_END}", span.0)
+}
+
 pub trait Codegen {
   fn emit(&self, _: &context::Context, output: &mut Vec<u8>) -> Result<(), std::io::Error> {
     use std::io::Write as IoWrite;
@@ -2,7 +2,7 @@ use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 
-use crate::ast::{ParameterType, Span};
+use crate::ast::{ParameterType, Span, TypeDeclaration};
 
 /// TODO: the store only holds Strings, this means a lot of allocations since
 /// the nodes also hold the strings. Ideally the store would only store
@@ -17,11 +17,22 @@ impl TypeInferenceStore {
     let mut map = HashMap::new();
 
     map.insert("int".to_string(), Rc::new(InferedType::Scalar));
-    map.insert("array".to_string(), Rc::new(InferedType::Unknown));
+    map.insert("bool".to_string(), Rc::new(InferedType::Scalar));
+    map.insert("byte".to_string(), Rc::new(InferedType::Scalar));
     map.insert("float".to_string(), Rc::new(InferedType::Scalar));
     map.insert("string".to_string(), Rc::new(InferedType::Scalar));
     map.insert("name".to_string(), Rc::new(InferedType::Scalar));
 
+    // the arrays are a compound type, its methods are registered with the
+    // builtins of the game.
+    map.insert(
+      "array".to_string(),
+      Rc::new(InferedType::Compound {
+        type_inference_map: RefCell::new(HashMap::new()),
+        extends: None
+      })
+    );
+
     Self { types: map }
   }
 
@@ -96,6 +107,41 @@ impl TypeInferenceStore {
 
 pub type TypeInferenceMap = HashMap<String, Rc<InferedType>>;
 
+/// The name the methods of the arrays give to the type of the items.
+pub const ARRAY_ITEMS_TYPE: &str = "T";
+
+/// Returns the type of the items of an array type, `int` for `array<int>`
+/// whose stringified form is `arrayint`.
+pub fn array_items_type(type_name: &str) -> Option<&str> {
+  type_name
+    .strip_prefix("array")
+    .filter(|items_type| !items_type.is_empty())
+}
+
+/// Returns the name the type is registered under in the map, the arrays share
+/// the `array` type whatever their items are.
+pub fn registered_type_name<'a>(map: &TypeInferenceMap, type_name: &'a str) -> &'a str {
+  match array_items_type(type_name) {
+    Some(_) if !map.contains_key(type_name) => "array",
+    _ => type_name
+  }
+}
+
+/// Returns the declaration of the type from its stringified form, the arrays
+/// get back the type of their items.
+pub fn type_declaration(type_name: &str) -> TypeDeclaration {
+  let (type_name, generic_type_assignment) = match array_items_type(type_name) {
+    Some(items_type) => ("array", Some(vec![type_declaration(items_type)])),
+    None => (type_name, None)
+  };
+
+  TypeDeclaration::Regular {
+    type_name: type_name.to_string(),
+    generic_type_assignment,
+    mangled_accessor: RefCell::new(None)
+  }
+}
+
 #[derive(Debug)]
 pub enum InferedType {
   /// Primitive types, or types that hold a single value
@@ -128,6 +174,31 @@ pub struct FunctionInferedType {
   pub span: Span
 }
 
+impl FunctionInferedType {
+  /// Returns the signature where the generic type is replaced by the given
+  /// type, in the parameters and in the return type.
+  pub fn with_generic_type(&self, generic_type: &str, replacement: &str) -> Self {
+    let replace = |type_name: &String| match type_name == generic_type {
+      true => replacement.to_string(),
+      false => type_name.clone()
+    };
+
+    Self {
+      parameters: self
+        .parameters
+        .iter()
+        .map(|parameter| FunctionInferedParameterType {
+          parameter_type: parameter.parameter_type,
+          infered_type: replace(&parameter.infered_type),
+          span: parameter.span
+        })
+        .collect(),
+      return_type: self.return_type.as_ref().map(replace),
+      span: self.span
+    }
+  }
+}
+
 #[derive(Debug)]
 pub struct FunctionInferedParameterType {
   pub parameter_type: ParameterType,
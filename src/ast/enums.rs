@@ -9,6 +9,8 @@ pub struct EnumDeclaration {
 
 impl Visited for EnumDeclaration {
   fn accept<T: visitor::Visitor>(&self, visitor: &mut T) {
+    visitor.visit_enum_declaration(self);
+
     for statement in &self.body_statements {
       statement.accept(visitor);
     }
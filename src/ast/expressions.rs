@@ -1,10 +1,12 @@
 use std::borrow::Borrow;
 use std::rc::Rc;
 
-use ariadne::{Label, Report};
-
-use super::codegen::type_inference::{FunctionInferedType, InferedType, TypeInferenceMap};
+use super::codegen::type_inference::{
+  array_items_type, registered_type_name, FunctionInferedType, InferedType, TypeInferenceMap,
+  ARRAY_ITEMS_TYPE
+};
 use super::inference::Type;
+use super::report_manager::Diagnostic;
 use super::*;
 
 #[derive(Debug)]
@@ -34,8 +36,8 @@ impl Expression {
 
   pub fn deduce_type(
     &self, current_context: &Rc<RefCell<Context>>, inference_map: &TypeInferenceMap,
-    global_inference_map: &TypeInferenceMap, span_manager: &SpanManager
-  ) -> Result<(), Vec<(Report, Span)>> {
+    global_inference_map: &TypeInferenceMap
+  ) -> Result<(), Vec<Diagnostic>> {
     {
       let self_infered_type_name: &Type = &self.infered_type_name.borrow();
       if let Type::Unknown = self_infered_type_name {
@@ -80,19 +82,12 @@ impl Expression {
               }
             }
             Err(message) => {
-              return Err(vec![(
-                Report::build(
-                  ariadne::ReportKind::Error,
-                  (),
-                  span_manager.get_left(identifier.span)
-                )
-                .with_message(&"Could not infer type for `this`")
-                .with_label(
-                  Label::new(span_manager.get_range(identifier.span)).with_message(&message)
-                )
-                .finish(),
-                identifier.span
-              )]);
+              return Err(vec![Diagnostic::error(
+                identifier.span,
+                "Could not infer type for `this`"
+              )
+              .with_code("unknown-this-type")
+              .with_label(identifier.span, message)]);
             }
           }
         } else if a.text == "parent" {
@@ -104,51 +99,49 @@ impl Expression {
               }
             }
             Err(message) => {
-              return Err(vec![(
-                Report::build(
-                  ariadne::ReportKind::Error,
-                  (),
-                  span_manager.get_left(identifier.span)
-                )
-                .with_message(&"Could not infer type for `parent`")
-                .with_label(
-                  Label::new(span_manager.get_range(identifier.span)).with_message(&message)
-                )
-                .finish(),
-                identifier.span
-              )]);
+              return Err(vec![Diagnostic::error(
+                identifier.span,
+                "Could not infer type for `parent`"
+              )
+              .with_code("unknown-parent-type")
+              .with_label(identifier.span, message)]);
             }
           }
         } else {
-          let a: &RefCell<Context> = current_context.borrow();
+          let variable_name = Context::get_ref(current_context)
+            .get_scoped_name(identifier.span, &identifier.text)
+            .to_string();
 
-          match a.borrow().get_variable_type_string(&identifier.text) {
+          match Context::find_variable_type(current_context, &variable_name) {
             Some(t) => {
-              if let Some(infered_type) = inference_map.get(t) {
-                self.set_infered_type(Type::Identifier(t.clone()), infered_type.clone());
+              if let Some(infered_type) =
+                global_inference_map.get(registered_type_name(global_inference_map, &t))
+              {
+                self.set_infered_type(Type::Identifier(t), infered_type.clone());
               }
             }
             None => {
-              return Err(vec![(
-                Report::build(
-                  ariadne::ReportKind::Error,
-                  (),
-                  span_manager.get_left(identifier.span)
-                )
-                .with_message(&"Unknown local variable")
-                .with_label(
-                  Label::new(span_manager.get_range(identifier.span))
-                    .with_message(&"No variable or property exists with such name")
-                )
-                .finish(),
-                identifier.span
+              return Err(vec![Diagnostic::error(
+                identifier.span,
+                "Unknown local variable"
+              )
+              .with_code("unknown-variable")
+              .with_label(
+                identifier.span,
+                "No variable or property exists with such name"
               )]);
             }
           }
         }
       }
       ExpressionBody::FunctionCall(function) => {
-        match inference_map.get(&function.accessor.text) {
+        // namespaced functions are registered with their mangled names
+        let function_name = match function.mangled_accessor.borrow().as_ref() {
+          Some(mangled_accessor) => mangled_accessor.clone(),
+          None => function.accessor.text.clone()
+        };
+
+        match inference_map.get(&function_name) {
           Some(infered_type) => match infered_type.as_ref() {
             crate::ast::codegen::type_inference::InferedType::Function(rc_function) => {
               function
@@ -157,7 +150,9 @@ impl Expression {
 
               match &(*rc_function).return_type {
                 Some(s) => {
-                  if let Some(infered_type) = global_inference_map.get(s) {
+                  if let Some(infered_type) =
+                    global_inference_map.get(registered_type_name(global_inference_map, s))
+                  {
                     self.set_infered_type(Type::Identifier(s.clone()), infered_type.clone());
                   } else {
                     // todo: handle unknown return type
@@ -169,48 +164,38 @@ impl Expression {
               }
             }
             _ => {
-              return Err(vec![(
-                Report::build(
-                  ariadne::ReportKind::Error,
-                  (),
-                  span_manager.get_left(function.accessor.span)
-                )
-                .with_message(&"Invalid function call")
-                .with_label(
-                  Label::new(span_manager.get_range(function.accessor.span))
-                    .with_message(&format!("{} is not a function.", &function.accessor.text))
-                )
-                .finish(),
-                function.accessor.span
+              return Err(vec![Diagnostic::error(
+                function.accessor.span,
+                "Invalid function call"
+              )
+              .with_code("not-a-function")
+              .with_label(
+                function.accessor.span,
+                format!("{} is not a function.", &function.accessor.text)
               )]);
             }
           },
           None => {
-            return Err(vec![(
-              Report::build(
-                ariadne::ReportKind::Warning,
-                (),
-                span_manager.get_left(function.accessor.span)
-              )
-              .with_message(&"Call to unknown function")
-              .with_label(
-                Label::new(span_manager.get_range(function.accessor.span)).with_message(&format!(
-                  "{} is not a known function.",
-                  &function.accessor.text
-                ))
-              )
-              .finish(),
-              function.accessor.span
+            return Err(vec![Diagnostic::warning(
+              function.accessor.span,
+              "Call to unknown function"
+            )
+            .with_code("unknown-function")
+            .with_label(
+              function.accessor.span,
+              format!("{} is not a known function.", &function.accessor.text)
             )]);
           }
         };
       }
       ExpressionBody::ClassInstantiation(instantiation) => {
-        if let Some(infered_type) = inference_map.get(&instantiation.class_name) {
-          self.set_infered_type(
-            Type::Identifier(instantiation.class_name.clone()),
-            infered_type.clone()
-          );
+        let class_name = match instantiation.mangled_accessor.borrow().as_ref() {
+          Some(mangled_accessor) => mangled_accessor.clone(),
+          None => instantiation.class_name.clone()
+        };
+
+        if let Some(infered_type) = inference_map.get(&class_name) {
+          self.set_infered_type(Type::Identifier(class_name), infered_type.clone());
         }
       }
       ExpressionBody::Lambda(lambda) => {
@@ -253,7 +238,7 @@ impl Expression {
           _ => {
             match &operation {
               OperationCode::Nesting => {
-                left.deduce_type(current_context, inference_map, inference_map, span_manager)?;
+                left.deduce_type(current_context, inference_map, inference_map)?;
 
                 // special check for lambda calls:
                 {
@@ -280,18 +265,14 @@ impl Expression {
                           } else {
                             let span = lambda.span;
 
-                            return Err(vec![(
-                              Report::build(
-                                ariadne::ReportKind::Warning,
-                                (),
-                                span_manager.get_left(span)
-                              )
-                              .with_message(&"Unknown return type in lambda")
-                              .with_label(Label::new(span_manager.get_range(span)).with_message(
-                                &format!("The returned type \"{t}\" is not a known type")
-                              ))
-                              .finish(),
-                              span
+                            return Err(vec![Diagnostic::warning(
+                              span,
+                              "Unknown return type in lambda"
+                            )
+                            .with_code("unknown-lambda-return-type")
+                            .with_label(
+                              span,
+                              format!("The returned type \"{t}\" is not a known type")
                             )]);
                           }
                         }
@@ -316,19 +297,12 @@ impl Expression {
                     _ => {
                       let span = left.body.get_span();
 
-                      return Err(vec![(
-                        Report::build(
-                          ariadne::ReportKind::Warning,
-                          (),
-                          span_manager.get_left(span)
-                        )
-                        .with_message(&"Invalid nesting")
-                        .with_label(Label::new(span_manager.get_range(span)).with_message(
-                          &"Nesting but left side expression does not result in a compound type."
-                        ))
-                        .finish(),
-                        span
-                      )]);
+                      return Err(vec![Diagnostic::warning(span, "Invalid nesting")
+                        .with_code("invalid-nesting")
+                        .with_label(
+                          span,
+                          "Nesting but left side expression does not result in a compound type."
+                        )]);
                     }
                   };
 
@@ -338,87 +312,104 @@ impl Expression {
                   _ => {
                     let span = left.body.get_span();
 
-                    return Err(vec![(
-                      Report::build(
-                        ariadne::ReportKind::Warning,
-                        (),
-                        span_manager.get_left(span)
-                      )
-                      .with_message(&"Invalid nesting")
+                    return Err(vec![Diagnostic::warning(span, "Invalid nesting")
+                      .with_code("invalid-nesting")
                       .with_label(
-                        Label::new(span_manager.get_range(span))
-                          .with_message(&"Nesting but left side expression is not an identifier.")
-                      )
-                      .finish(),
-                      span
-                    )]);
+                        span,
+                        "Nesting but left side expression is not an identifier."
+                      )]);
                   }
                 };
 
                 // now that we know the left side of the nesting is a compound type,
-                let top_most_context = &Context::get_top_most_context(&current_context);
-                let top_most_context: &RefCell<Context> = &top_most_context.borrow();
-                let top_most_context: &Context = &top_most_context.borrow();
-
-                for file_context in &top_most_context.children_contexts {
-                  let context = Context::get_ref(&file_context);
-                  for global_type_context in &context.children_contexts {
-                    let context = Context::get_ref(&global_type_context);
-                    match context.get_compound_name() {
-                      Some(compound_name) => {
-                        if &compound_name == left_type_identifier {
-                          // we need to handle class inheritance for nesting
-                          // on compound types.
-                          let mut used_type_inference_map = left_compound_type_inference_map;
-                          let mut used_extend = left_extended_type;
-
-                          loop {
-                            let result = right.deduce_type(
-                              &global_type_context,
-                              &used_type_inference_map.borrow(),
-                              inference_map,
-                              span_manager
-                            );
+                // find its declaration, it may be in a namespace.
+                let left_compound_name =
+                  registered_type_name(global_inference_map, left_type_identifier);
+
+                if let Some(global_type_context) =
+                  Context::find_compound_declaration(current_context, left_compound_name)
+                {
+                  // we need to handle class inheritance for nesting
+                  // on compound types.
+                  let mut used_type_context = global_type_context;
+                  let mut used_type_inference_map = left_compound_type_inference_map;
+                  let mut used_extend = left_extended_type;
+
+                  loop {
+                    let result = right.deduce_type(
+                      &used_type_context,
+                      &used_type_inference_map.borrow(),
+                      inference_map
+                    );
+
+                    if result.is_ok() {
+                      break;
+                    }
 
-                            if result.is_ok() {
-                              break;
-                            }
-
-                            match used_extend {
-                              // there is still a type in the inheritance tree
-                              Some(extend_type_identifier) => {
-                                dbg!(&extend_type_identifier);
-                                match global_inference_map.get(extend_type_identifier) {
-                                  Some(base_type) => {
-                                    match base_type.borrow() {
-                                      InferedType::Compound {
-                                        type_inference_map,
-                                        extends
-                                      } => {
-                                        used_type_inference_map = type_inference_map;
-                                        used_extend = extends;
-                                      }
-                                      _ => {
-                                        return result;
-                                      }
-                                    };
-                                  }
-                                  None => return result
-                                };
+                    match used_extend {
+                      // there is still a type in the inheritance tree
+                      Some(extend_type_identifier) => {
+                        match global_inference_map.get(extend_type_identifier) {
+                          Some(base_type) => {
+                            match base_type.borrow() {
+                              InferedType::Compound {
+                                type_inference_map,
+                                extends
+                              } => {
+                                // the properties are in the context of the
+                                // extended type
+                                if let Some(extended_type_context) =
+                                  Context::find_compound_declaration(
+                                    current_context,
+                                    extend_type_identifier
+                                  )
+                                {
+                                  used_type_context = extended_type_context;
+                                }
+
+                                used_type_inference_map = type_inference_map;
+                                used_extend = extends;
                               }
-                              None => return result
-                            }
+                              _ => {
+                                return result;
+                              }
+                            };
                           }
+                          None => return result
+                        };
+                      }
+                      None => return result
+                    }
+                  }
 
-                          self.set_infered_type(
-                            right.infered_type_name.borrow().clone(),
-                            right.infered_type.borrow().clone()
-                          );
-                        }
+                  // the methods of the arrays are declared for any type of
+                  // items, use the type of the items of this array instead.
+                  if let (Some(items_type), ExpressionBody::FunctionCall(call)) = (
+                    array_items_type(left_type_identifier)
+                      .filter(|_| left_compound_name == "array"),
+                    &right.body
+                  ) {
+                    call.specialize(ARRAY_ITEMS_TYPE, items_type);
+
+                    let return_type = call
+                      .infered_function_type
+                      .borrow()
+                      .as_ref()
+                      .and_then(|function| function.return_type.clone());
+
+                    if let Some(return_type) = return_type {
+                      if let Some(infered_type) = global_inference_map
+                        .get(registered_type_name(global_inference_map, &return_type))
+                      {
+                        right.set_infered_type(Type::Identifier(return_type), infered_type.clone());
                       }
-                      None => {}
-                    };
+                    }
                   }
+
+                  self.set_infered_type(
+                    right.infered_type_name.borrow().clone(),
+                    right.infered_type.borrow().clone()
+                  );
                 }
               }
               _ => {
@@ -442,32 +433,70 @@ impl Expression {
           None => {
             let span = expr.body.get_span();
 
-            return Err(vec![(
-              Report::build(
-                ariadne::ReportKind::Warning,
-                (),
-                span_manager.get_left(span)
-              )
-              .with_message(&"Cast to unknown type")
-              .with_label(
-                Label::new(span_manager.get_range(span))
-                  .with_message(&format!("{} is not a known type.", &type_name))
-              )
-              .finish(),
-              span
-            )]);
+            return Err(vec![Diagnostic::warning(span, "Cast to unknown type")
+              .with_code("unknown-cast-type")
+              .with_label(span, format!("{} is not a known type.", &type_name))]);
           }
         };
       }
       ExpressionBody::Group(expr) => {
         // deduce early the type of the expression:
-        expr.deduce_type(current_context, inference_map, inference_map, span_manager)?;
+        expr.deduce_type(current_context, inference_map, inference_map)?;
 
         self.set_infered_type(
           expr.infered_type_name.borrow().clone(),
           expr.infered_type.borrow().clone()
         );
       }
+      ExpressionBody::Ternary(ternary) => {
+        // the errors from the branches are reported when the visitors reach
+        // them
+        let _ = ternary
+          .consequence
+          .deduce_type(current_context, inference_map, inference_map);
+        let _ = ternary
+          .alternative
+          .deduce_type(current_context, inference_map, inference_map);
+
+        match ternary.typed_branch() {
+          Some(branch) => {
+            self.set_infered_type(
+              branch.infered_type_name.borrow().clone(),
+              branch.infered_type.borrow().clone()
+            );
+          }
+          None => {
+            // keep the type of the first branch so the error is reported once
+            self.set_infered_type(
+              ternary.consequence.infered_type_name.borrow().clone(),
+              ternary.consequence.infered_type.borrow().clone()
+            );
+
+            let consequence_span = ternary.consequence.body.get_span();
+            let alternative_span = ternary.alternative.body.get_span();
+
+            return Err(vec![Diagnostic::error(
+              ternary.span,
+              "Mismatched types in conditional expression"
+            )
+            .with_code("conditional-type-mismatch")
+            .with_label(
+              consequence_span,
+              format!(
+                "This branch is of type {}",
+                ternary.consequence.infered_type_name.borrow()
+              )
+            )
+            .with_label(
+              alternative_span,
+              format!(
+                "but this one is of type {}",
+                ternary.alternative.infered_type_name.borrow()
+              )
+            )]);
+          }
+        };
+      }
       ExpressionBody::Error => {}
     };
 
@@ -485,6 +514,10 @@ impl visitor::Visited for Expression {
 
 impl Codegen for Expression {
   fn emit(&self, context: &Context, f: &mut Vec<u8>) -> Result<(), std::io::Error> {
+    if !matches!(self.body, ExpressionBody::Error) {
+      emit_span_marker(f, self.body.get_span())?;
+    }
+
     self.body.emit(context, f)
   }
 }
@@ -513,6 +546,9 @@ pub enum ExpressionBody {
   /// Expressions surrounded by parenthesis
   Group(Rc<Expression>),
 
+  /// `condition ? consequence : alternative`
+  Ternary(Ternary),
+
   Error
 }
 
@@ -535,7 +571,8 @@ impl visitor::Visited for ExpressionBody {
       ExpressionBody::Group(x) => x.accept(visitor),
       ExpressionBody::ClassInstantiation(x) => x.accept(visitor),
       ExpressionBody::Not(x) => x.accept(visitor),
-      ExpressionBody::Lambda(x) => x.accept(visitor)
+      ExpressionBody::Lambda(x) => x.accept(visitor),
+      ExpressionBody::Ternary(x) => x.accept(visitor)
     }
   }
 }
@@ -573,7 +610,8 @@ impl Codegen for ExpressionBody {
         x.emit(context, f)?;
         write!(f, ")")
       }
-      ExpressionBody::Lambda(x) => x.emit(context, f)
+      ExpressionBody::Lambda(x) => x.emit(context, f),
+      ExpressionBody::Ternary(x) => x.emit(context, f)
     }
   }
 }
@@ -675,6 +713,7 @@ impl ExpressionBody {
       ExpressionBody::Nesting(x) => x.last().unwrap().body.get_span(),
       ExpressionBody::Cast(_, x) => x.body.get_span(),
       ExpressionBody::Group(x) => x.body.get_span(),
+      ExpressionBody::Ternary(x) => x.span,
       ExpressionBody::Error => todo!()
     }
   }
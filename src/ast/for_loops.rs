@@ -1,3 +1,4 @@
+use std::cmp::Ordering;
 use std::rc::Rc;
 
 use super::visitor::Visited;
@@ -13,10 +14,13 @@ pub struct ForStatement {
 
 impl Visited for ForStatement {
   fn accept<T: visitor::Visitor>(&self, visitor: &mut T) {
+    // the variable declared in the initialization belongs to the loop
+    visitor.visit_block_start();
     self.initialization.accept(visitor);
     self.condition.accept(visitor);
     self.iteration.accept(visitor);
     self.body_statements.accept(visitor);
+    visitor.visit_block_end();
   }
 }
 
@@ -42,39 +46,153 @@ impl Codegen for ForStatement {
 
 #[derive(Debug)]
 pub struct ForInStatement {
-  pub child: Rc<TypedIdentifier>,
-  pub parent: Rc<Expression>,
+  pub child: ForInVariable,
+
+  /// The variable of `for (idx, item) in items` that holds the index of the
+  /// item, the loop uses it as its counter
+  pub index: Option<Spanned<String>>,
+  pub collection: ForInCollection,
 
   pub body_statements: Vec<FunctionBodyStatement>,
 
-  pub indexor_name: RefCell<String>
+  /// The name of the intermediate indexing variable, it is derived from the
+  /// position of the loop in the source.
+  pub indexor_name: RefCell<String>,
+
+  /// A hash of the loop's position in the source, for the names of the
+  /// variables that hold the end and the step of a range.
+  pub stable_id: String
+}
+
+impl ForInStatement {
+  /// Returns the name of the variable that counts the iterations over an array.
+  pub fn counter_name(&self, context: &Context) -> String {
+    match &self.index {
+      Some(index) => context
+        .get_scoped_name(index.span, index.value())
+        .to_string(),
+      None => self.indexor_name.borrow().clone()
+    }
+  }
+
+  /// Returns the variables that hold the end and the step of a range, with
+  /// the expressions they are computed from once before the loop. The
+  /// literals are used as they are.
+  pub fn range_temporaries(&self) -> Vec<(String, &Rc<Expression>)> {
+    let (end, step) = match &self.collection {
+      ForInCollection::Range {
+        start: _,
+        end,
+        step
+      } => (end, step),
+      ForInCollection::Array(_) => return Vec::new()
+    };
+
+    let mut temporaries = Vec::new();
+
+    if !is_literal(end) {
+      temporaries.push((format!("end{}", self.stable_id), end));
+    }
+
+    if let Some(step) = step.as_ref().filter(|step| !is_literal(step)) {
+      temporaries.push((format!("step{}", self.stable_id), step));
+    }
+
+    temporaries
+  }
+}
+
+/// The variable that holds the items of a for..in loop
+#[derive(Debug)]
+pub enum ForInVariable {
+  /// `for item: ItemId in items`
+  Explicit {
+    declaration: Rc<TypedIdentifier>,
+    span: Span
+  },
+
+  /// `for item in items`, the type of the variable is inferred from the type
+  /// of the array by the [visitor::VariableDeclarationVisitor], or by the
+  /// [visitor::ExpressionTypeInferenceVisitor] for the arrays that are not
+  /// declared variables
+  Implicit { name: String, span: Span }
+}
+
+impl ForInVariable {
+  pub fn name(&self) -> Option<&str> {
+    match self {
+      ForInVariable::Explicit {
+        declaration,
+        span: _
+      } => declaration.names.first().map(String::as_str),
+      ForInVariable::Implicit { name, span: _ } => Some(name)
+    }
+  }
+
+  pub fn span(&self) -> Span {
+    match self {
+      ForInVariable::Explicit {
+        declaration: _,
+        span
+      }
+      | ForInVariable::Implicit { name: _, span } => *span
+    }
+  }
+
+  /// Returns the name the variable is emitted with.
+  pub fn scoped_name<'a>(&'a self, context: &'a Context) -> Option<&'a str> {
+    self
+      .name()
+      .map(|name| context.get_scoped_name(self.span(), name))
+  }
+}
+
+/// What a for..in loop iterates over
+#[derive(Debug)]
+pub enum ForInCollection {
+  /// `for item in items`, anything that has a `.Size()` and can be indexed
+  Array(Rc<Expression>),
+
+  /// `for i in start..end step -1`, the numbers from the start to the end,
+  /// excluded. The step is 1 by default.
+  Range {
+    start: Rc<Expression>,
+    end: Rc<Expression>,
+    step: Option<Rc<Expression>>
+  }
+}
+
+impl Visited for ForInCollection {
+  fn accept<T: visitor::Visitor>(&self, visitor: &mut T) {
+    match self {
+      ForInCollection::Array(array) => array.accept(visitor),
+      ForInCollection::Range { start, end, step } => {
+        start.accept(visitor);
+        end.accept(visitor);
+        step.accept(visitor);
+      }
+    }
+  }
 }
 
 impl Visited for ForInStatement {
   fn accept<T: visitor::Visitor>(&self, visitor: &mut T) {
-    match visitor.visitor_type() {
-      visitor::VisitorType::VariableDeclarationVisitor => {
-        // we generate a new random intermediate indexing variable for this for loop
-        let indexor = format!("idx{}", uuid::Uuid::new_v4().to_string().replace("-", ""));
-        let indexor_variable = TypedIdentifier {
-          names: vec![indexor.clone()],
-          type_declaration: TypeDeclaration::Regular {
-            type_name: "int".to_string(),
-            generic_type_assignment: None,
-            mangled_accessor: RefCell::new(None)
-          }
-        };
+    // the collection is evaluated before the variables of the loop exist
+    self.collection.accept(visitor);
 
-        visitor.register_variable_declaration(self.child.clone());
-        visitor.register_variable_declaration(Rc::new(indexor_variable));
-        self.indexor_name.replace(indexor);
-      }
-      _ => {}
-    };
+    visitor.visit_block_start();
+    visitor.visit_for_in_statement(self);
+
+    if let ForInVariable::Explicit {
+      declaration,
+      span: _
+    } = &self.child
+    {
+      declaration.accept(visitor);
+    }
 
-    self.child.accept(visitor);
-    self.parent.accept(visitor);
     self.body_statements.accept(visitor);
+    visitor.visit_block_end();
   }
 }
 
@@ -82,20 +200,92 @@ impl Codegen for ForInStatement {
   fn emit(&self, context: &Context, f: &mut Vec<u8>) -> Result<(), std::io::Error> {
     use std::io::Write as IoWrite;
 
-    write!(
-      f,
-      "for ({} = 0; {} < ",
-      self.indexor_name.borrow(),
-      self.indexor_name.borrow(),
-    )?;
-    self.parent.emit(context, f)?;
-    writeln!(f, ".Size(); {} += 1) {{", self.indexor_name.borrow())?;
-
-    if let Some(variable_name) = self.child.names.first() {
-      write!(f, "{} = ", variable_name)?;
-      self.parent.emit(context, f)?;
-      writeln!(f, "[{}];", self.indexor_name.borrow())?;
-    }
+    match &self.collection {
+      ForInCollection::Array(array) => {
+        let counter = self.counter_name(context);
+
+        write!(f, "for ({counter} = 0; {counter} < ")?;
+        array.emit(context, f)?;
+        writeln!(f, ".Size(); {counter} += 1) {{")?;
+
+        if let Some(variable_name) = self.child.scoped_name(context) {
+          write!(f, "{} = ", variable_name)?;
+          array.emit(context, f)?;
+          writeln!(f, "[{counter}];")?;
+        }
+      }
+      ForInCollection::Range { start, end, step } => {
+        let variable_name = self.child.scoped_name(context).unwrap_or_default();
+        let temporaries = self.range_temporaries();
+
+        // the start is computed before the end and the step
+        match temporaries.is_empty() {
+          true => {
+            write!(f, "for ({variable_name} = ")?;
+            start.emit(context, f)?;
+            write!(f, "; ")?;
+          }
+          false => {
+            write!(f, "{variable_name} = ")?;
+            start.emit(context, f)?;
+            writeln!(f, ";")?;
+
+            for (temporary_name, expression) in &temporaries {
+              write!(f, "{temporary_name} = ")?;
+              expression.emit(context, f)?;
+              writeln!(f, ";")?;
+            }
+
+            write!(f, "for (; ")?;
+          }
+        };
+
+        // the expressions of the range, or the variables that hold them
+        let emit_bound = |expression: &Rc<Expression>, f: &mut Vec<u8>| match temporaries
+          .iter()
+          .find(|(_, temporary)| Rc::ptr_eq(temporary, expression))
+        {
+          Some((temporary_name, _)) => write!(f, "{temporary_name}"),
+          None => expression.emit(context, f)
+        };
+
+        match step {
+          None => {
+            write!(f, "{variable_name} < ")?;
+            emit_bound(end, f)?;
+            writeln!(f, "; {variable_name} += 1) {{")?;
+          }
+          Some(step) => {
+            match step_sign(step) {
+              Some(Ordering::Less) => {
+                write!(f, "{variable_name} > ")?;
+                emit_bound(end, f)?;
+              }
+              Some(_) => {
+                write!(f, "{variable_name} < ")?;
+                emit_bound(end, f)?;
+              }
+              // the direction is only known once the step is computed
+              None => {
+                write!(f, "(")?;
+                emit_bound(step, f)?;
+                write!(f, " > 0 && {variable_name} < ")?;
+                emit_bound(end, f)?;
+                write!(f, ") || (")?;
+                emit_bound(step, f)?;
+                write!(f, " < 0 && {variable_name} > ")?;
+                emit_bound(end, f)?;
+                write!(f, ")")?;
+              }
+            };
+
+            write!(f, "; {variable_name} += ")?;
+            emit_bound(step, f)?;
+            writeln!(f, ") {{")?;
+          }
+        };
+      }
+    };
 
     self.body_statements.emit_join(context, f, "\n")?;
     writeln!(f, "}}")?;
@@ -103,3 +293,87 @@ impl Codegen for ForInStatement {
     Ok(())
   }
 }
+
+/// Returns whether the expression is a number, it gives the same value
+/// wherever it is computed.
+fn is_literal(expression: &Expression) -> bool {
+  matches!(
+    &expression.body,
+    ExpressionBody::Integer(_) | ExpressionBody::Float(_)
+  )
+}
+
+/// Returns the sign of the step if it is an integer literal.
+fn step_sign(step: &Expression) -> Option<Ordering> {
+  match &step.body {
+    ExpressionBody::Integer(integer) => {
+      integer.value().parse::<i64>().ok().map(|step| step.cmp(&0))
+    }
+    _ => None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::test_utils::{compile_source, normalize};
+
+  /// Returns the code emitted for the body of the `main` function.
+  fn main_body(source: &str) -> String {
+    let project = compile_source(source, false);
+    assert!(project.errors.is_empty(), "{:?}", project.errors);
+
+    let output = normalize(project.output("main.ws"));
+    let start = output.find("function main(").expect("no main function");
+
+    output[start..].to_string()
+  }
+
+  #[test]
+  fn range_ends_are_computed_once() {
+    let body = main_body(
+      "function main(items: array<int>) {\n  for i in 0..items.Size() {\n    items.PopBack();\n  }\n}\n"
+    );
+
+    assert_eq!(body.matches("items.Size()").count(), 1, "{body}");
+
+    let end = body
+      .lines()
+      .find_map(|line| line.strip_suffix(" = items.Size();"))
+      .unwrap_or_else(|| panic!("the end is not computed before the loop: {body}"));
+    assert!(end.starts_with("end"), "{body}");
+    assert!(body.contains(&format!("var {end}: int;")), "{body}");
+    assert!(
+      body.contains(&format!("for (; i < {end}; i += 1) {{")),
+      "{body}"
+    );
+  }
+
+  #[test]
+  fn range_steps_are_computed_once() {
+    let body = main_body(
+      "function step(): int {\n  return -1;\n}\n\nfunction main(n: int) {\n  for i in n..0 step step() {}\n}\n"
+    );
+
+    assert_eq!(body.matches("step()").count(), 1, "{body}");
+    assert!(body.contains("for (; (step"), "{body}");
+  }
+
+  #[test]
+  fn range_starts_are_computed_first() {
+    let body = main_body(
+      "function first(): int {\n  return 0;\n}\n\nfunction last(): int {\n  return 10;\n}\n\nfunction main() {\n  for i in first()..last() {}\n}\n"
+    );
+
+    let start = body.find("i = first();").expect("no start");
+    let end = body.find("= last();").expect("no end");
+    assert!(start < end, "{body}");
+  }
+
+  #[test]
+  fn range_literals_are_kept() {
+    let body = main_body("function main() {\n  for i in 10..0 step -2 {}\n}\n");
+
+    assert!(body.contains("for (i = 10; i > 0; i += -2) {"), "{body}");
+    assert!(!body.contains("var end"), "{body}");
+  }
+}
@@ -19,6 +19,20 @@ impl FunctionCall {
   pub fn get_function_name(&self) -> String {
     self.accessor.text.to_string()
   }
+
+  /// Replaces the generic type in the infered signature of the called
+  /// function, so the parameters are checked against the actual type.
+  pub fn specialize(&self, generic_type: &str, replacement: &str) {
+    let specialized = self
+      .infered_function_type
+      .borrow()
+      .as_ref()
+      .map(|function| Rc::new(function.with_generic_type(generic_type, replacement)));
+
+    if specialized.is_some() {
+      self.infered_function_type.replace(specialized);
+    }
+  }
 }
 
 impl Visited for FunctionCall {
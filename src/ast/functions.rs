@@ -32,8 +32,14 @@ impl visitor::Visited for FunctionDeclaration {
       _ => {}
     };
 
+    // the parameters are in a block of their own, the body can shadow them
+    visitor.visit_block_start();
     self.parameters.accept(visitor);
+    self.type_declaration.accept(visitor);
+    visitor.visit_block_start();
     self.body_statements.accept(visitor);
+    visitor.visit_block_end();
+    visitor.visit_block_end();
   }
 }
 
@@ -71,6 +77,7 @@ fn emit_function(
 ) -> Result<(), std::io::Error> {
   use std::io::Write as IoWrite;
 
+  emit_span_marker(f, this.span_name)?;
   this.function_type.emit(context, f)?;
 
   let generic_variant_suffix_prefix = match generic_variant_suffix.is_empty() {
@@ -110,6 +117,10 @@ fn emit_function(
         continue 'name_emitting;
       }
 
+      if let Some(span) = context.declaration_spans.get(name) {
+        emit_span_marker(f, *span)?;
+      }
+
       write!(f, "var {name}: ")?;
       declaration.type_declaration.emit(context, f)?;
       writeln!(f, ";")?;
@@ -196,10 +207,59 @@ impl FunctionBodyStatement {
       _ => None
     }
   }
+
+  /// Returns the expressions that are evaluated once when the statement
+  /// starts, the conditional expressions in them are computed right before the
+  /// statement.
+  pub fn leading_expressions(&self) -> Vec<&Rc<Expression>> {
+    match self {
+      FunctionBodyStatement::VariableDeclaration(x) => {
+        x.following_expression().into_iter().collect()
+      }
+      FunctionBodyStatement::Expression(x) | FunctionBodyStatement::Delete(x) => vec![x],
+      FunctionBodyStatement::Return(x) => x.iter().collect(),
+      FunctionBodyStatement::Assignement(x) => vec![&x.variable_name, &x.following_expression],
+      FunctionBodyStatement::IfStatement(IfStatement::If {
+        condition,
+        body_statements: _,
+        else_statements: _
+      }) => vec![condition],
+      FunctionBodyStatement::ForStatement(x) => match &x.initialization {
+        Some(VariableDeclarationOrAssignment::Declaration(declaration)) => {
+          declaration.following_expression().into_iter().collect()
+        }
+        Some(VariableDeclarationOrAssignment::Assignement(assignment)) => {
+          vec![&assignment.variable_name, &assignment.following_expression]
+        }
+        None => Vec::new()
+      },
+      FunctionBodyStatement::ForInStatement(x) => match &x.collection {
+        ForInCollection::Array(array) => vec![array],
+        ForInCollection::Range { start, end, step } => {
+          let mut expressions = vec![start, end];
+          expressions.extend(step);
+          expressions
+        }
+      },
+      FunctionBodyStatement::SwitchStatement(x) => vec![&x.compared],
+
+      // the conditions of the loops are evaluated on every iteration
+      FunctionBodyStatement::IfStatement(IfStatement::Else {
+        condition: _,
+        body_statements: _
+      })
+      | FunctionBodyStatement::WhileStatement(_)
+      | FunctionBodyStatement::DoWhileStatement(_)
+      | FunctionBodyStatement::Break
+      | FunctionBodyStatement::Continue => Vec::new()
+    }
+  }
 }
 
 impl visitor::Visited for FunctionBodyStatement {
   fn accept<T: visitor::Visitor>(&self, visitor: &mut T) {
+    visitor.visit_function_body_statement(self);
+
     match &self {
       FunctionBodyStatement::VariableDeclaration(x) => x.accept(visitor),
       FunctionBodyStatement::Expression(x) => x.accept(visitor),
@@ -222,6 +282,10 @@ impl Codegen for FunctionBodyStatement {
   fn emit(&self, context: &Context, f: &mut Vec<u8>) -> Result<(), std::io::Error> {
     use std::io::Write as IoWrite;
 
+    for expression in self.leading_expressions() {
+      Ternary::emit_preludes(expression, context, f)?;
+    }
+
     match self {
       FunctionBodyStatement::VariableDeclaration(x) => {
         x.emit(context, f)?;
@@ -298,7 +362,7 @@ impl Codegen for FunctionCallParameters {
   }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FunctionDeclarationParameter {
   pub parameter_type: ParameterType,
   pub typed_identifier: TypedIdentifier,
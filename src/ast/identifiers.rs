@@ -29,7 +29,7 @@ impl Codegen for IdentifierTerm {
         None => write!(f, "{}", self.text)?
       };
     } else {
-      write!(f, "{}", self.text)?;
+      write!(f, "{}", context.get_scoped_name(self.span, &self.text))?;
     }
 
     for indexing in &self.indexing {
@@ -42,7 +42,7 @@ impl Codegen for IdentifierTerm {
   }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TypedIdentifier {
   pub names: Vec<String>,
   pub type_declaration: TypeDeclaration
@@ -69,7 +69,7 @@ impl Visited for TypedIdentifier {
 /// ```
 ///
 /// `: int` is the typeDeclaration
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum TypeDeclaration {
   Regular {
     type_name: String,
@@ -88,6 +88,8 @@ impl Visited for TypeDeclaration {
         generic_type_assignment,
         mangled_accessor: _
       } => {
+        visitor.visit_type_declaration(self);
+
         if let Some(generic_types) = &generic_type_assignment {
           visitor.visit_generic_variable_declaration(self);
 
@@ -160,10 +162,15 @@ impl TypeDeclaration {
       TypeDeclaration::Regular {
         type_name,
         generic_type_assignment,
-        mangled_accessor: _
+        mangled_accessor
       } => {
+        let type_name = match mangled_accessor.borrow().as_ref() {
+          Some(mangled_accessor) => mangled_accessor.clone(),
+          None => type_name.clone()
+        };
+
         if let Some(generics) = &generic_type_assignment {
-          let mut output = type_name.clone();
+          let mut output = type_name;
 
           for generic in generics {
             output.push_str(&generic.to_string());
@@ -171,7 +178,7 @@ impl TypeDeclaration {
 
           output
         } else {
-          type_name.clone()
+          type_name
         }
       }
       TypeDeclaration::Lambda(lambda) => LambdaDeclaration::stringified_type_representation(
@@ -23,7 +23,9 @@ impl Visited for IfStatement {
         else_statements
       } => {
         condition.accept(visitor);
+        visitor.visit_block_start();
         body_statements.accept(visitor);
+        visitor.visit_block_end();
         else_statements.accept(visitor);
       }
       IfStatement::Else {
@@ -31,7 +33,9 @@ impl Visited for IfStatement {
         body_statements
       } => {
         condition.accept(visitor);
+        visitor.visit_block_start();
         body_statements.accept(visitor);
+        visitor.visit_block_end();
       }
     }
   }
@@ -7,7 +7,7 @@ use super::inference::Type;
 use super::visitor::Visited;
 use super::*;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LambdaDeclaration {
   pub parameters: Vec<FunctionDeclarationParameter>,
   pub type_declaration: Option<Rc<TypeDeclaration>>
@@ -167,6 +167,10 @@ pub struct Lambda {
   pub body_statements: Vec<FunctionBodyStatement>,
   pub span: Span,
 
+  /// A hash of the lambda's position in the source, so the class generated
+  /// for the lambda keeps the same name between compilations.
+  pub stable_id: String,
+
   pub mangled_accessor: RefCell<Option<String>>,
   pub captured_variables: RefCell<Vec<(String, Type)>>
 }
@@ -260,7 +264,7 @@ fn emit_lambda(
     // occurences of "this" with the new generated identifier:
     context
       .replace_this_with_self
-      .replace(Some(uuid::Uuid::new_v4().to_string().replace("-", "")));
+      .replace(Some(format!("self_{mangled_suffix}")));
 
     writeln!(f, " {{")?;
     match this.lambda_type {
@@ -319,8 +323,10 @@ fn emit_lambda(
 impl Visited for Lambda {
   fn accept<T: visitor::Visitor>(&self, visitor: &mut T) {
     visitor.visit_lambda(self);
+    visitor.visit_block_start();
     self.parameters.accept(visitor);
     self.body_statements.accept(visitor);
+    visitor.visit_block_end();
   }
 }
 
@@ -328,7 +334,7 @@ impl Codegen for Lambda {
   fn emit(&self, _: &Context, f: &mut Vec<u8>) -> Result<(), std::io::Error> {
     use std::io::Write as IoWrite;
 
-    let suffix = format!("wss{}", uuid::Uuid::new_v4().to_string().replace("-", ""));
+    let suffix = format!("wss{}", self.stable_id);
 
     write!(f, "(new lambda_{suffix} in thePlayer).capture(")?;
 
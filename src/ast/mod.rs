@@ -2,7 +2,7 @@ use std::cell::RefCell;
 use std::fmt::{Debug, Display};
 use std::rc::Rc;
 
-use self::codegen::Codegen;
+use self::codegen::{emit_span_marker, Codegen};
 
 pub mod codegen;
 pub mod inference;
@@ -84,6 +84,11 @@ pub use annotations::Annotation;
 
 // -----------------------------------------------------------------------------
 
+mod namespaces;
+pub use namespaces::{ImportStatement, NamespaceDeclaration};
+
+// -----------------------------------------------------------------------------
+
 mod functions;
 pub use functions::{
   FunctionBodyStatement, FunctionCallParameters, FunctionDeclaration, FunctionDeclarationParameter,
@@ -98,12 +103,14 @@ pub use ifs::IfStatement;
 // -----------------------------------------------------------------------------
 
 mod variables;
-pub use variables::{VariableAssignment, VariableDeclaration, VariableDeclarationOrAssignment};
+pub use variables::{
+  DefaultValue, VariableAssignment, VariableDeclaration, VariableDeclarationOrAssignment
+};
 
 // -----------------------------------------------------------------------------
 
 mod for_loops;
-pub use for_loops::{ForInStatement, ForStatement};
+pub use for_loops::{ForInCollection, ForInStatement, ForInVariable, ForStatement};
 
 // -----------------------------------------------------------------------------
 
@@ -127,6 +134,11 @@ pub use expressions::*;
 
 // -----------------------------------------------------------------------------
 
+mod ternary;
+pub use ternary::{EvaluationStep, Ternary};
+
+// -----------------------------------------------------------------------------
+
 mod function_call;
 pub use function_call::FunctionCall;
 
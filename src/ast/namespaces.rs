@@ -0,0 +1,113 @@
+use super::visitor::Visited;
+use super::*;
+
+/// A `namespace Foo { ... }` block, the declarations it contains are emitted
+/// with mangled names and are only reachable from the namespace itself or
+/// through import statements.
+#[derive(Debug)]
+pub struct NamespaceDeclaration {
+  pub path: Vec<String>,
+  pub statements: Vec<Statement>,
+
+  pub span_name: Span,
+
+  pub context: Rc<RefCell<Context>>
+}
+
+impl Visited for NamespaceDeclaration {
+  fn accept<T: visitor::Visitor>(&self, visitor: &mut T) {
+    visitor.visit_namespace_declaration(self);
+
+    // don't go further, the context building visitor will create a new one
+    // and continue traversing using the new one.
+    if let visitor::VisitorType::ContextBuildingVisitor = visitor.visitor_type() {
+      return;
+    }
+
+    self.statements.accept(visitor);
+  }
+}
+
+impl Codegen for NamespaceDeclaration {
+  fn emit(&self, _: &Context, f: &mut Vec<u8>) -> Result<(), std::io::Error> {
+    use std::io::Write as IoWrite;
+
+    let context = self.context.borrow();
+
+    for statement in &self.statements {
+      statement.emit(&context, f)?;
+      writeln!(f)?;
+      writeln!(f)?;
+    }
+
+    Ok(())
+  }
+}
+
+/// An `import Foo.bar;` or `import Foo.*;` statement, it makes the declarations
+/// of the namespace `Foo` visible to the scope the import is written in.
+#[derive(Debug, Clone)]
+pub struct ImportStatement {
+  pub path: Vec<String>,
+  pub is_wildcard: bool,
+
+  pub span: Span
+}
+
+impl ImportStatement {
+  /// Returns the path of the namespace the import points to, and the name of
+  /// the imported declaration if it is not a wildcard import.
+  pub fn namespace_and_item(&self) -> (&[String], Option<&String>) {
+    if self.is_wildcard {
+      (&self.path, None)
+    } else {
+      (&self.path[..self.path.len() - 1], self.path.last())
+    }
+  }
+}
+
+impl Visited for ImportStatement {
+  fn accept<T: visitor::Visitor>(&self, visitor: &mut T) {
+    visitor.visit_import_statement(self);
+  }
+}
+
+impl Codegen for ImportStatement {
+  fn emit(&self, _: &Context, _: &mut Vec<u8>) -> Result<(), std::io::Error> {
+    // imports only exist at compile time
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::test_utils::{compile_source, normalize};
+
+  #[test]
+  fn namespaced_declarations_are_mangled() {
+    let project = compile_source(
+      "namespace Mod.Net_Log {\n  function write_line() {}\n}\n\nimport Mod.Net_Log.*;\n\nfunction main() {\n  write_line();\n}\n",
+      false
+    );
+
+    assert!(project.errors.is_empty(), "{:?}", project.errors);
+
+    let output = normalize(project.output("main.ws"));
+    assert!(
+      output.contains("function Mod__Net_xLog__write_xline()"),
+      "{output}"
+    );
+    assert!(output.contains("Mod__Net_xLog__write_xline();"), "{output}");
+  }
+
+  #[test]
+  fn namespace_and_import_are_identifiers() {
+    let project = compile_source(
+      "function main(namespace: int) {\n  var import: int = namespace;\n}\n",
+      false
+    );
+
+    assert!(project.errors.is_empty(), "{:?}", project.errors);
+    assert!(normalize(project.output("main.ws")).contains("import = namespace;"));
+  }
+}
@@ -0,0 +1,123 @@
+use ariadne::{ColorGenerator, Label, Report, ReportKind};
+
+use crate::ast::{Span, SpanManager};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticKind {
+  Error,
+  Warning,
+  Advice
+}
+
+/// A message about the program the compiler reports to the user. It is kept
+/// apart from its rendering so the different outputs, the terminal or an
+/// editor, can present it their own way.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+  pub kind: DiagnosticKind,
+
+  /// A short and stable identifier of the problem, so the tools reading the
+  /// diagnostics don't have to rely on the message.
+  pub code: Option<&'static str>,
+
+  /// The span the diagnostic points at
+  pub span: Span,
+  pub message: String,
+
+  /// The labels shown under the code, they must be in the same source as the
+  /// span of the diagnostic.
+  pub labels: Vec<(Span, String)>,
+  pub help: Option<String>,
+
+  /// Where the code comes from when it is not written as is in the file, like
+  /// the macro it was expanded from
+  pub note: Option<String>
+}
+
+impl Diagnostic {
+  pub fn new(kind: DiagnosticKind, span: Span, message: impl Into<String>) -> Self {
+    Self {
+      kind,
+      code: None,
+      span,
+      message: message.into(),
+      labels: Vec::new(),
+      help: None,
+      note: None
+    }
+  }
+
+  pub fn error(span: Span, message: impl Into<String>) -> Self {
+    Self::new(DiagnosticKind::Error, span, message)
+  }
+
+  pub fn warning(span: Span, message: impl Into<String>) -> Self {
+    Self::new(DiagnosticKind::Warning, span, message)
+  }
+
+  pub fn advice(span: Span, message: impl Into<String>) -> Self {
+    Self::new(DiagnosticKind::Advice, span, message)
+  }
+
+  pub fn with_code(mut self, code: &'static str) -> Self {
+    self.code = Some(code);
+    self
+  }
+
+  pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Self {
+    self.labels.push((span, message.into()));
+    self
+  }
+
+  pub fn with_help(mut self, help: impl Into<String>) -> Self {
+    self.help = Some(help.into());
+    self
+  }
+
+  pub fn with_note(mut self, note: impl Into<String>) -> Self {
+    self.note = Some(note.into());
+    self
+  }
+
+  /// Returns the text of the first label, or the message if there is none.
+  pub fn label_message(&self) -> &str {
+    self
+      .labels
+      .first()
+      .map(|(_, message)| message.as_str())
+      .unwrap_or(&self.message)
+  }
+
+  pub fn to_report(&self, span_manager: &SpanManager) -> Report {
+    let kind = match self.kind {
+      DiagnosticKind::Error => ReportKind::Error,
+      DiagnosticKind::Warning => ReportKind::Warning,
+      DiagnosticKind::Advice => ReportKind::Advice
+    };
+
+    let mut colors = ColorGenerator::new();
+    let mut report = Report::build(kind, (), span_manager.get_left(self.span));
+
+    if !self.message.is_empty() {
+      report = report.with_message(&self.message);
+    }
+
+    for (span, message) in &self.labels {
+      report = report.with_label(
+        Label::new(span_manager.get_range(*span))
+          .with_message(message)
+          .with_color(colors.next())
+      );
+    }
+
+    if let Some(help) = &self.help {
+      report = report.with_help(help);
+    }
+
+    if let Some(note) = &self.note {
+      report = report.with_note(note);
+    }
+
+    report.finish()
+  }
+}
@@ -1,3 +1,5 @@
+mod diagnostic;
 mod report_manager;
 
+pub use diagnostic::*;
 pub use report_manager::*;
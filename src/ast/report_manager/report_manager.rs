@@ -1,70 +1,305 @@
-use core::panic;
-
-use ariadne::{Report, Source};
+use ariadne::Source;
+use serde::Serialize;
 
+use super::{Diagnostic, DiagnosticKind};
 use crate::ast::{Span, SpanManager};
-use crate::preprocessor::types::PreprocessorOutput;
+
+/// How the diagnostics are printed, `Json` prints one JSON record per line on
+/// the standard output for the tools that read them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageFormat {
+  Human,
+  Json
+}
 
 pub struct ReportManager {
-  reports: Vec<(Report, Span)>
+  diagnostics: Vec<Diagnostic>
 }
 
 impl ReportManager {
   pub fn new() -> Self {
     Self {
-      reports: Vec::new()
+      diagnostics: Vec::new()
     }
   }
 
-  pub fn push(&mut self, report: Report, span: Span) {
-    self.reports.push((report, span));
+  pub fn push(&mut self, diagnostic: Diagnostic) {
+    self.diagnostics.push(diagnostic);
   }
 
-  pub fn push_many(&mut self, reports: Vec<(Report, Span)>) {
-    for pair in reports {
-      self.push(pair.0, pair.1);
+  pub fn push_many(&mut self, diagnostics: Vec<Diagnostic>) {
+    for diagnostic in diagnostics {
+      self.push(diagnostic);
     }
   }
 
+  pub fn diagnostics(&self) -> &[Diagnostic] {
+    &self.diagnostics
+  }
+
+  pub fn diagnostics_mut(&mut self) -> &mut [Diagnostic] {
+    &mut self.diagnostics
+  }
+
+  pub fn has_errors(&self) -> bool {
+    self
+      .diagnostics
+      .iter()
+      .any(|diagnostic| diagnostic.kind == DiagnosticKind::Error)
+  }
+
+  pub fn count(&self, kind: DiagnosticKind) -> usize {
+    self
+      .diagnostics
+      .iter()
+      .filter(|diagnostic| diagnostic.kind == kind)
+      .count()
+  }
+
+  /// Returns whether the compilation failed, the warnings fail it too when
+  /// they are denied.
+  pub fn has_failed(&self, deny_warnings: bool) -> bool {
+    self.has_errors() || (deny_warnings && self.count(DiagnosticKind::Warning) > 0)
+  }
+
+  /// Returns the number of errors and warnings, as in `2 errors, 1 warning`.
+  pub fn summary(&self) -> String {
+    let errors = self.count(DiagnosticKind::Error);
+    let warnings = self.count(DiagnosticKind::Warning);
+
+    format!(
+      "{errors} error{}, {warnings} warning{}",
+      if errors == 1 { "" } else { "s" },
+      if warnings == 1 { "" } else { "s" }
+    )
+  }
+
   pub fn flush_reports(&mut self) {
-    self.reports.clear();
+    self.diagnostics.clear();
   }
 
-  pub fn consume(&mut self, content: &str) {
-    for (report, _) in &self.reports {
-      if let Err(err) = report.print(Source::from(&content)) {
+  /// Prints the reports in the terminal, each with the source it points to,
+  /// then clears them.
+  pub fn consume(&mut self, span_manager: &SpanManager, message_format: MessageFormat) {
+    for diagnostic in &self.diagnostics {
+      if message_format == MessageFormat::Json {
+        println!(
+          "{}",
+          JsonDiagnostic::new(diagnostic, span_manager).to_json()
+        );
+        continue;
+      }
+
+      let content = span_manager.get_source_content(&diagnostic.span);
+
+      if let Err(err) = diagnostic
+        .to_report(span_manager)
+        .print(Source::from(content.as_str()))
+      {
         panic!("{}", err);
       }
     }
 
     self.flush_reports();
   }
+}
 
-  pub fn consume_multiple_sources<'a>(
-    &mut self, span_manager: &'a mut SpanManager, preprocessor_output: &PreprocessorOutput
-  ) {
-    for (report, span) in &self.reports {
-      let source: &String = span_manager.get_source(span);
-      let source_content = if let Some(s) = preprocessor_output.source_files_content.get(source) {
-        s.content.borrow()
-      } else {
-        let mut output = None;
-
-        for (_name, value) in preprocessor_output.dependencies_files_content.iter() {
-          if let Some(s) = value.get(source) {
-            output = Some(s.content.borrow())
-          }
-        }
-
-        match output {
-          Some(s) => s,
-          None => panic!("attempt at reporting error but source {source} from span {} does not exist in neither the dependencies and the sources", span.0)
-        }
-      };
-
-      if let Err(err) = report.print(Source::from(source_content.as_str())) {
-        panic!("{}", err);
-      }
+/// The JSON record of a diagnostic. The lines and the columns start at 1, the
+/// columns are counted in characters.
+#[derive(Serialize)]
+pub struct JsonDiagnostic<'a> {
+  pub severity: &'static str,
+  pub code: Option<&'static str>,
+  pub file: Option<&'a str>,
+  pub range: Option<JsonRange>,
+  pub message: &'a str,
+  pub labels: Vec<JsonLabel<'a>>,
+  pub help: Option<&'a str>,
+  pub note: Option<&'a str>
+}
+
+#[derive(Serialize)]
+pub struct JsonLabel<'a> {
+  pub range: JsonRange,
+  pub message: &'a str
+}
+
+#[derive(Serialize)]
+pub struct JsonRange {
+  pub start: JsonPosition,
+  pub end: JsonPosition
+}
+
+#[derive(Serialize)]
+pub struct JsonPosition {
+  pub line: usize,
+  pub column: usize
+}
+
+impl<'a> JsonDiagnostic<'a> {
+  pub fn new(diagnostic: &'a Diagnostic, span_manager: &'a SpanManager) -> Self {
+    let severity = match diagnostic.kind {
+      DiagnosticKind::Error => "error",
+      DiagnosticKind::Warning => "warning",
+      DiagnosticKind::Advice => "advice"
+    };
+
+    let message = match diagnostic.message.is_empty() {
+      true => diagnostic.label_message(),
+      false => &diagnostic.message
+    };
+
+    let file = span_manager.get_source(&diagnostic.span);
+
+    Self {
+      severity,
+      code: diagnostic.code,
+      file: (!file.is_empty()).then_some(file.as_str()),
+      range: Some(JsonRange::new(diagnostic.span, span_manager)),
+      message,
+      labels: diagnostic
+        .labels
+        .iter()
+        .map(|(span, message)| JsonLabel {
+          range: JsonRange::new(*span, span_manager),
+          message
+        })
+        .collect(),
+      help: diagnostic.help.as_deref(),
+      note: diagnostic.note.as_deref()
+    }
+  }
+
+  pub fn to_json(&self) -> String {
+    serde_json::to_string(self).unwrap_or_default()
+  }
+}
+
+impl JsonRange {
+  fn new(span: Span, span_manager: &SpanManager) -> Self {
+    let content = span_manager.get_source_content(&span);
+
+    Self {
+      start: JsonPosition::new(content, span_manager.get_left(span)),
+      end: JsonPosition::new(content, span_manager.get_right(span))
+    }
+  }
+}
+
+impl JsonPosition {
+  fn new(content: &str, offset: usize) -> Self {
+    let mut offset = offset.min(content.len());
+    while !content.is_char_boundary(offset) {
+      offset -= 1;
     }
+
+    let before = &content[..offset];
+    let line_start = before.rfind('\n').map(|index| index + 1).unwrap_or(0);
+
+    Self {
+      line: before.matches('\n').count() + 1,
+      column: before[line_start..].chars().count() + 1
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::{json, Value};
+
+  use super::JsonDiagnostic;
+  use crate::ast::DiagnosticKind;
+  use crate::compiler;
+  use crate::config::read_config_from;
+  use crate::test_utils::TestDirectory;
+
+  /// Returns the JSON records of the diagnostics of the project made of the
+  /// `main.wss` file.
+  fn json_records(source: &str, kind: DiagnosticKind) -> Vec<Value> {
+    let directory = TestDirectory::new("json");
+    directory
+      .file(
+        "cahirc.toml",
+        "[package]\nname = \"test\"\nsrc = \"src\"\ndist = \"dist\"\n"
+      )
+      .file("src/main.wss", source);
+
+    let config = read_config_from(&directory.path).unwrap();
+    let compilation = compiler::analyse(&config, false).unwrap();
+
+    compilation
+      .report_manager
+      .diagnostics()
+      .iter()
+      .filter(|diagnostic| diagnostic.kind == kind)
+      .map(|diagnostic| {
+        serde_json::from_str(&JsonDiagnostic::new(diagnostic, &compilation.span_manager).to_json())
+          .unwrap()
+      })
+      .collect()
+  }
+
+  fn position(line: usize, column: usize) -> Value {
+    json!({ "line": line, "column": column })
+  }
+
+  #[test]
+  fn error_records_have_a_range_and_labels() {
+    let records = json_records(
+      "function total(count: int) {\n  for item in count {\n  }\n}\n",
+      DiagnosticKind::Error
+    );
+
+    assert_eq!(records.len(), 1, "{records:?}");
+    let record = &records[0];
+
+    let keys: Vec<_> = record.as_object().unwrap().keys().cloned().collect();
+    assert_eq!(
+      keys,
+      ["code", "file", "help", "labels", "message", "note", "range", "severity"]
+    );
+
+    assert_eq!(record["severity"], "error");
+    assert_eq!(record["code"], "unknown-variable-type");
+    assert!(record["file"].as_str().unwrap().ends_with("main.wss"));
+    assert!(record["message"].is_string());
+    assert_eq!(record["range"]["start"]["line"], 2);
+    assert!(
+      record["range"]["end"]["column"].as_u64() > record["range"]["start"]["column"].as_u64()
+    );
+
+    for label in record["labels"].as_array().unwrap() {
+      assert!(label["message"].is_string());
+      assert!(label["range"]["start"]["line"].is_u64());
+    }
+  }
+
+  #[test]
+  fn warning_records_point_to_the_original_code() {
+    let records = json_records(
+      "function main() {\n  print(1);\n  UNKNOWN!()\n}\n",
+      DiagnosticKind::Warning
+    );
+
+    assert_eq!(records.len(), 1, "{records:?}");
+    let record = &records[0];
+
+    assert_eq!(record["severity"], "warning");
+    assert_eq!(record["code"], "unknown-macro");
+    assert!(record["file"].as_str().unwrap().ends_with("main.wss"));
+    assert_eq!(record["message"], "Call to unknown macro");
+    assert_eq!(
+      record["range"],
+      json!({ "start": position(3, 3), "end": position(3, 10) })
+    );
+    assert_eq!(
+      record["labels"],
+      json!([{
+        "range": { "start": position(3, 3), "end": position(3, 10) },
+        "message": "UNKNOWN is not a known macro."
+      }])
+    );
+    assert_eq!(record["help"], Value::Null);
+    assert_eq!(record["note"], Value::Null);
   }
 }
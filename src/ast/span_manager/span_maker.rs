@@ -1,10 +1,15 @@
 use std::collections::HashMap;
 
 use super::{FilePathRef, Span, SpanManager};
+use crate::utils::stable_hash;
 
 pub struct SpanMaker<'a> {
   pub parent: &'a mut SpanManager,
   pub source_ref: FilePathRef,
+
+  /// A stable seed for the source, used to generate the names of the nodes
+  /// that need a unique identifier.
+  pub seed: String,
   pub pool: HashMap<(usize, usize), Span>
 }
 
@@ -15,4 +20,10 @@ impl<'a> SpanMaker<'a> {
       .entry((left, right))
       .or_insert_with(|| self.parent.new_span(self.source_ref, left, right))
   }
+
+  /// Returns an identifier for the node at the given position that is unique
+  /// to the node and stays the same between compilations.
+  pub fn stable_id(&self, position: usize) -> String {
+    stable_hash(&[&self.seed, &position.to_string()])
+  }
 }
@@ -3,7 +3,7 @@ use std::ops::Range;
 
 use super::SpanMaker;
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
 pub struct Span(pub usize);
 
 pub type FilePath = String;
@@ -21,6 +21,9 @@ pub struct SpanManager {
   /// Stores the path for the sources
   pub paths: Vec<FilePath>,
 
+  /// Stores the content of the sources, as they were given to the parser
+  pub contents: Vec<String>,
+
   pub spans: Vec<SpanRange>
 }
 
@@ -28,6 +31,7 @@ impl SpanManager {
   pub fn new() -> Self {
     Self {
       paths: Vec::new(),
+      contents: Vec::new(),
       spans: Vec::new()
     }
   }
@@ -44,26 +48,30 @@ impl SpanManager {
     Span(i)
   }
 
-  pub fn add_source(&mut self, source: FilePath) -> SpanMaker {
+  pub fn add_source(&mut self, source: FilePath, content: String, seed: String) -> SpanMaker<'_> {
     let source_ref = self.paths.len();
 
     self.paths.push(source);
+    self.contents.push(content);
 
     SpanMaker {
       parent: self,
       source_ref,
+      seed,
       pool: HashMap::new()
     }
   }
 
-  pub fn add_fake_source(&mut self) -> SpanMaker {
+  pub fn add_fake_source(&mut self) -> SpanMaker<'_> {
     let source_ref = self.paths.len();
 
     self.paths.push(String::new());
+    self.contents.push(String::new());
 
     SpanMaker {
       parent: self,
       source_ref,
+      seed: String::new(),
       pool: HashMap::new()
     }
   }
@@ -85,4 +93,14 @@ impl SpanManager {
 
     &self.paths[span.source_ref]
   }
+
+  pub fn get_source_ref(&self, source_ref: &Span) -> FilePathRef {
+    self.spans[source_ref.0].source_ref
+  }
+
+  pub fn get_source_content(&self, source_ref: &Span) -> &String {
+    let span = &self.spans[source_ref.0];
+
+    &self.contents[span.source_ref]
+  }
 }
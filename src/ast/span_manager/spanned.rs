@@ -13,6 +13,10 @@ impl<T> Spanned<T> {
   pub fn new(value: T, span: Span) -> Self {
     Self { span, value }
   }
+
+  pub fn value(&self) -> &T {
+    &self.value
+  }
 }
 
 impl<T> Codegen for Spanned<T>
@@ -7,7 +7,9 @@ pub enum Statement {
   ClassDeclaration(ClassDeclaration),
   StructDeclaration(StructDeclaration),
   EnumDeclaration(EnumDeclaration),
-  Annotation(Annotation)
+  Annotation(Annotation),
+  NamespaceDeclaration(NamespaceDeclaration),
+  Import(ImportStatement)
 }
 
 impl visitor::Visited for Statement {
@@ -18,7 +20,9 @@ impl visitor::Visited for Statement {
       Statement::ClassDeclaration(x) => x.accept(visitor),
       Statement::StructDeclaration(x) => x.accept(visitor),
       Statement::EnumDeclaration(x) => x.accept(visitor),
-      Statement::Annotation(x) => x.accept(visitor)
+      Statement::Annotation(x) => x.accept(visitor),
+      Statement::NamespaceDeclaration(x) => x.accept(visitor),
+      Statement::Import(x) => x.accept(visitor)
     }
   }
 }
@@ -31,7 +35,9 @@ impl Codegen for Statement {
       Statement::ClassDeclaration(x) => x.emit(context, f),
       Statement::StructDeclaration(x) => x.emit(context, f),
       Statement::EnumDeclaration(x) => x.emit(context, f),
-      Statement::Annotation(x) => x.emit(context, f)
+      Statement::Annotation(x) => x.emit(context, f),
+      Statement::NamespaceDeclaration(x) => x.emit(context, f),
+      Statement::Import(x) => x.emit(context, f)
     }
   }
 }
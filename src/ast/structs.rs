@@ -30,7 +30,12 @@ impl Codegen for StructDeclaration {
   fn emit(&self, _: &Context, f: &mut Vec<u8>) -> Result<(), std::io::Error> {
     use std::io::Write as IoWrite;
 
-    writeln!(f, "struct {} {{", self.name)?;
+    emit_span_marker(f, self.span_name)?;
+
+    match self.context.borrow().get_namespaced_name() {
+      Some(mangled_accessor) => writeln!(f, "struct {} {{", mangled_accessor)?,
+      None => writeln!(f, "struct {} {{", self.name)?
+    };
 
     for statement in &self.body_statements {
       statement.emit(&self.context.borrow(), f)?;
@@ -9,6 +9,7 @@ pub struct SwitchStatement {
 
 impl Visited for SwitchStatement {
   fn accept<T: visitor::Visitor>(&self, visitor: &mut T) {
+    self.compared.accept(visitor);
     self.cases.accept(visitor);
   }
 }
@@ -41,14 +42,18 @@ impl Visited for SwitchCaseStatement {
   fn accept<T: visitor::Visitor>(&self, visitor: &mut T) {
     match self {
       SwitchCaseStatement::Default { body_statements } => {
+        visitor.visit_block_start();
         body_statements.accept(visitor);
+        visitor.visit_block_end();
       }
       SwitchCaseStatement::Case {
         cases,
         body_statements
       } => {
         cases.accept(visitor);
+        visitor.visit_block_start();
         body_statements.accept(visitor);
+        visitor.visit_block_end();
       }
     };
   }
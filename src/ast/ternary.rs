@@ -0,0 +1,254 @@
+use super::inference::Type;
+use super::visitor::Visited;
+use super::*;
+
+/// A conditional expression, `condition ? consequence : alternative`.
+///
+/// WitcherScript has no such expression, its value is computed into a
+/// temporary variable by an `if/else` emitted right before the statement the
+/// expression is in, so only the branch that is picked is evaluated.
+#[derive(Debug)]
+pub struct Ternary {
+  pub condition: Rc<Expression>,
+  pub consequence: Rc<Expression>,
+  pub alternative: Rc<Expression>,
+  pub span: Span,
+
+  /// The name of the hoisted variable that holds the value of the expression,
+  /// it is derived from the position of the expression in the source.
+  pub temporary_name: String
+}
+
+impl Ternary {
+  /// Returns the branch whose type is the type of the whole expression, or
+  /// None when the types of the branches are incompatible.
+  pub fn typed_branch(&self) -> Option<&Rc<Expression>> {
+    let consequence_type: &Type = &self.consequence.infered_type_name.borrow();
+    let alternative_type: &Type = &self.alternative.infered_type_name.borrow();
+
+    match (consequence_type, alternative_type) {
+      (Type::Unknown, _) => Some(&self.alternative),
+      (_, Type::Unknown) => Some(&self.consequence),
+
+      // the values that are automatically casted go in the widest type
+      (Type::Int, Type::Float) | (Type::Name, Type::String) => Some(&self.alternative),
+      (Type::Float, Type::Int) | (Type::String, Type::Name) => Some(&self.consequence),
+      (consequence_type, alternative_type)
+        if consequence_type.equals_string(&alternative_type.to_string()) =>
+      {
+        Some(&self.consequence)
+      }
+      _ => None
+    }
+  }
+
+  /// Returns the conditional expressions that are computed before the
+  /// statement the expression is in, in the order they are evaluated. The
+  /// nested ones are left to the expressions they are in.
+  ///
+  /// The right side of `&&` and `||` is not always evaluated and the lambdas
+  /// run later, the conditional expressions they contain are not returned.
+  pub fn lowered_in(expression: &Expression) -> Vec<&Ternary> {
+    Self::evaluation_steps(&[expression])
+      .into_iter()
+      .filter_map(|step| match step {
+        EvaluationStep::Ternary(ternary) => Some(ternary),
+        EvaluationStep::Call => None
+      })
+      .collect()
+  }
+
+  /// Returns the conditional expressions computed before the statement and the
+  /// calls that stay in the statement, in the order they are evaluated in the
+  /// expressions. A call that comes before a conditional expression would be
+  /// made after it once the expression is computed before the statement.
+  pub fn evaluation_steps<'a>(expressions: &[&'a Expression]) -> Vec<EvaluationStep<'a>> {
+    let mut steps = Vec::new();
+
+    for expression in expressions {
+      collect_steps(expression, &mut steps);
+    }
+
+    steps
+  }
+
+  /// Emits the `if/else` statements that compute the conditional expressions
+  /// of the expression, they go before the statement the expression is in.
+  pub fn emit_preludes(
+    expression: &Expression, context: &Context, f: &mut Vec<u8>
+  ) -> Result<(), std::io::Error> {
+    for ternary in Self::lowered_in(expression) {
+      ternary.emit_prelude(context, f)?;
+    }
+
+    Ok(())
+  }
+
+  fn emit_prelude(&self, context: &Context, f: &mut Vec<u8>) -> Result<(), std::io::Error> {
+    use std::io::Write as IoWrite;
+
+    Self::emit_preludes(&self.condition, context, f)?;
+
+    write!(f, "if (")?;
+    self.condition.emit(context, f)?;
+    writeln!(f, ") {{")?;
+    self.emit_branch(&self.consequence, context, f)?;
+    writeln!(f, "}} else {{")?;
+    self.emit_branch(&self.alternative, context, f)?;
+    writeln!(f, "}}")
+  }
+
+  fn emit_branch(
+    &self, branch: &Expression, context: &Context, f: &mut Vec<u8>
+  ) -> Result<(), std::io::Error> {
+    use std::io::Write as IoWrite;
+
+    Self::emit_preludes(branch, context, f)?;
+
+    write!(f, "{} = ", self.temporary_name)?;
+    branch.emit(context, f)?;
+    writeln!(f, ";")
+  }
+}
+
+/// What is evaluated in a statement, in the order of the evaluation.
+pub enum EvaluationStep<'a> {
+  /// A conditional expression computed before the statement
+  Ternary(&'a Ternary),
+
+  /// A function or a method call, once its parameters are evaluated
+  Call
+}
+
+fn collect_steps<'a>(expression: &'a Expression, steps: &mut Vec<EvaluationStep<'a>>) {
+  match &expression.body {
+    ExpressionBody::Ternary(ternary) => steps.push(EvaluationStep::Ternary(ternary)),
+    ExpressionBody::Operation(left, OperationCode::BooleanJoin(_), _) => collect_steps(left, steps),
+    ExpressionBody::Operation(left, _, right) => {
+      collect_steps(left, steps);
+      collect_steps(right, steps);
+    }
+    ExpressionBody::Not(x) | ExpressionBody::Cast(_, x) | ExpressionBody::Group(x) => {
+      collect_steps(x, steps)
+    }
+    ExpressionBody::Nesting(expressions) => {
+      for x in expressions {
+        collect_steps(x, steps);
+      }
+    }
+    ExpressionBody::Identifier(identifier) => {
+      for index in &identifier.indexing {
+        collect_steps(index, steps);
+      }
+    }
+    ExpressionBody::FunctionCall(call) => {
+      for parameter in call.parameters.0.iter().flatten() {
+        collect_steps(parameter, steps);
+      }
+
+      steps.push(EvaluationStep::Call);
+    }
+    ExpressionBody::Integer(_)
+    | ExpressionBody::Float(_)
+    | ExpressionBody::String(_)
+    | ExpressionBody::Name(_)
+    | ExpressionBody::ClassInstantiation(_)
+    | ExpressionBody::Lambda(_)
+    | ExpressionBody::Error => {}
+  };
+}
+
+impl Visited for Ternary {
+  fn accept<T: visitor::Visitor>(&self, visitor: &mut T) {
+    self.condition.accept(visitor);
+    self.consequence.accept(visitor);
+    self.alternative.accept(visitor);
+  }
+}
+
+impl Codegen for Ternary {
+  fn emit(&self, _: &Context, f: &mut Vec<u8>) -> Result<(), std::io::Error> {
+    use std::io::Write as IoWrite;
+
+    // the value was computed before the statement
+    write!(f, "{}", self.temporary_name)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::test_utils::{compile_source, normalize};
+
+  const POP: &str = "function pop(): int {\n  return 1;\n}\n\n";
+
+  fn compile(main: &str) -> crate::test_utils::CompiledProject {
+    compile_source(&format!("{POP}{main}"), true)
+  }
+
+  /// Returns the index of the first line that starts and ends with the
+  /// given texts.
+  fn line(output: &str, start: &str, end: &str) -> usize {
+    output
+      .lines()
+      .position(|line| line.starts_with(start) && line.ends_with(end))
+      .unwrap_or_else(|| panic!("no line `{start}...{end}` in {output}"))
+  }
+
+  #[test]
+  fn conditional_expressions_are_computed_before_their_statement() {
+    let project = compile("function main(b: bool) {\n  var x: int = (b ? pop() : 2) + pop();\n}\n");
+    assert!(project.errors.is_empty(), "{:?}", project.errors);
+
+    let output = normalize(project.output("main.ws"));
+    let prelude = line(&output, "tern", " = pop();");
+    let statement = line(&output, "x = (tern", ")+pop();");
+
+    assert!(prelude < statement, "{output}");
+  }
+
+  #[test]
+  fn calls_of_the_condition_are_supported() {
+    let project = compile("function main() {\n  var x: int = pop() > 0 ? 1 : 2;\n}\n");
+    assert!(project.errors.is_empty(), "{:?}", project.errors);
+
+    let output = normalize(project.output("main.ws"));
+    assert!(output.contains("if (pop()>0) {"), "{output}");
+  }
+
+  #[test]
+  fn conditional_expressions_after_calls_are_reported() {
+    for main in [
+      "function main(b: bool) {\n  var x: int = pop() + (b ? pop() : 2);\n}\n",
+      "function add(a: int, b: int): int {\n  return a + b;\n}\n\nfunction main(b: bool) {\n  add(pop(), b ? 1 : 2);\n}\n",
+      "function main(b: bool, c: bool) {\n  var x: int = b ? pop() + (c ? 1 : 2) : 0;\n}\n"
+    ] {
+      let project = compile(main);
+
+      assert_eq!(
+        project.errors,
+        vec!["unsupported-conditional-expression"],
+        "{main}"
+      );
+    }
+  }
+
+  #[test]
+  fn conditional_expressions_are_computed_before_for_in_loops() {
+    let project = compile(
+      "function main(b: bool, first: array<int>, second: array<int>) {\n  var sum: int;\n\n  for item in (b ? first : second) {\n    sum += item;\n  }\n\n  for i in 0..(b ? 3 : 4) {\n    sum += i;\n  }\n}\n"
+    );
+    assert!(project.errors.is_empty(), "{:?}", project.errors);
+
+    let output = normalize(project.output("main.ws"));
+    assert!(output.contains("var item: int;"), "{output}");
+    line(&output, "var tern", ": array<int>;");
+
+    let array_prelude = line(&output, "tern", " = first;");
+    let array_loop = line(&output, "for (idx", " += 1) {");
+    assert!(array_prelude < array_loop, "{output}");
+
+    let range_prelude = line(&output, "tern", " = 3;");
+    let range_loop = line(&output, "for (; i < end", "; i += 1) {");
+    assert!(range_prelude < range_loop, "{output}");
+  }
+}
@@ -1,5 +1,6 @@
 use super::visitor::Visited;
 use super::*;
+use crate::utils::stable_hash;
 
 #[derive(Debug)]
 pub struct VariableAssignment {
@@ -55,11 +56,73 @@ impl Codegen for VariableDeclarationOrAssignment {
 pub enum VariableDeclaration {
   Explicit {
     declaration: Rc<TypedIdentifier>,
-    following_expression: Option<Rc<Expression>>
+    following_expression: Option<Rc<Expression>>,
+
+    /// The span of the declared names and their type
+    span: Span
   },
   Implicit {
     names: Vec<String>,
-    following_expression: Rc<Expression>
+    following_expression: Rc<Expression>,
+
+    /// The span of the declared names
+    span: Span
+  }
+}
+
+impl VariableDeclaration {
+  pub fn names(&self) -> &Vec<String> {
+    match self {
+      VariableDeclaration::Explicit {
+        declaration,
+        following_expression: _,
+        span: _
+      } => &declaration.names,
+      VariableDeclaration::Implicit {
+        names,
+        following_expression: _,
+        span: _
+      } => names
+    }
+  }
+
+  pub fn span(&self) -> Span {
+    match self {
+      VariableDeclaration::Explicit {
+        declaration: _,
+        following_expression: _,
+        span
+      }
+      | VariableDeclaration::Implicit {
+        names: _,
+        following_expression: _,
+        span
+      } => *span
+    }
+  }
+
+  pub fn following_expression(&self) -> Option<&Rc<Expression>> {
+    match self {
+      VariableDeclaration::Explicit {
+        declaration: _,
+        following_expression,
+        span: _
+      } => following_expression.as_ref(),
+      VariableDeclaration::Implicit {
+        names: _,
+        following_expression,
+        span: _
+      } => Some(following_expression)
+    }
+  }
+
+  /// Returns the names the declared variables are emitted with.
+  pub fn scoped_names(&self, context: &Context) -> Vec<String> {
+    self
+      .names()
+      .iter()
+      .map(|name| context.get_scoped_name(self.span(), name).to_string())
+      .collect()
   }
 }
 
@@ -70,14 +133,16 @@ impl visitor::Visited for VariableDeclaration {
     match &self {
       VariableDeclaration::Explicit {
         declaration,
-        following_expression
+        following_expression,
+        span: _
       } => {
         declaration.accept(visitor);
         following_expression.accept(visitor);
       }
       VariableDeclaration::Implicit {
         names: _,
-        following_expression
+        following_expression,
+        span: _
       } => {
         following_expression.accept(visitor);
       }
@@ -90,6 +155,7 @@ impl Codegen for VariableDeclaration {
     use std::io::Write as IoWrite;
     match context.context_type {
       ContextType::Global
+      | ContextType::Namespace { path: _ }
       | ContextType::ClassOrStruct
       | ContextType::State {
         parent_class_name: _
@@ -97,7 +163,8 @@ impl Codegen for VariableDeclaration {
         match &self {
           VariableDeclaration::Explicit {
             declaration,
-            following_expression
+            following_expression,
+            span: _
           } => {
             write!(f, "var ")?;
             declaration.emit(context, f)?;
@@ -115,7 +182,8 @@ impl Codegen for VariableDeclaration {
           }
           VariableDeclaration::Implicit {
             names: _,
-            following_expression: _
+            following_expression: _,
+            span: _
           } => {
             panic!("The compiler does not support implicit types for class attributes, please write the types of your attributes explicitly.");
           }
@@ -129,24 +197,38 @@ impl Codegen for VariableDeclaration {
         match &self {
           VariableDeclaration::Explicit {
             declaration,
-            following_expression
+            following_expression,
+            span
           } => {
             if let Some(expr) = &following_expression {
               if let Some(variable_name) = declaration.names.first() {
-                write!(f, "{variable_name}")?;
+                write!(f, "{}", context.get_scoped_name(*span, variable_name))?;
               }
 
               write!(f, " = ")?;
               expr.emit(context, f)?;
               writeln!(f, ";")?;
+            } else if context.resetting_declarations.contains(span) {
+              let default_value = DefaultValue::of(&declaration.type_declaration);
+
+              for variable_name in &declaration.names {
+                let variable_name = context.get_scoped_name(*span, variable_name);
+
+                match &default_value {
+                  DefaultValue::Literal(literal) => writeln!(f, "{variable_name} = {literal};")?,
+                  DefaultValue::Clear => writeln!(f, "{variable_name}.Clear();")?,
+                  DefaultValue::Empty(empty_name) => writeln!(f, "{variable_name} = {empty_name};")?
+                };
+              }
             }
           }
           VariableDeclaration::Implicit {
             names,
-            following_expression
+            following_expression,
+            span
           } => {
             if let Some(variable_name) = names.first() {
-              write!(f, "{variable_name}")?;
+              write!(f, "{}", context.get_scoped_name(*span, variable_name))?;
             }
 
             write!(f, " = ")?;
@@ -160,3 +242,117 @@ impl Codegen for VariableDeclaration {
     Ok(())
   }
 }
+
+/// How a variable gets back the default value of its type.
+pub enum DefaultValue {
+  /// The value of the scalar types
+  Literal(&'static str),
+
+  /// The arrays are emptied
+  Clear,
+
+  /// The other types, like the structs or the enums, are copied from a
+  /// variable of the function that is never assigned
+  Empty(String)
+}
+
+impl DefaultValue {
+  pub fn of(type_declaration: &TypeDeclaration) -> Self {
+    let (type_name, generic_type_assignment) = match type_declaration {
+      TypeDeclaration::Regular {
+        type_name,
+        generic_type_assignment,
+        mangled_accessor: _
+      } => (type_name.as_str(), generic_type_assignment),
+      TypeDeclaration::Lambda(_) => ("", &None)
+    };
+
+    match (type_name, generic_type_assignment) {
+      ("int" | "byte", None) => DefaultValue::Literal("0"),
+      ("float", None) => DefaultValue::Literal("0.0"),
+      ("bool", None) => DefaultValue::Literal("false"),
+      ("string", None) => DefaultValue::Literal("\"\""),
+      ("name", None) => DefaultValue::Literal("''"),
+      ("array", Some(_)) => DefaultValue::Clear,
+      _ => DefaultValue::Empty(format!(
+        "empty{}",
+        stable_hash(&[&type_declaration.to_string()])
+      ))
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::test_utils::{compile_source, normalize};
+
+  fn compile(source: &str) -> String {
+    let project = compile_source(source, false);
+    assert!(project.errors.is_empty(), "{:?}", project.errors);
+
+    normalize(project.output("main.ws"))
+  }
+
+  #[test]
+  fn declarations_in_loops_are_reset() {
+    let output = compile(
+      "function main(c: bool, i: int) {\n  while (c) {\n    var y: int;\n    y += i;\n  }\n}\n"
+    );
+
+    assert!(output.contains("var y: int;"), "{output}");
+    assert!(
+      output.contains("while (c) {\ny = 0;\ny += i;\n}"),
+      "{output}"
+    );
+  }
+
+  #[test]
+  fn declarations_are_reset_to_the_default_of_their_type() {
+    let output = compile(
+      "function main(c: bool) {\n  while (c) {\n    var f: float;\n    var b: bool;\n    var s, t: string;\n    var n: name;\n    var items: array<int>;\n  }\n}\n"
+    );
+
+    for reset in [
+      "f = 0.0;",
+      "b = false;",
+      "s = \"\";",
+      "t = \"\";",
+      "n = '';",
+      "items.Clear();"
+    ] {
+      assert!(output.contains(reset), "{reset} in {output}");
+    }
+  }
+
+  #[test]
+  fn compound_declarations_are_reset_from_an_empty_variable() {
+    let output = compile(
+      "struct Pair {\n  var a: int;\n}\n\nfunction main(c: bool) {\n  while (c) {\n    var p: Pair;\n    var q: Pair;\n  }\n}\n"
+    );
+
+    let empty = output
+      .lines()
+      .find_map(|line| line.strip_prefix("p = "))
+      .and_then(|value| value.strip_suffix(';'))
+      .unwrap_or_else(|| panic!("p is not reset: {output}"));
+
+    assert!(empty.starts_with("empty"), "{output}");
+    assert_eq!(
+      output.matches(&format!("var {empty}: Pair;")).count(),
+      1,
+      "{output}"
+    );
+    assert!(output.contains(&format!("q = {empty};")), "{output}");
+  }
+
+  #[test]
+  fn declarations_of_the_function_body_are_not_reset() {
+    let output = compile(
+      "function main(c: bool) {\n  var x: int;\n  var y: int = 2;\n\n  while (c) {\n    var z: int = 1;\n  }\n}\n"
+    );
+
+    assert!(!output.contains("x = 0;"), "{output}");
+    assert!(output.contains("z = 1;"), "{output}");
+    assert!(!output.contains("z = 0;"), "{output}");
+  }
+}
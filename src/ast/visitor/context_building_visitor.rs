@@ -1,16 +1,39 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
-use crate::ast::codegen::context::Context;
+use crate::ast::codegen::context::{Context, ContextType};
 use crate::ast::visitor::Visited;
 
 pub struct ContextBuildingVisitor {
   pub current_context: Rc<RefCell<Context>>
 }
 
+impl ContextBuildingVisitor {
+  /// Gives the declaration its mangled name if it is placed directly in a
+  /// namespace.
+  fn mangle_if_namespaced(&self, context: &Rc<RefCell<Context>>, name: &str) {
+    let is_namespace = matches!(
+      &self.current_context.borrow().context_type,
+      ContextType::Namespace { path: _ }
+    );
+
+    if !is_namespace {
+      return;
+    }
+
+    let namespace_path = Context::get_namespace_path(&self.current_context);
+
+    context
+      .borrow_mut()
+      .set_as_namespaced(&namespace_path, name);
+  }
+}
+
 impl super::Visitor for ContextBuildingVisitor {
   fn visit_function_declaration(&mut self, node: &crate::ast::FunctionDeclaration) {
     Context::set_parent_context(&node.context, &self.current_context);
+    node.context.borrow_mut().span = Some(node.span_name);
+    self.mangle_if_namespaced(&node.context, &node.name);
 
     // then make a new context building visitor for the context of the
     // FunctionDeclaration node.
@@ -23,6 +46,8 @@ impl super::Visitor for ContextBuildingVisitor {
 
   fn visit_class_declaration(&mut self, node: &crate::ast::ClassDeclaration) {
     Context::set_parent_context(&node.context, &self.current_context);
+    node.context.borrow_mut().span = Some(node.span_name);
+    self.mangle_if_namespaced(&node.context, &node.name);
 
     // then make a new context building visitor for the context of the
     // ClassDeclaration node.
@@ -35,6 +60,8 @@ impl super::Visitor for ContextBuildingVisitor {
 
   fn visit_struct_declaration(&mut self, node: &crate::ast::StructDeclaration) {
     Context::set_parent_context(&node.context, &self.current_context);
+    node.context.borrow_mut().span = Some(node.span_name);
+    self.mangle_if_namespaced(&node.context, &node.name);
 
     // then make a new context building visitor for the context of the
     // StructDeclaration node.
@@ -45,6 +72,22 @@ impl super::Visitor for ContextBuildingVisitor {
     node.body_statements.accept(&mut new_context_visitor);
   }
 
+  fn visit_namespace_declaration(&mut self, node: &crate::ast::NamespaceDeclaration) {
+    Context::set_parent_context(&node.context, &self.current_context);
+
+    // then make a new context building visitor for the context of the
+    // NamespaceDeclaration node.
+    let mut new_context_visitor = Self {
+      current_context: node.context.clone()
+    };
+
+    node.statements.accept(&mut new_context_visitor);
+  }
+
+  fn visit_import_statement(&mut self, node: &crate::ast::ImportStatement) {
+    self.current_context.borrow_mut().imports.push(node.clone());
+  }
+
   fn visitor_type(&self) -> super::VisitorType {
     super::VisitorType::ContextBuildingVisitor
   }
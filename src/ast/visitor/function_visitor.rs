@@ -1,19 +1,16 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
-use generic_call_visitor::GenericCallsVisitor;
+use crate::ast::visitor::{GenericCallsVisitor, Visited};
+use crate::ast::Context;
 
-use crate::ast::visitor::{generic_call_visitor, Visited};
-use crate::ast::{Context, ProgramInformation};
-
-pub struct FunctionVisitor<'a> {
-  pub program_information: &'a ProgramInformation,
+pub struct FunctionVisitor {
   pub current_context: Rc<RefCell<Context>>
 }
 
-impl super::Visitor for FunctionVisitor<'_> {
+impl super::Visitor for FunctionVisitor {
   fn visit_function_declaration(&mut self, node: &crate::ast::FunctionDeclaration) {
-    let mut generic_call_visitor = GenericCallsVisitor::new(self.program_information);
+    let mut generic_call_visitor = GenericCallsVisitor::new();
 
     node.accept(&mut generic_call_visitor);
 
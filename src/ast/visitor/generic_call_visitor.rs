@@ -2,18 +2,16 @@ use std::cell::RefCell;
 use std::rc::Rc;
 
 use crate::ast::codegen::context::{Context, ContextType};
-use crate::ast::{ProgramInformation, TypeDeclaration};
+use crate::ast::TypeDeclaration;
 
 /// Looks for generic calls and register them to the GenericCallRegister
-pub struct GenericCallsVisitor<'a> {
-  pub program_information: &'a ProgramInformation,
+pub struct GenericCallsVisitor {
   pub current_context: Rc<RefCell<Context>>
 }
 
-impl<'a> GenericCallsVisitor<'a> {
-  pub fn new(program_information: &'a ProgramInformation) -> Self {
+impl GenericCallsVisitor {
+  pub fn new() -> Self {
     Self {
-      program_information,
       current_context: Rc::new(RefCell::new(Context::new(
         "empty",
         None,
@@ -23,7 +21,7 @@ impl<'a> GenericCallsVisitor<'a> {
   }
 }
 
-impl super::Visitor for GenericCallsVisitor<'_> {
+impl super::Visitor for GenericCallsVisitor {
   fn visitor_type(&self) -> super::VisitorType {
     super::VisitorType::GenericCallsVisitor
   }
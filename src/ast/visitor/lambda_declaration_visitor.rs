@@ -5,7 +5,7 @@ use std::rc::Rc;
 use crate::ast::codegen::context::{Context, ContextType};
 use crate::ast::codegen::type_inference::TypeInferenceStore;
 use crate::ast::inference::Type;
-use crate::ast::{ExpressionBody, ReportManager, SpanManager};
+use crate::ast::{ExpressionBody, ReportManager};
 
 /// Looks for generic calls and register them to the GenericCallRegister
 pub struct LambdaDeclarationVisitor<'a> {
@@ -54,7 +54,7 @@ impl super::Visitor for LambdaDeclarationVisitor<'_> {
       &mut self.emitted_code,
       &mut self.emitted_types
     ) {
-      println!(
+      eprintln!(
         "Error while emitting code for {}: {}",
         self.current_context.borrow().name,
         err
@@ -67,7 +67,7 @@ impl super::Visitor for LambdaDeclarationVisitor<'_> {
       &mut self.current_context.borrow_mut(),
       &mut self.emitted_code
     ) {
-      println!(
+      eprintln!(
         "Error while emitting code for {}: {}",
         self.current_context.borrow().name,
         err
@@ -82,21 +82,19 @@ pub struct ClosureVisitor<'a> {
 
   pub current_context: Rc<RefCell<Context>>,
   pub inference_store: &'a mut TypeInferenceStore,
-  pub report_manager: &'a mut ReportManager,
-  pub span_manager: &'a mut SpanManager
+  pub report_manager: &'a mut ReportManager
 }
 
 impl<'a> ClosureVisitor<'a> {
   pub fn new(
     current_context: Rc<RefCell<Context>>, inference_store: &'a mut TypeInferenceStore,
-    report_manager: &'a mut ReportManager, span_manager: &'a mut SpanManager
+    report_manager: &'a mut ReportManager
   ) -> Self {
     Self {
       captured_variables: Vec::new(),
       current_context,
       inference_store,
-      report_manager,
-      span_manager
+      report_manager
     }
   }
 }
@@ -111,18 +109,20 @@ impl<'a> super::Visitor for ClosureVisitor<'a> {
       let result = node.deduce_type(
         &self.current_context,
         &self.inference_store.types,
-        &self.inference_store.types,
-        self.span_manager
+        &self.inference_store.types
       );
 
       if let Err(errors) = result {
         self.report_manager.push_many(errors);
       }
 
-      self.captured_variables.push((
-        identifier.text.clone(),
-        node.infered_type_name.borrow().clone()
-      ));
+      let variable_name = Context::get_ref(&self.current_context)
+        .get_scoped_name(identifier.span, &identifier.text)
+        .to_string();
+
+      self
+        .captured_variables
+        .push((variable_name, node.infered_type_name.borrow().clone()));
     }
   }
 }
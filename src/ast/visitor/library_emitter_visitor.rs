@@ -34,7 +34,7 @@ impl<'a> super::Visitor for LibraryEmitterVisitor<'a> {
     }
 
     if let Err(err) = node.emit(&self.current_context.borrow(), &mut self.emitted_code) {
-      println!(
+      eprintln!(
         "Error while emitting code for {}: {}",
         self.current_context.borrow().name,
         err
@@ -53,7 +53,7 @@ impl<'a> super::Visitor for LibraryEmitterVisitor<'a> {
     }
 
     if let Err(err) = node.emit(&self.current_context.borrow(), &mut self.emitted_code) {
-      println!(
+      eprintln!(
         "Error while emitting code for {}: {}",
         self.current_context.borrow().name,
         err
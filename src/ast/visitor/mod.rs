@@ -21,21 +21,43 @@ pub use lambda_declaration_visitor::LambdaDeclarationVisitor;
 mod type_inference_visitor;
 pub use type_inference_visitor::*;
 
+mod namespace_resolution_visitor;
+pub use namespace_resolution_visitor::NamespaceResolutionVisitor;
+
+mod ternary_visitor;
+pub use ternary_visitor::TernaryVisitor;
+
+mod position_visitor;
+pub use position_visitor::*;
+
 pub mod implementations;
 
 pub trait Visitor {
   fn visit_function_declaration(&mut self, _: &FunctionDeclaration) {}
   fn visit_class_declaration(&mut self, _: &ClassDeclaration) {}
   fn visit_struct_declaration(&mut self, _: &StructDeclaration) {}
+  fn visit_enum_declaration(&mut self, _: &EnumDeclaration) {}
+  fn visit_namespace_declaration(&mut self, _: &NamespaceDeclaration) {}
+  fn visit_import_statement(&mut self, _: &ImportStatement) {}
   fn visit_generic_function_call(&mut self, _: &FunctionCall) {}
   fn visit_function_call(&mut self, _: &FunctionCall) {}
+  fn visit_type_declaration(&mut self, _: &TypeDeclaration) {}
   fn visit_generic_variable_declaration(&mut self, _: &TypeDeclaration) {}
   fn visit_variable_declaration(&mut self, _: &VariableDeclaration) {}
+  fn visit_for_in_statement(&mut self, _: &ForInStatement) {}
+  fn visit_class_instantiation(&mut self, _: &ClassInstantiation) {}
   fn visit_generic_class_instantiation(&mut self, _: &ClassInstantiation) {}
   fn visit_lambda_declaration(&mut self, _: &LambdaDeclaration) {}
   fn visit_lambda(&mut self, _: &Lambda) {}
   fn visit_expression(&mut self, _: &Expression) {}
+  fn visit_function_body_statement(&mut self, _: &FunctionBodyStatement) {}
   fn visit_function_declaration_parameter(&mut self, _: &FunctionDeclarationParameter) {}
+
+  /// Called when entering and leaving the blocks of the function bodies, the
+  /// variables declared between the two calls are local to the block.
+  fn visit_block_start(&mut self) {}
+  fn visit_block_end(&mut self) {}
+
   fn register_variable_declaration(&mut self, _: Rc<TypedIdentifier>) {}
 
   fn visitor_type(&self) -> VisitorType;
@@ -53,5 +75,9 @@ pub enum VisitorType {
   VariableDeclarationVisitor,
   LambdaDeclarationVisitor,
   ClosureExpressionVisitor,
-  TypeInferenceVisitor
+  TypeInferenceVisitor,
+  NamespaceResolutionVisitor,
+  PositionVisitor,
+  TernaryVisitor,
+  DeclarationsVisitor
 }
@@ -0,0 +1,177 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use crate::ast::codegen::context::{Context, ContextType};
+use crate::ast::{ExpressionBody, FunctionCall, OperationCode, TypeDeclaration};
+
+/// Resolves the function calls and type names that refer to namespaced
+/// declarations, either from the enclosing namespaces or through import
+/// statements, and stores the mangled names the nodes should emit.
+pub struct NamespaceResolutionVisitor {
+  pub current_context: Rc<RefCell<Context>>,
+
+  /// The function calls that are on the right side of a nesting, they are
+  /// method calls and never refer to global functions.
+  method_calls: HashSet<*const FunctionCall>
+}
+
+impl NamespaceResolutionVisitor {
+  pub fn new(current_context: Rc<RefCell<Context>>) -> Self {
+    Self {
+      current_context,
+      method_calls: HashSet::new()
+    }
+  }
+
+  /// Returns the mangled name of the class or struct the type name refers to
+  /// if it is a namespaced one.
+  fn resolve_compound_name(context: &Rc<RefCell<Context>>, type_name: &str) -> Option<String> {
+    let declaration = Context::find_global_class_declaration(context, type_name)
+      .or_else(|| Context::find_global_struct_declaration(context, type_name))?;
+
+    let declaration = Context::get_ref(&declaration);
+
+    declaration.get_namespaced_name().cloned()
+  }
+
+  /// Returns whether the function name is a method of one of the classes the
+  /// context is in, in which case the call is an implicit `this.` call.
+  fn is_method_of_enclosing_class(context: &Rc<RefCell<Context>>, name: &str) -> bool {
+    let method_context_name = format!("method: {name}");
+    let mut current = Some(context.clone());
+
+    while let Some(context) = current {
+      let context_ref = Context::get_ref(&context);
+
+      if let ContextType::ClassOrStruct
+      | ContextType::State {
+        parent_class_name: _
+      } = &context_ref.context_type
+      {
+        let has_method = context_ref
+          .children_contexts
+          .iter()
+          .any(|child| Context::get_ref(child).name == method_context_name);
+
+        if has_method {
+          return true;
+        }
+      }
+
+      current = context_ref.parent_context.clone();
+    }
+
+    false
+  }
+}
+
+impl super::Visitor for NamespaceResolutionVisitor {
+  fn visitor_type(&self) -> super::VisitorType {
+    super::VisitorType::NamespaceResolutionVisitor
+  }
+
+  fn visit_class_declaration(&mut self, node: &crate::ast::ClassDeclaration) {
+    self.current_context = node.context.clone();
+
+    if let Some(parent_class_name) = &node.parent_class_name {
+      node
+        .parent_class_mangled_accessor
+        .replace(Self::resolve_compound_name(
+          &node.context,
+          parent_class_name
+        ));
+    }
+
+    if let Some(extended_class_name) = &node.extended_class_name {
+      node
+        .extended_class_mangled_accessor
+        .replace(Self::resolve_compound_name(
+          &node.context,
+          extended_class_name
+        ));
+    }
+  }
+
+  /// Update the current context with the latest context met in the AST
+  fn visit_function_declaration(&mut self, node: &crate::ast::FunctionDeclaration) {
+    self.current_context = node.context.clone();
+  }
+
+  /// Update the current context with the latest context met in the AST
+  fn visit_struct_declaration(&mut self, node: &crate::ast::StructDeclaration) {
+    self.current_context = node.context.clone();
+  }
+
+  /// Update the current context with the latest context met in the AST
+  fn visit_namespace_declaration(&mut self, node: &crate::ast::NamespaceDeclaration) {
+    self.current_context = node.context.clone();
+  }
+
+  fn visit_expression(&mut self, node: &crate::ast::Expression) {
+    if let ExpressionBody::Operation(_, OperationCode::Nesting, right) = &node.body {
+      if let ExpressionBody::FunctionCall(function_call) = &right.body {
+        self
+          .method_calls
+          .insert(function_call as *const FunctionCall);
+      }
+    }
+  }
+
+  fn visit_function_call(&mut self, node: &FunctionCall) {
+    if self.method_calls.contains(&(node as *const FunctionCall)) {
+      return;
+    }
+
+    // a mangled name was already given by the generic calls visitor
+    if node.mangled_accessor.borrow().is_some() {
+      return;
+    }
+
+    let function_name = node.get_function_name();
+    if Self::is_method_of_enclosing_class(&self.current_context, &function_name) {
+      return;
+    }
+
+    let Some(declaration) =
+      Context::find_global_function_declaration(&self.current_context, &function_name)
+    else {
+      return;
+    };
+
+    let namespaced_name = Context::get_ref(&declaration)
+      .get_namespaced_name()
+      .cloned();
+
+    if namespaced_name.is_some() {
+      node.mangled_accessor.replace(namespaced_name);
+    }
+  }
+
+  fn visit_type_declaration(&mut self, node: &TypeDeclaration) {
+    if let TypeDeclaration::Regular {
+      type_name,
+      generic_type_assignment: _,
+      mangled_accessor
+    } = node
+    {
+      if mangled_accessor.borrow().is_some() {
+        return;
+      }
+
+      let namespaced_name = Self::resolve_compound_name(&self.current_context, type_name);
+
+      if namespaced_name.is_some() {
+        mangled_accessor.replace(namespaced_name);
+      }
+    }
+  }
+
+  fn visit_class_instantiation(&mut self, node: &crate::ast::ClassInstantiation) {
+    let namespaced_name = Self::resolve_compound_name(&self.current_context, &node.class_name);
+
+    if namespaced_name.is_some() {
+      node.mangled_accessor.replace(namespaced_name);
+    }
+  }
+}
@@ -0,0 +1,129 @@
+use std::cell::RefCell;
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::ast::codegen::context::Context;
+use crate::ast::codegen::type_inference::InferedType;
+use crate::ast::inference::Type;
+use crate::ast::{Expression, ExpressionBody, Span, SpanManager};
+
+/// Looks for what is at a given offset of a source file: the context of the
+/// declaration the offset is in and the innermost expression that contains it.
+/// The information is copied out of the AST so it can be used by the language
+/// server once the traversal is over.
+pub struct PositionVisitor<'a> {
+  source: &'a str,
+  offset: usize,
+  span_manager: &'a SpanManager,
+
+  /// The context of the last declaration whose name is before the offset
+  pub current_context: Option<Rc<RefCell<Context>>>,
+  pub expression: Option<ExpressionAtPosition>
+}
+
+pub struct ExpressionAtPosition {
+  pub range: Range<usize>,
+  pub type_name: Type,
+  pub infered_type: Rc<InferedType>,
+
+  /// The span of the declaration the expression refers to, for the function
+  /// calls and the class instantiations.
+  pub definition: Option<Span>
+}
+
+impl<'a> PositionVisitor<'a> {
+  pub fn new(source: &'a str, offset: usize, span_manager: &'a SpanManager) -> Self {
+    Self {
+      source,
+      offset,
+      span_manager,
+      current_context: None,
+      expression: None
+    }
+  }
+
+  fn is_in_source(&self, span: Span) -> bool {
+    self.span_manager.get_source(&span) == self.source
+  }
+
+  fn enter_declaration(&mut self, span: Span, context: &Rc<RefCell<Context>>) {
+    if self.is_in_source(span) && self.span_manager.get_left(span) <= self.offset {
+      self.current_context = Some(context.clone());
+    }
+  }
+
+  fn find_definition(&self, node: &Expression) -> Option<Span> {
+    let current_context = self.current_context.as_ref()?;
+
+    match &node.body {
+      ExpressionBody::FunctionCall(call) => {
+        if let Some(function_type) = call.infered_function_type.borrow().as_ref() {
+          return Some(function_type.span);
+        }
+
+        Context::find_global_function_declaration(current_context, &call.accessor.text)
+          .and_then(|context| Context::get_ref(&context).span)
+      }
+      ExpressionBody::ClassInstantiation(instantiation) => {
+        let class_name = match instantiation.mangled_accessor.borrow().as_ref() {
+          Some(mangled_accessor) => mangled_accessor.clone(),
+          None => instantiation.class_name.clone()
+        };
+
+        Context::find_compound_declaration(current_context, &class_name)
+          .and_then(|context| Context::get_ref(&context).span)
+      }
+      _ => None
+    }
+  }
+}
+
+impl super::Visitor for PositionVisitor<'_> {
+  fn visitor_type(&self) -> super::VisitorType {
+    super::VisitorType::PositionVisitor
+  }
+
+  fn visit_function_declaration(&mut self, node: &crate::ast::FunctionDeclaration) {
+    self.enter_declaration(node.span_name, &node.context);
+  }
+
+  fn visit_class_declaration(&mut self, node: &crate::ast::ClassDeclaration) {
+    self.enter_declaration(node.span_name, &node.context);
+  }
+
+  fn visit_struct_declaration(&mut self, node: &crate::ast::StructDeclaration) {
+    self.enter_declaration(node.span_name, &node.context);
+  }
+
+  fn visit_expression(&mut self, node: &Expression) {
+    if let ExpressionBody::Error = &node.body {
+      return;
+    }
+
+    let span = node.body.get_span();
+    if !self.is_in_source(span) {
+      return;
+    }
+
+    let range = self.span_manager.get_range(span);
+    if !range.contains(&self.offset) && range.end != self.offset {
+      return;
+    }
+
+    // the expressions are visited from the outermost to the innermost, a
+    // nested expression with the same range replaces its parent.
+    let is_innermost = match &self.expression {
+      Some(expression) => range.len() <= expression.range.len(),
+      None => true
+    };
+
+    if is_innermost {
+      self.expression = Some(ExpressionAtPosition {
+        definition: self.find_definition(node),
+        range,
+        type_name: node.infered_type_name.borrow().clone(),
+        infered_type: node.infered_type.borrow().clone()
+      });
+    }
+  }
+}
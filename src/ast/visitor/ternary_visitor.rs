@@ -0,0 +1,208 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use crate::ast::codegen::context::{Context, ContextType};
+use crate::ast::codegen::type_inference::type_declaration;
+use crate::ast::inference::Type;
+use crate::ast::{
+  Diagnostic, EvaluationStep, Expression, ExpressionBody, FunctionBodyStatement, Span, Ternary,
+  TypedIdentifier
+};
+
+/// Checks the conditional expressions can be computed before the statements
+/// they are in, and declares the temporary variables that hold their values in
+/// the functions.
+pub struct TernaryVisitor {
+  pub current_context: Rc<RefCell<Context>>,
+
+  /// Whether the types of the expressions were inferred
+  static_analysis: bool,
+
+  /// The conditional expressions computed before the statements of the
+  /// function bodies, the other ones are reported.
+  lowered: HashSet<Span>,
+
+  /// The conditional expressions that come after a call of the expressions
+  /// they are in, computing them first would change the order of the calls.
+  after_calls: HashSet<Span>,
+
+  /// The conditional expressions in the bodies of the lambdas, the bodies are
+  /// emitted outside of the functions.
+  in_lambdas: HashSet<Span>,
+
+  /// The conditional expressions that cannot be computed
+  pub diagnostics: Vec<Diagnostic>
+}
+
+impl TernaryVisitor {
+  pub fn new(static_analysis: bool) -> Self {
+    Self {
+      current_context: Rc::new(RefCell::new(Context::new(
+        "empty",
+        None,
+        ContextType::Global
+      ))),
+      static_analysis,
+      lowered: HashSet::new(),
+      after_calls: HashSet::new(),
+      in_lambdas: HashSet::new(),
+      diagnostics: Vec::new()
+    }
+  }
+
+  fn mark_lowered(&mut self, expressions: &[&Expression]) {
+    let mut after_call = false;
+
+    for step in Ternary::evaluation_steps(expressions) {
+      let ternary = match step {
+        EvaluationStep::Ternary(ternary) => ternary,
+        EvaluationStep::Call => {
+          after_call = true;
+          continue;
+        }
+      };
+
+      self.lowered.insert(ternary.span);
+      if after_call {
+        self.after_calls.insert(ternary.span);
+      }
+
+      // each part of the expression is computed in a statement of its own
+      self.mark_lowered(&[&ternary.condition]);
+      self.mark_lowered(&[&ternary.consequence]);
+      self.mark_lowered(&[&ternary.alternative]);
+    }
+  }
+
+  fn unsupported(&mut self, span: Span, reason: &str) {
+    self.diagnostics.push(
+      Diagnostic::error(span, "Unsupported conditional expression")
+        .with_code("unsupported-conditional-expression")
+        .with_label(span, reason)
+    );
+  }
+}
+
+impl super::Visitor for TernaryVisitor {
+  fn visitor_type(&self) -> super::VisitorType {
+    super::VisitorType::TernaryVisitor
+  }
+
+  /// Update the current context with the latest context met in the AST
+  fn visit_class_declaration(&mut self, node: &crate::ast::ClassDeclaration) {
+    self.current_context = node.context.clone();
+  }
+
+  /// Update the current context with the latest context met in the AST
+  fn visit_function_declaration(&mut self, node: &crate::ast::FunctionDeclaration) {
+    self.current_context = node.context.clone();
+  }
+
+  /// Update the current context with the latest context met in the AST
+  fn visit_struct_declaration(&mut self, node: &crate::ast::StructDeclaration) {
+    self.current_context = node.context.clone();
+  }
+
+  fn visit_function_body_statement(&mut self, node: &FunctionBodyStatement) {
+    let expressions: Vec<&Expression> = node
+      .leading_expressions()
+      .into_iter()
+      .map(|expression| expression.as_ref())
+      .collect();
+
+    self.mark_lowered(&expressions);
+  }
+
+  fn visit_lambda(&mut self, node: &crate::ast::Lambda) {
+    use super::Visited;
+
+    let mut collector = TernaryCollector { spans: Vec::new() };
+    node.body_statements.accept(&mut collector);
+
+    self.in_lambdas.extend(collector.spans);
+  }
+
+  fn visit_expression(&mut self, node: &Expression) {
+    let ternary = match &node.body {
+      ExpressionBody::Ternary(ternary) => ternary,
+      _ => return
+    };
+
+    if self.in_lambdas.contains(&ternary.span) {
+      return self.unsupported(
+        ternary.span,
+        "Conditional expressions are not supported in lambdas"
+      );
+    }
+
+    if !self.lowered.contains(&ternary.span) {
+      return self.unsupported(
+        ternary.span,
+        "Conditional expressions are not supported in loop conditions, `else if` conditions, `case` labels, on the right side of `&&` and `||`, or outside of functions"
+      );
+    }
+
+    if self.after_calls.contains(&ternary.span) {
+      return self.unsupported(
+        ternary.span,
+        "Conditional expressions are computed before their statement, they are not supported after a call of the same statement"
+      );
+    }
+
+    let the_type = node.infered_type_name.borrow().clone();
+
+    match the_type {
+      Type::Unknown => {
+        let diagnostic =
+          Diagnostic::error(ternary.span, "Cannot infer conditional expression type")
+            .with_code("unknown-conditional-type")
+            .with_label(
+              ternary.span,
+              "The type of the branches is needed for the variable that holds the value"
+            );
+
+        self.diagnostics.push(match self.static_analysis {
+          true => diagnostic.with_help("Cast one of the branches, like `x as int`"),
+          false => diagnostic.with_help("Enable `static_analysis` in the package configuration")
+        });
+      }
+      Type::Void => {
+        self.diagnostics.push(
+          Diagnostic::error(ternary.span, "Conditional expression without a value")
+            .with_code("void-conditional-expression")
+            .with_label(ternary.span, "The branches do not return a value")
+            .with_help("Prefer an if/else statement here")
+        );
+      }
+      the_type => {
+        let mut context = self.current_context.borrow_mut();
+
+        context
+          .declaration_spans
+          .insert(ternary.temporary_name.clone(), ternary.span);
+        context.variable_declarations.push(Rc::new(TypedIdentifier {
+          names: vec![ternary.temporary_name.clone()],
+          type_declaration: type_declaration(&the_type.to_string())
+        }));
+      }
+    };
+  }
+}
+
+/// Collects the spans of the conditional expressions it visits.
+struct TernaryCollector {
+  spans: Vec<Span>
+}
+
+impl super::Visitor for TernaryCollector {
+  fn visitor_type(&self) -> super::VisitorType {
+    super::VisitorType::TernaryVisitor
+  }
+
+  fn visit_expression(&mut self, node: &Expression) {
+    if let ExpressionBody::Ternary(ternary) = &node.body {
+      self.spans.push(ternary.span);
+    }
+  }
+}
@@ -2,14 +2,14 @@ use std::borrow::Borrow;
 use std::cell::RefCell;
 use std::rc::Rc;
 
-use ariadne::{Label, Report, ReportKind};
-
 use crate::ast::codegen::context::{Context, ContextType};
-use crate::ast::codegen::type_inference::{InferedType, TypeInferenceStore};
+use crate::ast::codegen::type_inference::{
+  array_items_type, type_declaration, InferedType, TypeInferenceStore
+};
 use crate::ast::inference::Type;
 use crate::ast::{
-  Expression, FunctionDeclarationParameter, ReportManager, SpanManager, TypeDeclaration,
-  TypedIdentifier
+  Diagnostic, Expression, ForInCollection, ForInStatement, ForInVariable,
+  FunctionDeclarationParameter, ReportManager, TypeDeclaration, TypedIdentifier
 };
 
 use super::lambda_declaration_visitor::ClosureVisitor;
@@ -19,20 +19,18 @@ use super::lambda_declaration_visitor::ClosureVisitor;
 pub struct CompoundTypesVisitor<'a> {
   pub current_context: Rc<RefCell<Context>>,
   pub inference_store: &'a mut TypeInferenceStore,
-  pub report_manager: &'a mut ReportManager,
-  pub span_manager: &'a mut SpanManager
+  pub report_manager: &'a mut ReportManager
 }
 
 impl<'a> CompoundTypesVisitor<'a> {
   pub fn new(
     current_context: Rc<RefCell<Context>>, inference_store: &'a mut TypeInferenceStore,
-    report_manager: &'a mut ReportManager, span_manager: &'a mut SpanManager
+    report_manager: &'a mut ReportManager
   ) -> Self {
     Self {
       current_context,
       inference_store,
-      report_manager,
-      span_manager
+      report_manager
     }
   }
 }
@@ -44,19 +42,26 @@ impl super::Visitor for CompoundTypesVisitor<'_> {
 
   /// Update the current context with the latest context met in the AST
   fn visit_class_declaration(&mut self, node: &crate::ast::ClassDeclaration) {
+    // namespaced classes are registered with their mangled names
+    let name = Context::get_ref(&node.context)
+      .get_class_name()
+      .unwrap_or_else(|| node.name.clone());
+    let extended_class_name = match node.extended_class_mangled_accessor.borrow().as_ref() {
+      Some(mangled_accessor) => Some(mangled_accessor.clone()),
+      None => node.extended_class_name.clone()
+    };
+
     let result = self
       .inference_store
-      .register_compound(node.name.clone(), node.extended_class_name.clone());
+      .register_compound(name, extended_class_name);
 
     if let Err(reason) = result {
       let span = node.span_name;
 
       self.report_manager.push(
-        Report::build(ReportKind::Error, (), self.span_manager.get_left(span))
-          .with_message(&"Invalid class definition")
-          .with_label(Label::new(self.span_manager.get_range(span)).with_message(reason))
-          .finish(),
-        span
+        Diagnostic::error(span, "Invalid class definition")
+          .with_code("invalid-class-definition")
+          .with_label(span, reason)
       );
     }
 
@@ -104,18 +109,22 @@ impl super::Visitor for CompoundTypesVisitor<'_> {
             let span = node.span_name;
 
             self.report_manager.push(
-              Report::build(ReportKind::Error, (), self.span_manager.get_left(span))
-                .with_message(&"Invalid method definition")
-                .with_label(Label::new(self.span_manager.get_range(span)).with_message(reason))
-                .finish(),
-              span
+              Diagnostic::error(span, "Invalid method definition")
+                .with_code("invalid-method-definition")
+                .with_label(span, reason)
             );
           }
         }
       }
       _ => {
+        // namespaced functions are registered with their mangled names
+        let name = Context::get_ref(&node.context)
+          .get_namespaced_name()
+          .cloned()
+          .unwrap_or_else(|| node.name.clone());
+
         let result = self.inference_store.register_function(
-          node.name.clone(),
+          name,
           parameters,
           match &node.type_declaration {
             Some(decl) => Some(decl.to_string()),
@@ -128,11 +137,9 @@ impl super::Visitor for CompoundTypesVisitor<'_> {
           let span = node.span_name;
 
           self.report_manager.push(
-            Report::build(ReportKind::Error, (), self.span_manager.get_left(span))
-              .with_message(&"Invalid function definition")
-              .with_label(Label::new(self.span_manager.get_range(span)).with_message(reason))
-              .finish(),
-            span
+            Diagnostic::error(span, "Invalid function definition")
+              .with_code("invalid-function-definition")
+              .with_label(span, reason)
           );
         }
       }
@@ -141,19 +148,20 @@ impl super::Visitor for CompoundTypesVisitor<'_> {
 
   /// Update the current context with the latest context met in the AST
   fn visit_struct_declaration(&mut self, node: &crate::ast::StructDeclaration) {
-    let result = self
-      .inference_store
-      .register_compound(node.name.clone(), None);
+    // namespaced structs are registered with their mangled names
+    let name = Context::get_ref(&node.context)
+      .get_struct_name()
+      .unwrap_or_else(|| node.name.clone());
+
+    let result = self.inference_store.register_compound(name, None);
 
     if let Err(reason) = result {
       let span = node.span_name;
 
       self.report_manager.push(
-        Report::build(ReportKind::Error, (), self.span_manager.get_left(span))
-          .with_message(&"Invalid struct definition")
-          .with_label(Label::new(self.span_manager.get_range(span)).with_message(reason))
-          .finish(),
-        span
+        Diagnostic::error(span, "Invalid struct definition")
+          .with_code("invalid-struct-definition")
+          .with_label(span, reason)
       );
     }
 
@@ -167,20 +175,18 @@ impl super::Visitor for CompoundTypesVisitor<'_> {
 pub struct ExpressionTypeInferenceVisitor<'a> {
   pub current_context: Rc<RefCell<Context>>,
   pub inference_store: &'a mut TypeInferenceStore,
-  pub report_manager: &'a mut ReportManager,
-  pub span_manager: &'a mut SpanManager
+  pub report_manager: &'a mut ReportManager
 }
 
 impl<'a> ExpressionTypeInferenceVisitor<'a> {
   pub fn new(
     current_context: Rc<RefCell<Context>>, inference_store: &'a mut TypeInferenceStore,
-    report_manager: &'a mut ReportManager, span_manager: &'a mut SpanManager
+    report_manager: &'a mut ReportManager
   ) -> Self {
     Self {
       current_context,
       inference_store,
-      report_manager,
-      span_manager
+      report_manager
     }
   }
 }
@@ -209,8 +215,7 @@ impl super::Visitor for ExpressionTypeInferenceVisitor<'_> {
     let result = node.deduce_type(
       &self.current_context,
       &self.inference_store.types,
-      &self.inference_store.types,
-      self.span_manager
+      &self.inference_store.types
     );
 
     if let InferedType::Lambda(_) = node.infered_type.borrow().as_ref() {
@@ -229,29 +234,32 @@ impl super::Visitor for ExpressionTypeInferenceVisitor<'_> {
     match &node {
       crate::ast::VariableDeclaration::Explicit {
         declaration,
-        following_expression: _
+        following_expression: _,
+        span: _
       } => {
-        for variable_name in &declaration.names {
+        let scoped_names = node.scoped_names(&Context::get_ref(&self.current_context));
+
+        for variable_name in scoped_names {
           let type_declaration_string = declaration.type_declaration.to_string();
 
           self
             .current_context
             .borrow_mut()
             .local_variables_inference
-            .insert(variable_name.clone(), type_declaration_string);
+            .insert(variable_name, type_declaration_string);
         }
       }
       crate::ast::VariableDeclaration::Implicit {
-        names,
-        following_expression
+        names: _,
+        following_expression,
+        span: _
       } => {
         let expression: &Expression = &following_expression.borrow();
 
         let result = expression.deduce_type(
           &self.current_context,
           &self.inference_store.types,
-          &self.inference_store.types,
-          self.span_manager
+          &self.inference_store.types
         );
 
         if let Err(errors) = result {
@@ -265,14 +273,12 @@ impl super::Visitor for ExpressionTypeInferenceVisitor<'_> {
             let span = following_expression.body.get_span();
 
             self.report_manager.push(
-              Report::build(ReportKind::Error, (), self.span_manager.get_left(span))
-                .with_message(&"Cannot infer variable type")
+              Diagnostic::error(span, "Cannot infer variable type")
+                .with_code("unknown-variable-type")
                 .with_label(
-                  Label::new(self.span_manager.get_range(span))
-                    .with_message(&"Implicit variable declaration but resulting type is void")
+                  span,
+                  "Implicit variable declaration but resulting type is void"
                 )
-                .finish(),
-              span
             );
 
             return;
@@ -281,14 +287,13 @@ impl super::Visitor for ExpressionTypeInferenceVisitor<'_> {
             let span = following_expression.body.get_span();
 
             self.report_manager.push(
-              Report::build(ReportKind::Error, (), self.span_manager.get_left(span))
-                .with_message(&"Cannot infer variable type")
-                .with_label(Label::new(self.span_manager.get_range(span)).with_message(
-                  &"Implicit variable declaration but resulting type is unknown at the time"
-                ))
-                .with_help(&"Prefer an explicit type annotation here")
-                .finish(),
-              span
+              Diagnostic::error(span, "Cannot infer variable type")
+                .with_code("unknown-variable-type")
+                .with_label(
+                  span,
+                  "Implicit variable declaration but resulting type is unknown at the time"
+                )
+                .with_help("Prefer an explicit type annotation here")
             );
 
             return;
@@ -297,8 +302,9 @@ impl super::Visitor for ExpressionTypeInferenceVisitor<'_> {
         };
 
         let the_type = the_type.to_string();
+        let scoped_names = node.scoped_names(&Context::get_ref(&self.current_context));
 
-        for name in names {
+        for name in &scoped_names {
           self
             .current_context
             .borrow_mut()
@@ -307,7 +313,7 @@ impl super::Visitor for ExpressionTypeInferenceVisitor<'_> {
         }
 
         self.register_variable_declaration(Rc::new(TypedIdentifier {
-          names: names.clone(),
+          names: scoped_names,
           type_declaration: TypeDeclaration::Regular {
             type_name: the_type,
             generic_type_assignment: None,
@@ -318,6 +324,64 @@ impl super::Visitor for ExpressionTypeInferenceVisitor<'_> {
     };
   }
 
+  /// Declares the variables of the for..in loops over the arrays that are not
+  /// declared variables, with the type of the items of the inferred array.
+  fn visit_for_in_statement(&mut self, node: &ForInStatement) {
+    let (name, span, array) = match (&node.child, &node.collection) {
+      (ForInVariable::Implicit { name, span }, ForInCollection::Array(array)) => {
+        (name, *span, array)
+      }
+      _ => return
+    };
+
+    let scoped_name = Context::get_ref(&self.current_context)
+      .get_scoped_name(span, name)
+      .to_string();
+
+    // the variable declaration visitor already declared the loops over arrays
+    // of a declared type
+    if Context::get_ref(&self.current_context)
+      .local_variables_inference
+      .contains_key(&scoped_name)
+    {
+      return;
+    }
+
+    // the collection was inferred before the loop
+    let array_type = array.infered_type_name.borrow().to_string();
+    let items_type = match array_items_type(&array_type) {
+      Some(items_type) => items_type,
+      None => {
+        let collection_span = array.body.get_span();
+
+        self.report_manager.push(
+          Diagnostic::error(span, "Cannot infer variable type")
+            .with_code("unknown-variable-type")
+            .with_label(
+              collection_span,
+              format!("The collection is of type `{array_type}` and not an array")
+            )
+            .with_help(format!(
+              "Write the type of the items, like `for {name}: ItemType in ...`"
+            ))
+        );
+
+        return;
+      }
+    };
+
+    self
+      .current_context
+      .borrow_mut()
+      .local_variables_inference
+      .insert(scoped_name.clone(), items_type.to_string());
+
+    self.register_variable_declaration(Rc::new(TypedIdentifier {
+      names: vec![scoped_name],
+      type_declaration: type_declaration(items_type)
+    }));
+  }
+
   fn register_variable_declaration(&mut self, declaration: Rc<TypedIdentifier>) {
     self
       .current_context
@@ -332,8 +396,7 @@ impl super::Visitor for ExpressionTypeInferenceVisitor<'_> {
     let mut visitor = ClosureVisitor::new(
       self.current_context.clone(),
       self.inference_store,
-      self.report_manager,
-      self.span_manager
+      self.report_manager
     );
 
     node.body_statements.accept(&mut visitor);
@@ -363,28 +426,17 @@ impl super::Visitor for ExpressionTypeInferenceVisitor<'_> {
 }
 
 /// Does type inference for the local variables in the functions
-pub struct FunctionsInferenceVisitor<'a> {
-  pub current_context: Rc<RefCell<Context>>,
-  pub inference_store: &'a mut TypeInferenceStore,
-  pub report_manager: &'a mut ReportManager,
-  pub span_manager: &'a mut SpanManager
+pub struct FunctionsInferenceVisitor {
+  pub current_context: Rc<RefCell<Context>>
 }
 
-impl<'a> FunctionsInferenceVisitor<'a> {
-  pub fn new(
-    current_context: Rc<RefCell<Context>>, inference_store: &'a mut TypeInferenceStore,
-    report_manager: &'a mut ReportManager, span_manager: &'a mut SpanManager
-  ) -> Self {
-    Self {
-      current_context,
-      inference_store,
-      report_manager,
-      span_manager
-    }
+impl FunctionsInferenceVisitor {
+  pub fn new(current_context: Rc<RefCell<Context>>) -> Self {
+    Self { current_context }
   }
 }
 
-impl super::Visitor for FunctionsInferenceVisitor<'_> {
+impl super::Visitor for FunctionsInferenceVisitor {
   fn visitor_type(&self) -> super::VisitorType {
     super::VisitorType::TypeInferenceVisitor
   }
@@ -408,21 +460,14 @@ impl super::Visitor for FunctionsInferenceVisitor<'_> {
 /// Typechecks the function calls
 pub struct FunctionsCallsCheckerVisitor<'a> {
   pub current_context: Rc<RefCell<Context>>,
-  pub inference_store: &'a mut TypeInferenceStore,
-  pub report_manager: &'a mut ReportManager,
-  pub span_manager: &'a mut SpanManager
+  pub report_manager: &'a mut ReportManager
 }
 
 impl<'a> FunctionsCallsCheckerVisitor<'a> {
-  pub fn new(
-    current_context: Rc<RefCell<Context>>, inference_store: &'a mut TypeInferenceStore,
-    report_manager: &'a mut ReportManager, span_manager: &'a mut SpanManager
-  ) -> Self {
+  pub fn new(current_context: Rc<RefCell<Context>>, report_manager: &'a mut ReportManager) -> Self {
     Self {
       current_context,
-      inference_store,
-      report_manager,
-      span_manager
+      report_manager
     }
   }
 }
@@ -473,33 +518,21 @@ impl super::Visitor for FunctionsCallsCheckerVisitor<'_> {
             // the parameter is not optional but None was passed
             if some_supplied.is_none() {
               self.report_manager.push(
-                Report::build(
-                  ariadne::ReportKind::Error,
-                  (),
-                  self.span_manager.get_left(node.accessor.span)
-                )
-                .with_message(&"Missing required parameter")
-                .with_label(
-                  Label::new(self.span_manager.get_range(node.accessor.span)).with_message(
-                    &format!("Parameter n° {count} is required but is missing from function call")
+                Diagnostic::error(node.accessor.span, "Missing required parameter")
+                  .with_code("missing-parameter")
+                  .with_label(
+                    node.accessor.span,
+                    format!("Parameter n° {count} is required but is missing from function call")
                   )
-                )
-                .finish(),
-                node.accessor.span
               );
 
               self.report_manager.push(
-                Report::build(
-                  ariadne::ReportKind::Advice,
-                  (),
-                  self.span_manager.get_left(expected.span)
-                )
-                .with_label(
-                  Label::new(self.span_manager.get_range(expected.span))
-                    .with_message("Try passing a parameter of the following type")
-                )
-                .finish(),
-                expected.span
+                Diagnostic::advice(expected.span, "")
+                  .with_code("expected-parameter-type")
+                  .with_label(
+                    expected.span,
+                    "Try passing a parameter of the following type"
+                  )
               );
 
               continue;
@@ -517,35 +550,25 @@ impl super::Visitor for FunctionsCallsCheckerVisitor<'_> {
                 let span = supplied.body.get_span();
 
                 self.report_manager.push(
-                  Report::build(
-                    ariadne::ReportKind::Error,
-                    (),
-                    self.span_manager.get_left(span)
-                  )
-                  .with_message(&"Parameter type mismatch")
-                  .with_label(
-                    Label::new(self.span_manager.get_range(span)).with_message(&format!(
-                      "Parameter n°{count} is expected to be a {} but a {} was passed",
-                      &expected.infered_type,
-                      supplied_type.to_string()
-                    ))
-                  )
-                  .finish(),
-                  span
+                  Diagnostic::error(span, "Parameter type mismatch")
+                    .with_code("parameter-type-mismatch")
+                    .with_label(
+                      span,
+                      format!(
+                        "Parameter n°{count} is expected to be a {} but a {} was passed",
+                        &expected.infered_type,
+                        supplied_type.to_string()
+                      )
+                    )
                 );
 
                 self.report_manager.push(
-                  Report::build(
-                    ariadne::ReportKind::Advice,
-                    (),
-                    self.span_manager.get_left(expected.span)
-                  )
-                  .with_label(
-                    Label::new(self.span_manager.get_range(expected.span))
-                      .with_message("Try passing a parameter of the following type")
-                  )
-                  .finish(),
-                  expected.span
+                  Diagnostic::advice(expected.span, "")
+                    .with_code("expected-parameter-type")
+                    .with_label(
+                      expected.span,
+                      "Try passing a parameter of the following type"
+                    )
                 );
 
                 continue;
@@ -557,3 +580,55 @@ impl super::Visitor for FunctionsCallsCheckerVisitor<'_> {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use crate::test_utils::{compile_source, normalize};
+
+  const INVENTORY: &str = "class Inventory {\n  public function GetAllItems(): array<int> {\n    var items: array<int>;\n    return items;\n  }\n\n  public function GetGrid(): array<array<int>> {\n    var grid: array<array<int>>;\n    return grid;\n  }\n}\n\n";
+
+  #[test]
+  fn for_in_variables_get_the_items_type_of_calls() {
+    let project = compile_source(
+      &format!("{INVENTORY}function total(inventory: Inventory): int {{\n  var sum: int;\n\n  for item in inventory.GetAllItems() {{\n    sum += item;\n  }}\n\n  return sum;\n}}\n"),
+      true
+    );
+
+    assert!(project.errors.is_empty(), "{:?}", project.errors);
+    assert!(normalize(project.output("main.ws")).contains("var item: int;"));
+  }
+
+  #[test]
+  fn for_in_variables_get_the_items_type_of_nested_arrays() {
+    let project = compile_source(
+      &format!("{INVENTORY}function total(inventory: Inventory): int {{\n  var sum: int;\n\n  for row in inventory.GetGrid() {{\n    for cell in row {{\n      sum += cell;\n    }}\n  }}\n\n  return sum;\n}}\n"),
+      true
+    );
+
+    assert!(project.errors.is_empty(), "{:?}", project.errors);
+
+    let output = normalize(project.output("main.ws"));
+    assert!(output.contains("var row: array<int>;"), "{output}");
+    assert!(output.contains("var cell: int;"), "{output}");
+  }
+
+  #[test]
+  fn for_in_variables_over_other_types_are_reported() {
+    let project = compile_source(
+      &format!("{INVENTORY}function total(inventory: Inventory) {{\n  for item in inventory {{\n  }}\n}}\n"),
+      true
+    );
+
+    assert_eq!(project.errors, vec!["unknown-variable-type"]);
+  }
+
+  #[test]
+  fn for_in_variables_over_calls_need_the_static_analysis() {
+    let project = compile_source(
+      &format!("{INVENTORY}function total(inventory: Inventory) {{\n  for item in inventory.GetAllItems() {{\n  }}\n}}\n"),
+      false
+    );
+
+    assert_eq!(project.errors, vec!["unknown-variable-type"]);
+  }
+}
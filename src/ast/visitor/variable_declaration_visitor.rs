@@ -1,30 +1,334 @@
 use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 
 use crate::ast::codegen::context::{Context, ContextType};
-use crate::ast::{ProgramInformation, TypedIdentifier};
+use crate::ast::codegen::Codegen;
+use crate::ast::visitor::Visitor;
+use crate::ast::{
+  DefaultValue, Diagnostic, Expression, ExpressionBody, ForInCollection, ForInVariable,
+  OperationCode, Span, TypeDeclaration, TypedIdentifier, VariableDeclaration
+};
 
 /// Looks variable declarations and register them to the context of the current
 /// function. Allows for variable declarations anywhere in function bodies.
-pub struct VariableDeclarationVisitor<'a> {
-  pub program_information: &'a ProgramInformation,
-  pub current_context: Rc<RefCell<Context>>
+pub struct VariableDeclarationVisitor {
+  pub current_context: Rc<RefCell<Context>>,
+
+  /// Whether the types of the expressions are inferred, the variables of the
+  /// for..in loops over other expressions then get their type from the
+  /// [super::ExpressionTypeInferenceVisitor]
+  static_analysis: bool,
+
+  /// The types of the parameters of the current function, the variables of
+  /// the for..in loops over them get the type of their items.
+  parameters: HashMap<String, TypeDeclaration>,
+
+  /// The local variables of the blocks the visitor is in, from the outermost
+  /// block to the innermost one.
+  scopes: Vec<HashMap<String, ScopedVariable>>,
+
+  /// The names the variables of the current function are emitted with
+  scoped_variable_names: HashSet<String>,
+
+  /// The identifiers on the right side of a `.`, they are properties and not
+  /// local variables.
+  properties: HashSet<Span>,
+
+  /// The for..in loops whose variable type could not be inferred and the
+  /// variables that shadow a variable of another type
+  pub diagnostics: Vec<Diagnostic>
 }
 
-impl<'a> VariableDeclarationVisitor<'a> {
-  pub fn new(program_information: &'a ProgramInformation) -> Self {
+/// A local variable visible from the block being visited
+struct ScopedVariable {
+  /// The name the variable is emitted with
+  scoped_name: String,
+
+  /// The declared type, unknown for the implicit declarations until the type
+  /// inference runs
+  type_declaration: Option<TypeDeclaration>,
+  span: Span
+}
+
+impl VariableDeclarationVisitor {
+  pub fn new(static_analysis: bool) -> Self {
     Self {
-      program_information,
       current_context: Rc::new(RefCell::new(Context::new(
         "empty",
         None,
         ContextType::Global
-      )))
+      ))),
+      static_analysis,
+      parameters: HashMap::new(),
+      scopes: Vec::new(),
+      scoped_variable_names: HashSet::new(),
+      properties: HashSet::new(),
+      diagnostics: Vec::new()
+    }
+  }
+
+  /// Declares the variable in the innermost block and returns the name it is
+  /// emitted with, a variable that exists in another block of the function
+  /// gets a unique name. Shadowing a variable of an outer block with another
+  /// type is reported as an error.
+  fn declare_variable(
+    &mut self, name: &str, span: Span, type_declaration: Option<&TypeDeclaration>
+  ) -> String {
+    let scope = match self.scopes.last() {
+      Some(scope) => scope,
+      // not in a function
+      None => return name.to_string()
+    };
+
+    // declared twice in the same block
+    if let Some(variable) = scope.get(name) {
+      return variable.scoped_name.clone();
+    }
+
+    let shadowed = self
+      .find_scoped_variable(name)
+      .and_then(|variable| Some((variable.type_declaration.clone()?, variable.span)));
+
+    if let (Some((shadowed_type, shadowed_span)), Some(type_declaration)) =
+      (shadowed, type_declaration)
+    {
+      if shadowed_type.to_string() != type_declaration.to_string() {
+        self.diagnostics.push(
+          Diagnostic::error(span, "Variable shadows a variable of another type")
+            .with_code("shadowed-variable")
+            .with_label(
+              span,
+              format!(
+                "`{name}` is declared as {} here",
+                self.written_type(type_declaration)
+              )
+            )
+            .with_label(
+              shadowed_span,
+              format!(
+                "and as {} in an outer block",
+                self.written_type(&shadowed_type)
+              )
+            )
+            .with_help("Rename one of the two variables")
+        );
+      }
+    }
+
+    let mut scoped_name = name.to_string();
+    let mut suffix = 1;
+    while self.scoped_variable_names.contains(&scoped_name) {
+      suffix += 1;
+      scoped_name = format!("{name}_{suffix}");
+    }
+
+    if scoped_name != name {
+      self
+        .current_context
+        .borrow_mut()
+        .scoped_names
+        .insert((span, name.to_string()), scoped_name.clone());
+    }
+
+    self.set_declaration_span(&scoped_name, span);
+    self.scoped_variable_names.insert(scoped_name.clone());
+    if let Some(scope) = self.scopes.last_mut() {
+      scope.insert(
+        name.to_string(),
+        ScopedVariable {
+          scoped_name: scoped_name.clone(),
+          type_declaration: type_declaration.cloned(),
+          span
+        }
+      );
+    }
+
+    scoped_name
+  }
+
+  /// Declares the variables and registers them under the names they are
+  /// emitted with.
+  fn declare_typed_identifier(&mut self, declaration: &Rc<TypedIdentifier>, span: Span) {
+    let scoped_names: Vec<String> = declaration
+      .names
+      .iter()
+      .map(|name| self.declare_variable(name, span, Some(&declaration.type_declaration)))
+      .collect();
+
+    if scoped_names == declaration.names {
+      self.register_variable_declaration(declaration.clone());
+    } else {
+      self.register_variable_declaration(Rc::new(TypedIdentifier {
+        names: scoped_names,
+        type_declaration: declaration.type_declaration.clone()
+      }));
+    }
+  }
+
+  /// Makes the declaration give its variables back the default value of their
+  /// type, and declares the never assigned variable the ones of the other
+  /// types are copied from.
+  fn reset_declaration(&mut self, declaration: &TypedIdentifier, span: Span) {
+    self
+      .current_context
+      .borrow_mut()
+      .resetting_declarations
+      .insert(span);
+
+    if let DefaultValue::Empty(empty_name) = DefaultValue::of(&declaration.type_declaration) {
+      let is_declared = Context::get_ref(&self.current_context)
+        .variable_declarations
+        .iter()
+        .any(|declaration| declaration.names.contains(&empty_name));
+
+      if !is_declared {
+        self.set_declaration_span(&empty_name, span);
+        self.register_variable_declaration(Rc::new(TypedIdentifier {
+          names: vec![empty_name],
+          type_declaration: declaration.type_declaration.clone()
+        }));
+      }
+    }
+  }
+
+  /// Returns the variable the name refers to in the blocks the visitor is in.
+  /// Keeps the span of the first declaration of the variable, for the source
+  /// maps.
+  fn set_declaration_span(&mut self, variable_name: &str, span: Span) {
+    self
+      .current_context
+      .borrow_mut()
+      .declaration_spans
+      .entry(variable_name.to_string())
+      .or_insert(span);
+  }
+
+  fn find_scoped_variable(&self, name: &str) -> Option<&ScopedVariable> {
+    self.scopes.iter().rev().find_map(|scope| scope.get(name))
+  }
+
+  /// Returns the type as it is written in the sources.
+  fn written_type(&self, type_declaration: &TypeDeclaration) -> String {
+    let mut output = Vec::new();
+
+    match type_declaration.emit(&Context::get_ref(&self.current_context), &mut output) {
+      Ok(()) => String::from_utf8(output).unwrap_or_default(),
+      Err(_) => type_declaration.to_string()
+    }
+  }
+
+  /// Returns the type of the items of the array the expression is, if it is a
+  /// variable, a parameter or a property whose declared type is `array<T>`.
+  fn items_type(&self, collection: &Expression) -> Option<TypeDeclaration> {
+    let collection_type = match &collection.body {
+      ExpressionBody::Identifier(identifier) if identifier.indexing.is_empty() => {
+        let scoped_name = Context::get_ref(&self.current_context)
+          .get_scoped_name(identifier.span, &identifier.text)
+          .to_string();
+
+        self.find_declared_type(&scoped_name)
+      }
+      ExpressionBody::Operation(left, OperationCode::Nesting, right) => {
+        match (&left.body, &right.body) {
+          (ExpressionBody::Identifier(this), ExpressionBody::Identifier(property))
+            if this.text == "this" && this.indexing.is_empty() && property.indexing.is_empty() =>
+          {
+            self.find_property_type(&property.text)
+          }
+          _ => None
+        }
+      }
+      ExpressionBody::Group(expression) => return self.items_type(expression),
+      _ => None
+    }?;
+
+    match collection_type {
+      TypeDeclaration::Regular {
+        type_name,
+        generic_type_assignment: Some(mut generic_types),
+        mangled_accessor: _
+      } if type_name == "array" && generic_types.len() == 1 => generic_types.pop(),
+      _ => None
     }
   }
+
+  /// Returns the declared type of the variable, from the local variables and
+  /// the parameters of the function then from the properties and the globals
+  /// of the parent contexts.
+  fn find_declared_type(&self, variable_name: &str) -> Option<TypeDeclaration> {
+    if let Some(declared_type) =
+      declared_type(&Context::get_ref(&self.current_context), variable_name)
+    {
+      return Some(declared_type);
+    }
+
+    if let Some(parameter_type) = self.parameters.get(variable_name) {
+      return Some(parameter_type.clone());
+    }
+
+    let mut current = Context::get_ref(&self.current_context)
+      .parent_context
+      .clone();
+    while let Some(context) = current {
+      let context_ref = Context::get_ref(&context);
+
+      if let Some(declared_type) = declared_type(&context_ref, variable_name) {
+        return Some(declared_type);
+      }
+
+      current = context_ref.parent_context.clone();
+    }
+
+    None
+  }
+
+  /// Returns the declared type of the property of the class or the struct the
+  /// current function is in.
+  fn find_property_type(&self, property_name: &str) -> Option<TypeDeclaration> {
+    let mut current = Some(self.current_context.clone());
+
+    while let Some(context) = current {
+      let context_ref = Context::get_ref(&context);
+
+      if let ContextType::ClassOrStruct
+      | ContextType::State {
+        parent_class_name: _
+      } = &context_ref.context_type
+      {
+        return declared_type(&context_ref, property_name);
+      }
+
+      current = context_ref.parent_context.clone();
+    }
+
+    None
+  }
+}
+
+/// The number of blocks the visitor is in once in the body of a function, the
+/// parameters are in a block of their own.
+const FUNCTION_BODY_DEPTH: usize = 2;
+
+fn int_type() -> TypeDeclaration {
+  TypeDeclaration::Regular {
+    type_name: "int".to_string(),
+    generic_type_assignment: None,
+    mangled_accessor: RefCell::new(None)
+  }
 }
 
-impl super::Visitor for VariableDeclarationVisitor<'_> {
+/// Returns the type of the last declaration of the variable in the context.
+fn declared_type(context: &Context, variable_name: &str) -> Option<TypeDeclaration> {
+  context
+    .variable_declarations
+    .iter()
+    .rev()
+    .find(|declaration| declaration.names.iter().any(|name| name == variable_name))
+    .map(|declaration| declaration.type_declaration.clone())
+}
+
+impl super::Visitor for VariableDeclarationVisitor {
   fn visitor_type(&self) -> super::VisitorType {
     super::VisitorType::VariableDeclarationVisitor
   }
@@ -37,6 +341,9 @@ impl super::Visitor for VariableDeclarationVisitor<'_> {
   /// Update the current context with the latest context met in the AST
   fn visit_function_declaration(&mut self, node: &crate::ast::FunctionDeclaration) {
     self.current_context = node.context.clone();
+    self.parameters.clear();
+    self.scoped_variable_names.clear();
+    self.properties.clear();
   }
 
   /// Update the current context with the latest context met in the AST
@@ -44,22 +351,188 @@ impl super::Visitor for VariableDeclarationVisitor<'_> {
     self.current_context = node.context.clone();
   }
 
-  fn visit_variable_declaration(&mut self, node: &crate::ast::VariableDeclaration) {
+  fn visit_function_declaration_parameter(
+    &mut self, node: &crate::ast::FunctionDeclarationParameter
+  ) {
+    for parameter_name in &node.typed_identifier.names {
+      self.parameters.insert(
+        parameter_name.clone(),
+        node.typed_identifier.type_declaration.clone()
+      );
+
+      // parameters keep their names, the ones of the lambdas are emitted in a
+      // function of their own
+      self.scoped_variable_names.insert(parameter_name.clone());
+      if let Some(scope) = self.scopes.last_mut() {
+        scope.insert(
+          parameter_name.clone(),
+          ScopedVariable {
+            scoped_name: parameter_name.clone(),
+            type_declaration: Some(node.typed_identifier.type_declaration.clone()),
+            span: node.span
+          }
+        );
+      }
+    }
+  }
+
+  fn visit_variable_declaration(&mut self, node: &VariableDeclaration) {
     match &node {
-      crate::ast::VariableDeclaration::Explicit {
+      VariableDeclaration::Explicit {
         declaration,
-        following_expression: _
+        following_expression,
+        span
+      } => {
+        if self.scopes.is_empty() {
+          for name in &declaration.names {
+            self.set_declaration_span(name, *span);
+          }
+
+          self.register_variable_declaration(declaration.clone());
+        } else {
+          self.declare_typed_identifier(declaration, *span);
+        }
+
+        // the variables are declared at the start of the function, the
+        // declarations in the nested blocks like the ones of the loops can be
+        // run more than once.
+        if following_expression.is_none() && self.scopes.len() > FUNCTION_BODY_DEPTH {
+          self.reset_declaration(declaration, *span);
+        }
+      }
+      // implicit variables are registered by the type inference visitor, with
+      // the names they get here
+      VariableDeclaration::Implicit {
+        names,
+        following_expression: _,
+        span
       } => {
-        self.register_variable_declaration(declaration.clone());
+        for name in names {
+          self.declare_variable(name, *span, None);
+        }
+      }
+    };
+  }
+
+  fn visit_block_start(&mut self) {
+    self.scopes.push(HashMap::new());
+  }
+
+  fn visit_block_end(&mut self) {
+    self.scopes.pop();
+  }
+
+  /// Points the identifiers to the variable they use in the blocks the visitor
+  /// is in, for the ones that were renamed.
+  fn visit_expression(&mut self, node: &Expression) {
+    match &node.body {
+      ExpressionBody::Operation(_, OperationCode::Nesting, right) => {
+        if let ExpressionBody::Identifier(property) = &right.body {
+          self.properties.insert(property.span);
+        }
+      }
+      ExpressionBody::Identifier(identifier) if !self.properties.contains(&identifier.span) => {
+        let scoped_name = match self.find_scoped_variable(&identifier.text) {
+          Some(variable) if variable.scoped_name != identifier.text => variable.scoped_name.clone(),
+          _ => return
+        };
+
+        self
+          .current_context
+          .borrow_mut()
+          .scoped_names
+          .insert((identifier.span, identifier.text.clone()), scoped_name);
       }
-      // implicit variables are registered by the type inference visitor
-      crate::ast::VariableDeclaration::Implicit {
-        names: _,
-        following_expression: _
-      } => {}
+      _ => {}
     };
   }
 
+  fn visit_for_in_statement(&mut self, node: &crate::ast::ForInStatement) {
+    let array = match &node.collection {
+      ForInCollection::Array(array) => Some(array),
+      ForInCollection::Range {
+        start: _,
+        end: _,
+        step: _
+      } => None
+    };
+
+    match (&node.child, array) {
+      (ForInVariable::Explicit { declaration, span }, _) => {
+        self.declare_typed_identifier(declaration, *span)
+      }
+      // the variable of a range is its counter
+      (ForInVariable::Implicit { name, span }, None) => self.declare_typed_identifier(
+        &Rc::new(TypedIdentifier {
+          names: vec![name.clone()],
+          type_declaration: int_type()
+        }),
+        *span
+      ),
+      (ForInVariable::Implicit { name, span }, Some(array)) => match self.items_type(array) {
+        Some(type_declaration) => self.declare_typed_identifier(
+          &Rc::new(TypedIdentifier {
+            names: vec![name.clone()],
+            type_declaration
+          }),
+          *span
+        ),
+        // the type of the other arrays is known once their expression is
+        // inferred
+        None if self.static_analysis => {
+          self.declare_variable(name, *span, None);
+        }
+        None => {
+          let collection_span = array.body.get_span();
+
+          self.diagnostics.push(
+            Diagnostic::error(*span, "Cannot infer variable type")
+              .with_code("unknown-variable-type")
+              .with_label(
+                collection_span,
+                "Without the static analysis, the type of the items is only inferred from a variable or a property of type array<T>"
+              )
+              .with_help(format!(
+                "Write the type of the items, like `for {name}: ItemType in ...`"
+              ))
+          );
+        }
+      }
+    };
+
+    // declare the indexing variable of the loops over arrays, the `idx` of
+    // `for (idx, item) in items` or the generated one
+    match (&node.index, array) {
+      (Some(index), _) => self.declare_typed_identifier(
+        &Rc::new(TypedIdentifier {
+          names: vec![index.value().clone()],
+          type_declaration: int_type()
+        }),
+        index.span
+      ),
+      (None, Some(_)) => {
+        let (ForInVariable::Explicit { span, .. } | ForInVariable::Implicit { span, .. }) =
+          &node.child;
+
+        self.set_declaration_span(&node.indexor_name.borrow(), *span);
+        self.register_variable_declaration(Rc::new(TypedIdentifier {
+          names: vec![node.indexor_name.borrow().clone()],
+          type_declaration: int_type()
+        }));
+      }
+      (None, None) => {}
+    };
+
+    // the end and the step of the ranges are computed once before the loop
+    for (temporary_name, expression) in node.range_temporaries() {
+      self.set_declaration_span(&temporary_name, expression.body.get_span());
+      self.register_variable_declaration(Rc::new(TypedIdentifier {
+        names: vec![temporary_name],
+        type_declaration: int_type()
+      }));
+    }
+  }
+
   fn register_variable_declaration(&mut self, declaration: Rc<TypedIdentifier>) {
     for variable_name in &declaration.names {
       self
@@ -10,7 +10,9 @@ pub struct WhileStatement {
 impl Visited for WhileStatement {
   fn accept<T: visitor::Visitor>(&self, visitor: &mut T) {
     self.condition.accept(visitor);
+    visitor.visit_block_start();
     self.body_statements.accept(visitor);
+    visitor.visit_block_end();
   }
 }
 
@@ -37,7 +39,9 @@ pub struct DoWhileStatement {
 impl Visited for DoWhileStatement {
   fn accept<T: visitor::Visitor>(&self, visitor: &mut T) {
     self.condition.accept(visitor);
+    visitor.visit_block_start();
     self.body_statements.accept(visitor);
+    visitor.visit_block_end();
   }
 }
 
@@ -0,0 +1,987 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Instant;
+
+use crate::ast::codegen::context::{Context, ContextType};
+use crate::ast::codegen::type_inference::TypeInferenceStore;
+use crate::ast::visitor::{
+  CompoundTypesVisitor, ContextBuildingVisitor, ExpressionTypeInferenceVisitor, FunctionVisitor,
+  FunctionsCallsCheckerVisitor, FunctionsInferenceVisitor, LambdaDeclarationVisitor,
+  LibraryEmitterVisitor, NamespaceResolutionVisitor, TernaryVisitor, VariableDeclarationVisitor
+};
+use crate::ast::{
+  Diagnostic, FilePathRef, Program, ProgramInformation, ReportManager, Span, SpanMaker, SpanManager
+};
+use crate::config::Config;
+use crate::preprocessor::types::{
+  FileName, LibraryDefines, PreprocessorOptions, PreprocessorOutput, ProcessedFile,
+  DEFAULT_EXPANSION_DEPTH_LIMIT, DEFAULT_EXPANSION_SIZE_LIMIT
+};
+use crate::preprocessor::Expansion;
+use crate::source_map::{source_map_path, SourceMapBuilder};
+use crate::timings::Timings;
+use crate::utils::{stable_hash, stable_path, strip_pragmas};
+use crate::{parser, preprocessor, vanilla};
+
+/// Everything the compiler knows about the program once the analysis passes
+/// are done, the code is emitted from it.
+pub struct Compilation {
+  pub preprocessed_content: PreprocessorOutput,
+  pub global_context: Rc<RefCell<Context>>,
+  pub span_manager: SpanManager,
+  pub inference_store: TypeInferenceStore,
+  pub report_manager: ReportManager,
+
+  /// The time spent in the phases of the compilation so far
+  pub timings: Timings,
+
+  pub ast_list: Vec<ParsedFile>,
+  pub dependency_ast_list: Vec<ParsedFile>
+}
+
+pub struct ParsedFile {
+  pub file_path: PathBuf,
+  pub ast: Program,
+  pub filename: String,
+
+  /// The stable seed the mangled names of the file's nodes are derived from
+  pub seed: String,
+
+  /// The context created for the file during the first pass
+  pub file_context: RefCell<Option<Rc<RefCell<Context>>>>
+}
+
+/// Preprocesses and parses the files, then runs the visitors over the ASTs to
+/// collect the information needed to emit the code. Nothing is printed, the
+/// diagnostics are kept in the report manager of the returned compilation.
+pub fn analyse(config: &Config, static_analysis: bool) -> std::io::Result<Compilation> {
+  let preprocessed_content = preprocess(config)?;
+
+  analyse_files(config, static_analysis, preprocessed_content, |_| true)
+}
+
+/// Preprocesses the files of the project and of its dependencies.
+pub fn preprocess(config: &Config) -> std::io::Result<PreprocessorOutput> {
+  // the package constants can be replaced from the command line too
+  let mut defines = config.defines.clone();
+  let package_constants = [
+    ("PACKAGE_NAME", Some(&config.package.name)),
+    ("PACKAGE_VERSION", config.package.version.as_ref())
+  ];
+
+  for (name, value) in package_constants {
+    if let Some(value) = value {
+      if !config.undefines.contains(name) {
+        defines
+          .entry(name.to_string())
+          .or_insert_with(|| format!("{value:?}"));
+      }
+    }
+  }
+
+  let preprocessor_options = PreprocessorOptions {
+    expansion_depth_limit: config
+      .package
+      .macro_depth_limit
+      .unwrap_or(DEFAULT_EXPANSION_DEPTH_LIMIT),
+    expansion_size_limit: config
+      .package
+      .macro_size_limit
+      .unwrap_or(DEFAULT_EXPANSION_SIZE_LIMIT),
+    defines,
+    undefines: config.undefines.clone(),
+    library_defines: config
+      .library_packages
+      .iter()
+      .map(|(name, package)| {
+        let library_defines = LibraryDefines {
+          defines: package.macro_defines(),
+          undefines: package.undefines.iter().cloned().collect()
+        };
+
+        (name.clone(), library_defines)
+      })
+      .collect(),
+    package_dependencies: config
+      .library_dependencies
+      .iter()
+      .map(|(name, dependencies)| (Some(name.clone()), dependencies.clone()))
+      .chain([(
+        None,
+        config.dependency_declarations.keys().cloned().collect()
+      )])
+      .collect()
+  };
+
+  preprocessor::preprocess(
+    &config.package.src,
+    &config.dependencies,
+    &preprocessor_options
+  )
+}
+
+/// Parses and analyses the preprocessed files. The source files the filter
+/// rejects by their name are left out of the compilation, the files of the
+/// dependencies are always analysed.
+pub fn analyse_files(
+  config: &Config, static_analysis: bool, mut preprocessed_content: PreprocessorOutput,
+  filter: impl Fn(&str) -> bool
+) -> std::io::Result<Compilation> {
+  let mut timings = Timings::new();
+  timings.append(&mut preprocessed_content.timings);
+
+  let program_information = ProgramInformation::new();
+  let global_context = Rc::new(RefCell::new(Context::new(
+    "Program",
+    None,
+    ContextType::Global
+  )));
+
+  // 1.
+  // Build the list of AST from the files
+  let mut sources_span_manager = SpanManager::new();
+  let mut report_manager = ReportManager::new();
+  let mut dependency_ast_list = Vec::new();
+  let mut ast_list = Vec::new();
+
+  let mut original_sources = OriginalSources::default();
+  report_manager.push_many(preprocessor_diagnostics(
+    &preprocessed_content,
+    &mut sources_span_manager,
+    &mut original_sources
+  ));
+
+  // the sources of the parsed files, the diagnostics that point at them are
+  // moved to the original content once the analysis is done
+  let mut parsed_sources = HashMap::new();
+
+  let parsing_start = Instant::now();
+
+  // starting with the dependencies
+  for (name, value) in preprocessed_content.dependencies_files_content.iter() {
+    let dependency_path = config
+      .dependencies
+      .get(name)
+      .map(String::as_str)
+      .unwrap_or_default();
+
+    for (filename, file) in value.iter() {
+      let seed = stable_hash(&[
+        &config.package.name,
+        name,
+        &stable_path(&file.path, dependency_path)
+      ]);
+
+      // the source parse_file adds for the file
+      parsed_sources.insert(sources_span_manager.paths.len(), filename.clone());
+
+      if let Some(parsed_file) = parse_file(
+        filename,
+        file,
+        seed,
+        &program_information,
+        &mut sources_span_manager,
+        &mut report_manager
+      ) {
+        dependency_ast_list.push(parsed_file);
+      }
+    }
+  }
+
+  for (filename, file) in preprocessed_content.source_files_content.iter() {
+    if !filter(filename) {
+      continue;
+    }
+
+    let seed = stable_hash(&[
+      &config.package.name,
+      &stable_path(&file.path, &config.package.src)
+    ]);
+
+    parsed_sources.insert(sources_span_manager.paths.len(), filename.clone());
+
+    if let Some(parsed_file) = parse_file(
+      filename,
+      file,
+      seed,
+      &program_information,
+      &mut sources_span_manager,
+      &mut report_manager
+    ) {
+      ast_list.push(parsed_file);
+    }
+  }
+
+  timings.record("parsing", parsing_start.elapsed());
+
+  // 2.
+  // Traverse the AST to collect information about it
+  let analysis_start = Instant::now();
+  let mut inference_store = TypeInferenceStore::new();
+
+  if static_analysis {
+    vanilla::register_builtins(
+      &global_context,
+      &mut inference_store,
+      &mut sources_span_manager
+    );
+
+    if let Some(vanilla_scripts) = &config.package.vanilla_scripts {
+      vanilla::load_vanilla_scripts(
+        vanilla_scripts,
+        &global_context,
+        &mut inference_store,
+        &mut sources_span_manager
+      )?;
+    }
+  }
+
+  // perform a first pass over the dependencies to build their contexts, so
+  // namespaced declarations are known before the names are resolved.
+  for parsed_file in &dependency_ast_list {
+    // create a context for this file, and register it into the global context
+    let file_context = Rc::new(RefCell::new(Context::new(
+      &format!("file: {:#?}", parsed_file.file_path.file_name().unwrap()),
+      None,
+      ContextType::Global
+    )));
+
+    file_context.borrow_mut().set_as_library(&parsed_file.seed);
+
+    Context::set_parent_context(&file_context, &global_context);
+
+    let mut context_builder = ContextBuildingVisitor {
+      current_context: file_context.clone()
+    };
+
+    use crate::ast::visitor::Visited;
+
+    parsed_file.ast.accept(&mut context_builder);
+    parsed_file.file_context.replace(Some(file_context));
+  }
+
+  for parsed_file in &dependency_ast_list {
+    let file_context = parsed_file
+      .file_context
+      .borrow()
+      .clone()
+      .expect("missing file context in the 2nd compilation pass");
+
+    // the types of the dependencies are not inferred
+    let mut variable_declaration_visitor = VariableDeclarationVisitor::new(false);
+    let mut namespace_resolution_visitor = NamespaceResolutionVisitor::new(file_context.clone());
+
+    let mut function_visitor = FunctionVisitor {
+      current_context: file_context.clone()
+    };
+
+    use crate::ast::visitor::Visited;
+
+    parsed_file.ast.accept(&mut namespace_resolution_visitor);
+    parsed_file.ast.accept(&mut function_visitor);
+    parsed_file.ast.accept(&mut variable_declaration_visitor);
+    report_manager.push_many(variable_declaration_visitor.diagnostics);
+  }
+
+  // perform a first pass to build the contexts
+  for parsed_file in &ast_list {
+    let file_context_name = format!("file: {:#?}", parsed_file.file_path.file_name().unwrap());
+
+    // create a context for this file, and register it into the global context
+    let file_context = Rc::new(RefCell::new(Context::new(
+      &file_context_name,
+      None,
+      ContextType::Global
+    )));
+    Context::set_parent_context(&file_context, &global_context);
+
+    let mut context_builder = ContextBuildingVisitor {
+      current_context: file_context.clone()
+    };
+
+    use crate::ast::visitor::Visited;
+    parsed_file.ast.accept(&mut context_builder);
+    parsed_file.file_context.replace(Some(file_context));
+  }
+
+  // then perform the pass of visitors
+  for parsed_file in &ast_list {
+    let file_context = parsed_file
+      .file_context
+      .borrow()
+      .clone()
+      .expect("missing file context in the 2nd compilation pass");
+
+    let mut variable_declaration_visitor = VariableDeclarationVisitor::new(static_analysis);
+    let mut namespace_resolution_visitor = NamespaceResolutionVisitor::new(file_context.clone());
+
+    let mut function_visitor = FunctionVisitor {
+      current_context: file_context.clone()
+    };
+
+    use crate::ast::visitor::Visited;
+
+    parsed_file.ast.accept(&mut namespace_resolution_visitor);
+    parsed_file.ast.accept(&mut function_visitor);
+    parsed_file.ast.accept(&mut variable_declaration_visitor);
+    report_manager.push_many(variable_declaration_visitor.diagnostics);
+
+    if static_analysis {
+      let mut compound_types_visitor = CompoundTypesVisitor::new(
+        file_context.clone(),
+        &mut inference_store,
+        &mut report_manager
+      );
+      parsed_file.ast.accept(&mut compound_types_visitor);
+    }
+  }
+
+  // 2.1
+  // do a second pass for the type inference
+  if static_analysis {
+    for parsed_file in &ast_list {
+      use crate::ast::visitor::Visited;
+
+      let mut expression_inference_visitor = ExpressionTypeInferenceVisitor::new(
+        global_context.clone(),
+        &mut inference_store,
+        &mut report_manager
+      );
+
+      parsed_file.ast.accept(&mut expression_inference_visitor);
+
+      let mut functions_inference_visitor = FunctionsInferenceVisitor::new(global_context.clone());
+
+      parsed_file.ast.accept(&mut functions_inference_visitor);
+
+      let mut function_call_checker_visitor =
+        FunctionsCallsCheckerVisitor::new(global_context.clone(), &mut report_manager);
+
+      parsed_file.ast.accept(&mut function_call_checker_visitor);
+    }
+  }
+
+  // 2.2
+  // declare the variables of the conditional expressions, it needs the types
+  // from the inference
+  for parsed_file in &ast_list {
+    use crate::ast::visitor::Visited;
+
+    let mut ternary_visitor = TernaryVisitor::new(static_analysis);
+
+    parsed_file.ast.accept(&mut ternary_visitor);
+    report_manager.push_many(ternary_visitor.diagnostics);
+  }
+
+  timings.record("analysis", analysis_start.elapsed());
+
+  map_diagnostics_to_original(
+    &mut report_manager,
+    &mut sources_span_manager,
+    &preprocessed_content,
+    &parsed_sources,
+    &mut original_sources
+  );
+
+  Ok(Compilation {
+    preprocessed_content,
+    global_context,
+    span_manager: sources_span_manager,
+    inference_store,
+    report_manager,
+    timings,
+    ast_list,
+    dependency_ast_list
+  })
+}
+
+/// The original content of the files, each is added as a source of its own
+/// the first time a diagnostic points at it.
+#[derive(Default)]
+struct OriginalSources {
+  source_refs: HashMap<FileName, FilePathRef>
+}
+
+impl OriginalSources {
+  fn source_ref(
+    &mut self, filename: &str, preprocessed_content: &PreprocessorOutput,
+    span_manager: &mut SpanManager
+  ) -> FilePathRef {
+    if let Some(source_ref) = self.source_refs.get(filename) {
+      return *source_ref;
+    }
+
+    let source_ref = span_manager.paths.len();
+    let original_content = preprocessed_content
+      .get_file(filename)
+      .map(|file| file.original_content.clone())
+      .unwrap_or_default();
+
+    span_manager.add_source(filename.to_string(), original_content, String::new());
+    self.source_refs.insert(filename.to_string(), source_ref);
+
+    source_ref
+  }
+}
+
+/// Converts the diagnostics of the preprocessor, their ranges point to the
+/// original content of the files.
+fn preprocessor_diagnostics(
+  preprocessed_content: &PreprocessorOutput, span_manager: &mut SpanManager,
+  original_sources: &mut OriginalSources
+) -> Vec<Diagnostic> {
+  let mut output = Vec::new();
+
+  for diagnostic in &preprocessed_content.diagnostics {
+    let source_ref =
+      original_sources.source_ref(&diagnostic.filename, preprocessed_content, span_manager);
+    let span = span_manager.new_span(source_ref, diagnostic.range.start, diagnostic.range.end);
+
+    let mut output_diagnostic = Diagnostic::new(diagnostic.kind, span, diagnostic.message.clone())
+      .with_code(diagnostic.code)
+      .with_label(span, diagnostic.label.clone());
+
+    if let Some(help) = &diagnostic.help {
+      output_diagnostic = output_diagnostic.with_help(help.clone());
+    }
+
+    output.push(output_diagnostic);
+  }
+
+  output
+}
+
+/// Moves the diagnostics that point at the parsed content of the files to
+/// their original content, so they show the code as it is written. The code
+/// that comes from a macro expansion is reported at the call written in the
+/// file, with a note about the expansion.
+fn map_diagnostics_to_original(
+  report_manager: &mut ReportManager, span_manager: &mut SpanManager,
+  preprocessed_content: &PreprocessorOutput, parsed_sources: &HashMap<FilePathRef, FileName>,
+  original_sources: &mut OriginalSources
+) {
+  let mut stripped_lines = HashMap::new();
+
+  for diagnostic in report_manager.diagnostics_mut() {
+    let parsed_ref = span_manager.get_source_ref(&diagnostic.span);
+    let Some(filename) = parsed_sources.get(&parsed_ref) else {
+      continue;
+    };
+    let Some(file) = preprocessed_content.get_file(filename) else {
+      continue;
+    };
+
+    let original_ref = original_sources.source_ref(filename, preprocessed_content, span_manager);
+    let lines = stripped_lines.entry(parsed_ref).or_insert_with(|| {
+      StrippedLines::new(&span_manager.contents[parsed_ref], &file.content.borrow())
+    });
+    let origins = file.origins.borrow();
+
+    let start = lines.preprocessed_offset(span_manager.get_left(diagnostic.span));
+    if let Some(expansion) = origins.expansion_at(start) {
+      let note = expansion_note(filename, &file.original_content, expansion);
+      diagnostic.note.get_or_insert(note);
+    }
+
+    let mut map_span = |span: Span| {
+      if span_manager.get_source_ref(&span) != parsed_ref {
+        return span;
+      }
+
+      let range = span_manager.get_range(span);
+      let range = origins.original_range(
+        lines.preprocessed_offset(range.start)..lines.preprocessed_offset(range.end)
+      );
+
+      span_manager.new_span(original_ref, range.start, range.end)
+    };
+
+    diagnostic.span = map_span(diagnostic.span);
+    for (span, _) in &mut diagnostic.labels {
+      *span = map_span(*span);
+    }
+  }
+}
+
+/// Returns the note of a diagnostic in the code of an expansion, it points at
+/// the call written in the file.
+fn expansion_note(filename: &str, original_content: &str, expansion: &Expansion) -> String {
+  let before = &original_content[..expansion.call_site.start];
+  let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+  let call_site = format!(
+    "{filename}:{}:{}",
+    before.matches('\n').count() + 1,
+    before[line_start..].chars().count() + 1
+  );
+
+  match expansion.parent {
+    None => format!(
+      "in expansion of macro {}! called at {call_site}",
+      expansion.macro_name
+    ),
+    Some(_) => format!(
+      "in expansion of macro {}! called at {call_site}, expanded from {}",
+      expansion.macro_name,
+      expansion.describe_chain()
+    )
+  }
+}
+
+/// The starts of the lines of the parsed content of a file and of its
+/// preprocessed content. The parser reads the preprocessed content with the
+/// lines of its pragmas emptied, so the offsets are moved from one content to
+/// the other by line and column.
+pub struct StrippedLines {
+  parsed: Vec<usize>,
+  preprocessed: Vec<usize>
+}
+
+impl StrippedLines {
+  pub fn new(parsed: &str, preprocessed: &str) -> Self {
+    Self {
+      parsed: line_starts(parsed),
+      preprocessed: line_starts(preprocessed)
+    }
+  }
+
+  /// Returns the offset in the preprocessed content of the offset in the
+  /// parsed content.
+  pub fn preprocessed_offset(&self, offset: usize) -> usize {
+    // the first line starts at 0, so there is always one
+    let line = self.parsed.partition_point(|start| *start <= offset) - 1;
+
+    match self.preprocessed.get(line) {
+      Some(start) => start + offset - self.parsed[line],
+      None => offset
+    }
+  }
+
+  /// Returns the offset in the parsed content of the offset in the
+  /// preprocessed content, an offset in a pragma is at the end of its emptied
+  /// line.
+  pub fn parsed_offset(&self, offset: usize) -> usize {
+    let line = self.preprocessed.partition_point(|start| *start <= offset) - 1;
+
+    match self.parsed.get(line) {
+      Some(start) => {
+        let offset = start + offset - self.preprocessed[line];
+
+        match self.parsed.get(line + 1) {
+          Some(next_line) => offset.min(next_line - 1),
+          None => offset
+        }
+      }
+      None => offset
+    }
+  }
+}
+
+fn line_starts(content: &str) -> Vec<usize> {
+  std::iter::once(0)
+    .chain(content.match_indices('\n').map(|(index, _)| index + 1))
+    .collect()
+}
+
+/// Parses the preprocessed content of a file, a parsing error is pushed to the
+/// report manager and `None` is returned.
+fn parse_file(
+  filename: &str, file: &ProcessedFile, seed: String, program_information: &ProgramInformation,
+  span_manager: &mut SpanManager, report_manager: &mut ReportManager
+) -> Option<ParsedFile> {
+  let content = strip_pragmas(&file.content.borrow());
+  let mut span_maker = span_manager.add_source(filename.to_string(), content.clone(), seed.clone());
+
+  if file
+    .content
+    .borrow()
+    .contains("#pragma cahirc-preprocessor-print")
+  {
+    eprintln!("{}", &file.content.borrow());
+  }
+
+  let expr = parser::ProgramParser::new().parse(program_information, &mut span_maker, &content);
+
+  match expr {
+    Ok(ast) => Some(ParsedFile {
+      ast,
+      file_path: file.path.clone(),
+      filename: filename.to_string(),
+      seed,
+      file_context: RefCell::new(None)
+    }),
+    Err(error) => {
+      report_manager.push(parse_error_diagnostic(error, &mut span_maker, file));
+
+      None
+    }
+  }
+}
+
+fn parse_error_diagnostic(
+  error: lalrpop_util::ParseError<usize, lalrpop_util::lexer::Token, &str>,
+  span_maker: &mut SpanMaker, file: &ProcessedFile
+) -> Diagnostic {
+  let absolute_path = dunce::canonicalize(std::env::current_dir().unwrap().join(&file.path))
+    .unwrap_or_else(|_| file.path.clone());
+  let file_uri = format!(
+    "file://{}",
+    absolute_path.to_str().unwrap().replace("\\", "/")
+  );
+
+  match error {
+    lalrpop_util::ParseError::InvalidToken { location } => {
+      let span = span_maker.span(location, location + 1, "invalid token");
+
+      Diagnostic::error(span, format!("Invalid token in {file_uri}"))
+        .with_code("invalid-token")
+        .with_label(span, "The invalid token")
+    }
+    lalrpop_util::ParseError::UnrecognizedEOF { location, expected } => {
+      let span = span_maker.span(location, location, "unrecognized eof");
+
+      Diagnostic::error(span, format!("Unrecognized EOF in {file_uri}"))
+        .with_code("unexpected-eof")
+        .with_label(span, format!("Expected {}", expected.join(" | ")))
+    }
+    lalrpop_util::ParseError::UnrecognizedToken { token, expected } => {
+      let span = span_maker.span(token.0, token.2, "unrecognized token");
+
+      Diagnostic::error(span, format!("Unrecognized token in {file_uri}"))
+        .with_code("unexpected-token")
+        .with_label(span, format!("Expected {}", expected.join(" | ")))
+    }
+    lalrpop_util::ParseError::ExtraToken { token } => {
+      let span = span_maker.span(token.0, token.2, "extra token");
+
+      Diagnostic::error(span, format!("Extra token in {file_uri}"))
+        .with_code("extra-token")
+        .with_label(span, format!("Unexpected `{}`", token.1))
+    }
+    lalrpop_util::ParseError::User { error } => {
+      let span = span_maker.span(0, 0, "user error");
+
+      Diagnostic::error(span, format!("Syntax error in {file_uri}"))
+        .with_code("syntax-error")
+        .with_label(span, error)
+    }
+  }
+}
+
+/// Emits the code of the analysed program in the dist directory, the
+/// directory is cleared first.
+pub fn emit(compilation: &Compilation, config: &Config) -> std::io::Result<()> {
+  // the directory does not exist on the first compilation
+  let _ = std::fs::remove_dir_all(&config.package.dist);
+
+  std::fs::create_dir_all(&config.package.dist)?;
+
+  for (path, content) in generate(compilation, config) {
+    write_output(&path, &content)?;
+  }
+
+  Ok(())
+}
+
+/// Generates the code of the analysed program, and returns the content of
+/// every output file by its path in the dist directory. The files that would
+/// be empty are omitted.
+pub fn generate(compilation: &Compilation, config: &Config) -> BTreeMap<PathBuf, String> {
+  generate_files(compilation, config, |_| true)
+}
+
+/// Generates the outputs of the source files the filter accepts, by their name,
+/// and the output of the libraries.
+pub fn generate_files(
+  compilation: &Compilation, config: &Config, filter: impl Fn(&str) -> bool
+) -> BTreeMap<PathBuf, String> {
+  let global_context = &compilation.global_context;
+  let mut source_map_builder = SourceMapBuilder::new(compilation, &config.root);
+  let mut outputs = BTreeMap::new();
+
+  // 3.
+  // Emit code using the information we collected in the previous step
+  for parsed_file in &compilation.ast_list {
+    if !filter(&parsed_file.filename) {
+      continue;
+    }
+
+    let new_path = output_path(&parsed_file.file_path, config);
+
+    use crate::ast::codegen::Codegen;
+    let mut output_code = Vec::new();
+    parsed_file
+      .ast
+      .emit(&global_context.borrow(), &mut output_code)
+      .expect("failed to emit code");
+
+    match std::str::from_utf8(&output_code) {
+      Ok(s) => insert_output(&mut outputs, &mut source_map_builder, new_path, s),
+      Err(e) => eprintln!("{}", e)
+    };
+
+    // (*global_context).borrow().print(0);
+  }
+
+  // 4.
+  // emit code for the libraries code, especially the generic functions that
+  // were used.
+  let generated_code_file = library_output_path(config);
+
+  let mut file_content = Vec::new();
+
+  for parsed_file in &compilation.dependency_ast_list {
+    use crate::ast::visitor::Visited;
+    let mut visitor = LibraryEmitterVisitor::new(global_context, &mut file_content);
+    parsed_file.ast.accept(&mut visitor);
+
+    let mut visitor = LambdaDeclarationVisitor::new(&mut file_content);
+    parsed_file.ast.accept(&mut visitor);
+  }
+
+  for parsed_file in &compilation.ast_list {
+    use crate::ast::visitor::Visited;
+
+    let mut visitor = LambdaDeclarationVisitor::new(&mut file_content);
+    parsed_file.ast.accept(&mut visitor);
+  }
+
+  match std::str::from_utf8(&file_content) {
+    Ok(s) => insert_output(
+      &mut outputs,
+      &mut source_map_builder,
+      generated_code_file,
+      s
+    ),
+    Err(e) => eprintln!("{}", e)
+  };
+
+  outputs
+}
+
+/// Returns the path of the output of a source file, in the dist directory.
+pub fn output_path(file_path: &Path, config: &Config) -> PathBuf {
+  let relative_path = file_path
+    .strip_prefix(&config.package.src)
+    .unwrap_or_else(|_| panic!("could not form the path to {file_path:?}'s output file"));
+
+  Path::new(&config.package.dist)
+    .join(relative_path)
+    .with_extension("ws")
+}
+
+/// Returns the path of the output that holds the code of the libraries and of
+/// the lambdas.
+pub fn library_output_path(config: &Config) -> PathBuf {
+  Path::new(&config.package.dist)
+    .join(format!("wss{}", stable_hash(&[&config.package.name])))
+    .with_extension("ws")
+}
+
+/// Writes the output file, and the directories it is in
+pub fn write_output(path: &Path, content: &str) -> std::io::Result<()> {
+  if let Some(parent) = path.parent() {
+    std::fs::create_dir_all(parent)?;
+  }
+
+  fs::write(path, content)
+}
+
+/// Inserts the formatted code and its source map in the outputs, unless the
+/// code is empty.
+fn insert_output(
+  outputs: &mut BTreeMap<PathBuf, String>, source_map_builder: &mut SourceMapBuilder,
+  path: PathBuf, emitted_code: &str
+) {
+  let (code, mut source_map) = source_map_builder.take_span_markers(emitted_code);
+
+  if code.trim().is_empty() {
+    return;
+  }
+
+  // the formatting only indents the lines and drops the trailing newline, the
+  // source map still matches the lines once truncated.
+  let code = format_code(&code);
+  source_map.lines.truncate(code.lines().count());
+
+  outputs.insert(source_map_path(&path), source_map.to_json());
+  outputs.insert(path, code);
+}
+
+fn format_code(origin: &str) -> String {
+  let mut lines = Vec::new();
+  let mut depth = 0;
+
+  for line in origin.lines() {
+    if line.starts_with('}') && depth > 0 {
+      depth -= 1;
+    }
+
+    lines.push(format!("{}{}", "  ".repeat(depth), line));
+
+    if line.ends_with('{') {
+      depth += 1;
+    }
+  }
+
+  lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::config::{read_config_from, Config};
+  use crate::test_utils::{compile_config, TestDirectory};
+
+  fn write_project(directory: &TestDirectory) {
+    directory
+      .file(
+        "project/cahirc.toml",
+        "[package]\nname = \"stable\"\nsrc = \"src\"\ndist = \"dist\"\nstatic_analysis = true\n\n[dependencies]\nlib = \"../lib\"\n"
+      )
+      .file(
+        "project/src/main.wss",
+        "function main(items: array<int>) {\n  var counter: Counter<int>;\n  var double: fn(x: int): int;\n\n  double = |x: int| {\n    return x * 2 as int;\n  };\n\n  for item: int in items {\n    counter.push(item);\n  }\n}\n"
+      )
+      .file(
+        "lib/main.wss",
+        "class Counter<T> {\n  var value: T;\n\n  public function push(segment: T) {\n    this.value += segment;\n  }\n}\n"
+      );
+  }
+
+  #[test]
+  fn identical_inputs_give_identical_outputs() {
+    let first = TestDirectory::new("stable");
+    let second = TestDirectory::new("stable");
+
+    write_project(&first);
+    write_project(&second);
+
+    let first = compile_config(&read_config_from(&first.path.join("project")).unwrap());
+    let second = compile_config(&read_config_from(&second.path.join("project")).unwrap());
+
+    assert!(first.errors.is_empty(), "{:?}", first.errors);
+
+    // the source maps point to the sources relative to the project
+    assert_eq!(first.outputs, second.outputs);
+    assert!(first.outputs.contains_key("main.ws.map"));
+    assert!(first
+      .output("main.ws.map")
+      .contains("\"file\":\"src/main.wss\""));
+    assert!(first.output("main.ws").contains("wss779fdedb13957e4e_int"));
+  }
+
+  /// Returns a project that depends on a library with its own macro
+  /// constants.
+  fn library_project(main: &str) -> TestDirectory {
+    let directory = TestDirectory::new("library-macros");
+
+    directory
+      .file(
+        "project/cahirc.toml",
+        "[package]\nname = \"project\"\nsrc = \"src\"\ndist = \"dist\"\n\n[dependencies]\nlib = \"../lib\"\n"
+      )
+      .file("project/src/main.wss", main)
+      .file(
+        "lib/cahirc.toml",
+        "[package]\nname = \"lib\"\ndefines = { LIB_DEBUG = true, LIB_LEVEL = 3 }\nundefines = [\"LIB_TRACE\"]\n"
+      )
+      .file(
+        "lib/main.wss",
+        "#define const LIB_LEVEL = 1;\n#define const LIB_TRACE;\n\nfunction lib_level(): int {\n  return LIB_LEVEL!;\n}\n\n#ifdef LIB_DEBUG {\n  function lib_debug() {}\n};\n\n#ifdef LIB_TRACE {\n  function lib_trace() {}\n};\n"
+      );
+
+    directory
+  }
+
+  /// Returns the content of the file of the library once preprocessed.
+  fn preprocessed_library(config: &Config) -> String {
+    let compilation = super::analyse(config, false).unwrap();
+    let files = &compilation.preprocessed_content.dependencies_files_content["lib"];
+    let content = files.values().next().unwrap().content.borrow().clone();
+
+    content
+  }
+
+  #[test]
+  fn libraries_have_their_own_macro_constants() {
+    let directory = library_project("function main() {}\n");
+    let library = preprocessed_library(&read_config_from(&directory.path.join("project")).unwrap());
+
+    assert!(library.contains("return 3;"), "{library}");
+    assert!(library.contains("lib_debug"));
+    assert!(!library.contains("lib_trace"));
+  }
+
+  #[test]
+  fn library_macro_constants_are_not_exported() {
+    let directory =
+      library_project("#ifdef LIB_DEBUG {\n  function debug() {}\n};\n\nfunction main() {}\n");
+    let project = compile_config(&read_config_from(&directory.path.join("project")).unwrap());
+
+    assert!(project.errors.is_empty(), "{:?}", project.errors);
+    assert!(!project.output("main.ws").contains("debug"));
+  }
+
+  #[test]
+  fn project_constants_win_over_the_ones_of_the_libraries() {
+    let directory = library_project("function main() {}\n");
+    let mut config = read_config_from(&directory.path.join("project")).unwrap();
+    config
+      .defines
+      .insert("LIB_LEVEL".to_string(), "5".to_string());
+
+    assert!(preprocessed_library(&config).contains("return 5;"));
+  }
+
+  /// Returns the code of the project once preprocessed, with the package
+  /// declared in `cahirc.toml`.
+  fn preprocessed_package(package: &str, main: &str) -> (String, Vec<&'static str>) {
+    let directory = TestDirectory::new("package-constants");
+    directory
+      .file(
+        "cahirc.toml",
+        &format!("[package]\n{package}src = \"src\"\ndist = \"dist\"\n")
+      )
+      .file("src/main.wss", main);
+
+    let output = super::preprocess(&read_config_from(&directory.path).unwrap()).unwrap();
+    let content = output.source_files_content.values().next().unwrap();
+    let content = content.content.borrow().clone();
+
+    (
+      content,
+      output
+        .diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic.code)
+        .collect()
+    )
+  }
+
+  #[test]
+  fn package_constants_are_quoted_strings() {
+    let (content, codes) = preprocessed_package(
+      "name = \"my-mod\"\nversion = \"1.2.0 \\\"beta\\\"\"\n",
+      "function main() {\n  print(PACKAGE_NAME!, PACKAGE_VERSION!);\n}\n"
+    );
+
+    assert!(codes.is_empty(), "{codes:?}");
+    assert!(
+      content.contains("print(\"my-mod\", \"1.2.0 \\\"beta\\\"\");"),
+      "{content}"
+    );
+  }
+
+  #[test]
+  fn package_version_is_only_defined_with_a_version() {
+    let (content, codes) = preprocessed_package(
+      "name = \"my-mod\"\n",
+      "function main() {\n  print(PACKAGE_NAME!);\n  print(PACKAGE_VERSION!);\n}\n"
+    );
+
+    assert_eq!(codes, vec!["unknown-macro"]);
+    assert!(content.contains("print(\"my-mod\");"), "{content}");
+  }
+}
@@ -1,49 +1,565 @@
-use std::collections::HashMap;
-use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
 
 use serde::Deserialize;
 
+use crate::ast::MessageFormat;
+use crate::dependencies;
+
 #[derive(Deserialize, Debug)]
 pub struct Config {
   pub package: ConfigPackage,
-  pub dependencies: HashMap<String, String>
+
+  /// The directory of the `cahirc.toml` file
+  #[serde(skip)]
+  pub root: PathBuf,
+
+  /// The dependencies as they are declared in the `cahirc.toml` file
+  #[serde(rename = "dependencies", default)]
+  pub dependency_declarations: BTreeMap<String, DependencyDeclaration>,
+
+  /// The source directory of every dependency, including the dependencies of
+  /// the dependencies. The remote ones are in the cache.
+  #[serde(skip)]
+  pub dependencies: HashMap<String, String>,
+
+  /// The `[profile.<name>]` sections, one of them is selected with `--profile`
+  #[serde(rename = "profile", default)]
+  pub profiles: BTreeMap<String, ConfigProfile>,
+
+  /// The macro constants that are defined before the files are read, by the
+  /// selected profile and the `-D` flags.
+  #[serde(skip)]
+  pub defines: BTreeMap<String, String>,
+
+  /// The macro constants that stay undefined even if the files define them, by
+  /// the selected profile and the `-U` flags.
+  #[serde(skip)]
+  pub undefines: BTreeSet<String>,
+
+  /// The `[package]` section of the `cahirc.toml` file of every dependency
+  #[serde(skip)]
+  pub library_packages: BTreeMap<String, LibraryPackage>,
+
+  /// The names of the dependencies every dependency declares
+  #[serde(skip)]
+  pub library_dependencies: BTreeMap<String, BTreeSet<String>>
+}
+
+/// A `[profile.<name>]` section, its options replace the ones of the package
+/// when it is selected.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigProfile {
+  pub static_analysis: Option<bool>,
+  pub macro_depth_limit: Option<usize>,
+  pub macro_size_limit: Option<usize>,
+
+  /// The macro constants to define, the strings are the code of their values
+  /// and the other values are written as they are in the toml file.
+  #[serde(default)]
+  pub defines: BTreeMap<String, toml::Value>,
+
+  #[serde(default)]
+  pub undefines: Vec<String>
+}
+
+/// The `cahirc.toml` file of a library, every field is optional and a library
+/// without one is a directory of `.wss` files without dependencies.
+#[derive(Deserialize, Debug, Default)]
+pub struct LibraryConfig {
+  #[serde(default)]
+  pub package: LibraryPackage,
+
+  #[serde(rename = "dependencies", default)]
+  pub dependency_declarations: BTreeMap<String, DependencyDeclaration>
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct LibraryPackage {
+  pub name: Option<String>,
+
+  /// The version of the library, the packages that depend on it can require
+  /// it.
+  pub version: Option<String>,
+
+  /// The directory of the `.wss` files in the library, its root if there is
+  /// none.
+  pub src: Option<String>,
+
+  /// The macro constants the files of the library see, like the `defines` of
+  /// the profiles. The constants the project sets win over them.
+  #[serde(default)]
+  pub defines: BTreeMap<String, toml::Value>,
+
+  /// The macro constants the files of the library cannot define
+  #[serde(default)]
+  pub undefines: Vec<String>
 }
 
 #[derive(Deserialize, Debug)]
 pub struct ConfigPackage {
   pub name: String,
+
+  /// The version of the package, the `PACKAGE_VERSION!` macro expands to it
+  pub version: Option<String>,
   pub src: String,
   pub dist: String,
-  pub static_analysis: Option<bool>
+  pub static_analysis: Option<bool>,
+
+  /// Path to the vanilla `content/scripts` directory, its declarations are
+  /// used by the static analysis to type check the calls to the game's code.
+  pub vanilla_scripts: Option<String>,
+
+  /// The maximum number of nested macro expansions, see the preprocessor
+  /// options for the default.
+  pub macro_depth_limit: Option<usize>,
+
+  /// The maximum size in bytes of a file once its macros are expanded
+  pub macro_size_limit: Option<usize>
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum DependencyDeclaration {
+  /// `name = "path"`, the short form of `name = { path = "path" }`
+  Path(String),
+  Table(DependencyTable)
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DependencyTable {
+  pub path: Option<String>,
+  pub git: Option<String>,
+
+  /// The branch, the tag or the commit of the git repository, its default
+  /// branch if there is none.
+  pub rev: Option<String>,
+  pub archive: Option<String>,
+
+  /// The version the `cahirc.toml` file of the library must have
+  pub version: Option<String>
+}
+
+/// Where the files of a dependency come from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DependencySource {
+  Path(String),
+  Git { url: String, rev: Option<String> },
+  Archive { url: String }
+}
+
+impl std::fmt::Display for DependencySource {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      DependencySource::Path(path) => write!(f, "{path}"),
+      DependencySource::Git {
+        url,
+        rev: Some(rev)
+      } => write!(f, "{url} at {rev}"),
+      DependencySource::Git { url, rev: None } => write!(f, "{url}"),
+      DependencySource::Archive { url } => write!(f, "{url}")
+    }
+  }
+}
+
+impl Config {
+  /// Applies the profile and the defines of the command line to the config.
+  /// Returns an error message if the profile does not exist.
+  pub fn apply_command_options(&mut self, options: &CommandOptions) -> Result<(), String> {
+    if let Some(name) = &options.profile {
+      let profile = self.profiles.get(name).ok_or_else(|| {
+        let profiles: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+
+        match profiles.is_empty() {
+          true => format!("unknown profile `{name}`, the cahirc.toml file has no profile"),
+          false => format!(
+            "unknown profile `{name}`, expected one of `{}`",
+            profiles.join("`, `")
+          )
+        }
+      })?;
+
+      self.package.static_analysis = profile.static_analysis.or(self.package.static_analysis);
+      self.package.macro_depth_limit = profile.macro_depth_limit.or(self.package.macro_depth_limit);
+      self.package.macro_size_limit = profile.macro_size_limit.or(self.package.macro_size_limit);
+
+      for (name, value) in &profile.defines {
+        self.defines.insert(name.clone(), macro_value(value));
+      }
+
+      self.undefines.extend(profile.undefines.iter().cloned());
+    }
+
+    // the flags are applied in order, the last one wins
+    for option in &options.defines {
+      match option {
+        DefineOption::Define { name, value } => {
+          self.undefines.remove(name);
+          self.defines.insert(name.clone(), value.clone());
+        }
+        DefineOption::Undefine(name) => {
+          self.defines.remove(name);
+          self.undefines.insert(name.clone());
+        }
+      }
+    }
+
+    Ok(())
+  }
+}
+
+impl LibraryPackage {
+  /// Returns the values of the macro constants the library defines.
+  pub fn macro_defines(&self) -> BTreeMap<String, String> {
+    self
+      .defines
+      .iter()
+      .map(|(name, value)| (name.clone(), macro_value(value)))
+      .collect()
+  }
+}
+
+/// Returns the code of a macro constant set in a `cahirc.toml` file, the strings
+/// are the code itself and the other values are written as they are in the
+/// file.
+fn macro_value(value: &toml::Value) -> String {
+  match value {
+    toml::Value::String(code) => code.clone(),
+    value => value.to_string()
+  }
+}
+
+impl DependencyDeclaration {
+  /// Returns the version of the library the declaration requires, if any.
+  pub fn version(&self) -> Option<&str> {
+    match self {
+      DependencyDeclaration::Path(_) => None,
+      DependencyDeclaration::Table(table) => table.version.as_deref()
+    }
+  }
+
+  pub fn source(&self) -> Result<DependencySource, String> {
+    let table = match self {
+      DependencyDeclaration::Path(path) => return Ok(DependencySource::Path(path.clone())),
+      DependencyDeclaration::Table(table) => table
+    };
+
+    let source_count = [&table.path, &table.git, &table.archive]
+      .iter()
+      .filter(|source| source.is_some())
+      .count();
+
+    if source_count != 1 {
+      return Err("expected exactly one of `path`, `git` or `archive`".to_string());
+    }
+
+    if table.rev.is_some() && table.git.is_none() {
+      return Err("`rev` can only be used with `git`".to_string());
+    }
+
+    match (&table.path, &table.git, &table.archive) {
+      (Some(path), _, _) => Ok(DependencySource::Path(path.clone())),
+      (_, Some(url), _) => Ok(DependencySource::Git {
+        url: url.clone(),
+        rev: table.rev.clone()
+      }),
+      (_, _, Some(url)) => Ok(DependencySource::Archive { url: url.clone() }),
+      _ => unreachable!()
+    }
+  }
 }
 
 pub fn read_config() -> std::io::Result<Config> {
-  let default_path = ".".to_string();
-  let args: Vec<String> = std::env::args().collect();
-  let first_arg = args
-    .iter()
+  read_config_from(&project_directory())
+}
+
+/// The flags whose value is the next argument
+const FLAGS_WITH_VALUE: [&str; 3] = ["-D", "-U", "--profile"];
+
+/// Returns the directory of the project to compile, the first argument that is
+/// neither a flag, the value of a flag, nor the `fetch` subcommand. Or the
+/// current directory.
+pub fn project_directory() -> PathBuf {
+  let mut is_flag_value = false;
+
+  std::env::args()
     .skip(1)
-    .find(|arg| !arg.starts_with("-"))
-    .unwrap_or(&default_path);
+    .filter(|arg| arg != "fetch")
+    .find(|arg| {
+      let is_directory = !is_flag_value && !arg.starts_with("-");
+      is_flag_value = FLAGS_WITH_VALUE.contains(&arg.as_str());
+
+      is_directory
+    })
+    .map(PathBuf::from)
+    .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// The options of the command line that change how the project is compiled.
+#[derive(Debug, Clone)]
+pub struct CommandOptions {
+  /// Set with `--message-format=<human|json>`
+  pub message_format: MessageFormat,
+
+  /// Set with `--deny-warnings`, the warnings fail the compilation like the
+  /// errors do.
+  pub deny_warnings: bool,
+
+  /// Set with `--timings`, the time spent in each phase of the compilation is
+  /// printed at the end.
+  pub timings: bool,
+
+  /// Set with `--profile <name>`, the `[profile.<name>]` section of the
+  /// `cahirc.toml` file to use.
+  pub profile: Option<String>,
+
+  /// Set with `-D NAME[=value]` and `-U NAME`, in the order they are given
+  pub defines: Vec<DefineOption>
+}
+
+#[derive(Debug, Clone)]
+pub enum DefineOption {
+  /// `-D NAME[=value]`, the value is `true` if there is none
+  Define { name: String, value: String },
+
+  /// `-U NAME`
+  Undefine(String)
+}
+
+/// Reads the options from the arguments of the command, or returns an error
+/// message if one of them is invalid.
+pub fn command_options() -> Result<CommandOptions, String> {
+  command_options_from(std::env::args().skip(1))
+}
+
+/// Reads the options from the arguments, without the name of the program. The
+/// arguments that are not flags are the project directory.
+pub fn command_options_from(
+  args: impl IntoIterator<Item = String>
+) -> Result<CommandOptions, String> {
+  let mut options = CommandOptions {
+    message_format: MessageFormat::Human,
+    deny_warnings: false,
+    timings: false,
+    profile: None,
+    defines: Vec::new()
+  };
+
+  let mut args = args.into_iter();
+  while let Some(arg) = args.next() {
+    // the flags with a value accept it in the same argument too, as `-DNAME`
+    // or `--profile=name`
+    let flag_value = |flag: &str, args: &mut dyn Iterator<Item = String>| {
+      let value = match arg.strip_prefix(flag) {
+        Some("") => args.next(),
+        Some(value) if flag.starts_with("--") && !value.starts_with('=') => return None,
+        Some(value) => Some(value.strip_prefix('=').unwrap_or(value).to_string()),
+        None => return None
+      };
+
+      Some(
+        value
+          .filter(|value| !value.is_empty())
+          .ok_or(format!("missing value for `{flag}`"))
+      )
+    };
+
+    if let Some(define) = flag_value("-D", &mut args) {
+      let define = define?;
+
+      options.defines.push(match define.split_once('=') {
+        Some((name, value)) => DefineOption::Define {
+          name: name.to_string(),
+          value: value.to_string()
+        },
+        None => DefineOption::Define {
+          name: define,
+          value: String::from("true")
+        }
+      });
+    } else if let Some(name) = flag_value("-U", &mut args) {
+      options.defines.push(DefineOption::Undefine(name?));
+    } else if let Some(profile) = flag_value("--profile", &mut args) {
+      options.profile = Some(profile?);
+    } else if arg == "--deny-warnings" {
+      options.deny_warnings = true;
+    } else if arg == "--timings" {
+      options.timings = true;
+    } else if let Some(format) = arg.strip_prefix("--message-format=") {
+      options.message_format = match format {
+        "human" => MessageFormat::Human,
+        "json" => MessageFormat::Json,
+        _ => {
+          return Err(format!(
+            "unknown message format `{format}`, expected `human` or `json`"
+          ))
+        }
+      };
+    } else if arg == "--message-format" {
+      return Err(
+        "missing value for `--message-format`, expected `--message-format=<human|json>`"
+          .to_string()
+      );
+    } else if arg.starts_with('-') && arg != "--watch" {
+      return Err(format!("unknown flag `{arg}`"));
+    }
+  }
+
+  Ok(options)
+}
+
+/// Reads the `cahirc.toml` file of the project in the given directory, the
+/// paths it contains are made relative to that directory. The remote
+/// dependencies are found in the cache, as they were locked by `cahirc fetch`.
+pub fn read_config_from(cwd: &Path) -> std::io::Result<Config> {
+  let mut config = read_declarations_from(cwd)?;
+
+  let resolved = dependencies::resolve(cwd, &config)?;
+  config.dependencies = resolved.source_directories;
+  config.library_packages = resolved.packages;
+  config.library_dependencies = resolved.library_dependencies;
+
+  Ok(config)
+}
+
+/// Reads the `cahirc.toml` file of the library in the given directory, if it
+/// has one.
+pub fn read_library_config_from(directory: &Path) -> std::io::Result<LibraryConfig> {
+  match std::fs::read_to_string(directory.join("cahirc.toml")) {
+    Ok(content) => Ok(toml::from_str(&content)?),
+    Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(LibraryConfig::default()),
+    Err(error) => Err(error)
+  }
+}
 
-  let cwd = Path::new(first_arg);
+/// Reads the `cahirc.toml` file of the project in the given directory without
+/// looking for the dependencies.
+pub fn read_declarations_from(cwd: &Path) -> std::io::Result<Config> {
   let config_path = cwd.join("cahirc.toml");
   let content = std::fs::read_to_string(config_path)?;
 
   let mut config: Config = toml::from_str(&content)?;
 
+  config.root = cwd.to_path_buf();
+
   config.package.src = cwd.join(config.package.src).to_str().unwrap().to_string();
   config.package.dist = cwd.join(config.package.dist).to_str().unwrap().to_string();
+  config.package.vanilla_scripts = config
+    .package
+    .vanilla_scripts
+    .map(|path| cwd.join(path).to_str().unwrap().to_string());
+
+  Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_utils::TestDirectory;
+
+  fn options(args: &[&str]) -> Result<CommandOptions, String> {
+    command_options_from(args.iter().map(|arg| arg.to_string()))
+  }
+
+  /// Reads a project with a `debug` profile, and applies the options to it.
+  fn apply(args: &[&str]) -> Config {
+    let directory = TestDirectory::new("config");
 
-  let keys: Vec<String> = config.dependencies.keys().map(String::to_string).collect();
-  for dep_name in keys {
-    let dep_path = config.dependencies.get(&dep_name).unwrap();
+    directory.file(
+      "cahirc.toml",
+      "[package]\nname = \"test\"\nsrc = \"src\"\ndist = \"dist\"\n\n[profile.debug]\ndefines = { LEVEL = 1, DEBUG = true, NAME = \"'debug'\" }\nundefines = [\"TRACE\", \"FAST\"]\n"
+    );
+
+    let mut config = read_declarations_from(&directory.path).unwrap();
+    config
+      .apply_command_options(&options(args).unwrap())
+      .unwrap();
+
+    config
+  }
 
-    config.dependencies.insert(
-      dep_name.to_string(),
-      cwd.join(dep_path).to_str().unwrap().to_string()
+  #[test]
+  fn unknown_flags_are_rejected() {
+    assert_eq!(
+      options(&["--profle", "debug"]).unwrap_err(),
+      "unknown flag `--profle`"
     );
+    assert_eq!(options(&["-X"]).unwrap_err(), "unknown flag `-X`");
+    assert!(options(&["project", "--watch", "--timings", "--deny-warnings"]).is_ok());
   }
 
-  Ok(config)
+  #[test]
+  fn flags_without_their_value_are_rejected() {
+    assert_eq!(
+      options(&["--message-format", "json"]).unwrap_err(),
+      "missing value for `--message-format`, expected `--message-format=<human|json>`"
+    );
+    assert_eq!(
+      options(&["--profile"]).unwrap_err(),
+      "missing value for `--profile`"
+    );
+    assert_eq!(options(&["-D"]).unwrap_err(), "missing value for `-D`");
+    assert_eq!(options(&["-U="]).unwrap_err(), "missing value for `-U`");
+  }
+
+  #[test]
+  fn flag_values_are_read_attached_or_separated() {
+    let options = options(&["--profile=debug", "-DLEVEL=2", "-U", "DEBUG"]).unwrap();
+
+    assert_eq!(options.profile.as_deref(), Some("debug"));
+    assert!(matches!(
+      options.defines.as_slice(),
+      [DefineOption::Define { name, value }, DefineOption::Undefine(undefined)]
+        if name == "LEVEL" && value == "2" && undefined == "DEBUG"
+    ));
+  }
+
+  #[test]
+  fn command_line_defines_override_the_profile() {
+    let config = apply(&[
+      "--profile",
+      "debug",
+      "-D",
+      "LEVEL=2",
+      "-U",
+      "DEBUG",
+      "-D",
+      "TRACE"
+    ]);
+
+    assert_eq!(config.defines.get("LEVEL").map(String::as_str), Some("2"));
+    assert_eq!(
+      config.defines.get("NAME").map(String::as_str),
+      Some("'debug'")
+    );
+    assert_eq!(
+      config.defines.get("TRACE").map(String::as_str),
+      Some("true")
+    );
+    assert!(!config.defines.contains_key("DEBUG"));
+    assert_eq!(
+      config.undefines,
+      BTreeSet::from(["DEBUG".to_string(), "FAST".to_string()])
+    );
+  }
+
+  #[test]
+  fn the_last_flag_wins() {
+    let config = apply(&["-U", "LEVEL", "-D", "LEVEL=3", "-D", "FLAG", "-U", "FLAG"]);
+
+    assert_eq!(config.defines.get("LEVEL").map(String::as_str), Some("3"));
+    assert!(!config.defines.contains_key("FLAG"));
+    assert!(config.undefines.contains("FLAG"));
+    assert!(!config.undefines.contains("LEVEL"));
+  }
+
+  #[test]
+  fn profiles_are_only_applied_when_selected() {
+    let config = apply(&["-D", "LEVEL=2"]);
+
+    assert_eq!(config.defines.len(), 1);
+    assert!(config.undefines.is_empty());
+  }
 }
@@ -0,0 +1,206 @@
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use sha2::{Digest, Sha256};
+
+use super::{error, move_into_cache};
+
+/// Returns the directory of the extracted archive in the cache.
+pub fn cache_path(cache_directory: &Path, sha256: &str) -> PathBuf {
+  cache_directory.join("archives").join(sha256)
+}
+
+/// Downloads the archive and extracts it in the cache, then returns its hash.
+/// The archive is refused if its hash is not the expected one.
+pub fn fetch(
+  cache_directory: &Path, url: &str, expected_sha256: Option<&str>
+) -> std::io::Result<String> {
+  let content = download(url)?;
+  let sha256 = format!("{:x}", Sha256::digest(&content));
+
+  if let Some(expected_sha256) = expected_sha256 {
+    if expected_sha256 != sha256 {
+      return Err(error(format!(
+        "the archive {url} changed since it was locked, expected the sha256 {expected_sha256} but got {sha256}"
+      )));
+    }
+  }
+
+  let destination = cache_path(cache_directory, &sha256);
+  if destination.exists() {
+    return Ok(sha256);
+  }
+
+  let temporary_directory = cache_directory.join(format!("tmp-{}", std::process::id()));
+  if temporary_directory.exists() {
+    std::fs::remove_dir_all(&temporary_directory)?;
+  }
+
+  let result = extract(&content, &temporary_directory).and_then(|_| {
+    // most archives have the files in a single directory
+    let root = single_directory(&temporary_directory)?.unwrap_or(temporary_directory.clone());
+
+    move_into_cache(&root, &destination)
+  });
+
+  if temporary_directory.exists() {
+    std::fs::remove_dir_all(&temporary_directory)?;
+  }
+
+  result.map(|_| sha256)
+}
+
+/// Returns the path of an archive that is read from the disk, the `file://`
+/// urls and the paths.
+pub fn local_path(url: &str) -> Option<&str> {
+  match url.starts_with("http://") || url.starts_with("https://") {
+    true => None,
+    false => Some(url.strip_prefix("file://").unwrap_or(url))
+  }
+}
+
+/// Reads the archive from the disk for the `file://` urls and the paths, or
+/// downloads it with curl.
+fn download(url: &str) -> std::io::Result<Vec<u8>> {
+  if let Some(path) = local_path(url) {
+    return std::fs::read(path).map_err(|err| error(format!("could not read {path}: {err}")));
+  }
+
+  let output = Command::new("curl")
+    .args(["--silent", "--show-error", "--fail", "--location", url])
+    .output()
+    .map_err(|err| error(format!("could not run curl, is it installed? {err}")))?;
+
+  if !output.status.success() {
+    return Err(error(format!(
+      "could not download {url}: {}",
+      String::from_utf8_lossy(&output.stderr).trim()
+    )));
+  }
+
+  Ok(output.stdout)
+}
+
+/// Extracts a zip, a tar or a gzipped tar archive, the format is found from
+/// the content.
+fn extract(content: &[u8], destination: &Path) -> std::io::Result<()> {
+  std::fs::create_dir_all(destination)?;
+
+  if content.starts_with(b"PK\x03\x04") {
+    return zip::ZipArchive::new(Cursor::new(content))
+      .and_then(|mut archive| archive.extract(destination))
+      .map_err(|err| error(format!("invalid zip archive: {err}")));
+  }
+
+  if content.starts_with(&[0x1f, 0x8b]) {
+    return tar::Archive::new(flate2::read::GzDecoder::new(content)).unpack(destination);
+  }
+
+  tar::Archive::new(content).unpack(destination)
+}
+
+fn single_directory(directory: &Path) -> std::io::Result<Option<PathBuf>> {
+  let entries: Vec<_> = std::fs::read_dir(directory)?.collect::<Result<_, _>>()?;
+
+  match entries.as_slice() {
+    [entry] if entry.file_type()?.is_dir() => Ok(Some(entry.path())),
+    _ => Ok(None)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Write;
+
+  use super::*;
+  use crate::test_utils::TestDirectory;
+
+  /// Returns the content of a gzipped tar archive with the files in a
+  /// `library` directory.
+  fn tar_archive(files: &[(&str, &str)]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+      Vec::new(),
+      flate2::Compression::default()
+    ));
+
+    for (path, content) in files {
+      let mut header = tar::Header::new_gnu();
+      header.set_size(content.len() as u64);
+      header.set_mode(0o644);
+      header.set_cksum();
+
+      builder
+        .append_data(&mut header, format!("library/{path}"), content.as_bytes())
+        .unwrap();
+    }
+
+    builder.into_inner().unwrap().finish().unwrap()
+  }
+
+  /// Returns the content of a zip archive with the files at its root.
+  fn zip_archive(files: &[(&str, &str)]) -> Vec<u8> {
+    let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+
+    for (path, content) in files {
+      writer
+        .start_file(*path, zip::write::FileOptions::default())
+        .unwrap();
+      writer.write_all(content.as_bytes()).unwrap();
+    }
+
+    writer.finish().unwrap().into_inner()
+  }
+
+  /// Writes the archive in the directory and returns its `file://` url.
+  fn write_archive(directory: &TestDirectory, name: &str, content: &[u8]) -> String {
+    std::fs::write(directory.path.join(name), content).unwrap();
+
+    format!("{}/{name}", directory.url())
+  }
+
+  #[test]
+  fn single_directory_is_the_root_of_the_library() {
+    let archives = TestDirectory::new("archives");
+    let cache = TestDirectory::new("archive-cache");
+    let content = tar_archive(&[("cahirc.toml", ""), ("src/a.wss", "function a() {}\n")]);
+    let url = write_archive(&archives, "library.tar.gz", &content);
+
+    let sha256 = fetch(&cache.path, &url, None).unwrap();
+    let directory = cache_path(&cache.path, &sha256);
+
+    assert_eq!(sha256, format!("{:x}", Sha256::digest(&content)));
+    assert!(directory.join("cahirc.toml").exists());
+    assert!(directory.join("src/a.wss").exists());
+  }
+
+  #[test]
+  fn zip_archives_are_extracted() {
+    let archives = TestDirectory::new("archives");
+    let cache = TestDirectory::new("archive-cache");
+    let content = zip_archive(&[("a.wss", "function a() {}\n"), ("b.wss", "")]);
+    let url = write_archive(&archives, "library.zip", &content);
+
+    let sha256 = fetch(&cache.path, &url, None).unwrap();
+    let directory = cache_path(&cache.path, &sha256);
+
+    assert!(directory.join("a.wss").exists());
+    assert!(directory.join("b.wss").exists());
+  }
+
+  #[test]
+  fn changed_archives_are_refused() {
+    let archives = TestDirectory::new("archives");
+    let cache = TestDirectory::new("archive-cache");
+    let url = write_archive(&archives, "library.tar.gz", &tar_archive(&[("a.wss", "")]));
+    let sha256 = fetch(&cache.path, &url, None).unwrap();
+
+    write_archive(&archives, "library.tar.gz", &tar_archive(&[("b.wss", "")]));
+    let error = fetch(&cache.path, &url, Some(&sha256)).unwrap_err();
+
+    assert!(
+      error.to_string().contains("changed since it was locked"),
+      "{error}"
+    );
+  }
+}
@@ -0,0 +1,168 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use super::{error, move_into_cache};
+use crate::utils::stable_hash;
+
+/// Returns the directory of the commit of the repository in the cache.
+pub fn cache_path(cache_directory: &Path, url: &str, commit: &str) -> PathBuf {
+  cache_directory
+    .join("git")
+    .join(stable_hash(&[url]))
+    .join(commit)
+}
+
+/// Clones the repository and checks out the revision, or its default branch,
+/// then moves the files in the cache. Returns the commit the revision resolved
+/// to.
+pub fn fetch(cache_directory: &Path, url: &str, rev: Option<&str>) -> std::io::Result<String> {
+  let temporary_directory = cache_directory.join(format!("tmp-{}", std::process::id()));
+  if temporary_directory.exists() {
+    std::fs::remove_dir_all(&temporary_directory)?;
+  }
+
+  let result = checkout(&temporary_directory, url, rev).and_then(|commit| {
+    std::fs::remove_dir_all(temporary_directory.join(".git"))?;
+    move_into_cache(
+      &temporary_directory,
+      &cache_path(cache_directory, url, &commit)
+    )?;
+
+    Ok(commit)
+  });
+
+  if temporary_directory.exists() {
+    std::fs::remove_dir_all(&temporary_directory)?;
+  }
+
+  result
+}
+
+fn checkout(directory: &Path, url: &str, rev: Option<&str>) -> std::io::Result<String> {
+  // a revision like `--output=file` would be read as an option of git
+  if let Some(rev) = rev.filter(|rev| rev.starts_with('-')) {
+    return Err(error(format!(
+      "invalid revision `{rev}`, a revision cannot start with `-`"
+    )));
+  }
+
+  // the `--` ends the options, an url like `--upload-pack=command` is a path
+  run_git(
+    Command::new("git")
+      .args(["clone", "--quiet", "--", url])
+      .arg(directory)
+  )?;
+
+  if let Some(rev) = rev {
+    run_git(
+      Command::new("git")
+        .arg("-C")
+        .arg(directory)
+        .args(["checkout", "--quiet", rev, "--"])
+    )?;
+  }
+
+  let commit = run_git(
+    Command::new("git")
+      .arg("-C")
+      .arg(directory)
+      .args(["rev-parse", "HEAD"])
+  )?;
+
+  Ok(commit.trim().to_string())
+}
+
+/// Runs the git command and returns its output, or its error output as the
+/// error.
+fn run_git(command: &mut Command) -> std::io::Result<String> {
+  let output = command
+    .output()
+    .map_err(|err| error(format!("could not run git, is it installed? {err}")))?;
+
+  if !output.status.success() {
+    return Err(error(format!(
+      "git failed: {}",
+      String::from_utf8_lossy(&output.stderr).trim()
+    )));
+  }
+
+  Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_utils::TestDirectory;
+
+  /// Returns a repository with a commit of the file, tagged `v1`.
+  fn repository() -> TestDirectory {
+    let repository = TestDirectory::new("git-repository");
+
+    repository.git(&["init", "--quiet"]);
+    repository.file("a.wss", "function a() {}\n");
+    repository.git(&["add", "."]);
+    repository.git(&["commit", "--quiet", "-m", "first"]);
+    repository.git(&["tag", "v1"]);
+
+    repository
+  }
+
+  #[test]
+  fn default_branch_is_fetched_without_history() {
+    let repository = repository();
+    let cache = TestDirectory::new("git-cache");
+
+    let commit = fetch(&cache.path, &repository.url(), None).unwrap();
+    let directory = cache_path(&cache.path, &repository.url(), &commit);
+
+    assert_eq!(commit, repository.git(&["rev-parse", "HEAD"]));
+    assert!(directory.join("a.wss").exists());
+    assert!(!directory.join(".git").exists());
+  }
+
+  #[test]
+  fn revision_is_checked_out() {
+    let repository = repository();
+    let cache = TestDirectory::new("git-cache");
+    let first_commit = repository.git(&["rev-parse", "HEAD"]);
+
+    repository.file("a.wss", "function b() {}\n");
+    repository.git(&["commit", "--quiet", "-am", "second"]);
+
+    let commit = fetch(&cache.path, &repository.url(), Some("v1")).unwrap();
+    let directory = cache_path(&cache.path, &repository.url(), &commit);
+
+    assert_eq!(commit, first_commit);
+    assert_eq!(
+      std::fs::read_to_string(directory.join("a.wss")).unwrap(),
+      "function a() {}\n"
+    );
+  }
+
+  #[test]
+  fn revisions_are_not_options() {
+    let repository = repository();
+    let cache = TestDirectory::new("git-cache");
+
+    let error = fetch(&cache.path, &repository.url(), Some("--orphan=main")).unwrap_err();
+
+    assert!(
+      error.to_string().contains("cannot start with `-`"),
+      "{error}"
+    );
+  }
+
+  #[test]
+  fn urls_are_not_options() {
+    let cache = TestDirectory::new("git-cache");
+    let marker = cache.path.join("marker");
+    let url = format!("--upload-pack=touch {}", marker.to_str().unwrap());
+
+    // without the `--` git reads the url as an option, and the directory as
+    // the repository
+    let error = fetch(&cache.path, &url, None).unwrap_err();
+
+    assert!(error.to_string().contains(&format!("'{url}'")), "{error}");
+    assert!(!marker.exists());
+  }
+}
@@ -0,0 +1,161 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::DependencySource;
+
+pub const LOCKFILE_NAME: &str = "cahirc.lock";
+
+const LOCKFILE_HEADER: &str =
+  "# This file is generated by `cahirc fetch`, it is not meant to be edited by hand.\n\n";
+
+/// The `cahirc.lock` file, it records what the remote dependencies resolved to
+/// so every build of the project uses the exact same code.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct Lockfile {
+  #[serde(default, rename = "dependency")]
+  pub dependencies: Vec<LockedDependency>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LockedDependency {
+  pub name: String,
+
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub git: Option<String>,
+
+  /// The revision as it is declared in the `cahirc.toml` file
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub rev: Option<String>,
+
+  /// The commit the revision resolved to
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub commit: Option<String>,
+
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub archive: Option<String>,
+
+  /// The hash of the archive, a different archive at the same url is refused
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub sha256: Option<String>
+}
+
+impl Lockfile {
+  /// Reads the lockfile of the project in the given directory, a missing
+  /// lockfile is an empty one.
+  pub fn read(project_directory: &Path) -> std::io::Result<Self> {
+    match std::fs::read_to_string(project_directory.join(LOCKFILE_NAME)) {
+      Ok(content) => Ok(toml::from_str(&content)?),
+      Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+      Err(error) => Err(error)
+    }
+  }
+
+  pub fn write(&self, project_directory: &Path) -> std::io::Result<()> {
+    let content = toml::to_string(self).map_err(std::io::Error::other)?;
+
+    std::fs::write(
+      project_directory.join(LOCKFILE_NAME),
+      format!("{LOCKFILE_HEADER}{content}")
+    )
+  }
+
+  /// Returns the locked dependency if it was locked from the same source.
+  pub fn get(&self, name: &str, source: &DependencySource) -> Option<&LockedDependency> {
+    self
+      .dependencies
+      .iter()
+      .find(|locked| locked.name == name && locked.is_locked_from(source))
+  }
+}
+
+impl LockedDependency {
+  fn is_locked_from(&self, source: &DependencySource) -> bool {
+    match source {
+      DependencySource::Path(_) => false,
+      DependencySource::Git { url, rev } => {
+        self.git.as_ref() == Some(url) && &self.rev == rev && self.commit.is_some()
+      }
+      DependencySource::Archive { url } => {
+        self.archive.as_ref() == Some(url) && self.sha256.is_some()
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_utils::TestDirectory;
+
+  fn git_dependency(rev: Option<&str>) -> LockedDependency {
+    LockedDependency {
+      name: "library".to_string(),
+      git: Some("https://example.com/library.git".to_string()),
+      rev: rev.map(String::from),
+      commit: Some("0123456789abcdef".to_string()),
+      archive: None,
+      sha256: None
+    }
+  }
+
+  fn git_source(rev: Option<&str>) -> DependencySource {
+    DependencySource::Git {
+      url: "https://example.com/library.git".to_string(),
+      rev: rev.map(String::from)
+    }
+  }
+
+  #[test]
+  fn missing_lockfile_is_empty() {
+    let directory = TestDirectory::new("lockfile");
+
+    assert!(Lockfile::read(&directory.path)
+      .unwrap()
+      .dependencies
+      .is_empty());
+  }
+
+  #[test]
+  fn lockfile_is_read_back() {
+    let directory = TestDirectory::new("lockfile");
+    let lockfile = Lockfile {
+      dependencies: vec![git_dependency(Some("v1"))]
+    };
+
+    lockfile.write(&directory.path).unwrap();
+    let content = std::fs::read_to_string(directory.path.join(LOCKFILE_NAME)).unwrap();
+    let read = Lockfile::read(&directory.path).unwrap();
+
+    assert!(content.starts_with(LOCKFILE_HEADER));
+    assert!(!content.contains("archive"));
+    assert_eq!(
+      read
+        .get("library", &git_source(Some("v1")))
+        .unwrap()
+        .commit
+        .as_deref(),
+      Some("0123456789abcdef")
+    );
+  }
+
+  #[test]
+  fn changed_declarations_are_not_locked() {
+    let lockfile = Lockfile {
+      dependencies: vec![git_dependency(Some("v1"))]
+    };
+
+    assert!(lockfile.get("library", &git_source(Some("v1"))).is_some());
+    assert!(lockfile.get("library", &git_source(Some("v2"))).is_none());
+    assert!(lockfile.get("library", &git_source(None)).is_none());
+    assert!(lockfile.get("other", &git_source(Some("v1"))).is_none());
+    assert!(lockfile
+      .get(
+        "library",
+        &DependencySource::Archive {
+          url: "https://example.com/library.git".to_string()
+        }
+      )
+      .is_none());
+  }
+}
@@ -0,0 +1,726 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+mod archive;
+mod git;
+pub mod lockfile;
+
+use self::lockfile::{LockedDependency, Lockfile};
+use crate::config::{
+  read_declarations_from, read_library_config_from, Config, DependencyDeclaration,
+  DependencySource, LibraryPackage
+};
+
+/// The dependencies of the project and of its dependencies, by name
+#[derive(Debug)]
+pub struct ResolvedDependencies {
+  pub source_directories: HashMap<String, String>,
+
+  /// The `[package]` section of the `cahirc.toml` file of the libraries
+  pub packages: BTreeMap<String, LibraryPackage>,
+
+  /// The names of the dependencies every library declares
+  pub library_dependencies: BTreeMap<String, BTreeSet<String>>
+}
+
+/// Returns the source directory of every dependency of the project, and of
+/// their own dependencies. The remote dependencies must have been fetched, they
+/// are found in the cache from what the lockfile recorded so the builds work
+/// offline.
+pub fn resolve(project_directory: &Path, config: &Config) -> std::io::Result<ResolvedDependencies> {
+  resolve_in(
+    project_directory,
+    config,
+    &cache_directory(project_directory)
+  )
+}
+
+fn resolve_in(
+  project_directory: &Path, config: &Config, cache_directory: &Path
+) -> std::io::Result<ResolvedDependencies> {
+  let lockfile = Lockfile::read(project_directory)?;
+
+  let graph = DependencyGraph::walk(project_directory, config, &mut |name, source| {
+    lockfile
+      .get(name, source)
+      .and_then(|locked| locked_directory(cache_directory, locked))
+      .filter(|directory| directory.exists())
+      .ok_or_else(|| {
+        error(format!(
+          "the dependency `{name}` is not fetched, run `cahirc fetch`"
+        ))
+      })
+  })?;
+
+  let mut resolved = ResolvedDependencies {
+    source_directories: graph.source_directories(),
+    packages: BTreeMap::new(),
+    library_dependencies: BTreeMap::new()
+  };
+
+  for (name, dependency) in graph.dependencies {
+    resolved.packages.insert(name.clone(), dependency.package);
+    resolved
+      .library_dependencies
+      .insert(name, dependency.dependencies);
+  }
+
+  Ok(resolved)
+}
+
+/// Downloads the remote dependencies of the project and of its dependencies in
+/// the cache, then writes the lockfile. The dependencies that are already
+/// locked are fetched again at the locked revision, a dependency is only
+/// updated once it is removed from the lockfile or its declaration changes.
+pub fn fetch(project_directory: &Path) -> std::io::Result<()> {
+  fetch_in(project_directory, &cache_directory(project_directory))
+}
+
+fn fetch_in(project_directory: &Path, cache_directory: &Path) -> std::io::Result<()> {
+  let config = read_declarations_from(project_directory)?;
+  let previous_lockfile = Lockfile::read(project_directory)?;
+  let mut lockfile = Lockfile::default();
+
+  std::fs::create_dir_all(cache_directory)?;
+
+  DependencyGraph::walk(project_directory, &config, &mut |name, source| {
+    let locked = previous_lockfile.get(name, source);
+
+    let locked_dependency = match source {
+      DependencySource::Path(_) => unreachable!("the local dependencies are not fetched"),
+      DependencySource::Git { url, rev } => {
+        let commit = match locked.and_then(|locked| locked.commit.clone()) {
+          Some(commit) if git::cache_path(cache_directory, url, &commit).exists() => commit,
+          Some(commit) => git::fetch(cache_directory, url, Some(&commit))?,
+          None => git::fetch(cache_directory, url, rev.as_deref())?
+        };
+
+        eprintln!("{name}: {url} at {commit}");
+
+        LockedDependency {
+          name: name.to_string(),
+          git: Some(url.clone()),
+          rev: rev.clone(),
+          commit: Some(commit),
+          archive: None,
+          sha256: None
+        }
+      }
+      DependencySource::Archive { url } => {
+        let sha256 = match locked.and_then(|locked| locked.sha256.clone()) {
+          Some(sha256) if archive::cache_path(cache_directory, &sha256).exists() => sha256,
+          expected_sha256 => archive::fetch(cache_directory, url, expected_sha256.as_deref())?
+        };
+
+        eprintln!("{name}: {url} with the sha256 {sha256}");
+
+        LockedDependency {
+          name: name.to_string(),
+          git: None,
+          rev: None,
+          commit: None,
+          archive: Some(url.clone()),
+          sha256: Some(sha256)
+        }
+      }
+    };
+
+    let directory = locked_directory(cache_directory, &locked_dependency).unwrap();
+    lockfile.dependencies.push(locked_dependency);
+
+    Ok(directory)
+  })?;
+
+  lockfile.dependencies.sort_by(|a, b| a.name.cmp(&b.name));
+  lockfile.write(project_directory)
+}
+
+/// A library of the dependency graph
+struct ResolvedDependency {
+  /// The source it was declared with, the paths and the local archives are
+  /// absolute so the same library declared by two other libraries has the same
+  /// source.
+  source: DependencySource,
+
+  /// The library or the project that first declared it
+  required_by: String,
+
+  /// The version the libraries that declared it require, the first one to
+  /// require one sets it.
+  required_version: Option<RequiredVersion>,
+  package: LibraryPackage,
+  source_directory: PathBuf,
+
+  /// The names of the dependencies it declares
+  dependencies: BTreeSet<String>
+}
+
+struct RequiredVersion {
+  version: String,
+  required_by: String
+}
+
+/// The dependencies of the project and their own dependencies, each library is
+/// only present once even when several others depend on it.
+struct DependencyGraph {
+  dependencies: BTreeMap<String, ResolvedDependency>
+}
+
+impl DependencyGraph {
+  /// Walks the graph from the project, `locate` returns the directory of a
+  /// remote dependency.
+  fn walk(
+    project_directory: &Path, config: &Config,
+    locate: &mut dyn FnMut(&str, &DependencySource) -> std::io::Result<PathBuf>
+  ) -> std::io::Result<Self> {
+    let mut graph = Self {
+      dependencies: BTreeMap::new()
+    };
+
+    let mut chain = vec![config.package.name.clone()];
+
+    graph.visit(
+      &canonical_path(project_directory),
+      project_directory,
+      &config.dependency_declarations,
+      &mut chain,
+      locate
+    )?;
+
+    Ok(graph)
+  }
+
+  fn visit(
+    &mut self, project_directory: &Path, declaring_directory: &Path,
+    declarations: &BTreeMap<String, DependencyDeclaration>, chain: &mut Vec<String>,
+    locate: &mut dyn FnMut(&str, &DependencySource) -> std::io::Result<PathBuf>
+  ) -> std::io::Result<()> {
+    for (name, declaration) in declarations {
+      let source = match dependency_source(name, declaration)? {
+        DependencySource::Path(path) => DependencySource::Path(
+          canonical_path(&declaring_directory.join(path))
+            .to_string_lossy()
+            .to_string()
+        ),
+        DependencySource::Archive { url } => match archive::local_path(&url) {
+          Some(path) => DependencySource::Archive {
+            url: canonical_path(&declaring_directory.join(path))
+              .to_string_lossy()
+              .to_string()
+          },
+          None => DependencySource::Archive { url }
+        },
+        source => source
+      };
+
+      if chain[1..].contains(name) {
+        return Err(error(format!(
+          "cyclic dependencies: {} -> {name}",
+          chain.join(" -> ")
+        )));
+      }
+
+      let required_by = chain.last().unwrap();
+      let required_version = declaration.version();
+
+      if let Some(existing) = self.dependencies.get_mut(name) {
+        if existing.source != source {
+          return Err(error(format!(
+            "conflicting dependencies `{name}`: `{}` requires {} but `{required_by}` requires {source}",
+            existing.required_by, existing.source
+          )));
+        }
+
+        if let Some(version) = required_version {
+          match &existing.required_version {
+            Some(existing_version) if existing_version.version != version => {
+              return Err(error(format!(
+                "conflicting versions of `{name}`: `{}` requires the version {} but `{required_by}` requires the version {version}",
+                existing_version.required_by, existing_version.version
+              )));
+            }
+            Some(_) => {}
+            None => {
+              check_version(name, &existing.package, version, required_by)?;
+
+              existing.required_version = Some(RequiredVersion {
+                version: version.to_string(),
+                required_by: required_by.clone()
+              });
+            }
+          }
+        }
+
+        // the library is shared with the libraries that already required it
+        continue;
+      }
+
+      let directory = match &source {
+        DependencySource::Path(path) => PathBuf::from(path),
+        _ => locate(name, &source)?
+      };
+
+      if canonical_path(&directory) == project_directory {
+        return Err(error(format!(
+          "cyclic dependencies: {} -> {name}, which is the project itself",
+          chain.join(" -> ")
+        )));
+      }
+
+      let library_config = read_library_config_from(&directory).map_err(|err| {
+        error(format!(
+          "could not read the cahirc.toml file of the dependency `{name}`: {err}"
+        ))
+      })?;
+
+      if let Some(library_name) = &library_config.package.name {
+        if let Some((other_name, other)) = self
+          .dependencies
+          .iter()
+          .find(|(_, other)| other.package.name.as_ref() == Some(library_name))
+        {
+          let versions = match (&other.package.version, &library_config.package.version) {
+            (Some(other_version), Some(version)) if other_version != version => {
+              format!(", at the versions {other_version} and {version}")
+            }
+            _ => String::new()
+          };
+
+          return Err(error(format!(
+            "conflicting dependencies: the library `{library_name}` is required as `{other_name}` and as `{name}`{versions}"
+          )));
+        }
+      }
+
+      if let Some(version) = required_version {
+        check_version(name, &library_config.package, version, required_by)?;
+      }
+
+      let source_directory = match &library_config.package.src {
+        Some(src) => directory.join(src),
+        None => directory.clone()
+      };
+
+      self.dependencies.insert(
+        name.clone(),
+        ResolvedDependency {
+          source,
+          required_by: required_by.clone(),
+          required_version: required_version.map(|version| RequiredVersion {
+            version: version.to_string(),
+            required_by: required_by.clone()
+          }),
+          package: library_config.package.clone(),
+          source_directory,
+          dependencies: library_config
+            .dependency_declarations
+            .keys()
+            .cloned()
+            .collect()
+        }
+      );
+
+      chain.push(name.clone());
+      self.visit(
+        project_directory,
+        &directory,
+        &library_config.dependency_declarations,
+        chain,
+        locate
+      )?;
+      chain.pop();
+    }
+
+    Ok(())
+  }
+
+  fn source_directories(&self) -> HashMap<String, String> {
+    self
+      .dependencies
+      .iter()
+      .map(|(name, dependency)| {
+        (
+          name.clone(),
+          dependency.source_directory.to_str().unwrap().to_string()
+        )
+      })
+      .collect()
+  }
+}
+
+fn dependency_source(
+  name: &str, declaration: &DependencyDeclaration
+) -> std::io::Result<DependencySource> {
+  declaration
+    .source()
+    .map_err(|message| error(format!("invalid dependency `{name}`: {message}")))
+}
+
+/// Checks the library has the version the declaration requires.
+fn check_version(
+  name: &str, package: &LibraryPackage, version: &str, required_by: &str
+) -> std::io::Result<()> {
+  match &package.version {
+    Some(library_version) if library_version == version => Ok(()),
+    Some(library_version) => Err(error(format!(
+      "`{required_by}` requires the version {version} of `{name}` but the library is at the version {library_version}"
+    ))),
+    None => Err(error(format!(
+      "`{required_by}` requires the version {version} of `{name}` but the library has no version"
+    )))
+  }
+}
+
+fn locked_directory(cache_directory: &Path, locked: &LockedDependency) -> Option<PathBuf> {
+  match (&locked.git, &locked.commit, &locked.sha256) {
+    (Some(url), Some(commit), _) => Some(git::cache_path(cache_directory, url, commit)),
+    (None, _, Some(sha256)) => Some(archive::cache_path(cache_directory, sha256)),
+    _ => None
+  }
+}
+
+/// Returns the directory where the remote dependencies are stored, it is set
+/// with the `CAHIRC_CACHE` environment variable and is in the home directory of
+/// the user otherwise.
+fn cache_directory(project_directory: &Path) -> PathBuf {
+  if let Some(directory) = std::env::var_os("CAHIRC_CACHE") {
+    return PathBuf::from(directory);
+  }
+
+  match std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")) {
+    Some(home) => PathBuf::from(home).join(".cahirc").join("cache"),
+    None => project_directory.join(".cahirc").join("cache")
+  }
+}
+
+/// Moves the fetched files to their directory in the cache, unless they are
+/// already there.
+fn move_into_cache(from: &Path, to: &Path) -> std::io::Result<()> {
+  if to.exists() {
+    return Ok(());
+  }
+
+  if let Some(parent) = to.parent() {
+    std::fs::create_dir_all(parent)?;
+  }
+
+  std::fs::rename(from, to)
+}
+
+fn canonical_path(path: &Path) -> PathBuf {
+  dunce::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn error(message: String) -> std::io::Error {
+  std::io::Error::other(message)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_utils::TestDirectory;
+
+  struct Project {
+    directory: TestDirectory,
+    cache: TestDirectory,
+    repository: TestDirectory,
+    archives: TestDirectory
+  }
+
+  /// Returns a project that depends on a git repository and on a tar archive.
+  fn project() -> Project {
+    let repository = TestDirectory::new("dependency-repository");
+    repository.git(&["init", "--quiet"]);
+    repository.file("src/git_library.wss", "function git_library() {}\n");
+    repository.file("cahirc.toml", "[package]\nsrc = \"src\"\n");
+    repository.git(&["add", "."]);
+    repository.git(&["commit", "--quiet", "-m", "first"]);
+
+    let archives = TestDirectory::new("dependency-archives");
+    let mut builder = tar::Builder::new(Vec::new());
+    builder
+      .append_path_with_name(
+        repository.path.join("src/git_library.wss"),
+        "archive_library.wss"
+      )
+      .unwrap();
+    std::fs::write(
+      archives.path.join("library.tar"),
+      builder.into_inner().unwrap()
+    )
+    .unwrap();
+
+    let directory = TestDirectory::new("dependency-project");
+    directory.file(
+      "cahirc.toml",
+      &format!(
+        "[package]\nname = \"project\"\nsrc = \"src\"\ndist = \"dist\"\n\n[dependencies]\ngit_library = {{ git = \"{}\" }}\narchive_library = {{ archive = \"{}/library.tar\" }}\n",
+        repository.url(),
+        archives.url()
+      )
+    );
+
+    Project {
+      directory,
+      cache: TestDirectory::new("dependency-cache"),
+      repository,
+      archives
+    }
+  }
+
+  impl Project {
+    fn fetch(&self) -> std::io::Result<()> {
+      fetch_in(&self.directory.path, &self.cache.path)
+    }
+
+    fn resolve(&self) -> std::io::Result<HashMap<String, String>> {
+      let config = read_declarations_from(&self.directory.path)?;
+
+      resolve_in(&self.directory.path, &config, &self.cache.path)
+        .map(|resolved| resolved.source_directories)
+    }
+  }
+
+  #[test]
+  fn fetched_dependencies_are_resolved_from_the_cache() {
+    let project = project();
+
+    project.fetch().unwrap();
+    let dependencies = project.resolve().unwrap();
+
+    assert!(Path::new(&dependencies["git_library"])
+      .join("git_library.wss")
+      .exists());
+    assert!(Path::new(&dependencies["archive_library"])
+      .join("archive_library.wss")
+      .exists());
+
+    let lockfile = Lockfile::read(&project.directory.path).unwrap();
+    let names: Vec<&str> = lockfile
+      .dependencies
+      .iter()
+      .map(|locked| locked.name.as_str())
+      .collect();
+
+    assert_eq!(names, vec!["archive_library", "git_library"]);
+    assert_eq!(
+      lockfile.dependencies[1].commit,
+      Some(project.repository.git(&["rev-parse", "HEAD"]))
+    );
+  }
+
+  #[test]
+  fn dependencies_must_be_fetched() {
+    let project = project();
+    let error = project.resolve().unwrap_err();
+
+    assert!(error.to_string().contains("run `cahirc fetch`"), "{error}");
+  }
+
+  #[test]
+  fn locked_commits_are_kept() {
+    let project = project();
+    project.fetch().unwrap();
+    let locked_commit = project.repository.git(&["rev-parse", "HEAD"]);
+
+    project
+      .repository
+      .file("src/git_library.wss", "function updated() {}\n");
+    project
+      .repository
+      .git(&["commit", "--quiet", "-am", "second"]);
+
+    // the cache is emptied too, the locked commit is cloned again
+    std::fs::remove_dir_all(&project.cache.path).unwrap();
+    project.fetch().unwrap();
+
+    let lockfile = Lockfile::read(&project.directory.path).unwrap();
+    let dependencies = project.resolve().unwrap();
+
+    assert_eq!(lockfile.dependencies[1].commit, Some(locked_commit));
+    assert_eq!(
+      std::fs::read_to_string(Path::new(&dependencies["git_library"]).join("git_library.wss"))
+        .unwrap(),
+      "function git_library() {}\n"
+    );
+  }
+
+  #[test]
+  fn changed_archives_are_refused() {
+    let project = project();
+    project.fetch().unwrap();
+
+    std::fs::write(project.archives.path.join("library.tar"), b"").unwrap();
+    std::fs::remove_dir_all(&project.cache.path).unwrap();
+
+    let error = project.fetch().unwrap_err();
+
+    assert!(
+      error.to_string().contains("changed since it was locked"),
+      "{error}"
+    );
+  }
+
+  #[test]
+  fn local_archives_are_relative_to_their_declaration() {
+    let project = project();
+    let library = TestDirectory::new("archive-declaring-library");
+
+    std::fs::copy(
+      project.archives.path.join("library.tar"),
+      library.path.join("nested.tar")
+    )
+    .unwrap();
+    library.file(
+      "cahirc.toml",
+      "[package]\nname = \"library\"\n\n[dependencies]\nnested = { archive = \"nested.tar\" }\n"
+    );
+    std::fs::copy(
+      project.archives.path.join("library.tar"),
+      project.directory.path.join("library.tar")
+    )
+    .unwrap();
+    project.directory.file(
+      "cahirc.toml",
+      &format!(
+        "[package]\nname = \"project\"\nsrc = \"src\"\ndist = \"dist\"\n\n[dependencies]\narchive_library = {{ archive = \"library.tar\" }}\nlibrary = {{ path = \"{}\" }}\n",
+        library.path.to_str().unwrap().replace('\\', "/")
+      )
+    );
+
+    // the tests run from the directory of the crate, not from the project
+    assert_ne!(
+      canonical_path(&std::env::current_dir().unwrap()),
+      canonical_path(&project.directory.path)
+    );
+
+    project.fetch().unwrap();
+    let dependencies = project.resolve().unwrap();
+
+    for name in ["archive_library", "nested"] {
+      assert!(
+        Path::new(&dependencies[name])
+          .join("archive_library.wss")
+          .exists(),
+        "{name} was not extracted"
+      );
+    }
+  }
+
+  /// Returns a project with the dependencies, and the libraries with their
+  /// `cahirc.toml` files, the dependencies are the ones of the project.
+  fn path_project(dependencies: &str, libraries: &[(&str, &str)]) -> TestDirectory {
+    let directory = TestDirectory::new("versions");
+
+    directory.file(
+      "project/cahirc.toml",
+      &format!(
+        "[package]\nname = \"project\"\nsrc = \"src\"\ndist = \"dist\"\n\n[dependencies]\n{dependencies}"
+      )
+    );
+
+    for (name, config) in libraries {
+      directory.file(&format!("{name}/cahirc.toml"), config);
+    }
+
+    directory
+  }
+
+  fn resolve_path_project(directory: &TestDirectory) -> std::io::Result<ResolvedDependencies> {
+    let project_directory = directory.path.join("project");
+    let config = read_declarations_from(&project_directory)?;
+
+    resolve_in(&project_directory, &config, &directory.path.join("cache"))
+  }
+
+  #[test]
+  fn required_versions_are_checked() {
+    let directory = path_project(
+      "shared = { path = \"../shared\", version = \"2.0\" }\n",
+      &[(
+        "shared",
+        "[package]\nname = \"shared\"\nversion = \"1.0\"\n"
+      )]
+    );
+
+    let error = resolve_path_project(&directory).unwrap_err().to_string();
+
+    assert!(
+      error.contains("requires the version 2.0 of `shared` but the library is at the version 1.0"),
+      "{error}"
+    );
+  }
+
+  #[test]
+  fn libraries_requiring_different_versions_conflict() {
+    let directory = path_project(
+      "a = \"../a\"\nb = \"../b\"\n",
+      &[
+        (
+          "a",
+          "[dependencies]\nshared = { path = \"../shared\", version = \"1.0\" }\n"
+        ),
+        (
+          "b",
+          "[dependencies]\nshared = { path = \"../shared\", version = \"2.0\" }\n"
+        ),
+        ("shared", "[package]\nversion = \"1.0\"\n")
+      ]
+    );
+
+    let error = resolve_path_project(&directory).unwrap_err().to_string();
+
+    assert!(
+      error.contains("conflicting versions of `shared`: `a` requires the version 1.0 but `b` requires the version 2.0"),
+      "{error}"
+    );
+  }
+
+  #[test]
+  fn shared_libraries_keep_their_package() {
+    let directory = path_project(
+      "a = \"../a\"\nshared = { path = \"../shared\", version = \"1.0\" }\n",
+      &[
+        (
+          "a",
+          "[dependencies]\nshared = { path = \"../shared\", version = \"1.0\" }\n"
+        ),
+        (
+          "shared",
+          "[package]\nname = \"shared\"\nversion = \"1.0\"\ndefines = { LEVEL = 2 }\n"
+        )
+      ]
+    );
+
+    let resolved = resolve_path_project(&directory).unwrap();
+    let shared = &resolved.packages["shared"];
+
+    assert_eq!(resolved.packages.len(), 2);
+    assert_eq!(
+      resolved.library_dependencies["a"],
+      BTreeSet::from(["shared".to_string()])
+    );
+    assert_eq!(shared.version.as_deref(), Some("1.0"));
+    assert_eq!(shared.macro_defines()["LEVEL"], "2");
+  }
+
+  #[test]
+  fn same_library_at_two_versions_conflicts() {
+    let directory = path_project(
+      "old = \"../old\"\nnew = \"../new\"\n",
+      &[
+        ("old", "[package]\nname = \"shared\"\nversion = \"1.0\"\n"),
+        ("new", "[package]\nname = \"shared\"\nversion = \"2.0\"\n")
+      ]
+    );
+
+    let error = resolve_path_project(&directory).unwrap_err().to_string();
+
+    assert!(
+      error.contains(
+        "the library `shared` is required as `new` and as `old`, at the versions 2.0 and 1.0"
+      ),
+      "{error}"
+    );
+  }
+}
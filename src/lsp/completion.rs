@@ -0,0 +1,235 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use lsp_types::{CompletionItem, CompletionItemKind};
+
+use crate::ast::codegen::type_inference::{FunctionInferedType, InferedType, TypeInferenceStore};
+use crate::ast::{Context, ExpressionBody};
+
+/// A part of the expression whose members are completed, `foo` and `bar()` in
+/// `foo.bar().`
+pub enum Segment {
+  Identifier(String),
+  Call(String)
+}
+
+/// Returns the segments of the expression before the dot that precedes the
+/// offset. The part of the member name that was already typed after the dot is
+/// ignored.
+pub fn receiver_before(content: &str, offset: usize) -> Option<Vec<Segment>> {
+  let bytes = content.as_bytes();
+  let mut end = offset.min(content.len());
+  let mut segments = Vec::new();
+
+  end = skip_identifier(bytes, end);
+
+  while end > 0 && bytes[end - 1] == b'.' {
+    end -= 1;
+
+    let mut is_call = false;
+    if end > 0 && bytes[end - 1] == b')' {
+      let mut depth = 0;
+
+      loop {
+        if end == 0 {
+          return None;
+        }
+
+        end -= 1;
+        match bytes[end] {
+          b')' => depth += 1,
+          b'(' => {
+            depth -= 1;
+
+            if depth == 0 {
+              break;
+            }
+          }
+          _ => {}
+        };
+      }
+
+      is_call = true;
+    }
+
+    let name_end = end;
+    end = skip_identifier(bytes, end);
+
+    if end == name_end {
+      return None;
+    }
+
+    let name = content[end..name_end].to_string();
+    segments.push(match is_call {
+      true => Segment::Call(name),
+      false => Segment::Identifier(name)
+    });
+  }
+
+  if segments.is_empty() {
+    return None;
+  }
+
+  segments.reverse();
+
+  Some(segments)
+}
+
+fn skip_identifier(bytes: &[u8], mut end: usize) -> usize {
+  while end > 0 && (bytes[end - 1].is_ascii_alphanumeric() || bytes[end - 1] == b'_') {
+    end -= 1;
+  }
+
+  end
+}
+
+/// Returns the stringified type the segments result in when they are used from
+/// the given context.
+pub fn resolve_receiver(
+  segments: &[Segment], context: &Rc<RefCell<Context>>, inference_store: &TypeInferenceStore
+) -> Option<String> {
+  let mut segments = segments.iter();
+
+  let mut type_name = match segments.next()? {
+    Segment::Identifier(name) if name == "this" => {
+      ExpressionBody::get_type_for_this(context, &inference_store.types)
+        .ok()?
+        .to_string()
+    }
+    Segment::Identifier(name) if name == "parent" => {
+      ExpressionBody::get_type_for_parent(context, &inference_store.types)
+        .ok()?
+        .to_string()
+    }
+    Segment::Identifier(name) => Context::find_variable_type(context, name)?,
+    Segment::Call(name) => match inference_store.types.get(name)?.as_ref() {
+      InferedType::Function(function) => function.return_type.clone()?,
+      _ => return None
+    }
+  };
+
+  for segment in segments {
+    let compound_chain = compound_chain(&type_name, inference_store);
+
+    type_name = match segment {
+      Segment::Identifier(name) => compound_chain.iter().find_map(|(compound_name, _)| {
+        let compound_context = Context::find_compound_declaration(context, compound_name)?;
+        let field_type = Context::get_ref(&compound_context)
+          .get_variable_type_string(name)
+          .cloned();
+
+        field_type
+      })?,
+      Segment::Call(name) => {
+        compound_chain
+          .iter()
+          .find_map(|(_, compound)| match compound.as_ref() {
+            InferedType::Compound {
+              type_inference_map,
+              extends: _
+            } => match type_inference_map.borrow().get(name).map(Rc::as_ref) {
+              Some(InferedType::Function(function)) => function.return_type.clone(),
+              _ => None
+            },
+            _ => None
+          })?
+      }
+    };
+  }
+
+  Some(type_name)
+}
+
+/// Returns the methods and the properties of the compound type, including the
+/// ones it inherits.
+pub fn members(
+  type_name: &str, context: &Rc<RefCell<Context>>, inference_store: &TypeInferenceStore
+) -> Vec<CompletionItem> {
+  let mut labels = HashSet::new();
+  let mut items = Vec::new();
+
+  for (compound_name, compound) in compound_chain(type_name, inference_store) {
+    if let InferedType::Compound {
+      type_inference_map,
+      extends: _
+    } = compound.as_ref()
+    {
+      for (name, infered_type) in type_inference_map.borrow().iter() {
+        if let InferedType::Function(function) = infered_type.as_ref() {
+          if labels.insert(name.clone()) {
+            items.push(CompletionItem {
+              label: name.clone(),
+              kind: Some(CompletionItemKind::METHOD),
+              detail: Some(signature(name, function)),
+              ..Default::default()
+            });
+          }
+        }
+      }
+    }
+
+    if let Some(compound_context) = Context::find_compound_declaration(context, &compound_name) {
+      for (name, field_type) in &Context::get_ref(&compound_context).local_variables_inference {
+        if labels.insert(name.clone()) {
+          items.push(CompletionItem {
+            label: name.clone(),
+            kind: Some(CompletionItemKind::FIELD),
+            detail: Some(field_type.clone()),
+            ..Default::default()
+          });
+        }
+      }
+    }
+  }
+
+  items.sort_by(|a, b| a.label.cmp(&b.label));
+
+  items
+}
+
+/// Returns the compound type and the types it extends, from the child to the
+/// base type.
+fn compound_chain(
+  type_name: &str, inference_store: &TypeInferenceStore
+) -> Vec<(String, Rc<InferedType>)> {
+  let mut output: Vec<(String, Rc<InferedType>)> = Vec::new();
+  let mut current = Some(type_name.to_string());
+
+  while let Some(name) = current {
+    // a type extending itself would loop forever
+    if output.iter().any(|(existing, _)| existing == &name) {
+      break;
+    }
+
+    let infered_type = match inference_store.types.get(&name) {
+      Some(infered_type) => infered_type.clone(),
+      None => break
+    };
+
+    current = match infered_type.as_ref() {
+      InferedType::Compound {
+        type_inference_map: _,
+        extends
+      } => extends.clone(),
+      _ => None
+    };
+
+    output.push((name, infered_type));
+  }
+
+  output
+}
+
+fn signature(name: &str, function: &FunctionInferedType) -> String {
+  let parameters: Vec<&str> = function
+    .parameters
+    .iter()
+    .map(|parameter| parameter.infered_type.as_str())
+    .collect();
+
+  match &function.return_type {
+    Some(return_type) => format!("function {name}({}): {return_type}", parameters.join(", ")),
+    None => format!("function {name}({})", parameters.join(", "))
+  }
+}
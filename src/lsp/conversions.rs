@@ -0,0 +1,66 @@
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use lsp_types::{Position, Url};
+
+/// Converts a byte offset in the content into a LSP position, whose character
+/// is counted in UTF-16 code units.
+pub fn offset_to_position(content: &str, offset: usize) -> Position {
+  let mut offset = offset.min(content.len());
+  while !content.is_char_boundary(offset) {
+    offset -= 1;
+  }
+
+  let before = &content[..offset];
+  let line_start = before.rfind('\n').map(|index| index + 1).unwrap_or(0);
+
+  Position {
+    line: before.matches('\n').count() as u32,
+    character: before[line_start..].encode_utf16().count() as u32
+  }
+}
+
+/// Converts a LSP position into a byte offset in the content, a position past
+/// the end of a line is moved to the end of the line.
+pub fn position_to_offset(content: &str, position: Position) -> usize {
+  let mut line_start = 0;
+
+  for _ in 0..position.line {
+    match content[line_start..].find('\n') {
+      Some(index) => line_start += index + 1,
+      None => return content.len()
+    };
+  }
+
+  let mut character = 0;
+  for (index, c) in content[line_start..].char_indices() {
+    if c == '\n' || character >= position.character as usize {
+      return line_start + index;
+    }
+
+    character += c.len_utf16();
+  }
+
+  content.len()
+}
+
+pub fn offsets_to_range(content: &str, range: Range<usize>) -> lsp_types::Range {
+  lsp_types::Range {
+    start: offset_to_position(content, range.start),
+    end: offset_to_position(content, range.end)
+  }
+}
+
+/// Returns the canonical form of the path so the paths from the client and the
+/// ones from the compiler can be compared.
+pub fn canonical_path(path: &Path) -> PathBuf {
+  dunce::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+pub fn path_to_url(path: &str) -> Option<Url> {
+  if path.is_empty() {
+    return None;
+  }
+
+  Url::from_file_path(canonical_path(Path::new(path))).ok()
+}
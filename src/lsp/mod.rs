@@ -0,0 +1,599 @@
+//! The language server, started with `cahirc lsp`. It communicates over stdio
+//! and analyses the whole project again every time a file is saved.
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::ops::Range;
+use std::panic::AssertUnwindSafe;
+use std::path::PathBuf;
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+  DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, DidSaveTextDocument,
+  Notification as _, PublishDiagnostics
+};
+use lsp_types::request::{Completion, GotoDefinition, HoverRequest, Request as _};
+use lsp_types::{
+  CompletionOptions, CompletionParams, CompletionResponse, DiagnosticSeverity,
+  DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+  GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents, HoverParams,
+  HoverProviderCapability, InitializeParams, Location, MarkupContent, MarkupKind, OneOf,
+  PublishDiagnosticsParams, ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind,
+  TextDocumentSyncOptions, TextDocumentSyncSaveOptions, Url
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::ast::codegen::type_inference::InferedType;
+use crate::ast::inference::Type;
+use crate::ast::visitor::{PositionVisitor, Visited};
+use crate::ast::{Diagnostic, DiagnosticKind, Span};
+use crate::compiler::{self, Compilation, ParsedFile, StrippedLines};
+use crate::config::read_config_from;
+use crate::preprocessor::types::ProcessedFile;
+
+mod completion;
+mod conversions;
+
+use conversions::*;
+
+pub fn run() -> Result<(), Box<dyn Error + Sync + Send>> {
+  let (connection, io_threads) = Connection::stdio();
+
+  let capabilities = serde_json::to_value(ServerCapabilities {
+    text_document_sync: Some(TextDocumentSyncCapability::Options(
+      TextDocumentSyncOptions {
+        open_close: Some(true),
+        change: Some(TextDocumentSyncKind::FULL),
+        save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+        ..Default::default()
+      }
+    )),
+    hover_provider: Some(HoverProviderCapability::Simple(true)),
+    definition_provider: Some(OneOf::Left(true)),
+    completion_provider: Some(CompletionOptions {
+      trigger_characters: Some(vec![".".to_string()]),
+      ..Default::default()
+    }),
+    ..Default::default()
+  })?;
+
+  let params: InitializeParams = serde_json::from_value(connection.initialize(capabilities)?)?;
+  let root = match params.root_uri.and_then(|uri| uri.to_file_path().ok()) {
+    Some(root) => root,
+    None => std::env::current_dir()?
+  };
+
+  let mut server = Server::new(root);
+  server.main_loop(&connection)?;
+
+  io_threads.join()?;
+
+  Ok(())
+}
+
+struct Server {
+  /// The directory with the cahirc.toml file
+  root: PathBuf,
+
+  /// The result of the last analysis that went through
+  compilation: Option<Compilation>,
+
+  /// The content of the opened documents, as they are in the editor
+  documents: HashMap<Url, String>,
+
+  /// The documents the last analysis published diagnostics for, they are
+  /// cleared by the next one if they no longer have any.
+  published_documents: HashSet<Url>
+}
+
+impl Server {
+  fn new(root: PathBuf) -> Self {
+    Self {
+      root,
+      compilation: None,
+      documents: HashMap::new(),
+      published_documents: HashSet::new()
+    }
+  }
+
+  fn main_loop(&mut self, connection: &Connection) -> Result<(), Box<dyn Error + Sync + Send>> {
+    for message in &connection.receiver {
+      match message {
+        Message::Request(request) => {
+          if connection.handle_shutdown(&request)? {
+            return Ok(());
+          }
+
+          let response = self.handle_request(request);
+          connection.sender.send(Message::Response(response))?;
+        }
+        Message::Notification(notification) => {
+          for notification in self.handle_notification(notification) {
+            connection
+              .sender
+              .send(Message::Notification(notification))?;
+          }
+        }
+        Message::Response(_) => {}
+      };
+    }
+
+    Ok(())
+  }
+
+  fn handle_request(&mut self, request: Request) -> Response {
+    match request.method.as_str() {
+      HoverRequest::METHOD => respond(request, |params| self.hover(params)),
+      GotoDefinition::METHOD => respond(request, |params| self.definition(params)),
+      Completion::METHOD => respond(request, |params| self.completion(params)),
+      _ => Response::new_err(
+        request.id,
+        ErrorCode::MethodNotFound as i32,
+        format!("unsupported request {}", request.method)
+      )
+    }
+  }
+
+  /// Handles the notification and returns the ones to send back to the client.
+  fn handle_notification(&mut self, notification: Notification) -> Vec<Notification> {
+    match notification.method.as_str() {
+      DidOpenTextDocument::METHOD => {
+        if let Ok(params) = serde_json::from_value::<DidOpenTextDocumentParams>(notification.params)
+        {
+          self
+            .documents
+            .insert(params.text_document.uri, params.text_document.text);
+        }
+
+        self.analyse()
+      }
+      DidChangeTextDocument::METHOD => {
+        if let Ok(params) =
+          serde_json::from_value::<DidChangeTextDocumentParams>(notification.params)
+        {
+          if let Some(change) = params.content_changes.into_iter().last() {
+            self.documents.insert(params.text_document.uri, change.text);
+          }
+        }
+
+        Vec::new()
+      }
+      DidCloseTextDocument::METHOD => {
+        if let Ok(params) =
+          serde_json::from_value::<DidCloseTextDocumentParams>(notification.params)
+        {
+          self.documents.remove(&params.text_document.uri);
+        }
+
+        Vec::new()
+      }
+      DidSaveTextDocument::METHOD => self.analyse(),
+      _ => Vec::new()
+    }
+  }
+
+  /// Analyses the project from the files on the disk, and returns the
+  /// diagnostics to publish. The result of the previous analysis is kept if the
+  /// new one could not finish.
+  fn analyse(&mut self) -> Vec<Notification> {
+    let config = match read_config_from(&self.root) {
+      Ok(config) => config,
+      Err(error) => {
+        eprintln!("could not read the cahirc.toml file: {error}");
+
+        return Vec::new();
+      }
+    };
+
+    let result = std::panic::catch_unwind(AssertUnwindSafe(|| compiler::analyse(&config, true)));
+
+    match result {
+      Ok(Ok(compilation)) => {
+        self.compilation = Some(compilation);
+
+        self.publish_diagnostics()
+      }
+      Ok(Err(error)) => {
+        eprintln!("could not analyse the project: {error}");
+
+        Vec::new()
+      }
+      Err(_) => {
+        eprintln!("the analysis of the project was interrupted");
+
+        Vec::new()
+      }
+    }
+  }
+
+  fn publish_diagnostics(&mut self) -> Vec<Notification> {
+    let compilation = match &self.compilation {
+      Some(compilation) => compilation,
+      None => return Vec::new()
+    };
+
+    let span_manager = &compilation.span_manager;
+    let preprocessed_content = &compilation.preprocessed_content;
+
+    // only the files of the project and its dependencies get diagnostics, not
+    // the vanilla scripts.
+    let mut urls = HashMap::new();
+    let filenames = preprocessed_content.source_files_content.keys().chain(
+      preprocessed_content
+        .dependencies_files_content
+        .values()
+        .flat_map(|files| files.keys())
+    );
+
+    for filename in filenames {
+      if let Some(url) = path_to_url(filename) {
+        urls.insert(filename.as_str(), url);
+      }
+    }
+
+    let mut documents: HashMap<Url, Vec<lsp_types::Diagnostic>> = HashMap::new();
+    for url in urls.values() {
+      documents.insert(url.clone(), Vec::new());
+    }
+
+    for url in self.published_documents.drain() {
+      documents.entry(url).or_default();
+    }
+
+    for diagnostic in compilation.report_manager.diagnostics() {
+      let source = span_manager.get_source(&diagnostic.span);
+
+      if let Some(url) = urls.get(source.as_str()) {
+        if let Some(diagnostics) = documents.get_mut(url) {
+          diagnostics.push(to_lsp_diagnostic(diagnostic, compilation));
+        }
+      }
+    }
+
+    self.published_documents = documents
+      .iter()
+      .filter(|(_, diagnostics)| !diagnostics.is_empty())
+      .map(|(url, _)| url.clone())
+      .collect();
+
+    documents
+      .into_iter()
+      .map(|(uri, diagnostics)| {
+        Notification::new(
+          PublishDiagnostics::METHOD.to_string(),
+          PublishDiagnosticsParams {
+            uri,
+            diagnostics,
+            version: None
+          }
+        )
+      })
+      .collect()
+  }
+
+  fn hover(&self, params: HoverParams) -> Option<Hover> {
+    let position = params.text_document_position_params;
+    let (compilation, parsed_file) = self.find_parsed_file(&position.text_document.uri)?;
+    let offsets = SourceOffsets::new(compilation, &parsed_file.filename)?;
+
+    let mut visitor = PositionVisitor::new(
+      &parsed_file.filename,
+      offsets.parsed_offset(position_to_offset(offsets.original, position.position)),
+      &compilation.span_manager
+    );
+    parsed_file.ast.accept(&mut visitor);
+
+    let expression = visitor.expression?;
+    let type_name = match (&expression.type_name, expression.infered_type.as_ref()) {
+      (Type::Unknown, _) => return None,
+      (Type::Identifier(name), InferedType::Lambda(_)) => format!("lambda {name}"),
+      (type_name, _) => type_name.to_string()
+    };
+
+    Some(Hover {
+      contents: HoverContents::Markup(MarkupContent {
+        kind: MarkupKind::Markdown,
+        value: format!("```witcherscript\n{type_name}\n```")
+      }),
+      range: Some(offsets.original_range(expression.range))
+    })
+  }
+
+  fn definition(&self, params: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+    let position = params.text_document_position_params;
+    let (compilation, parsed_file) = self.find_parsed_file(&position.text_document.uri)?;
+    let offsets = SourceOffsets::new(compilation, &parsed_file.filename)?;
+
+    let mut visitor = PositionVisitor::new(
+      &parsed_file.filename,
+      offsets.parsed_offset(position_to_offset(offsets.original, position.position)),
+      &compilation.span_manager
+    );
+    parsed_file.ast.accept(&mut visitor);
+
+    let span = visitor.expression?.definition?;
+
+    Some(GotoDefinitionResponse::Scalar(span_location(
+      compilation,
+      span
+    )?))
+  }
+
+  /// Completes the members of the expression before the dot. The expression is
+  /// read from the document as it is in the editor, but its type comes from
+  /// the last analysis, at the same offset in the file as it was saved.
+  fn completion(&self, params: CompletionParams) -> Option<CompletionResponse> {
+    let position = params.text_document_position;
+    let (compilation, parsed_file) = self.find_parsed_file(&position.text_document.uri)?;
+    let offsets = SourceOffsets::new(compilation, &parsed_file.filename)?;
+    let content = match self.documents.get(&position.text_document.uri) {
+      Some(content) => content,
+      None => offsets.original
+    };
+
+    let offset = position_to_offset(content, position.position);
+    let segments = completion::receiver_before(content, offset)?;
+
+    let mut visitor = PositionVisitor::new(
+      &parsed_file.filename,
+      offsets.parsed_offset(offset),
+      &compilation.span_manager
+    );
+    parsed_file.ast.accept(&mut visitor);
+
+    let context = visitor
+      .current_context
+      .or_else(|| parsed_file.file_context.borrow().clone())?;
+
+    let type_name =
+      completion::resolve_receiver(&segments, &context, &compilation.inference_store)?;
+
+    Some(CompletionResponse::Array(completion::members(
+      &type_name,
+      &context,
+      &compilation.inference_store
+    )))
+  }
+
+  fn find_parsed_file(&self, uri: &Url) -> Option<(&Compilation, &ParsedFile)> {
+    let compilation = self.compilation.as_ref()?;
+    let path = canonical_path(&uri.to_file_path().ok()?);
+
+    let parsed_file = compilation
+      .ast_list
+      .iter()
+      .chain(compilation.dependency_ast_list.iter())
+      .find(|parsed_file| canonical_path(&parsed_file.file_path) == path)?;
+
+    Some((compilation, parsed_file))
+  }
+}
+
+/// Deserializes the parameters of the request and responds with what the
+/// handler returns.
+fn respond<P: DeserializeOwned, R: Serialize>(
+  request: Request, handler: impl FnOnce(P) -> R
+) -> Response {
+  match serde_json::from_value::<P>(request.params) {
+    Ok(params) => Response::new_ok(request.id, handler(params)),
+    Err(error) => Response::new_err(
+      request.id,
+      ErrorCode::InvalidParams as i32,
+      error.to_string()
+    )
+  }
+}
+
+/// Returns the content of the source as it was given to the parser, the spans
+/// are offsets in this content.
+fn find_source_content<'a>(compilation: &'a Compilation, filename: &str) -> Option<&'a String> {
+  let span_manager = &compilation.span_manager;
+  let index = span_manager
+    .paths
+    .iter()
+    .position(|path| path == filename)?;
+
+  span_manager.contents.get(index)
+}
+
+/// Moves the offsets between the file as it is in the editor and the content
+/// given to the parser, through the emptied lines of the pragmas and the
+/// origins of the preprocessed code.
+struct SourceOffsets<'a> {
+  /// The content of the file on the disk, where the editor positions are
+  original: &'a str,
+
+  /// The file and its lines when the file was preprocessed, the vanilla
+  /// scripts are parsed as they are.
+  preprocessed: Option<(&'a ProcessedFile, StrippedLines)>
+}
+
+impl<'a> SourceOffsets<'a> {
+  fn new(compilation: &'a Compilation, filename: &str) -> Option<Self> {
+    let parsed = find_source_content(compilation, filename)?;
+
+    Some(match compilation.preprocessed_content.get_file(filename) {
+      Some(file) => Self {
+        original: &file.original_content,
+        preprocessed: Some((file, StrippedLines::new(parsed, &file.content.borrow())))
+      },
+      None => Self {
+        original: parsed,
+        preprocessed: None
+      }
+    })
+  }
+
+  /// Returns the offset in the parsed content of the offset in the original
+  /// content.
+  fn parsed_offset(&self, original: usize) -> usize {
+    match &self.preprocessed {
+      Some((file, lines)) => lines.parsed_offset(file.origins.borrow().content_offset(original)),
+      None => original
+    }
+  }
+
+  /// Returns the range in the editor of the range of the parsed content.
+  fn original_range(&self, parsed: Range<usize>) -> lsp_types::Range {
+    let range = match &self.preprocessed {
+      Some((file, lines)) => file.origins.borrow().original_range(
+        lines.preprocessed_offset(parsed.start)..lines.preprocessed_offset(parsed.end)
+      ),
+      None => parsed
+    };
+
+    offsets_to_range(self.original, range)
+  }
+}
+
+fn span_location(compilation: &Compilation, span: Span) -> Option<Location> {
+  let span_manager = &compilation.span_manager;
+  let filename = span_manager.get_source(&span);
+  let offsets = SourceOffsets::new(compilation, filename)?;
+
+  Some(Location {
+    uri: path_to_url(filename)?,
+    range: offsets.original_range(span_manager.get_range(span))
+  })
+}
+
+fn to_lsp_diagnostic(diagnostic: &Diagnostic, compilation: &Compilation) -> lsp_types::Diagnostic {
+  let span_manager = &compilation.span_manager;
+  let content = span_manager.get_source_content(&diagnostic.span);
+
+  let severity = match diagnostic.kind {
+    DiagnosticKind::Error => DiagnosticSeverity::ERROR,
+    DiagnosticKind::Warning => DiagnosticSeverity::WARNING,
+    DiagnosticKind::Advice => DiagnosticSeverity::HINT
+  };
+
+  let mut lines = Vec::new();
+  if !diagnostic.message.is_empty() {
+    lines.push(diagnostic.message.clone());
+  }
+
+  for (_, label) in &diagnostic.labels {
+    lines.push(label.clone());
+  }
+
+  if let Some(help) = &diagnostic.help {
+    lines.push(format!("help: {help}"));
+  }
+
+  if let Some(note) = &diagnostic.note {
+    lines.push(format!("note: {note}"));
+  }
+
+  lsp_types::Diagnostic {
+    range: offsets_to_range(content, span_manager.get_range(diagnostic.span)),
+    severity: Some(severity),
+    source: Some("cahirc".to_string()),
+    message: lines.join("\n"),
+    ..Default::default()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use lsp_types::{Position, TextDocumentIdentifier, TextDocumentPositionParams};
+
+  use super::*;
+  use crate::test_utils::TestDirectory;
+
+  /// The macro above `main` is expanded into more lines than its call, so the
+  /// code after the call is not at the same offset in the parsed content.
+  const SOURCE: &str = "#define function LOG(message) {
+  var text: string;
+  text = message;
+  text += \"!\";
+};
+
+function target(): int {
+  return 1;
+}
+
+function main() {
+  var value: int;
+
+  LOG!(\"start\")
+  value = target();
+}
+";
+
+  fn server(directory: &TestDirectory) -> (Server, Url) {
+    directory
+      .file(
+        "cahirc.toml",
+        "[package]\nname = \"test\"\nsrc = \"src\"\ndist = \"dist\"\nstatic_analysis = true\n"
+      )
+      .file("src/main.wss", SOURCE);
+
+    let mut server = Server::new(directory.path.clone());
+    server.analyse();
+
+    let uri = Url::from_file_path(directory.path.join("src/main.wss")).unwrap();
+
+    (server, uri)
+  }
+
+  fn position(uri: &Url, line: u32, character: u32) -> TextDocumentPositionParams {
+    TextDocumentPositionParams {
+      text_document: TextDocumentIdentifier { uri: uri.clone() },
+      position: Position { line, character }
+    }
+  }
+
+  #[test]
+  fn hover_after_a_macro_expansion_is_in_the_edited_file() {
+    let directory = TestDirectory::new("lsp");
+    let (server, uri) = server(&directory);
+
+    let hover = server
+      .hover(HoverParams {
+        text_document_position_params: position(&uri, 14, 3),
+        work_done_progress_params: Default::default()
+      })
+      .expect("no hover after the macro call");
+
+    let HoverContents::Markup(content) = hover.contents else {
+      panic!("the hover is not markdown");
+    };
+
+    assert_eq!(content.value, "```witcherscript\nint\n```");
+    assert_eq!(
+      hover.range,
+      Some(lsp_types::Range {
+        start: Position {
+          line: 14,
+          character: 2
+        },
+        end: Position {
+          line: 14,
+          character: 7
+        }
+      })
+    );
+  }
+
+  #[test]
+  fn definition_after_a_macro_expansion_is_in_the_edited_file() {
+    let directory = TestDirectory::new("lsp");
+    let (server, uri) = server(&directory);
+
+    let response = server
+      .definition(GotoDefinitionParams {
+        text_document_position_params: position(&uri, 14, 11),
+        work_done_progress_params: Default::default(),
+        partial_result_params: Default::default()
+      })
+      .expect("no definition after the macro call");
+
+    let GotoDefinitionResponse::Scalar(location) = response else {
+      panic!("the definition is not a single location");
+    };
+
+    assert_eq!(location.uri, uri);
+    assert_eq!(location.range.start.line, 6);
+  }
+}
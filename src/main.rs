@@ -1,406 +1,238 @@
-use std::cell::RefCell;
-use std::fs;
-use std::path::{Path, PathBuf};
-use std::rc::Rc;
-
 mod ast;
+mod compiler;
 mod config;
+mod dependencies;
+mod lsp;
 mod preprocessor;
+mod source_map;
+#[cfg(test)]
+mod test_utils;
+mod timings;
 mod utils;
+mod vanilla;
+mod watch;
 
 extern crate lalrpop_util;
 
-use ariadne::Source;
-use ast::codegen::type_inference::TypeInferenceStore;
-use ast::span_manager::SpanManager;
-use ast::visitor::FunctionsInferenceVisitor;
-use ast::{Program, ProgramInformation, ReportManager};
-use config::{read_config, Config};
-use lalrpop_util::lalrpop_mod;
+use std::io::Write;
+use std::time::Instant;
 
-use crate::ast::codegen::context::{Context, ContextType};
-use crate::ast::visitor::{
-  CompoundTypesVisitor, ContextBuildingVisitor, ExpressionTypeInferenceVisitor, FunctionVisitor,
-  FunctionsCallsCheckerVisitor, LambdaDeclarationVisitor, LibraryEmitterVisitor,
-  VariableDeclarationVisitor
-};
-use crate::utils::strip_pragmas;
+use ast::{JsonDiagnostic, MessageFormat};
+use config::{command_options, project_directory, read_config, CommandOptions, Config};
+use lalrpop_util::lalrpop_mod;
 
 lalrpop_mod!(pub parser);
 
 fn main() {
-  let config = read_config().expect("Could not read the config cahirc.toml file");
-
-  compile_source_directory(&config).expect("main error");
-}
+  if std::env::args().nth(1).as_deref() == Some("lsp") {
+    lsp::run().expect("language server error");
 
-fn compile_source_directory(config: &Config) -> std::io::Result<()> {
-  let preprocessed_content = preprocessor::preprocess(&config.package.src, &config.dependencies)?;
-
-  let program_information = ProgramInformation::new();
-  let global_context = Rc::new(RefCell::new(Context::new(
-    "Program",
-    None,
-    ContextType::Global
-  )));
-
-  // 1.
-  // Build the list of AST from the files
-  let mut sources_span_manager = SpanManager::new();
-  let mut dependency_ast_list = Vec::new();
-  let mut ast_list = Vec::new();
-
-  // starting with the dependencies
-  for (_name, value) in preprocessed_content.dependencies_files_content.iter() {
-    for (filename, file) in value.iter() {
-      let content = strip_pragmas(&file.content.borrow());
-      let mut span_maker = sources_span_manager.add_source(filename.clone());
-
-      if file
-        .content
-        .borrow()
-        .contains("#pragma cahirc-preprocessor-print")
-      {
-        println!("{}", &file.content.borrow());
-      }
-
-      let expr = parser::ProgramParser::new()
-        .parse(&program_information, &mut span_maker, &content)
-        .unwrap();
-
-      dependency_ast_list.push(ParsedFile {
-        ast: expr,
-        file_path: file.path.clone(),
-        filename: filename.clone()
-      });
-    }
+    return;
   }
 
-  for (filename, file) in preprocessed_content.source_files_content.iter() {
-    use ariadne::{ColorGenerator, Label, Report, ReportKind};
-
-    let content = strip_pragmas(&file.content.borrow());
-    let mut span_maker = sources_span_manager.add_source(filename.clone());
-
-    if file
-      .content
-      .borrow()
-      .contains("#pragma cahirc-preprocessor-print")
-    {
-      println!("{}", &file.content.borrow());
+  if std::env::args().nth(1).as_deref() == Some("fetch") {
+    if let Err(error) = dependencies::fetch(&project_directory()) {
+      eprintln!("could not fetch the dependencies: {error}");
+      std::process::exit(1);
     }
 
-    let expr = parser::ProgramParser::new().parse(&program_information, &mut span_maker, &content);
-    let expr = match expr {
-      Ok(expr) => expr,
-      Err(error) => {
-        match error {
-          lalrpop_util::ParseError::InvalidToken { location } => {
-            let mut colors = ColorGenerator::new();
-            let a = colors.next();
-            let absolute_path =
-              dunce::canonicalize(std::env::current_dir().unwrap().join(&file.path)).unwrap();
-
-            Report::build(ReportKind::Error, (), location)
-              .with_message(&format!(
-                "Invalid token in file://{}",
-                absolute_path.to_str().unwrap().replace("\\", "/")
-              ))
-              .with_label(
-                Label::new(location..location.checked_add(1).unwrap_or(location))
-                  .with_message("The invalid token")
-                  .with_color(a)
-              )
-              .finish()
-              .print(Source::from(&content))
-              .unwrap();
-          }
-          lalrpop_util::ParseError::UnrecognizedEOF {
-            location: _,
-            expected: _
-          } => {
-            println!("Unrecognized EOF in {}", filename);
-          }
-          lalrpop_util::ParseError::UnrecognizedToken { token, expected } => {
-            let mut colors = ColorGenerator::new();
-            let a = colors.next();
-            let absolute_path =
-              dunce::canonicalize(std::env::current_dir().unwrap().join(&file.path)).unwrap();
-
-            Report::build(ReportKind::Error, (), token.0)
-              .with_message(&format!(
-                "Unrecognized token in file://{}",
-                absolute_path.to_str().unwrap().replace("\\", "/")
-              ))
-              .with_label(
-                Label::new(token.0..token.2)
-                  .with_message(format!("Expected {}", expected.join(" | ")))
-                  .with_color(a)
-              )
-              .finish()
-              .print(Source::from(&content))
-              .unwrap();
-          }
-          lalrpop_util::ParseError::ExtraToken { token: _ } => todo!(),
-          lalrpop_util::ParseError::User { error: _ } => todo!()
-        };
-
-        continue;
-      }
-    };
-
-    ast_list.push(ParsedFile {
-      ast: expr,
-      file_path: file.path.clone(),
-      filename: filename.clone()
-    });
+    return;
   }
 
-  // 2.
-  // Traverse the AST to collect information about it
-  let mut inference_store = TypeInferenceStore::new();
-
-  for parsed_file in &dependency_ast_list {
-    let mut variable_declaration_visitor = VariableDeclarationVisitor::new(&program_information);
-
-    // create a context for this file, and register it into the global context
-    let file_context = Rc::new(RefCell::new(Context::new(
-      &format!("file: {:#?}", parsed_file.file_path.file_name().unwrap()),
-      None,
-      ContextType::Global
-    )));
-
-    file_context.borrow_mut().set_as_library();
+  if std::env::args().nth(1).as_deref() == Some("map") {
+    let argument = std::env::args().nth(2).unwrap_or_default();
 
-    Context::set_parent_context(&file_context, &global_context);
-
-    let mut context_builder = ContextBuildingVisitor {
-      current_context: file_context.clone()
-    };
-
-    let mut function_visitor = FunctionVisitor {
-      program_information: &program_information,
-      current_context: file_context.clone()
-    };
-
-    use ast::visitor::Visited;
+    if let Err(error) = source_map::print_origin(&argument) {
+      eprintln!("{error}");
+      std::process::exit(1);
+    }
 
-    parsed_file.ast.accept(&mut context_builder);
-    parsed_file.ast.accept(&mut function_visitor);
-    parsed_file.ast.accept(&mut variable_declaration_visitor);
+    return;
   }
 
-  let mut report_manager = ReportManager::new();
-  // perform a first pass to build the contexts
-  for parsed_file in &ast_list {
-    let file_context_name = format!("file: {:#?}", parsed_file.file_path.file_name().unwrap());
-
-    // create a context for this file, and register it into the global context
-    let file_context = Rc::new(RefCell::new(Context::new(
-      &file_context_name,
-      None,
-      ContextType::Global
-    )));
-    Context::set_parent_context(&file_context, &global_context);
-
-    let mut context_builder = ContextBuildingVisitor {
-      current_context: file_context.clone()
-    };
+  let options = match command_options() {
+    Ok(options) => options,
+    Err(error) => {
+      eprintln!("{error}");
+      std::process::exit(1);
+    }
+  };
 
-    use ast::visitor::Visited;
-    parsed_file.ast.accept(&mut context_builder);
+  if options.message_format == MessageFormat::Json {
+    report_panics_as_json();
   }
 
-  // then perform the pass of visitors
-  for parsed_file in &ast_list {
-    let file_context_name = format!("file: {:#?}", parsed_file.file_path.file_name().unwrap());
-
-    let gc = global_context.borrow();
+  if std::env::args().any(|arg| arg == "--watch") {
+    watch::watch(&project_directory(), options).expect("watch error");
 
-    let Some(file_context) = gc
-      .children_contexts
-      .iter()
-      .find(|c| c.borrow().name == file_context_name)
-    else {
-      panic!("Missing context in 2nd compilation pass for file {file_context_name}");
-    };
+    return;
+  }
 
-    let mut variable_declaration_visitor = VariableDeclarationVisitor::new(&program_information);
+  let mut config = match read_config() {
+    Ok(config) => config,
+    Err(error) => {
+      eprintln!("could not read the cahirc.toml file: {error}");
+      std::process::exit(1);
+    }
+  };
 
-    let mut function_visitor = FunctionVisitor {
-      program_information: &program_information,
-      current_context: file_context.clone()
-    };
+  if let Err(error) = config.apply_command_options(&options) {
+    eprintln!("{error}");
+    std::process::exit(1);
+  }
 
-    use ast::visitor::Visited;
+  let exit_code =
+    compile_source_directory(&config, &options, &mut std::io::stderr()).expect("main error");
 
-    parsed_file.ast.accept(&mut function_visitor);
-    parsed_file.ast.accept(&mut variable_declaration_visitor);
+  if exit_code != 0 {
+    std::process::exit(exit_code);
+  }
+}
 
-    if config.package.static_analysis.unwrap_or(false) {
-      let mut compound_types_visitor = CompoundTypesVisitor::new(
-        file_context.clone(),
-        &mut inference_store,
-        &mut report_manager,
-        &mut sources_span_manager
-      );
-      parsed_file.ast.accept(&mut compound_types_visitor);
-      let file = preprocessed_content
-        .source_files_content
-        .get(&parsed_file.filename);
-      if let Some(file) = file {
-        report_manager.consume(&file.content.borrow());
-      }
-    }
+/// Compiles the project and returns the exit code of the command, `1` when the
+/// compilation failed in which case the dist directory is left untouched. The
+/// summary and the timings are written in `messages`, the standard error.
+fn compile_source_directory(
+  config: &Config, options: &CommandOptions, messages: &mut impl Write
+) -> std::io::Result<i32> {
+  let mut compilation = compiler::analyse(config, config.package.static_analysis.unwrap_or(false))?;
+
+  let summary = compilation.report_manager.summary();
+  let has_failed = compilation.report_manager.has_failed(options.deny_warnings);
+
+  compilation
+    .report_manager
+    .consume(&compilation.span_manager, options.message_format);
+
+  writeln!(messages, "{summary}")?;
+
+  if has_failed {
+    writeln!(
+      messages,
+      "the compilation failed, the dist directory was left untouched"
+    )?;
+  } else {
+    let start = Instant::now();
+    compiler::emit(&compilation, config)?;
+    compilation
+      .timings
+      .record("code generation", start.elapsed());
   }
 
-  // 2.1
-  // do a second pass for the type inference
-  if config.package.static_analysis.unwrap_or(false) {
-    for parsed_file in &ast_list {
-      use ast::visitor::Visited;
-
-      let mut expression_inference_visitor = ExpressionTypeInferenceVisitor::new(
-        global_context.clone(),
-        &mut inference_store,
-        &mut report_manager,
-        &mut sources_span_manager
-      );
+  if options.timings {
+    writeln!(messages, "{}", compilation.timings.report())?;
+  }
 
-      parsed_file.ast.accept(&mut expression_inference_visitor);
+  Ok(if has_failed { 1 } else { 0 })
+}
 
-      let file = preprocessed_content
-        .source_files_content
-        .get(&parsed_file.filename);
-      if let Some(file) = file {
-        report_manager.consume(&file.content.borrow());
+/// Prints the panics of the compiler as JSON records too, so the tools reading
+/// the output learn that the compilation was interrupted.
+fn report_panics_as_json() {
+  let default_hook = std::panic::take_hook();
+
+  std::panic::set_hook(Box::new(move |info| {
+    let message = match info.payload().downcast_ref::<&str>() {
+      Some(message) => message.to_string(),
+      None => match info.payload().downcast_ref::<String>() {
+        Some(message) => message.clone(),
+        None => "the compiler panicked".to_string()
       }
+    };
 
-      let mut functions_inference_visitor = FunctionsInferenceVisitor::new(
-        global_context.clone(),
-        &mut inference_store,
-        &mut report_manager,
-        &mut sources_span_manager
-      );
-
-      parsed_file.ast.accept(&mut functions_inference_visitor);
-      report_manager.consume_multiple_sources(&mut sources_span_manager, &preprocessed_content);
+    let diagnostic = JsonDiagnostic {
+      severity: "error",
+      code: Some("internal-error"),
+      file: None,
+      range: None,
+      message: &message,
+      labels: Vec::new(),
+      help: None,
+      note: None
+    };
 
-      let mut function_call_checker_visitor = FunctionsCallsCheckerVisitor::new(
-        global_context.clone(),
-        &mut inference_store,
-        &mut report_manager,
-        &mut sources_span_manager
-      );
+    println!("{}", diagnostic.to_json());
+    default_hook(info);
+  }));
+}
 
-      parsed_file.ast.accept(&mut function_call_checker_visitor);
-      report_manager.consume_multiple_sources(&mut sources_span_manager, &preprocessed_content);
-    }
+#[cfg(test)]
+mod tests {
+  use super::compile_source_directory;
+  use crate::config::{command_options_from, read_config_from};
+  use crate::test_utils::TestDirectory;
+
+  /// Compiles the project made of the `main.wss` file with the options, and
+  /// returns the exit code, whether the dist directory was written and the
+  /// messages of the command.
+  fn run(source: &str, args: &[&str]) -> (i32, bool, String) {
+    let directory = TestDirectory::new("command");
+    directory
+      .file(
+        "cahirc.toml",
+        "[package]\nname = \"test\"\nsrc = \"src\"\ndist = \"dist\"\n"
+      )
+      .file("src/main.wss", source);
+
+    let config = read_config_from(&directory.path).unwrap();
+    let options = command_options_from(
+      ["--message-format=json"]
+        .iter()
+        .chain(args)
+        .map(|arg| arg.to_string())
+    )
+    .unwrap();
+
+    let mut messages = Vec::new();
+    let exit_code = compile_source_directory(&config, &options, &mut messages).unwrap();
+
+    (
+      exit_code,
+      directory.path.join("dist").exists(),
+      String::from_utf8(messages).unwrap()
+    )
   }
 
-  // 3.
-  // Emit code using the information we collected in the previous step
-  if let Err(_) = std::fs::remove_dir_all(&config.package.dist) {}
-
-  for parsed_file in &ast_list {
-    let new_path = parsed_file
-      .file_path
-      .strip_prefix(&config.package.src)
-      .expect(&format!(
-        "could not form the path to {:?}'s output file",
-        &parsed_file.file_path,
-      ))
-      .to_path_buf();
-
-    let mut new_path = Path::new(&config.package.dist).join(new_path);
-
-    new_path.set_extension("ws");
-
-    use ast::codegen::Codegen;
-    let mut output_code = Vec::new();
-    parsed_file
-      .ast
-      .emit(&global_context.borrow(), &mut output_code)
-      .expect("failed to emit code");
-
-    std::fs::create_dir_all(&new_path.parent().unwrap())
-      .expect("failed to recursively make the output directories");
-
-    match std::str::from_utf8(&output_code) {
-      Ok(s) => {
-        if !s.trim().is_empty() {
-          fs::write(new_path, format_code(s)).expect("failed to write output file")
-        }
-      }
-      Err(e) => println!("{}", e)
-    };
+  fn compile(source: &str, args: &[&str]) -> (i32, bool) {
+    let (exit_code, has_dist, _) = run(source, args);
 
-    // (*global_context).borrow().print(0);
+    (exit_code, has_dist)
   }
 
-  // 4.
-  // emit code for the libraries code, especially the generic functions that
-  // were used.
-  let generated_code_file = Path::new(&config.package.dist)
-    .join(uuid::Uuid::new_v4().to_string())
-    .with_extension("ws");
-
-  let mut file_content = Vec::new();
-
-  std::fs::create_dir_all(&generated_code_file.parent().unwrap())
-    .expect("failed to recursively make the output directories");
-
-  for parsed_file in &dependency_ast_list {
-    use ast::visitor::Visited;
-    let mut visitor = LibraryEmitterVisitor::new(&global_context, &mut file_content);
-    parsed_file.ast.accept(&mut visitor);
+  #[test]
+  fn errors_exit_with_code_one() {
+    let source = "function total(count: int) {\n  for item in count {\n  }\n}\n";
 
-    let mut visitor = LambdaDeclarationVisitor::new(&mut file_content);
-    parsed_file.ast.accept(&mut visitor);
+    assert_eq!(compile(source, &[]), (1, false));
+    assert_eq!(compile("function main() {\n}\n", &[]), (0, true));
   }
 
-  for parsed_file in &ast_list {
-    use ast::visitor::Visited;
+  #[test]
+  fn warnings_exit_with_code_one_when_denied() {
+    let source = "@register('unused', a)\n\nfunction main() {\n}\n";
 
-    let mut visitor = LambdaDeclarationVisitor::new(&mut file_content);
-    parsed_file.ast.accept(&mut visitor);
+    assert_eq!(compile(source, &[]), (0, true));
+    assert_eq!(compile(source, &["--deny-warnings"]), (1, false));
   }
 
-  match std::str::from_utf8(&file_content) {
-    Ok(s) => {
-      if !s.trim().is_empty() {
-        fs::write(&generated_code_file, format_code(s)).expect("failed to write output file")
-      }
-    }
-    Err(e) => println!("{}", e)
-  };
-
-  Ok(())
-}
-
-fn format_code(origin: &str) -> String {
-  let mut lines: Vec<String> = origin.lines().map(|s| s.to_string()).collect();
-  let mut depth = 0;
+  #[test]
+  fn timings_are_printed_with_the_option() {
+    let source = "function main() {\n}\n";
 
-  for i in 0..lines.len() {
-    if lines[i].starts_with("}") && depth > 0 {
-      depth -= 1;
-    }
+    let (_, _, messages) = run(source, &[]);
+    assert_eq!(messages, "0 errors, 0 warnings\n");
 
-    let formated_line = format!("{}{}", "  ".repeat(depth), lines[i]);
+    let (_, _, messages) = run(source, &["--timings"]);
+    let lines: Vec<_> = messages.lines().collect();
 
-    if lines[i].ends_with("{") {
-      depth += 1;
+    assert_eq!(lines[..2], ["0 errors, 0 warnings", "timings:"]);
+    for phase in ["parsing", "analysis", "code generation", "total"] {
+      assert!(
+        lines
+          .iter()
+          .any(|line| line.trim_start().starts_with(phase) && line.ends_with("ms")),
+        "{messages}"
+      );
     }
-
-    lines[i] = formated_line;
   }
-
-  lines.join("\n")
-}
-
-struct ParsedFile {
-  file_path: PathBuf,
-  ast: Program,
-  filename: String
 }
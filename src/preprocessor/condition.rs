@@ -0,0 +1,395 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use super::tokens::{is_identifier_char, is_identifier_start};
+use super::types::*;
+
+/// Evaluates the condition of a `#if` or a `#elif`. The names are the macros
+/// that are defined, a `#define const` has its value and a macro without one
+/// is `true`. A name that is not defined is false, and so is any comparison
+/// with it. Returns the label of the error if the condition is not valid.
+pub fn evaluate_condition(
+  registered_macros: &HashMap<String, &MacroDefinition>, condition: &str
+) -> Result<bool, String> {
+  let mut parser = ConditionParser {
+    tokens: lex_condition(condition)?,
+    position: 0,
+    registered_macros
+  };
+
+  let value = parser.parse_or()?;
+
+  match parser.tokens.get(parser.position) {
+    None => Ok(value.is_true()),
+    Some(token) => Err(format!("Unexpected {token} in the condition."))
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+  Undefined,
+  Bool(bool),
+  Number(f64),
+  String(String)
+}
+
+impl Value {
+  /// Reads the value of a `#define const`, the quotes of the strings are
+  /// removed.
+  fn parse(value: &str) -> Self {
+    let value = value.trim();
+
+    let number = value
+      .starts_with(|c: char| c.is_ascii_digit() || c == '-')
+      .then(|| value.parse().ok())
+      .flatten();
+
+    match (value, number) {
+      ("true", _) => Value::Bool(true),
+      ("false", _) => Value::Bool(false),
+      (_, Some(number)) => Value::Number(number),
+      _ => Value::String(unquote(value).to_string())
+    }
+  }
+
+  fn is_true(&self) -> bool {
+    match self {
+      Value::Undefined => false,
+      Value::Bool(value) => *value,
+      Value::Number(value) => *value != 0.0,
+      Value::String(value) => !value.is_empty()
+    }
+  }
+
+  fn kind(&self) -> &'static str {
+    match self {
+      Value::Undefined => "an undefined name",
+      Value::Bool(_) => "a boolean",
+      Value::Number(_) => "a number",
+      Value::String(_) => "a string"
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ConditionToken<'a> {
+  Identifier(&'a str),
+  Number(f64),
+  String(&'a str),
+  Operator(&'static str)
+}
+
+impl std::fmt::Display for ConditionToken<'_> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ConditionToken::Identifier(name) => write!(f, "`{name}`"),
+      ConditionToken::Number(number) => write!(f, "`{number}`"),
+      ConditionToken::String(string) => write!(f, "`\"{string}\"`"),
+      ConditionToken::Operator(operator) => write!(f, "`{operator}`")
+    }
+  }
+}
+
+/// The operators of the conditions, the longest ones first so `<=` is not read
+/// as `<` followed by `=`.
+const OPERATORS: [&str; 11] = ["&&", "||", "==", "!=", "<=", ">=", "<", ">", "!", "(", ")"];
+
+fn lex_condition(condition: &str) -> Result<Vec<ConditionToken<'_>>, String> {
+  let mut tokens = Vec::new();
+  let mut rest = condition.trim_start();
+
+  while let Some(c) = rest.chars().next() {
+    let length = if is_identifier_start(c) {
+      let length = rest
+        .find(|c: char| !is_identifier_char(c))
+        .unwrap_or(rest.len());
+
+      tokens.push(ConditionToken::Identifier(&rest[..length]));
+      length
+    } else if c.is_ascii_digit() || is_negative_number(rest) {
+      // the conditions have no subtraction, a `-` before a digit is a sign
+      let length = rest[1..]
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .map_or(rest.len(), |length| length + 1);
+      let number = rest[..length]
+        .parse()
+        .map_err(|_| format!("`{}` is not a valid number.", &rest[..length]))?;
+
+      tokens.push(ConditionToken::Number(number));
+      length
+    } else if c == '"' || c == '\'' {
+      let length = rest[1..]
+        .find(c)
+        .map(|end| end + 2)
+        .ok_or_else(|| format!("The string `{rest}` is never closed."))?;
+
+      tokens.push(ConditionToken::String(&rest[1..length - 1]));
+      length
+    } else {
+      let operator = OPERATORS
+        .iter()
+        .find(|operator| rest.starts_with(*operator))
+        .ok_or_else(|| format!("Unexpected `{c}` in the condition."))?;
+
+      tokens.push(ConditionToken::Operator(operator));
+      operator.len()
+    };
+
+    rest = rest[length..].trim_start();
+  }
+
+  Ok(tokens)
+}
+
+fn is_negative_number(text: &str) -> bool {
+  text
+    .strip_prefix('-')
+    .is_some_and(|number| number.starts_with(|c: char| c.is_ascii_digit()))
+}
+
+/// A recursive descent parser that evaluates the condition as it reads it,
+/// from the lowest precedence to the highest: `||`, `&&`, `!`, the comparisons
+/// and the operands.
+struct ConditionParser<'a> {
+  tokens: Vec<ConditionToken<'a>>,
+  position: usize,
+  registered_macros: &'a HashMap<String, &'a MacroDefinition>
+}
+
+impl<'a> ConditionParser<'a> {
+  fn next(&mut self) -> Option<ConditionToken<'a>> {
+    let token = self.tokens.get(self.position).cloned();
+    self.position += 1;
+
+    token
+  }
+
+  /// Consumes the operator if it is the next token.
+  fn eat(&mut self, operator: &'static str) -> bool {
+    let is_next = self.tokens.get(self.position) == Some(&ConditionToken::Operator(operator));
+
+    if is_next {
+      self.position += 1;
+    }
+
+    is_next
+  }
+
+  fn expect(&mut self, operator: &str) -> Result<(), String> {
+    match self.next() {
+      Some(ConditionToken::Operator(found)) if found == operator => Ok(()),
+      Some(token) => Err(format!("Expected `{operator}` but found {token}.")),
+      None => Err(format!(
+        "Expected `{operator}` at the end of the condition."
+      ))
+    }
+  }
+
+  fn parse_or(&mut self) -> Result<Value, String> {
+    let mut value = self.parse_and()?;
+
+    while self.eat("||") {
+      let right = self.parse_and()?;
+      value = Value::Bool(value.is_true() || right.is_true());
+    }
+
+    Ok(value)
+  }
+
+  fn parse_and(&mut self) -> Result<Value, String> {
+    let mut value = self.parse_not()?;
+
+    while self.eat("&&") {
+      let right = self.parse_not()?;
+      value = Value::Bool(value.is_true() && right.is_true());
+    }
+
+    Ok(value)
+  }
+
+  fn parse_not(&mut self) -> Result<Value, String> {
+    if self.eat("!") {
+      return Ok(Value::Bool(!self.parse_not()?.is_true()));
+    }
+
+    self.parse_comparison()
+  }
+
+  fn parse_comparison(&mut self) -> Result<Value, String> {
+    let left = self.parse_operand()?;
+
+    let operator = match self.tokens.get(self.position) {
+      Some(ConditionToken::Operator(operator @ ("==" | "!=" | "<" | "<=" | ">" | ">="))) => {
+        *operator
+      }
+      _ => return Ok(left)
+    };
+
+    self.position += 1;
+    let right = self.parse_operand()?;
+
+    let ordering = match (&left, &right) {
+      (Value::Undefined, _) | (_, Value::Undefined) => return Ok(Value::Bool(false)),
+      (Value::Number(left), Value::Number(right)) => left.partial_cmp(right),
+      (Value::String(left), Value::String(right)) => Some(left.cmp(right)),
+      (Value::Bool(left), Value::Bool(right)) if matches!(operator, "==" | "!=") => {
+        Some(left.cmp(right))
+      }
+      _ => {
+        return Err(format!(
+          "Cannot compare {} with {} using `{operator}`.",
+          left.kind(),
+          right.kind()
+        ))
+      }
+    };
+
+    let is_true = match (operator, ordering) {
+      (_, None) => false,
+      ("==", Some(ordering)) => ordering == Ordering::Equal,
+      ("!=", Some(ordering)) => ordering != Ordering::Equal,
+      ("<", Some(ordering)) => ordering == Ordering::Less,
+      ("<=", Some(ordering)) => ordering != Ordering::Greater,
+      (">", Some(ordering)) => ordering == Ordering::Greater,
+      (_, Some(ordering)) => ordering != Ordering::Less
+    };
+
+    Ok(Value::Bool(is_true))
+  }
+
+  fn parse_operand(&mut self) -> Result<Value, String> {
+    match self.next() {
+      Some(ConditionToken::Operator("(")) => {
+        let value = self.parse_or()?;
+        self.expect(")")?;
+
+        Ok(value)
+      }
+      Some(ConditionToken::Identifier("defined")) => {
+        self.expect("(")?;
+
+        let is_defined = match self.next() {
+          Some(ConditionToken::Identifier(name)) => self.registered_macros.contains_key(name),
+          _ => return Err("defined() takes the name of a macro.".to_string())
+        };
+
+        self.expect(")")?;
+
+        Ok(Value::Bool(is_defined))
+      }
+      Some(ConditionToken::Identifier("true")) => Ok(Value::Bool(true)),
+      Some(ConditionToken::Identifier("false")) => Ok(Value::Bool(false)),
+      Some(ConditionToken::Identifier(name)) => Ok(match self.registered_macros.get(name) {
+        Some(MacroDefinition::Constant(constant)) => Value::parse(&constant.value),
+        Some(MacroDefinition::Function(_)) => Value::Bool(true),
+        None => Value::Undefined
+      }),
+      Some(ConditionToken::Number(number)) => Ok(Value::Number(number)),
+      Some(ConditionToken::String(string)) => Ok(Value::String(string.to_string())),
+      Some(token) => Err(format!("Unexpected {token} in the condition.")),
+      None => Err("The condition ends too early.".to_string())
+    }
+  }
+}
+
+fn unquote(value: &str) -> &str {
+  for quote in ['"', '\''] {
+    if let Some(inner) = value
+      .strip_prefix(quote)
+      .and_then(|value| value.strip_suffix(quote))
+    {
+      return inner;
+    }
+  }
+
+  value
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Evaluates the condition with the `#define const` of the values.
+  fn evaluate(condition: &str, constants: &[(&str, &str)]) -> Result<bool, String> {
+    let definitions: HashMap<String, MacroDefinition> = constants
+      .iter()
+      .map(|(name, value)| {
+        let constant = MacroConstant {
+          name: name.to_string(),
+          value: value.to_string()
+        };
+
+        (name.to_string(), MacroDefinition::Constant(constant))
+      })
+      .collect();
+
+    let registered_macros = definitions
+      .iter()
+      .map(|(name, definition)| (name.clone(), definition))
+      .collect();
+
+    evaluate_condition(&registered_macros, condition)
+  }
+
+  #[test]
+  fn negative_numbers_are_compared() {
+    assert_eq!(evaluate("LEVEL >= -1", &[("LEVEL", "0")]), Ok(true));
+    assert_eq!(evaluate("LEVEL >= -1", &[("LEVEL", "-2")]), Ok(false));
+    assert_eq!(evaluate("LEVEL == -1.5", &[("LEVEL", "-1.5")]), Ok(true));
+    assert_eq!(evaluate("-1 < 0", &[]), Ok(true));
+  }
+
+  #[test]
+  fn names_have_the_values_of_their_constants() {
+    let constants = [("DEBUG", "true"), ("VERSION", "410"), ("NAME", "\"mod\"")];
+
+    assert_eq!(evaluate("DEBUG", &constants), Ok(true));
+    assert_eq!(
+      evaluate("VERSION >= 400 && VERSION < 500", &constants),
+      Ok(true)
+    );
+    assert_eq!(evaluate("NAME == 'mod'", &constants), Ok(true));
+    assert_eq!(evaluate("NAME != \"other\"", &constants), Ok(true));
+  }
+
+  #[test]
+  fn undefined_names_are_false() {
+    assert_eq!(evaluate("MISSING", &[]), Ok(false));
+    assert_eq!(evaluate("!MISSING", &[]), Ok(true));
+    assert_eq!(evaluate("MISSING == 0", &[]), Ok(false));
+    assert_eq!(evaluate("MISSING != 0", &[]), Ok(false));
+    assert_eq!(evaluate("defined(MISSING)", &[]), Ok(false));
+    assert_eq!(evaluate("defined(DEBUG)", &[("DEBUG", "false")]), Ok(true));
+  }
+
+  #[test]
+  fn operators_have_their_precedence() {
+    assert_eq!(evaluate("true || false && false", &[]), Ok(true));
+    assert_eq!(evaluate("(true || false) && false", &[]), Ok(false));
+    assert_eq!(evaluate("!false && !(1 > 2)", &[]), Ok(true));
+  }
+
+  #[test]
+  fn invalid_conditions_are_reported() {
+    assert_eq!(
+      evaluate("VERSION > \"400\"", &[("VERSION", "410")]),
+      Err("Cannot compare a number with a string using `>`.".to_string())
+    );
+    assert_eq!(
+      evaluate("1 +", &[]),
+      Err("Unexpected `+` in the condition.".to_string())
+    );
+    assert_eq!(
+      evaluate("(true", &[]),
+      Err("Expected `)` at the end of the condition.".to_string())
+    );
+    assert_eq!(
+      evaluate("1 2", &[]),
+      Err("Unexpected `2` in the condition.".to_string())
+    );
+    assert_eq!(
+      evaluate("- 1", &[]),
+      Err("Unexpected `-` in the condition.".to_string())
+    );
+  }
+}
@@ -1,100 +1,295 @@
 use std::collections::HashMap;
+use std::ops::Range;
 
-use regex::Captures;
+use crate::ast::DiagnosticKind;
 
+use super::condition::evaluate_condition;
+use super::origins::{copy_with_origins, OriginMap};
+use super::tokens::{directive_name, matching_brace, Token, TokenKind, Tokens};
 use super::types::*;
 
+/// Replaces the conditional blocks with the code of their first branch whose
+/// condition is true, and removes them if there is none. A block is a `#if`,
+/// `#ifdef` or `#ifndef` branch followed by any number of `#elif` branches and
+/// an optional `#else` branch, and it ends with a `;`:
+/// `#if A && !B { ... } #elif defined(C) { ... } #else { ... };`
+/// The nested blocks are filtered as well, and the malformed ones are reported
+/// in the diagnostics.
 pub fn filter_conditionals(
-  registered_macros: &HashMap<String, MacroDefinition>, new_content: &mut String,
-  regex_collection: &RegexCollection, condition_type: ConditionType
+  registered_macros: &HashMap<String, &MacroDefinition>, file: &ProcessedFile, filename: &str,
+  diagnostics: &mut Vec<PreprocessorDiagnostic>
 ) {
-  loop {
-    let content_copy = new_content.clone();
-    let regex = match &condition_type {
-      ConditionType::IfDefined => &regex_collection.macro_ifdef,
-      ConditionType::IfNotDefined => &regex_collection.macro_ifndef
-    };
-    let cap = regex.captures(&content_copy);
+  let content = file.content.take();
+  let content_origins = file.origins.take();
+
+  let mut filter = ConditionalFilter {
+    registered_macros,
+    content: &content,
+    content_origins: &content_origins,
+    filename,
+    diagnostics,
+    output: String::with_capacity(content.len()),
+    origins: OriginMap::default()
+  };
+
+  filter.filter(0..content.len());
+
+  file.origins.replace(filter.origins);
+  file.content.replace(filter.output);
+}
+
+struct ConditionalFilter<'a> {
+  registered_macros: &'a HashMap<String, &'a MacroDefinition>,
+  content: &'a str,
+  content_origins: &'a OriginMap,
+  filename: &'a str,
+  diagnostics: &'a mut Vec<PreprocessorDiagnostic>,
+  output: String,
+  origins: OriginMap
+}
+
+impl ConditionalFilter<'_> {
+  /// Pushes the code of the range to the output, without the conditional
+  /// blocks whose conditions are false.
+  fn filter(&mut self, range: Range<usize>) {
+    let content = self.content;
+    let mut tokens = Tokens::new(content, range.start);
+    let mut copied_until = range.start;
+
+    while let Some(token) = tokens.next() {
+      if token.range.start >= range.end {
+        break;
+      }
+
+      let directive = match token.text(content) {
+        "#" => directive_name(content, token.range.start),
+        _ => None
+      };
+
+      if matches!(directive, Some("if" | "ifdef" | "ifndef" | "elif" | "else")) {
+        self.copy(copied_until..token.range.start);
+      }
+
+      let end = match directive {
+        Some("if" | "ifdef" | "ifndef") => match parse_conditional(content, token.range.start) {
+          Ok(conditional) => {
+            if let Some(body) = self.taken_branch(&conditional) {
+              self.filter(body);
+            }
 
-    if cap.is_none() {
-      break;
+            conditional.end
+          }
+          Err(label) => self.report_malformed(token.range.start, label)
+        },
+        Some(directive @ ("elif" | "else")) => {
+          let label =
+            format!("#{directive} must follow the block of a #if, without a `;` between them.");
+
+          self.report_malformed(token.range.start, label)
+        }
+        _ => continue
+      };
+
+      tokens = Tokens::new(content, end);
+      copied_until = end;
     }
 
-    let cap = cap.unwrap();
-    let start = new_content.find(&cap[0]).unwrap();
+    self.copy(copied_until..range.end.max(copied_until));
+  }
 
-    filter_conditional(
-      registered_macros,
-      new_content,
-      regex_collection,
-      &condition_type,
-      cap,
-      start
+  /// Pushes the code of the range to the output as it is.
+  fn copy(&mut self, range: Range<usize>) {
+    copy_with_origins(
+      self.content,
+      self.content_origins,
+      range,
+      &mut self.output,
+      &mut self.origins
     );
   }
-}
 
-fn filter_conditional(
-  registered_macros: &HashMap<String, MacroDefinition>, new_content: &mut String,
-  regex_collection: &RegexCollection, condition_type: &ConditionType, capture: Captures,
-  start: usize
-) {
-  let full_match = &capture[0];
-
-  // if we were able to find another nested ifdef macro inside the current macro,
-  // we will proceed from bottom to top, from the most nested to the global macros
-  // while expanding.
-  //
-  // we go forward in the fullmatch by just 1 to go past the # in #ifdef so it
-  // doesn't match with itself infinitely.
-  let regex = match condition_type {
-    ConditionType::IfDefined => &regex_collection.macro_ifdef,
-    ConditionType::IfNotDefined => &regex_collection.macro_ifndef
-  };
+  /// Returns the body of the first branch whose condition is true, the
+  /// conditions that are not valid are reported and are false.
+  fn taken_branch(&mut self, conditional: &Conditional) -> Option<Range<usize>> {
+    for branch in &conditional.branches {
+      let is_true = match branch.condition {
+        Condition::IfDefined(name) => self.registered_macros.contains_key(name),
+        Condition::IfNotDefined(name) => !self.registered_macros.contains_key(name),
+        Condition::Expression(expression) => {
+          match evaluate_condition(self.registered_macros, expression) {
+            Ok(is_true) => is_true,
+            Err(label) => {
+              let range = self.locate(branch.directive_start);
 
-  if let Some(sub_capture) = regex.captures(&full_match[1..]) {
-    let sub_start = full_match.find(&sub_capture[0]).unwrap();
+              self.diagnostics.push(PreprocessorDiagnostic {
+                kind: DiagnosticKind::Error,
+                code: "invalid-condition",
+                filename: self.filename.to_string(),
+                range,
+                message: "Invalid condition".to_string(),
+                label,
+                help: None
+              });
 
-    filter_conditional(
-      registered_macros,
-      new_content,
-      regex_collection,
-      condition_type,
-      sub_capture,
-      start + sub_start
-    );
+              false
+            }
+          }
+        }
+        Condition::Else => true
+      };
+
+      if is_true {
+        return Some(branch.body.clone());
+      }
+    }
+
+    None
+  }
+
+  /// Reports the malformed directive at the given index, and returns the
+  /// index where its line ends so the line is removed and only reported once.
+  fn report_malformed(&mut self, start: usize, label: String) -> usize {
+    let range = self.locate(start);
 
-    // we let the next iteration of the pre processor parse the current macro,
-    // if it doesn't have more nested macros in it that is.
-    return;
+    self.diagnostics.push(PreprocessorDiagnostic {
+      kind: DiagnosticKind::Error,
+      code: "malformed-conditional",
+      filename: self.filename.to_string(),
+      range,
+      message: "Malformed conditional block".to_string(),
+      label,
+      help: None
+    });
+
+    self.content[start..]
+      .find('\n')
+      .map_or(self.content.len(), |index| start + index)
+  }
+
+  /// Returns the range of the directive at the given index in the original
+  /// content of the file.
+  fn locate(&self, start: usize) -> Range<usize> {
+    let length = directive_name(self.content, start).map_or(0, str::len);
+
+    // +1 for the #
+    self
+      .content_origins
+      .original_range(start..start + length + 1)
   }
+}
+
+struct Conditional<'a> {
+  branches: Vec<Branch<'a>>,
+
+  /// The index where the block ends in the content
+  end: usize
+}
 
-  let constant_name = &capture[1];
-  let body = &capture[2];
-  let is_defined = registered_macros.contains_key(constant_name);
-
-  new_content.replace_range(
-    start..start + full_match.len(),
-    match condition_type {
-      ConditionType::IfDefined => {
-        if is_defined {
-          body
-        } else {
-          ""
+struct Branch<'a> {
+  condition: Condition<'a>,
+
+  /// The index of the `#` of the branch's directive
+  directive_start: usize,
+  body: Range<usize>
+}
+
+enum Condition<'a> {
+  IfDefined(&'a str),
+  IfNotDefined(&'a str),
+  Expression(&'a str),
+  Else
+}
+
+/// Parses the conditional block at the given index, or returns the label of
+/// the error if it is malformed.
+fn parse_conditional(content: &str, start: usize) -> Result<Conditional<'_>, String> {
+  let mut branches = Vec::new();
+  let mut directive_start = start;
+
+  loop {
+    let mut tokens = Tokens::new(content, directive_start + 1);
+    let directive = match tokens.next() {
+      Some(token) => token,
+      None => return Err("The conditional block ends too early.".to_string())
+    };
+    let directive_end = directive.range.end;
+    let directive = directive.text(content);
+
+    let missing_block = || format!("The #{directive} must be followed by a block.");
+
+    let (condition, opening_brace) = match directive {
+      "if" | "elif" => {
+        let opening_brace = tokens
+          .find(|token| token.text(content) == "{")
+          .ok_or_else(missing_block)?;
+        let condition = content[directive_end..opening_brace.range.start].trim();
+
+        if condition.is_empty() {
+          return Err(format!("The #{directive} has no condition."));
         }
+
+        (Condition::Expression(condition), opening_brace)
       }
-      ConditionType::IfNotDefined => {
-        if is_defined {
-          ""
-        } else {
-          body
-        }
+      "ifdef" | "ifndef" => {
+        let name = tokens
+          .next_significant()
+          .filter(|name| name.kind == TokenKind::Identifier)
+          .ok_or_else(|| format!("The #{directive} must be followed by the name of a macro."))?
+          .text(content);
+
+        let condition = match directive {
+          "ifdef" => Condition::IfDefined(name),
+          _ => Condition::IfNotDefined(name)
+        };
+
+        (
+          condition,
+          next_block(&mut tokens, content).ok_or_else(missing_block)?
+        )
+      }
+      _ => (
+        Condition::Else,
+        next_block(&mut tokens, content).ok_or_else(missing_block)?
+      )
+    };
+
+    let closing_brace = matching_brace(content, opening_brace.range.start)
+      .ok_or_else(|| format!("The block of the #{directive} is never closed."))?;
+
+    let is_else = matches!(condition, Condition::Else);
+    branches.push(Branch {
+      condition,
+      directive_start,
+      body: opening_brace.range.end..closing_brace.start
+    });
+
+    // the block ends with `};`, or continues with a `#elif` or a `#else`
+    let next = Tokens::new(content, closing_brace.end).next_significant();
+    let next_directive = next
+      .as_ref()
+      .filter(|token| token.text(content) == "#")
+      .and_then(|token| directive_name(content, token.range.start));
+
+    match (next, next_directive) {
+      (Some(next), _) if next.text(content) == ";" => {
+        return Ok(Conditional {
+          branches,
+          end: next.range.end
+        })
+      }
+      (Some(next), Some("elif" | "else")) if !is_else => directive_start = next.range.start,
+      _ => {
+        return Err(format!(
+          "The block of the #{directive} must end with a `;`."
+        ))
       }
     }
-  );
+  }
 }
 
-pub enum ConditionType {
-  IfDefined,
-  IfNotDefined
+/// Returns the `{` that opens the next block, if it is the next token.
+fn next_block(tokens: &mut Tokens, content: &str) -> Option<Token> {
+  tokens
+    .next_significant()
+    .filter(|token| token.text(content) == "{")
 }
@@ -1,196 +1,570 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::path::Path;
+use std::rc::Rc;
 
-use crate::ast::span_manager::SpanManager;
-use crate::ast::ProgramInformation;
+use crate::ast::DiagnosticKind;
 use crate::preprocessor::MacroConstant;
+use crate::utils::convert_line_endings;
 
+use super::expansions::Expansion;
+use super::hygiene::rename_local_variables;
+use super::macro_call::{parse_macro_call, MalformedCall};
+use super::macro_scopes::MacroScopes;
+use super::origins::{copy_with_origins, Origin, OriginMap};
 use super::pragma_replace::get_pragma_replace_directives;
+use super::scopes::{ScopeKind, ScopeTracker};
+use super::tokens::{
+  directive_name, is_identifier_char, matching_brace, tokenize, TokenKind, Tokens
+};
 use super::types::*;
 
-pub fn expand_macros(
-  registered_macros: &mut HashMap<String, MacroDefinition>, new_content: &mut String,
-  regex_collection: &RegexCollection
-) -> bool {
-  loop {
-    let cap = regex_collection.macro_function.captures(&new_content);
+/// Expands the macros of the files, the macros a file defines are known to all
+/// the files of its package.
+pub struct MacroExpander<'a> {
+  pub macros: MacroScopes,
+
+  /// The number of expansions in each file, so the local variables of each
+  /// expansion get a name that is unique in the file and does not depend on
+  /// the other files
+  file_expansions: HashMap<FileName, usize>,
+
+  /// The malformed definitions and calls, and the expansions that reached the
+  /// limits of the options
+  pub diagnostics: Vec<PreprocessorDiagnostic>,
+
+  options: &'a PreprocessorOptions,
+
+  /// The files that grew past the size limit, their macros are not expanded
+  /// anymore
+  halted_files: HashSet<FileName>,
+
+  /// The call sites whose expansions reached the depth limit, so a recursive
+  /// macro is reported once per call
+  reported_call_sites: HashSet<(FileName, usize)>,
+
+  /// The package of the file whose macros are expanded, `None` for the sources
+  package: Option<DependencyName>
+}
+
+impl<'a> MacroExpander<'a> {
+  /// Creates the expander with the macro constants the options define.
+  pub fn new(options: &'a PreprocessorOptions) -> Self {
+    Self {
+      macros: MacroScopes::new(options),
+      file_expansions: HashMap::new(),
+      diagnostics: Vec::new(),
+      options,
+      halted_files: HashSet::new(),
+      reported_call_sites: HashSet::new(),
+      package: None
+    }
+  }
+
+  /// Returns the number of expansions in all the files.
+  pub fn expansions(&self) -> usize {
+    self.file_expansions.values().sum()
+  }
 
-    if cap.is_none() {
-      break;
+  /// A pass over the content of a file of the package, it registers the
+  /// macros the file defines or includes and expands the calls to the macros
+  /// the package knows. The calls the expansions produce are left for the next
+  /// pass, the content is only read once per pass. The origins of the file
+  /// keep track of the code that comes from the expansions, from one pass to
+  /// the next. Returns whether the content changed.
+  pub fn expand_macros(
+    &mut self, dependency: Option<&str>, filename: &str, file: &ProcessedFile
+  ) -> bool {
+    if self.halted_files.contains(filename) {
+      return false;
     }
 
-    let cap = cap.unwrap();
-    let macro_name = &cap[1];
-    let macro_parameters = &cap[2].trim();
-    let macro_body = &cap[3].trim();
+    self.package = dependency.map(str::to_string);
 
-    let f = parse_macro_function(macro_parameters, macro_body);
-    let body_length = f.body.len();
-    let macro_end = new_content.find(&f.body).unwrap();
+    let input = file.content.take();
+    let input_origins = file.origins.take();
+    let mut output = String::with_capacity(input.len());
+    let mut origins = OriginMap::default();
+    let mut scopes = ScopeTracker::default();
+    let mut tokens = Tokens::new(&input, 0);
+    let mut copied_until = 0;
+    let mut has_changed = false;
 
-    registered_macros.insert(macro_name.to_string(), MacroDefinition::Function(f));
+    while let Some(token) = tokens.next() {
+      let text = token.text(&input);
+      let start = token.range.start;
 
-    let macro_start_pattern = format!("#define function {macro_name}");
-    let macro_start = new_content.find(&macro_start_pattern).unwrap();
-    let closing_bracket_index = &new_content[macro_end + body_length..].find("};").unwrap();
+      scopes.observe(&token, &input);
+
+      let directive = match text {
+        "#" => directive_name(&input, start),
+        _ => None
+      };
+      let is_definition = directive == Some("define");
+      let is_include = directive == Some("include");
+
+      // the calls to unknown macros are reported once all macros are expanded,
+      // and `a!= b` is a comparison
+      let is_any_call = token.kind == TokenKind::Identifier && is_macro_call(&input, &token.range);
+      let package = match is_any_call {
+        true => self.resolve_call(&input_origins, start, text),
+        false => None
+      };
+      let is_call = package.is_some();
+      let is_builtin = is_any_call && !is_call && BUILTIN_MACROS.contains(&text);
+
+      if !is_definition && !is_include && !is_call && !is_builtin {
+        continue;
+      }
+
+      copy_with_origins(
+        &input,
+        &input_origins,
+        copied_until..start,
+        &mut output,
+        &mut origins
+      );
+
+      let end = if is_definition {
+        self.register_macro(filename, &input_origins, &input, start)
+      } else if is_include {
+        let directive = input_origins.original_range(start..start + "#include".len());
+        let directory = file.path.parent().unwrap_or(Path::new(""));
+
+        self.include_header(filename, &directive, directory, &input, start)
+      } else if is_builtin {
+        // +1 for the !
+        let end = start + text.len() + 1;
+        let call_site = input_origins.original_range(start..end);
+        let value = builtin_macro_value(text, file, &scopes, call_site.start);
+
+        origins.push(
+          output.len()..output.len() + value.len(),
+          input_origins.origin_of(start..end)
+        );
+        output.push_str(&value);
+
+        end
+      } else {
+        let expansion = new_expansion(&input_origins, start, text, package.flatten());
+
+        self.expand_call(
+          filename,
+          &input,
+          &mut output,
+          &mut origins,
+          start,
+          expansion
+        )
+      };
+
+      tokens = Tokens::new(&input, end);
+      copied_until = end;
+      has_changed = true;
+
+      if output.len() > self.options.expansion_size_limit {
+        self.report_size_limit(filename, origins.expansion_at(output.len() - 1));
+        self.halted_files.insert(filename.to_string());
+
+        break;
+      }
+    }
 
-    new_content.replace_range(
-      macro_start..macro_end + body_length + closing_bracket_index + 2,
-      ""
+    copy_with_origins(
+      &input,
+      &input_origins,
+      copied_until..input.len(),
+      &mut output,
+      &mut origins
     );
+    file.content.replace(output);
+    file.origins.replace(origins);
+
+    has_changed
   }
 
-  loop {
-    let cap = regex_collection.macro_const.captures(&new_content);
+  /// Returns the package whose macros the body of the called macro sees, if
+  /// the macro is known. A call from the body of an exported macro sees the
+  /// macros of the package that exports it first, then the macros of the file.
+  fn resolve_call(
+    &self, origins: &OriginMap, call_start: usize, macro_name: &str
+  ) -> Option<Option<DependencyName>> {
+    let expansion_package = origins
+      .expansion_at(call_start)
+      .map(|expansion| expansion.package.as_deref())
+      .filter(|package| *package != self.package.as_deref());
+
+    expansion_package
+      .and_then(|package| self.macros.resolve(package, macro_name))
+      .or_else(|| self.macros.resolve(self.package.as_deref(), macro_name))
+      .map(|(package, _)| package.map(str::to_string))
+  }
 
-    if cap.is_none() {
-      break;
+  /// Registers the macro the `#define` directive at the given index defines,
+  /// and returns the index where the directive ends. A malformed directive is
+  /// reported and its first line is removed.
+  fn register_macro(
+    &mut self, filename: &str, origins: &OriginMap, content: &str, start: usize
+  ) -> usize {
+    match parse_macro_definition(content, start) {
+      Ok(parsed) => {
+        self.define_macro(parsed.definition, parsed.is_exported);
+
+        parsed.end
+      }
+      Err(label) => {
+        let range = origins.original_range(start..start + "#define".len());
+
+        self.diagnostics.push(PreprocessorDiagnostic {
+          kind: DiagnosticKind::Error,
+          code: "malformed-macro-definition",
+          filename: filename.to_string(),
+          range,
+          message: "Malformed macro definition".to_string(),
+          label,
+          help: None
+        });
+
+        content[start..]
+          .find('\n')
+          .map_or(content.len(), |index| start + index)
+      }
     }
+  }
 
-    let cap = cap.unwrap();
-    let macro_name = &cap[1];
+  /// Defines the macro in the package of the file, unless the options set it.
+  fn define_macro(&mut self, definition: MacroDefinition, is_exported: bool) {
+    let name = match &definition {
+      MacroDefinition::Function(function) => function.name.clone(),
+      MacroDefinition::Constant(constant) => constant.name.clone()
+    };
 
-    let macro_value = match regex_collection.macro_const_value.captures(&cap[0]) {
-      Some(value_capture) => value_capture[1].to_string(),
-      None => String::from("true")
+    // the values of the options and of the library win over the ones of the
+    // files, the directive is removed either way
+    if !self.options.is_set(self.package.as_deref(), &name) {
+      self
+        .macros
+        .package_mut(self.package.as_deref())
+        .define(name, definition, is_exported);
+    }
+  }
+
+  /// Reads the header the `#include` directive at the given index includes,
+  /// and returns the index where the directive ends. The path of the header is
+  /// relative to the directory of the file or the header that includes it, and
+  /// every header is read once per package. The directive is the range of the
+  /// `#include` in the file, the problems of the headers it includes are
+  /// reported there as well.
+  fn include_header(
+    &mut self, filename: &str, directive: &Range<usize>, directory: &Path, content: &str,
+    start: usize
+  ) -> usize {
+    let (path, end) = match parse_include(content, start) {
+      Ok(include) => include,
+      Err(label) => {
+        self.report_include(filename, directive, "malformed-include", label);
+
+        return content[start..]
+          .find('\n')
+          .map_or(content.len(), |index| start + index);
+      }
     };
 
-    println!("registering macro const: {macro_name} = {macro_value}");
+    let header_path = directory.join(path);
+    let header_content = match std::fs::read_to_string(&header_path) {
+      Ok(header_content) => convert_line_endings(header_content),
+      Err(_) => {
+        let label = format!("`{}` does not exist.", header_path.display());
+        self.report_include(filename, directive, "header-not-found", label);
 
-    registered_macros.insert(
-      macro_name.to_string(),
-      MacroDefinition::Constant(MacroConstant {
-        name: macro_name.to_string(),
-        value: macro_value.to_string()
-      })
-    );
+        return end;
+      }
+    };
+
+    let is_first_include = self
+      .macros
+      .package_mut(self.package.as_deref())
+      .included_headers
+      .insert(dunce::canonicalize(&header_path).unwrap_or(header_path.clone()));
 
-    let macro_start = new_content
-      .find(&format!("#define const {macro_name}"))
-      .unwrap();
-    let macro_end = macro_start + &cap[0].len();
+    if is_first_include {
+      self.read_header(filename, directive, &header_path, &header_content);
+    }
 
-    new_content.replace_range(macro_start..macro_end, "");
+    end
   }
 
-  let content_copy = new_content.clone();
-  for cap in regex_collection.macro_call.captures_iter(&content_copy) {
-    let macro_name = &cap[1];
+  /// Defines the macros of the header and reads the headers it includes, a
+  /// header only contains `#define` and `#include` directives.
+  fn read_header(
+    &mut self, filename: &str, directive: &Range<usize>, header_path: &Path, content: &str
+  ) {
+    let directory = header_path.parent().unwrap_or(Path::new(""));
+    let mut tokens = Tokens::new(content, 0);
+
+    while let Some(token) = tokens.next_significant() {
+      let start = token.range.start;
+      let line = content[..start].matches('\n').count() + 1;
+      let location = format!("{}:{line}", header_path.display());
+
+      let end = match (token.text(content), directive_name(content, start)) {
+        ("#", Some("define")) => match parse_macro_definition(content, start) {
+          Ok(parsed) => {
+            self.define_macro(parsed.definition, parsed.is_exported);
+
+            parsed.end
+          }
+          Err(label) => {
+            let label = format!("{label} In {location}.");
+            self.report_include(filename, directive, "malformed-macro-definition", label);
 
-    // println!("found macro call: {macro_name}");
+            content[start..]
+              .find('\n')
+              .map_or(content.len(), |index| start + index)
+          }
+        },
+        ("#", Some("include")) => {
+          self.include_header(filename, directive, directory, content, start)
+        }
+        (text, _) => {
+          let label = format!(
+            "A header only contains #define and #include directives, but `{text}` was found in {location}."
+          );
+          self.report_include(filename, directive, "invalid-header", label);
 
-    if !registered_macros.contains_key(macro_name) {
-      println!("Warning, macro call to unkown macro: {}", &macro_name);
+          return;
+        }
+      };
 
-      continue;
+      tokens = Tokens::new(content, end);
     }
-
-    expand_macro_call(new_content, macro_name, &registered_macros);
   }
 
-  let contains_macro_call = regex_collection.macro_call.find(&new_content).is_some();
+  /// Reports a problem of the `#include` directive or of the header it
+  /// includes.
+  fn report_include(
+    &mut self, filename: &str, directive: &Range<usize>, code: &'static str, label: String
+  ) {
+    let message = match code {
+      "malformed-macro-definition" => "Malformed macro definition",
+      "invalid-header" => "Invalid header",
+      _ => "Invalid include"
+    };
 
-  contains_macro_call
-}
+    self.diagnostics.push(PreprocessorDiagnostic {
+      kind: DiagnosticKind::Error,
+      code,
+      filename: filename.to_string(),
+      range: directive.clone(),
+      message: message.to_string(),
+      label,
+      help: None
+    });
+  }
 
-fn expand_macro_call(
-  content: &mut String, macro_name: &str, registered_macros: &HashMap<String, MacroDefinition>
-) {
-  let macro_call_index = content.find(&format!("{macro_name}!"));
+  /// Pushes the expansion of the call to the output, and returns the index
+  /// where the call ends in the content.
+  fn expand_call(
+    &mut self, filename: &str, content: &str, output: &mut String, origins: &mut OriginMap,
+    call_start: usize, expansion: Rc<Expansion>
+  ) -> usize {
+    let macro_name = expansion.macro_name.as_str();
+    let definition = self
+      .macros
+      .get(expansion.package.as_deref(), macro_name)
+      .expect("only the calls to known macros are expanded");
+
+    if expansion.depth > self.options.expansion_depth_limit {
+      // the call is removed so the recursion ends
+      let end = match (
+        definition,
+        parse_macro_call(content, call_start, macro_name)
+      ) {
+        (MacroDefinition::Function(_), Ok(call)) => call.range.end,
+        _ => call_start + macro_name.len() + 1
+      };
 
-  if macro_call_index.is_none() {
-    println!("could not find macro call {macro_name}!");
+      self.report_depth_limit(filename, &expansion);
 
-    return;
+      return end;
+    }
+
+    match expand_macro_call(
+      content,
+      output,
+      call_start,
+      macro_name,
+      definition,
+      self
+        .file_expansions
+        .entry(filename.to_string())
+        .or_default()
+    ) {
+      Ok(Expanded { end, body_start }) => {
+        // the `$` takes the code before the call into the body
+        origins.truncate(body_start);
+        origins.push(body_start..output.len(), Origin::Expansion(expansion));
+
+        end
+      }
+      Err(malformed_call) => {
+        self.diagnostics.push(PreprocessorDiagnostic {
+          kind: DiagnosticKind::Error,
+          code: "malformed-macro-call",
+          filename: filename.to_string(),
+          range: expansion.call_site.clone(),
+          message: malformed_call.message,
+          label: malformed_call.label,
+          help: expansion.parent.as_deref().map(chain_help)
+        });
+
+        // the call is removed so it is only reported once
+        malformed_call.range.end
+      }
+    }
   }
 
-  let macro_call_index = macro_call_index.unwrap();
-  let definition = registered_macros.get(macro_name).unwrap();
+  fn report_depth_limit(&mut self, filename: &str, expansion: &Expansion) {
+    let call_site = (filename.to_string(), expansion.call_site.start);
+    if !self.reported_call_sites.insert(call_site) {
+      return;
+    }
 
-  match definition {
-    MacroDefinition::Function(function) => {
-      // +1 to remove the opening parenthesis and the !
-      let mut slice = &content[macro_call_index + macro_name.len() + 2..];
-      let mut parameters = Vec::new();
+    let root_name = expansion.chain()[0].to_string();
+
+    self.diagnostics.push(PreprocessorDiagnostic {
+      kind: DiagnosticKind::Error,
+      code: "macro-expansion-limit",
+      filename: filename.to_string(),
+      range: expansion.call_site.clone(),
+      message: "Macro expansion depth limit exceeded".to_string(),
+      label: format!(
+        "{root_name} expands into more than {} nested macro calls, the call to {} is removed.",
+        self.options.expansion_depth_limit, expansion.macro_name
+      ),
+      help: Some(chain_help(expansion))
+    });
+  }
 
-      loop {
-        if slice.starts_with(",") {
-          slice = &slice[1..];
-        }
+  fn report_size_limit(&mut self, filename: &str, expansion: Option<&Rc<Expansion>>) {
+    let label = format!(
+      "The file grows past {} bytes, the rest of its macros are not expanded.",
+      self.options.expansion_size_limit
+    );
 
-        while slice.starts_with(" ") {
-          slice = &slice[1..];
-        }
+    self.diagnostics.push(PreprocessorDiagnostic {
+      kind: DiagnosticKind::Error,
+      code: "macro-expansion-limit",
+      filename: filename.to_string(),
+      range: expansion.map_or(0..0, |expansion| expansion.call_site.clone()),
+      message: "Macro expansion size limit exceeded".to_string(),
+      label,
+      help: expansion.map(|expansion| chain_help(expansion))
+    });
+  }
+}
 
-        if parameters.len() == function.parameters.len() || slice.starts_with(")") {
-          break;
-        }
+/// Returns the expansion of the call at the given index, its parent is the
+/// expansion the call comes from, if any.
+fn new_expansion(
+  origins: &OriginMap, call_start: usize, macro_name: &str, package: Option<DependencyName>
+) -> Rc<Expansion> {
+  let parent = origins.expansion_at(call_start).cloned();
+  let call_site = match &parent {
+    Some(parent) => parent.call_site.clone(),
+    None => origins.original_range(call_start..call_start + macro_name.len())
+  };
 
-        if slice.trim().starts_with("{{") {
-          // it's a body of code,
+  Rc::new(Expansion {
+    macro_name: macro_name.to_string(),
+    package,
+    depth: parent.as_ref().map_or(0, |parent| parent.depth) + 1,
+    parent,
+    call_site
+  })
+}
 
-          let body_end_index = slice.find("}}");
+/// The macros whose values depend on where they are called
+const BUILTIN_MACROS: [&str; 4] = ["__FILE__", "__LINE__", "__FUNCTION__", "__CLASS__"];
+
+/// Returns the literal the builtin macro expands to, the call site is the
+/// index of the call in the original content. A call that comes from an
+/// expansion is on the line of the call written in the file, so a logging
+/// macro prints where it is used.
+fn builtin_macro_value(
+  macro_name: &str, file: &ProcessedFile, scopes: &ScopeTracker, call_site: usize
+) -> String {
+  match macro_name {
+    "__FILE__" => format!("{:?}", file.relative_path),
+    "__LINE__" => {
+      let line = file.original_content[..call_site].matches('\n').count();
+
+      (line + 1).to_string()
+    }
+    "__FUNCTION__" => format!(
+      "{:?}",
+      scopes.enclosing(ScopeKind::Function).unwrap_or_default()
+    ),
+    _ => format!(
+      "{:?}",
+      scopes.enclosing(ScopeKind::Class).unwrap_or_default()
+    )
+  }
+}
 
-          if body_end_index.is_none() {
-            panic!("Unterminated body of code in macro call {}", macro_name);
-          }
+/// Returns the chain of macros that led to the expansion, as a help message.
+fn chain_help(expansion: &Expansion) -> String {
+  format!("expanded from {}", expansion.describe_chain())
+}
 
-          let body_end_index = body_end_index.unwrap();
+/// Returns whether the identifier is followed by a `!` that makes it a macro
+/// call, rather than the `!=` of a comparison.
+pub fn is_macro_call(content: &str, identifier: &Range<usize>) -> bool {
+  let rest = &content[identifier.end..];
 
-          parameters.push(&slice[2..body_end_index]);
+  rest.starts_with('!') && !rest.starts_with("!=")
+}
 
-          slice = &slice[2 + body_end_index + 2..];
-        } else {
-          let program_information = ProgramInformation::new();
-          let mut span_manager = SpanManager::new();
-          let mut span_maker = span_manager.add_fake_source();
+struct Expanded {
+  /// The index where the call ends in the content
+  end: usize,
 
-          let parsing_result = crate::parser::ExpressionParser::new().parse(
-            &program_information,
-            &mut span_maker,
-            &slice
-          );
+  /// The index where the body starts in the output
+  body_start: usize
+}
 
-          let parameter_slice = match parsing_result {
-            Ok(_) => unreachable!(),
-            // A bit of a weird case here, we expect to have an unrecognized token
-            // which will help us know when the expression end. So we get the
-            // index out of the error to build the slice.
-            Err(err) => match err {
-              lalrpop_util::ParseError::UnrecognizedToken { token, expected: _ } => {
-                let token_position = token.0;
-
-                &slice[..token_position]
-              }
-              lalrpop_util::ParseError::InvalidToken { location: _ } => unreachable!(),
-              lalrpop_util::ParseError::UnrecognizedEOF {
-                location: _,
-                expected: _
-              } => unreachable!(),
-              lalrpop_util::ParseError::ExtraToken { token: _ } => unreachable!(),
-              lalrpop_util::ParseError::User { error: _ } => unreachable!()
-            }
-          };
-
-          parameters.push(&parameter_slice);
-          slice = &slice[parameter_slice.len()..];
-
-          // this is the old, buggy, way of doing it:
-          // let end = slice.len();
-          // let comma_index = slice.find(",").unwrap_or(end);
-          // let paren_index = slice.find(")").unwrap_or(end);
-
-          // parameters.push(&slice[..comma_index.min(paren_index)]);
-          // slice = &slice[comma_index.min(paren_index)..];
-        }
+/// Pushes the expansion of the call to the output.
+fn expand_macro_call(
+  content: &str, output: &mut String, call_start: usize, macro_name: &str,
+  definition: &MacroDefinition, expansions: &mut usize
+) -> Result<Expanded, MalformedCall> {
+  match definition {
+    MacroDefinition::Function(function) => {
+      let call = parse_macro_call(content, call_start, macro_name)?;
+
+      if call.arguments.len() != function.parameters.len() {
+        return Err(MalformedCall {
+          message: "Wrong number of macro arguments".to_string(),
+          label: format!(
+            "{macro_name} takes {} arguments but {} were given.",
+            function.parameters.len(),
+            call.arguments.len()
+          ),
+          range: call.range
+        });
       }
 
-      let mut body = function.body.clone();
-      for i in 0..function.parameters.len() {
-        let parameter = &function.parameters[i];
-        let value = &parameters[i];
+      *expansions += 1;
 
-        body = body.replace(parameter, value.trim());
-      }
+      let body = rename_local_variables(&function.body, &function.parameters, *expansions);
+      let arguments = function
+        .parameters
+        .iter()
+        .map(String::as_str)
+        .zip(call.arguments.iter().map(|value| value.trim()))
+        .collect();
+
+      let mut body = substitute_parameters(&body, &arguments);
 
       let findreplace_directives = get_pragma_replace_directives(&body);
 
@@ -198,101 +572,527 @@ fn expand_macro_call(
         body = body.replace(&directive.find, &directive.replace);
       }
 
-      let end = slice.as_ptr() as usize - content.as_ptr() as usize;
-      let start = if body.contains("$") {
-        let line_before_macro_call = &content[..macro_call_index];
-
-        let start = if let Some(line) = line_before_macro_call.rfind(";") {
-          line + 1
-        } else {
-          0
+      // the `$` is replaced by the code of the statement before the call, up to
+      // the previous `;`
+      if body.contains("$") {
+        let start = match output.rfind(";") {
+          Some(line) => line + 1,
+          None => 0
         };
 
-        body = body.replace("$", &content[start..macro_call_index]);
+        body = body.replace("$", &output[start..]);
 
         // +1 to remove the ";"
-        start + 1
-      } else {
-        macro_call_index
-      };
+        output.truncate((start + 1).min(output.len()));
+      }
+
+      let body_start = output.len();
+      output.push_str(&body);
 
-      content.replace_range(start..end + 1, &body);
+      Ok(Expanded {
+        end: call.range.end,
+        body_start
+      })
     }
     MacroDefinition::Constant(constant) => {
-      *content = content.replacen(&format!("{}!", constant.name), &constant.value, 1);
+      let body_start = output.len();
+      output.push_str(&constant.value);
+
+      Ok(Expanded {
+        // +1 for the !
+        end: call_start + constant.name.len() + 1,
+        body_start
+      })
     }
   }
 }
 
-fn parse_macro_function(macro_parameters: &str, macro_body: &str) -> MacroFunction {
-  let parameters = if !macro_parameters.is_empty() {
-    let mut slice = &macro_parameters[..];
-    let mut output = Vec::new();
-
-    loop {
-      if slice.starts_with(",") {
-        slice = &slice[1..];
+/// Replaces the parameters of the macro with the values of the call. Only the
+/// whole identifiers are replaced, and never inside the string and name
+/// literals. A `#param` is replaced with the value as a string literal and a
+/// `#'param'` with the value as a name literal, and the `##` operator pastes the
+/// code on its sides together: `On ## state_name` gives `OnCombat`.
+fn substitute_parameters(body: &str, arguments: &HashMap<&str, &str>) -> String {
+  let mut output = String::with_capacity(body.len());
+  let tokens = tokenize(body);
+  let mut index = 0;
+
+  while let Some(token) = tokens.get(index) {
+    let text = token.text(body);
+    let next = tokens.get(index + 1);
+    let next_text = next.map_or("", |next| next.text(body));
+
+    index += 1;
+
+    match token.kind {
+      TokenKind::Symbol if text == "#" && next_text == "#" => {
+        // the whitespace around the operator is removed
+        output.truncate(output.trim_end().len());
+        index += 1;
+
+        while tokens
+          .get(index)
+          .is_some_and(|token| token.kind == TokenKind::Whitespace)
+        {
+          index += 1;
+        }
       }
+      TokenKind::Symbol if text == "#" => match (next.map(|next| next.kind), next_text) {
+        (Some(TokenKind::Identifier), parameter) if arguments.contains_key(parameter) => {
+          output.push_str(&stringify(arguments[parameter], '"'));
+          index += 1;
+        }
+        (Some(TokenKind::NameLiteral), name) if arguments.contains_key(name.trim_matches('\'')) => {
+          output.push_str(&stringify(arguments[name.trim_matches('\'')], '\''));
+          index += 1;
+        }
+        _ => output.push_str(text)
+      },
+      TokenKind::Identifier => output.push_str(arguments.get(text).unwrap_or(&text)),
+      TokenKind::Pragma => output.push_str(&substitute_pragma_parameters(text, arguments)),
+      _ => output.push_str(text)
+    }
+  }
 
-      if slice.starts_with(")") {
-        break;
-      }
+  output
+}
 
-      let end = slice.len();
-      let comma_index = slice.find(",");
-      let paren_index = slice.find(")");
-      let parameter = &slice[..comma_index.unwrap_or(end).min(paren_index.unwrap_or(end))];
+/// Returns the value of a parameter as a literal with the given quotes, its
+/// whitespace is collapsed so the literal stays on one line. The literals have
+/// no escapes, so the quotes of the value become the other kind of quotes.
+fn stringify(value: &str, quote: char) -> String {
+  let other_quote = match quote {
+    '"' => "'",
+    _ => "\""
+  };
 
-      output.push(parameter.trim().to_string());
+  let value = value
+    .split_whitespace()
+    .collect::<Vec<_>>()
+    .join(" ")
+    .replace(quote, other_quote);
 
-      slice = &slice[parameter.len()..];
+  format!("{quote}{value}{quote}")
+}
 
-      if comma_index.is_none() {
-        break;
-      }
-    }
+/// The patterns of the `#pragma` directives are plain text, so the parameters
+/// are replaced in the quotes as well.
+fn substitute_pragma_parameters(pragma: &str, arguments: &HashMap<&str, &str>) -> String {
+  let mut output = String::with_capacity(pragma.len());
+  let mut rest = pragma;
+
+  while let Some(c) = rest.chars().next() {
+    let length = match rest.find(|c: char| !is_identifier_char(c)) {
+      Some(0) => c.len_utf8(),
+      Some(length) => length,
+      None => rest.len()
+    };
 
-    output
-  } else {
-    Vec::new()
-  };
+    let word = &rest[..length];
+    output.push_str(arguments.get(word).unwrap_or(&word));
+    rest = &rest[length..];
+  }
 
-  // before starting to parse, we must find the actual end of the body since the
-  // regex is greedy. It is done on purpose to allow recursive macros and macros
-  // that expand into #define calls.
-  let mut slice = &macro_body[..];
-  loop {
-    let define_index = slice.find("#define function");
-    let macro_end_index = slice.find("};");
+  output
+}
+
+/// A `#define` directive
+struct ParsedDefinition {
+  definition: MacroDefinition,
+
+  /// Whether the directive is a `#define export`, the macro is then known to
+  /// the packages that depend on the one that defines it.
+  is_exported: bool,
 
-    let macro_end_index = match macro_end_index {
-      Some(i) => i,
-      None => slice.len()
+  /// The index where the directive ends
+  end: usize
+}
+
+/// Parses the `#define` directive at the given index, or returns the label of
+/// the error if the directive is malformed.
+fn parse_macro_definition(content: &str, start: usize) -> Result<ParsedDefinition, String> {
+  // the `define` that follows the `#`
+  let directive = Tokens::new(content, start + 1).next();
+  let mut tokens = Tokens::new(
+    content,
+    directive.map_or(start + 1, |token| token.range.end)
+  );
+
+  let mut kind = tokens.next_significant();
+  let is_exported = kind
+    .as_ref()
+    .is_some_and(|kind| kind.text(content) == "export");
+
+  if is_exported {
+    kind = tokens.next_significant();
+  }
+
+  let kind = kind
+    .filter(|kind| matches!(kind.text(content), "function" | "const"))
+    .ok_or("#define must be followed by `function` or `const`.")?;
+
+  let name = tokens
+    .next_significant()
+    .filter(|name| name.kind == TokenKind::Identifier)
+    .ok_or("The macro has no name.")?
+    .text(content)
+    .to_string();
+
+  if kind.text(content) == "const" {
+    // the definition ends with the last `;` of its line
+    let line_start = kind.range.end;
+    let line_end = content[line_start..]
+      .find('\n')
+      .map(|index| line_start + index)
+      .unwrap_or(content.len());
+    let line = &content[line_start..line_end];
+    let semicolon = line
+      .rfind(';')
+      .ok_or_else(|| format!("The definition of {name} must end with a `;`."))?;
+
+    let value = match line[..semicolon].find("= ") {
+      Some(equal) => line[equal + 2..semicolon].to_string(),
+      None => String::from("true")
     };
 
-    if define_index.is_none() || macro_end_index < define_index.unwrap() {
-      // let macro_end_index = slice.find("};");
+    return Ok(ParsedDefinition {
+      definition: MacroDefinition::Constant(MacroConstant { name, value }),
+      is_exported,
+      end: line_start + semicolon + 1
+    });
+  }
 
-      slice = &slice[..macro_end_index];
+  if tokens.next_significant().map(|token| token.text(content)) != Some("(") {
+    return Err(format!("The parameters of {name} are missing."));
+  }
+
+  let mut parameters = Vec::new();
+  loop {
+    let token = tokens
+      .next_significant()
+      .ok_or_else(|| format!("The parameters of {name} are never closed."))?;
+
+    match (token.kind, token.text(content)) {
+      (_, ")") => break,
+      (_, ",") => {}
+      (TokenKind::Identifier, parameter) => parameters.push(parameter.to_string()),
+      (_, text) => return Err(format!("`{text}` is not a valid parameter of {name}."))
+    }
+  }
+
+  let opening_brace = tokens
+    .next_significant()
+    .filter(|token| token.text(content) == "{")
+    .ok_or_else(|| format!("The body of {name} is missing."))?;
 
-      break;
+  let closing_brace = matching_brace(content, opening_brace.range.start)
+    .ok_or_else(|| format!("The body of {name} is never closed."))?;
+  let body = content[opening_brace.range.end..closing_brace.start].trim();
+
+  // the definition ends with `};`
+  let mut end = closing_brace.end;
+  if let Some(semicolon) = Tokens::new(content, end).next_significant() {
+    if semicolon.text(content) == ";" {
+      end = semicolon.range.end;
     }
+  }
+
+  Ok(ParsedDefinition {
+    definition: MacroDefinition::Function(MacroFunction {
+      name,
+      parameters,
+      body: body.to_string()
+    }),
+    is_exported,
+    end
+  })
+}
+
+/// Parses the `#include` directive at the given index, and returns the path of
+/// the header with the index where the directive ends. Or the label of the
+/// error if the directive is malformed.
+fn parse_include(content: &str, start: usize) -> Result<(&str, usize), String> {
+  // the `include` that follows the `#`
+  let directive = Tokens::new(content, start + 1).next();
+  let mut tokens = Tokens::new(
+    content,
+    directive.map_or(start + 1, |token| token.range.end)
+  );
+
+  let path = tokens
+    .next_significant()
+    .filter(|path| path.kind == TokenKind::StringLiteral)
+    .map(|path| (path.text(content), path.range.end))
+    .filter(|(path, _)| path.len() > 2 && path.ends_with('"'))
+    .ok_or("#include must be followed by the path of a header, like `#include \"macros.wssh\"`.")?;
+
+  // the directive may end with a `;`
+  let end = match Tokens::new(content, path.1).next_significant() {
+    Some(semicolon) if semicolon.text(content) == ";" => semicolon.range.end,
+    _ => path.1
+  };
 
-    slice = &slice[define_index.unwrap() + "#define function".len()..];
+  Ok((&path.0[1..path.0.len() - 1], end))
+}
 
-    let macro_end_index = slice.find("};");
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_utils::{normalize, preprocess_files, preprocess_source, processed_file};
 
-    if macro_end_index.is_none() {
-      panic!("2, unfinished macro");
+  fn limits(depth: usize, size: usize) -> PreprocessorOptions {
+    PreprocessorOptions {
+      expansion_depth_limit: depth,
+      expansion_size_limit: size,
+      ..Default::default()
     }
+  }
+
+  /// Returns the codes, messages and labels of the diagnostics.
+  fn diagnostics(output: &PreprocessorOutput) -> Vec<(&str, &str, &str)> {
+    output
+      .diagnostics
+      .iter()
+      .map(|diagnostic| {
+        (
+          diagnostic.code,
+          diagnostic.message.as_str(),
+          diagnostic.label.as_str()
+        )
+      })
+      .collect()
+  }
+
+  #[test]
+  fn recursive_macros_stop_at_the_depth_limit() {
+    let source = "#define function OUTER(x) {\n  INNER!(x)\n};\n#define function INNER(x) {\n  INNER!(x)\n};\n\nfunction main() {\n  OUTER!(1)\n}\n";
+    let output = preprocess_source(source, &limits(4, usize::MAX));
+    let file = processed_file(&output, "main.wss");
+
+    assert_eq!(
+      diagnostics(&output),
+      vec![(
+        "macro-expansion-limit",
+        "Macro expansion depth limit exceeded",
+        "OUTER expands into more than 4 nested macro calls, the call to INNER is removed."
+      )]
+    );
+
+    // the diagnostic is at the call written in the file
+    let diagnostic = &output.diagnostics[0];
+    assert_eq!(&file.original_content[diagnostic.range.clone()], "OUTER");
+    assert!(diagnostic
+      .help
+      .as_ref()
+      .unwrap()
+      .starts_with("expanded from OUTER! -> INNER!"));
+    assert!(!file.content.borrow().contains("INNER!"));
+  }
+
+  #[test]
+  fn recursive_macros_are_reported_once_per_call() {
+    let source =
+      "#define function LOOP() {\n  LOOP!()\n};\n\nfunction main() {\n  LOOP!()\n  LOOP!()\n}\n";
+    let output = preprocess_source(source, &limits(8, usize::MAX));
+
+    assert_eq!(output.diagnostics.len(), 2);
+  }
 
-    slice = &slice[macro_end_index.unwrap() + "};".len()..];
+  #[test]
+  fn growing_files_stop_at_the_size_limit() {
+    let source =
+      "#define function GROW(x) {\n  GROW!(x x)\n};\n\nfunction main() {\n  GROW!(a)\n}\n";
+    let output = preprocess_source(source, &limits(1000, 4096));
+    let file = processed_file(&output, "main.wss");
+
+    assert_eq!(
+      diagnostics(&output),
+      vec![(
+        "macro-expansion-limit",
+        "Macro expansion size limit exceeded",
+        "The file grows past 4096 bytes, the rest of its macros are not expanded."
+      )]
+    );
+    assert_eq!(
+      &file.original_content[output.diagnostics[0].range.clone()],
+      "GROW"
+    );
+    assert!(file.content.borrow().len() < 2 * 4096);
   }
 
-  let end = slice.as_ptr() as usize - macro_body.as_ptr() as usize + slice.len();
+  #[test]
+  fn unclosed_macro_bodies_are_reported() {
+    let source = "#define function OPEN(x) {\n  x\n\nfunction main() {}\n";
+    let output = preprocess_source(source, &PreprocessorOptions::default());
+
+    assert_eq!(
+      diagnostics(&output),
+      vec![(
+        "malformed-macro-definition",
+        "Malformed macro definition",
+        "The body of OPEN is never closed."
+      )]
+    );
+  }
+
+  #[test]
+  fn unclosed_macro_calls_are_reported() {
+    let source = "#define function ID(x) {\n  x\n};\n\nfunction main() {\n  ID!(1\n}\n";
+    let output = preprocess_source(source, &PreprocessorOptions::default());
+
+    assert_eq!(output.diagnostics.len(), 1);
+    assert_eq!(output.diagnostics[0].code, "malformed-macro-call");
+  }
+
+  fn substitute(body: &str, arguments: &[(&str, &str)]) -> String {
+    substitute_parameters(body, &arguments.iter().copied().collect())
+  }
+
+  #[test]
+  fn parameters_are_stringified() {
+    let arguments = [("x", "count  *\n  \"x\""), ("name", "Combat")];
+
+    assert_eq!(substitute("#x", &arguments), "\"count * 'x'\"");
+    assert_eq!(substitute("#'name'", &arguments), "'Combat'");
+    assert_eq!(
+      substitute("print(#name);", &arguments),
+      "print(\"Combat\");"
+    );
+  }
+
+  #[test]
+  fn hashes_without_parameters_are_kept() {
+    let arguments = [("x", "1")];
+
+    assert_eq!(substitute("#other", &arguments), "#other");
+    assert_eq!(substitute("#'other'", &arguments), "#'other'");
+    assert_eq!(substitute("\"x\" 'x' x", &arguments), "\"x\" 'x' 1");
+  }
+
+  #[test]
+  fn code_is_pasted() {
+    let arguments = [("state", "Combat"), ("suffix", "Entered")];
+
+    assert_eq!(
+      substitute("this.On ## state ## suffix();", &arguments),
+      "this.OnCombatEntered();"
+    );
+    assert_eq!(substitute("prefix##state", &arguments), "prefixCombat");
+    assert_eq!(substitute("a ##\n  b", &arguments), "ab");
+  }
+
+  #[test]
+  fn stringified_and_pasted_names_are_expanded() {
+    let source = "#define function LOGGER(channel) {\n  function Log ## channel(message: string) {\n    LogChannel(#'channel', message);\n  }\n};\n\nLOGGER!(Combat)\n";
+    let output = preprocess_source(source, &PreprocessorOptions::default());
+    let file = processed_file(&output, "main.wss");
+
+    assert!(output.diagnostics.is_empty());
+    assert_eq!(
+      normalize(&file.content.borrow()),
+      "function LogCombat(message: string) {\nLogChannel('Combat', message);\n}"
+    );
+  }
+
+  #[test]
+  fn local_variables_are_numbered_per_file() {
+    let macros =
+      "#define function LOOP(count) {\n  var i: int;\n  for (i = 0; i < count; i += 1) {}\n};\n";
+    let caller = "function b() {\n  LOOP!(1)\n  LOOP!(2)\n}\n";
+    let options = PreprocessorOptions::default();
+
+    let before = preprocess_files(
+      &[
+        ("src/macros.wss", macros),
+        ("src/a.wss", "function a() {\n  LOOP!(1)\n}\n"),
+        ("src/b.wss", caller)
+      ],
+      &options
+    );
+    let after = preprocess_files(
+      &[
+        ("src/macros.wss", macros),
+        ("src/a.wss", "function a() {\n  LOOP!(1)\n  LOOP!(2)\n}\n"),
+        ("src/b.wss", caller)
+      ],
+      &options
+    );
+
+    let content = |output: &PreprocessorOutput| processed_file(output, "b.wss").content.take();
+    let before = content(&before);
+
+    assert!(before.contains("var i__1: int;"), "{before}");
+    assert!(before.contains("var i__2: int;"), "{before}");
+    assert_eq!(before, content(&after));
+  }
+
+  #[test]
+  fn exported_variables_are_visible_to_the_caller() {
+    let source = "#define function COUNT(items) {\n  #export var total: int;\n  var i: int;\n  for (i = 0; i < items.Size(); i += 1) {\n    total += items[i];\n  }\n};\n\nfunction main(items: array<int>): int {\n  COUNT!(items)\n  return total;\n}\n";
+    let output = preprocess_source(source, &PreprocessorOptions::default());
+    let content = processed_file(&output, "main.wss").content.take();
+
+    assert!(output.diagnostics.is_empty());
+    assert!(content.contains("var total: int;"), "{content}");
+    assert!(content.contains("var i__1: int;"), "{content}");
+    assert!(content.contains("total += items[i__1];"), "{content}");
+    assert!(!content.contains("#export"), "{content}");
+  }
+
+  /// Returns the content of `src/logs/player.wss` once preprocessed, with
+  /// the `LOG!` macro defined in another file.
+  fn preprocess_player(source: &str) -> String {
+    let output = preprocess_files(
+      &[
+        (
+          "src/macros.wss",
+          "#define function LOG(message) {\n  print(__FILE__! + \":\" + __LINE__! + \" \" + __FUNCTION__! + \": \" + message);\n};\n"
+        ),
+        ("src/logs/player.wss", source)
+      ],
+      &PreprocessorOptions::default()
+    );
+
+    assert!(output.diagnostics.is_empty(), "{:?}", diagnostics(&output));
+
+    normalize(&processed_file(&output, "player.wss").content.take())
+  }
+
+  #[test]
+  fn file_and_line_macros_expand_to_the_call_site() {
+    let content = preprocess_player(
+      "function main() {\n  print(__FILE__!, __LINE__!);\n\n  LOG!(\"jumped\")\n}\n"
+    );
+
+    // the expansion of `LOG!` gets the file and the line of its call
+    assert!(
+      content.contains("print(\"logs/player.wss\", 2);"),
+      "{content}"
+    );
+    assert!(
+      content.contains(
+        "print(\"logs/player.wss\" + \":\" + 4 + \" \" + \"main\" + \": \" + \"jumped\");"
+      ),
+      "{content}"
+    );
+  }
+
+  #[test]
+  fn function_and_class_macros_expand_to_the_enclosing_names() {
+    let content = preprocess_player(
+      "class Player {\n  function jump() {\n    print(__CLASS__!, __FUNCTION__!);\n  }\n}\n\nstate Idle in Player {\n  event OnEnterState() {\n    print(__CLASS__!, __FUNCTION__!);\n  }\n}\n\nfunction main() {\n  print(__CLASS__!, __FUNCTION__!);\n}\n"
+    );
+
+    assert!(
+      content.contains("print(\"Player\", \"jump\");"),
+      "{content}"
+    );
+    assert!(
+      content.contains("print(\"Idle\", \"OnEnterState\");"),
+      "{content}"
+    );
 
-  MacroFunction {
-    parameters,
-    body: macro_body[..end].to_string()
+    // outside of a class the class is an empty string
+    assert!(content.contains("print(\"\", \"main\");"), "{content}");
   }
 }
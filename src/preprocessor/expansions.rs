@@ -0,0 +1,61 @@
+use std::ops::Range;
+use std::rc::Rc;
+
+/// A macro expansion, and the expansions it comes from
+#[derive(Debug)]
+pub struct Expansion {
+  pub macro_name: String,
+
+  /// The package whose macros the calls of the expanded body see, `None` for
+  /// the sources
+  pub package: Option<String>,
+
+  /// The expansion the call comes from, there is none for the calls written in
+  /// the file
+  pub parent: Option<Rc<Expansion>>,
+
+  /// The range of the call in the original content of the file. The nested
+  /// expansions have the range of the first call of the chain, as their calls
+  /// are not in the original content.
+  pub call_site: Range<usize>,
+
+  /// The number of expansions in the chain, 1 for a call written in the file
+  pub depth: usize
+}
+
+impl Expansion {
+  /// Returns the names of the macros of the chain, from the call written in the
+  /// file to this expansion.
+  pub fn chain(&self) -> Vec<&str> {
+    let mut chain = vec![self.macro_name.as_str()];
+    let mut parent = self.parent.as_ref();
+
+    while let Some(expansion) = parent {
+      chain.push(&expansion.macro_name);
+      parent = expansion.parent.as_ref();
+    }
+
+    chain.reverse();
+    chain
+  }
+
+  /// Returns the chain of macros that led to the expansion, as in
+  /// `A! -> B! -> C!`. The middle of the long chains is elided.
+  pub fn describe_chain(&self) -> String {
+    let chain: Vec<String> = self
+      .chain()
+      .into_iter()
+      .map(|name| format!("{name}!"))
+      .collect();
+
+    if chain.len() > 10 {
+      format!(
+        "{} -> ... -> {}",
+        chain[..5].join(" -> "),
+        chain[chain.len() - 4..].join(" -> ")
+      )
+    } else {
+      chain.join(" -> ")
+    }
+  }
+}
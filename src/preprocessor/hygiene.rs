@@ -0,0 +1,175 @@
+use std::collections::HashSet;
+
+use super::tokens::{tokenize, Token, TokenKind};
+
+#[derive(PartialEq)]
+enum Scope {
+  /// The body of a class, a state or a struct, the variables declared in it
+  /// are fields.
+  Type,
+  Function,
+  Block
+}
+
+/// Renames the variables the body of a macro declares so they cannot clash
+/// with the variables of the caller, every expansion gets its own suffix. The
+/// fields of the classes the macro declares keep their names, as well as the
+/// variables marked with `#export` which are meant to be used by the caller.
+pub fn rename_local_variables(body: &str, parameters: &[String], expansion: usize) -> String {
+  let tokens = tokenize(body);
+  let significant_tokens: Vec<usize> = (0..tokens.len())
+    .filter(|i| !is_trivia(&tokens[*i]))
+    .collect();
+
+  let text = |i: usize| tokens[i].text(body);
+  let mut local_variables = HashSet::new();
+  let mut removed_tokens = HashSet::new();
+  let mut scopes = Vec::new();
+  let mut next_scope = None;
+  let mut is_exported = false;
+  let mut is_added_field = false;
+
+  for (position, &i) in significant_tokens.iter().enumerate() {
+    let next = significant_tokens.get(position + 1).copied();
+
+    match text(i) {
+      "class" | "state" | "statemachine" | "struct" => next_scope = Some(Scope::Type),
+      "function" | "event" => next_scope = Some(Scope::Function),
+      "{" => scopes.push(next_scope.take().unwrap_or(Scope::Block)),
+      "}" => {
+        scopes.pop();
+      }
+      ";" => next_scope = None,
+      "@" if next.is_some_and(|next| text(next) == "addField") => is_added_field = true,
+      "#" if next == Some(i + 1) && text(i + 1) == "export" => {
+        is_exported = true;
+
+        // the marker is not emitted, nor the whitespace after it
+        removed_tokens.extend([i, i + 1]);
+        if tokens
+          .get(i + 2)
+          .is_some_and(|token| token.kind == TokenKind::Whitespace)
+        {
+          removed_tokens.insert(i + 2);
+        }
+      }
+      "var" => {
+        let is_field = is_added_field
+          || scopes.iter().rev().find(|scope| **scope != Scope::Block) == Some(&Scope::Type);
+
+        if !is_field && !is_exported {
+          // `var a, b: int;` declares both a and b
+          let mut declaration = significant_tokens[position + 1..]
+            .iter()
+            .map(|i| &tokens[*i]);
+
+          while let Some(name) = declaration.next() {
+            if name.kind != TokenKind::Identifier {
+              break;
+            }
+
+            if !parameters
+              .iter()
+              .any(|parameter| parameter == name.text(body))
+            {
+              local_variables.insert(name.text(body));
+            }
+
+            if declaration.next().map(|token| token.text(body)) != Some(",") {
+              break;
+            }
+          }
+        }
+
+        is_exported = false;
+        is_added_field = false;
+      }
+      _ => {}
+    }
+  }
+
+  let mut output = String::with_capacity(body.len());
+  let mut previous_token: Option<&Token> = None;
+
+  for (i, token) in tokens.iter().enumerate() {
+    if removed_tokens.contains(&i) {
+      continue;
+    }
+
+    let token_text = token.text(body);
+    let is_member_access = previous_token.is_some_and(|previous| previous.text(body) == ".");
+
+    if token.kind == TokenKind::Identifier
+      && !is_member_access
+      && local_variables.contains(token_text)
+    {
+      output.push_str(&format!("{token_text}__{expansion}"));
+    } else {
+      output.push_str(token_text);
+    }
+
+    if !is_trivia(token) {
+      previous_token = Some(token);
+    }
+  }
+
+  output
+}
+
+fn is_trivia(token: &Token) -> bool {
+  matches!(token.kind, TokenKind::Whitespace | TokenKind::Comment)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn rename(body: &str, parameters: &[&str]) -> String {
+    let parameters: Vec<String> = parameters.iter().map(|p| p.to_string()).collect();
+
+    rename_local_variables(body, &parameters, 2)
+  }
+
+  #[test]
+  fn local_variables_get_the_suffix_of_the_expansion() {
+    assert_eq!(
+      rename(
+        "var i: int;\nfor (i = 0; i < count; i += 1) {\n  this.i = i;\n}",
+        &["count"]
+      ),
+      "var i__2: int;\nfor (i__2 = 0; i__2 < count; i__2 += 1) {\n  this.i = i__2;\n}"
+    );
+  }
+
+  #[test]
+  fn every_declared_variable_is_renamed() {
+    assert_eq!(
+      rename("var a, b: int;\na = b;", &[]),
+      "var a__2, b__2: int;\na__2 = b__2;"
+    );
+  }
+
+  #[test]
+  fn parameters_keep_their_names() {
+    assert_eq!(rename("var x: int;\nx = 1;", &["x"]), "var x: int;\nx = 1;");
+  }
+
+  #[test]
+  fn exported_variables_keep_their_names() {
+    assert_eq!(
+      rename("#export var result: int;\nvar i: int;\nresult = i;", &[]),
+      "var result: int;\nvar i__2: int;\nresult = i__2;"
+    );
+  }
+
+  #[test]
+  fn fields_keep_their_names() {
+    assert_eq!(
+      rename(
+        "class Counter {\n  var count: int;\n\n  function add() {\n    var step: int;\n    count += step;\n  }\n}\n@addField(CPlayer)\nvar added: int;",
+        &[]
+      ),
+      "class Counter {\n  var count: int;\n\n  function add() {\n    var step__2: int;\n    count += step__2;\n  }\n}\n@addField(CPlayer)\nvar added: int;"
+    );
+  }
+}
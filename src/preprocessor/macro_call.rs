@@ -0,0 +1,194 @@
+use std::ops::Range;
+
+use super::tokens::{is_identifier_char, Tokens};
+
+/// A call to a macro function, split in its arguments
+pub struct MacroCall {
+  /// The byte range of the call, from the name of the macro to the closing
+  /// parenthesis
+  pub range: Range<usize>,
+  pub arguments: Vec<String>
+}
+
+/// A call that could not be split in its arguments
+pub struct MalformedCall {
+  pub message: String,
+  pub label: String,
+
+  /// The code that is removed from the content so the call is only reported
+  /// once
+  pub range: Range<usize>
+}
+
+/// Splits the arguments of the call to the macro function at `start`. The
+/// arguments are token trees separated by commas so any code can be passed as
+/// long as its `()`, `[]`, `{}` and `<>` are balanced, the commas inside them
+/// or inside the literals do not separate the arguments. The double braces of
+/// the `{{ ... }}` arguments are removed.
+pub fn parse_macro_call(
+  content: &str, start: usize, macro_name: &str
+) -> Result<MacroCall, MalformedCall> {
+  // +1 for the !
+  let name_end = start + macro_name.len() + 1;
+  let mut tokens = Tokens::new(content, name_end);
+
+  let opening_parenthesis = match tokens.next() {
+    Some(token) if token.text(content) == "(" => token,
+    _ => {
+      return Err(MalformedCall {
+        message: "Missing macro arguments".to_string(),
+        label: format!(
+          "{macro_name} is a macro function, its arguments must follow in parentheses."
+        ),
+        range: start..name_end
+      })
+    }
+  };
+
+  let mut closing_delimiters = Vec::new();
+  let mut arguments = Vec::new();
+  let mut argument_start = opening_parenthesis.range.end;
+
+  for token in tokens {
+    let text = token.text(content);
+
+    match text {
+      "(" => closing_delimiters.push(")"),
+      "[" => closing_delimiters.push("]"),
+      "{" => closing_delimiters.push("}"),
+      "<" if is_type_arguments(content, token.range.start) => closing_delimiters.push(">"),
+      ")" | "]" | "}" | ">" if closing_delimiters.last() == Some(&text) => {
+        closing_delimiters.pop();
+      }
+      ")" if closing_delimiters.is_empty() => {
+        arguments.push(argument(&content[argument_start..token.range.start]));
+
+        return Ok(MacroCall {
+          range: start..token.range.end,
+          arguments: without_trailing_comma(arguments)
+        });
+      }
+      ")" | "]" | "}" => {
+        return Err(MalformedCall {
+          message: format!("Unbalanced `{text}` in macro arguments"),
+          label: match closing_delimiters.last() {
+            Some(expected) => {
+              format!(
+                "expected a `{expected}` before the `{text}` in the arguments of {macro_name}."
+              )
+            }
+            None => format!("the arguments of {macro_name} close a `{text}` that was never opened.")
+          },
+          range: start..name_end
+        });
+      }
+      "," if closing_delimiters.is_empty() => {
+        arguments.push(argument(&content[argument_start..token.range.start]));
+        argument_start = token.range.end;
+      }
+      _ => {}
+    }
+  }
+
+  Err(MalformedCall {
+    message: "Unterminated macro call".to_string(),
+    label: format!("the arguments of {macro_name} are never closed."),
+    range: start..name_end
+  })
+}
+
+fn argument(code: &str) -> String {
+  let code = code.trim();
+
+  match code
+    .strip_prefix("{{")
+    .and_then(|code| code.strip_suffix("}}"))
+  {
+    Some(body) => body.to_string(),
+    None => code.to_string()
+  }
+}
+
+/// `FOO!()` has no arguments and `FOO!(a, b,)` has two.
+fn without_trailing_comma(mut arguments: Vec<String>) -> Vec<String> {
+  if arguments.last().is_some_and(String::is_empty) {
+    arguments.pop();
+  }
+
+  arguments
+}
+
+/// Returns whether the `<` at the given index opens a list of types like in
+/// `array<int>` rather than being a comparison.
+fn is_type_arguments(content: &str, index: usize) -> bool {
+  if !content[..index].ends_with(is_identifier_char) {
+    return false;
+  }
+
+  let mut depth = 0;
+  for c in content[index..].chars() {
+    match c {
+      '<' => depth += 1,
+      '>' if depth == 1 => return true,
+      '>' => depth -= 1,
+      c if is_identifier_char(c) || c.is_whitespace() || c == ',' => {}
+      _ => return false
+    }
+  }
+
+  false
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Returns the arguments of the call to `F!` at the start of the code.
+  fn arguments(code: &str) -> Vec<String> {
+    match parse_macro_call(code, 0, "F") {
+      Ok(call) => call.arguments,
+      Err(call) => panic!("{}: {}", call.message, call.label)
+    }
+  }
+
+  /// Returns whether the first `<` of the code opens a list of types.
+  fn opens_type_arguments(code: &str) -> bool {
+    is_type_arguments(code, code.find('<').unwrap())
+  }
+
+  #[test]
+  fn nested_type_arguments_are_balanced() {
+    assert!(opens_type_arguments("array<int>"));
+    assert!(opens_type_arguments("array<array<int>>"));
+    assert!(opens_type_arguments("Map<string, array<Map<int, bool>>>"));
+
+    assert_eq!(
+      arguments("F!(array<array<int>>, Map<string, array<int>>)"),
+      vec!["array<array<int>>", "Map<string, array<int>>"]
+    );
+  }
+
+  #[test]
+  fn comparisons_are_not_type_arguments() {
+    assert!(!opens_type_arguments("a < b"));
+    assert!(!opens_type_arguments("a<b"));
+    assert!(!opens_type_arguments("a<=b"));
+    assert!(!opens_type_arguments("a<b && c>d"));
+    assert!(!opens_type_arguments("a<b)"));
+
+    assert_eq!(
+      arguments("F!(a < b, c > d, e<f, g)"),
+      vec!["a < b", "c > d", "e<f", "g"]
+    );
+  }
+
+  #[test]
+  fn commas_inside_delimiters_do_not_split_the_arguments() {
+    assert!(!opens_type_arguments("a<f(b, c)>"));
+
+    assert_eq!(
+      arguments("F!(f(a, b), [c, d], {{ e, f }}, a<f(b, c), \"g, h\")"),
+      vec!["f(a, b)", "[c, d]", " e, f ", "a<f(b, c)", "\"g, h\""]
+    );
+  }
+}
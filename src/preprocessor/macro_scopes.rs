@@ -0,0 +1,311 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::PathBuf;
+
+use super::types::*;
+
+/// The macros the packages define. Every package has its own, and it sees the
+/// constants of the options, its own macros and the macros its own
+/// dependencies export. So a library can use helper macros without leaking
+/// them, and the macros of a dependency of a dependency stay hidden.
+#[derive(Default)]
+pub struct MacroScopes {
+  /// The constants of the options, they win over the definitions of the files
+  defines: HashMap<String, MacroDefinition>,
+  sources: PackageMacros,
+  dependencies: BTreeMap<DependencyName, PackageMacros>
+}
+
+#[derive(Default)]
+pub struct PackageMacros {
+  macros: HashMap<String, MacroDefinition>,
+
+  /// The macros the package exports to the packages that depend on it
+  exported: HashSet<String>,
+
+  /// The headers the package included, each is only read once
+  pub included_headers: HashSet<PathBuf>,
+
+  /// The dependencies the package declares, it sees the macros they export
+  dependencies: BTreeSet<DependencyName>
+}
+
+impl MacroScopes {
+  /// Creates the scopes with the constants of the options, and the ones the
+  /// libraries define for their own files.
+  pub fn new(options: &PreprocessorOptions) -> Self {
+    let dependencies = options
+      .library_defines
+      .iter()
+      .map(|(dependency, library)| {
+        let macros = PackageMacros {
+          macros: constants(&library.defines),
+          ..Default::default()
+        };
+
+        (dependency.clone(), macros)
+      })
+      .collect();
+
+    let mut scopes = Self {
+      defines: constants(&options.defines),
+      dependencies,
+      ..Default::default()
+    };
+
+    for (package, dependencies) in &options.package_dependencies {
+      scopes.package_mut(package.as_deref()).dependencies = dependencies.clone();
+    }
+
+    scopes
+  }
+
+  /// Returns the macros of the package, the dependency is `None` for the
+  /// sources.
+  pub fn package_mut(&mut self, dependency: Option<&str>) -> &mut PackageMacros {
+    match dependency {
+      Some(dependency) => self.dependencies.entry(dependency.to_string()).or_default(),
+      None => &mut self.sources
+    }
+  }
+
+  /// Returns the macro of the name the package sees, if any. The dependencies
+  /// are searched in the order of their names.
+  pub fn get<'s>(&'s self, dependency: Option<&'s str>, name: &str) -> Option<&'s MacroDefinition> {
+    self
+      .resolve(dependency, name)
+      .map(|(_, definition)| definition)
+  }
+
+  /// Returns the macro of the name the package sees, with the package whose
+  /// macros the calls of its body see. It is the package that exports the
+  /// macro, so an exported macro can call the helpers of its library.
+  pub fn resolve<'s>(
+    &'s self, dependency: Option<&'s str>, name: &str
+  ) -> Option<(Option<&'s str>, &'s MacroDefinition)> {
+    let package = match dependency {
+      Some(dependency) => self.dependencies.get(dependency),
+      None => Some(&self.sources)
+    };
+
+    let own_macro = self
+      .defines
+      .get(name)
+      .or_else(|| package.and_then(|package| package.macros.get(name)));
+
+    if let Some(definition) = own_macro {
+      return Some((dependency, definition));
+    }
+
+    self
+      .direct_dependencies(package)
+      .find_map(|(other, macros)| {
+        macros
+          .get_exported(name)
+          .map(|definition| (Some(other.as_str()), definition))
+      })
+  }
+
+  /// Returns all the macros the package sees, like [MacroScopes::get].
+  pub fn visible_macros(&self, dependency: Option<&str>) -> HashMap<String, &MacroDefinition> {
+    let mut macros = HashMap::new();
+
+    let package = match dependency {
+      Some(dependency) => self.dependencies.get(dependency),
+      None => Some(&self.sources)
+    };
+
+    let own_macros = package.map(|package| package.macros.iter());
+    let exported_macros = self.direct_dependencies(package).flat_map(|(_, other)| {
+      other
+        .macros
+        .iter()
+        .filter(|(name, _)| other.exported.contains(*name))
+    });
+
+    // the first ones win
+    for (name, definition) in self
+      .defines
+      .iter()
+      .chain(own_macros.into_iter().flatten())
+      .chain(exported_macros)
+    {
+      macros.entry(name.clone()).or_insert(definition);
+    }
+
+    macros
+  }
+
+  /// Returns the macros of the dependencies the package declares, in the order
+  /// of their names.
+  fn direct_dependencies<'s>(
+    &'s self, package: Option<&'s PackageMacros>
+  ) -> impl Iterator<Item = (&'s DependencyName, &'s PackageMacros)> {
+    self
+      .dependencies
+      .iter()
+      .filter(move |(name, _)| package.is_some_and(|package| package.dependencies.contains(*name)))
+  }
+}
+
+impl PackageMacros {
+  /// Defines the macro, or replaces the previous definition of the name.
+  pub fn define(&mut self, name: String, definition: MacroDefinition, is_exported: bool) {
+    if is_exported {
+      self.exported.insert(name.clone());
+    } else {
+      self.exported.remove(&name);
+    }
+
+    self.macros.insert(name, definition);
+  }
+
+  fn get_exported(&self, name: &str) -> Option<&MacroDefinition> {
+    match self.exported.contains(name) {
+      true => self.macros.get(name),
+      false => None
+    }
+  }
+}
+
+fn constants(defines: &BTreeMap<String, String>) -> HashMap<String, MacroDefinition> {
+  defines
+    .iter()
+    .map(|(name, value)| {
+      let constant = MacroConstant {
+        name: name.clone(),
+        value: value.clone()
+      };
+
+      (name.clone(), MacroDefinition::Constant(constant))
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_utils::{preprocess_files, processed_file};
+
+  /// The options of a project that depends on `a`, which depends on `b`.
+  fn options() -> PreprocessorOptions {
+    let mut options = PreprocessorOptions::default();
+    options.defines.insert("LEVEL".to_string(), "1".to_string());
+    options.package_dependencies = BTreeMap::from([
+      (None, BTreeSet::from(["a".to_string()])),
+      (Some("a".to_string()), BTreeSet::from(["b".to_string()])),
+      (Some("b".to_string()), BTreeSet::new())
+    ]);
+
+    options
+  }
+
+  fn constant(name: &str, value: &str) -> MacroDefinition {
+    MacroDefinition::Constant(MacroConstant {
+      name: name.to_string(),
+      value: value.to_string()
+    })
+  }
+
+  fn scopes() -> MacroScopes {
+    let mut scopes = MacroScopes::new(&options());
+
+    let a = scopes.package_mut(Some("a"));
+    a.define("A_EXPORT".to_string(), constant("A_EXPORT", "a"), true);
+    a.define("A_HELPER".to_string(), constant("A_HELPER", "a"), false);
+    a.define("LEVEL".to_string(), constant("LEVEL", "2"), true);
+
+    let b = scopes.package_mut(Some("b"));
+    b.define("B_EXPORT".to_string(), constant("B_EXPORT", "b"), true);
+
+    scopes
+      .package_mut(None)
+      .define("SHARED".to_string(), constant("SHARED", "sources"), false);
+    scopes
+      .package_mut(Some("a"))
+      .define("SHARED".to_string(), constant("SHARED", "a"), true);
+
+    scopes
+  }
+
+  fn value<'s>(scopes: &'s MacroScopes, package: Option<&'s str>, name: &str) -> Option<&'s str> {
+    match scopes.get(package, name)? {
+      MacroDefinition::Constant(constant) => Some(&constant.value),
+      MacroDefinition::Function(function) => Some(&function.name)
+    }
+  }
+
+  #[test]
+  fn exported_macros_are_visible_to_the_direct_dependents() {
+    let scopes = scopes();
+
+    assert_eq!(value(&scopes, None, "A_EXPORT"), Some("a"));
+    assert_eq!(value(&scopes, Some("a"), "B_EXPORT"), Some("b"));
+    assert_eq!(value(&scopes, None, "B_EXPORT"), None);
+    assert_eq!(value(&scopes, Some("b"), "A_EXPORT"), None);
+  }
+
+  #[test]
+  fn private_macros_stay_in_their_package() {
+    let scopes = scopes();
+
+    assert_eq!(value(&scopes, Some("a"), "A_HELPER"), Some("a"));
+    assert_eq!(value(&scopes, None, "A_HELPER"), None);
+  }
+
+  #[test]
+  fn own_macros_and_options_win() {
+    let scopes = scopes();
+
+    assert_eq!(value(&scopes, None, "SHARED"), Some("sources"));
+    assert_eq!(value(&scopes, None, "LEVEL"), Some("1"));
+    assert_eq!(value(&scopes, Some("a"), "LEVEL"), Some("1"));
+  }
+
+  #[test]
+  fn visible_macros_follow_the_dependencies() {
+    let scopes = scopes();
+    let names = |package: Option<&str>| -> BTreeSet<String> {
+      scopes.visible_macros(package).into_keys().collect()
+    };
+
+    assert_eq!(
+      names(None),
+      BTreeSet::from(["A_EXPORT", "LEVEL", "SHARED"].map(String::from))
+    );
+    assert_eq!(
+      names(Some("a")),
+      BTreeSet::from(["A_EXPORT", "A_HELPER", "B_EXPORT", "LEVEL", "SHARED"].map(String::from))
+    );
+    assert_eq!(
+      names(Some("b")),
+      BTreeSet::from(["B_EXPORT", "LEVEL"].map(String::from))
+    );
+  }
+
+  #[test]
+  fn exported_macros_expand_in_their_package() {
+    let output = preprocess_files(
+      &[
+        ("src/main.wss", "function main() {\n  A!()\n  B!()\n}\n"),
+        ("a/a.wss", "#define export function A() {\n  B!()\n};\n"),
+        (
+          "b/b.wss",
+          "#define export function B() {\n  print(\"b\");\n};\n"
+        )
+      ],
+      &options()
+    );
+    let main = processed_file(&output, "main.wss");
+
+    // `B!` is visible in the body of `A!` but not in the sources
+    assert_eq!(main.content.borrow().matches("print(\"b\");").count(), 1);
+    assert_eq!(
+      output
+        .diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic.code)
+        .collect::<Vec<_>>(),
+      vec!["unknown-macro"]
+    );
+  }
+}
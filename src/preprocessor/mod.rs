@@ -1,18 +1,31 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::Path;
+use std::time::Instant;
 
-use regex::{Regex, RegexBuilder};
-
+mod condition;
 mod conditionals;
 mod expand_macros;
+mod expansions;
+mod hygiene;
+mod macro_call;
+mod macro_scopes;
+mod origins;
 mod pragma_replace;
 mod registry;
+mod scopes;
+pub mod tokens;
 pub mod types;
 
-use crate::utils::convert_line_endings;
+use crate::ast::DiagnosticKind;
+use crate::timings::Timings;
+use crate::utils::{convert_line_endings, stable_path};
 
 use self::conditionals::filter_conditionals;
+use self::expand_macros::MacroExpander;
+pub use self::expansions::Expansion;
+use self::origins::OriginMap;
+use self::tokens::{TokenKind, Tokens};
 use self::types::*;
 
 /// Entry point for the pre-processor,
@@ -20,125 +33,163 @@ use self::types::*;
 /// names. And returns as output the files content from the source directory
 /// and the files content from the dependencies.
 pub fn preprocess(
-  source_directory: &str, dependencies: &HashMap<String, String>
+  source_directory: &str, dependencies: &HashMap<String, String>, options: &PreprocessorOptions
 ) -> std::io::Result<PreprocessorOutput> {
   let mut output = PreprocessorOutput {
-    dependencies_files_content: HashMap::new(),
-    source_files_content: HashMap::new()
+    dependencies_files_content: BTreeMap::new(),
+    source_files_content: BTreeMap::new(),
+    diagnostics: Vec::new(),
+    timings: Timings::new()
   };
 
-  for (name, content) in get_wss_files_content_for_directory(&Path::new(source_directory))? {
-    output.source_files_content.insert(name, content);
-  }
+  let mut timings = Timings::new();
+
+  timings.measure("reading the files", || -> std::io::Result<()> {
+    for (name, content) in get_wss_files_content_for_directory(&Path::new(source_directory))? {
+      output.source_files_content.insert(name, content);
+    }
 
-  for (name, value) in dependencies.iter() {
-    if value.starts_with("https://") {
-      todo!();
-    } else {
+    for (name, value) in dependencies.iter() {
       output.dependencies_files_content.insert(
         name.to_string(),
-        HashMap::from_iter(get_wss_files_content_for_directory(&Path::new(value))?.into_iter())
+        BTreeMap::from_iter(get_wss_files_content_for_directory(&Path::new(value))?.into_iter())
       );
     }
-  }
-
-  let regex_collection = RegexCollection {
-    macro_call: Regex::new(r"(\w+)!").unwrap(),
-    macro_function: RegexBuilder::new(r"#define function (\w+)\((.*?)\) \{(.*)\};")
-      .multi_line(true)
-      .dot_matches_new_line(true)
-      .build()
-      .unwrap(),
-    macro_const: Regex::new(r"#define const (\w+).*;").unwrap(),
-    macro_const_value: Regex::new(r"= (.*);").unwrap(),
-    macro_ifdef: RegexBuilder::new(r"#ifdef (\w*) \{(.*?)\};")
-      .multi_line(true)
-      .dot_matches_new_line(true)
-      .build()
-      .unwrap(),
-    macro_ifndef: RegexBuilder::new(r"#ifndef (\w*) \{(.*?)\};")
-      .multi_line(true)
-      .dot_matches_new_line(true)
-      .build()
-      .unwrap()
-  };
 
-  registry::handle_registers(&mut output);
+    Ok(())
+  })?;
 
-  let mut registered_macros = HashMap::new();
-  let mut contains_macro_call = true;
-  while contains_macro_call {
-    contains_macro_call = false;
+  timings.measure("registers", || registry::handle_registers(&mut output));
 
-    for (_name, files) in output.dependencies_files_content.iter() {
-      for (_filename, content) in files.iter() {
-        let mut new_content = content.content.borrow().to_string();
+  let mut expander = MacroExpander::new(options);
+  let mut passes = 0;
+  let expansion_start = Instant::now();
 
-        let file_still_contains_macro_calls =
-          expand_macros::expand_macros(&mut registered_macros, &mut new_content, &regex_collection);
+  // every pass reads each file once, and continues as long as a pass changes
+  // something since the macros may be defined in any file of the package and
+  // expand into calls to other macros. The calls to unknown macros are left as
+  // they are.
+  let mut has_changed = true;
+  while has_changed {
+    has_changed = false;
+    passes += 1;
 
-        contains_macro_call = contains_macro_call || file_still_contains_macro_calls;
+    for (dependency, filename, file) in package_files(&output) {
+      let file_has_changed = expander.expand_macros(dependency, filename, file);
 
-        content.content.replace(new_content);
-      }
+      has_changed = has_changed || file_has_changed;
     }
+  }
 
-    for (_filename, content) in output.source_files_content.iter() {
-      let mut new_content = content.content.borrow().to_string();
-
-      let file_still_contains_macro_calls =
-        expand_macros::expand_macros(&mut registered_macros, &mut new_content, &regex_collection);
+  timings.record(
+    &format!(
+      "macro expansion ({passes} passes, {} expansions)",
+      expander.expansions()
+    ),
+    expansion_start.elapsed()
+  );
+
+  // the macros each package sees
+  let mut visible_macros = HashMap::new();
+  for (dependency, _, _) in package_files(&output) {
+    visible_macros
+      .entry(dependency.map(str::to_string))
+      .or_insert_with(|| expander.macros.visible_macros(dependency));
+  }
 
-      contains_macro_call = contains_macro_call || file_still_contains_macro_calls;
+  // a final pass over the files to remove the conditional macros
+  let mut diagnostics = std::mem::take(&mut expander.diagnostics);
+  timings.measure("conditionals", || {
+    for (dependency, filename, file) in package_files(&output) {
+      let registered_macros = &visible_macros[&dependency.map(str::to_string)];
 
-      content.content.replace(new_content);
+      filter_conditionals(registered_macros, file, filename, &mut diagnostics);
     }
-  }
+  });
 
-  // a final pass over the files to remove the conditional macros
-  for (_name, files) in output.dependencies_files_content.iter() {
-    for (_filename, content) in files.iter() {
-      let mut new_content = content.content.borrow().to_string();
-
-      filter_conditionals(
-        &registered_macros,
-        &mut new_content,
-        &regex_collection,
-        conditionals::ConditionType::IfDefined
-      );
+  output.diagnostics.append(&mut diagnostics);
 
-      filter_conditionals(
-        &registered_macros,
-        &mut new_content,
-        &regex_collection,
-        conditionals::ConditionType::IfNotDefined
-      );
+  // the calls in the blocks whose conditions are false are not reported
+  timings.measure("unknown macros", || {
+    report_unknown_macro_calls(&mut output, &visible_macros)
+  });
 
-      content.content.replace(new_content);
-    }
-  }
+  output.timings = timings;
 
-  for (_filename, content) in output.source_files_content.iter() {
-    let mut new_content = content.content.borrow().to_string();
+  Ok(output)
+}
 
-    filter_conditionals(
-      &registered_macros,
-      &mut new_content,
-      &regex_collection,
-      conditionals::ConditionType::IfDefined
-    );
+/// Pushes a diagnostic for every call to a macro that was never defined, they
+/// are the calls that remain once the macros are expanded and the conditional
+/// blocks filtered.
+fn report_unknown_macro_calls(
+  output: &mut PreprocessorOutput,
+  visible_macros: &HashMap<Option<DependencyName>, HashMap<String, &MacroDefinition>>
+) {
+  let sources = output
+    .source_files_content
+    .iter()
+    .map(|(filename, file)| (None, filename, file));
+  let dependencies = output
+    .dependencies_files_content
+    .iter()
+    .flat_map(|(dependency, files)| {
+      files
+        .iter()
+        .map(move |(filename, file)| (Some(dependency.clone()), filename, file))
+    });
 
-    filter_conditionals(
-      &registered_macros,
-      &mut new_content,
-      &regex_collection,
-      conditionals::ConditionType::IfNotDefined
-    );
+  for (dependency, filename, file) in sources.chain(dependencies) {
+    let registered_macros = &visible_macros[&dependency];
+    let content = file.content.borrow();
+    let origins = file.origins.borrow();
+    let mut reported_macros = HashSet::new();
+
+    for token in Tokens::new(&content, 0) {
+      let macro_name = token.text(&content);
+
+      if token.kind != TokenKind::Identifier
+        || !expand_macros::is_macro_call(&content, &token.range)
+        || registered_macros.contains_key(macro_name)
+        || !reported_macros.insert(macro_name.to_string())
+      {
+        continue;
+      }
 
-    content.content.replace(new_content);
+      let range = origins.original_range(token.range.clone());
+
+      output.diagnostics.push(PreprocessorDiagnostic {
+        kind: DiagnosticKind::Warning,
+        code: "unknown-macro",
+        filename: filename.clone(),
+        range,
+        message: "Call to unknown macro".to_string(),
+        label: format!("{macro_name} is not a known macro."),
+        help: None
+      });
+    }
   }
+}
 
-  Ok(output)
+/// Returns the files of the dependencies then the ones of the sources, with
+/// the dependency they belong to, `None` for the sources.
+fn package_files(
+  output: &PreprocessorOutput
+) -> impl Iterator<Item = (Option<&str>, &FileName, &ProcessedFile)> {
+  let dependencies = output
+    .dependencies_files_content
+    .iter()
+    .flat_map(|(dependency, files)| {
+      files
+        .iter()
+        .map(move |(filename, file)| (Some(dependency.as_str()), filename, file))
+    });
+  let sources = output
+    .source_files_content
+    .iter()
+    .map(|(filename, file)| (None, filename, file));
+
+  dependencies.chain(sources)
 }
 
 fn get_wss_files_content_for_directory(
@@ -158,15 +209,16 @@ fn get_wss_files_content_for_directory(
 
   let mut output = Vec::new();
   for filename in files {
-    let content = RefCell::new(convert_line_endings(std::fs::read_to_string(
-      filename.path()
-    )?));
+    let original_content = convert_line_endings(std::fs::read_to_string(filename.path())?);
 
     output.push((
       filename.path().to_str().unwrap().to_string(),
       ProcessedFile {
-        content,
-        path: filename.path().to_path_buf()
+        content: RefCell::new(original_content.clone()),
+        origins: RefCell::new(OriginMap::new(original_content.len())),
+        original_content,
+        path: filename.path().to_path_buf(),
+        relative_path: stable_path(filename.path(), dir.to_str().unwrap_or_default())
       }
     ));
   }
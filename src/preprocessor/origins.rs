@@ -0,0 +1,329 @@
+use std::ops::Range;
+use std::rc::Rc;
+
+use super::expansions::Expansion;
+
+/// Where a part of the preprocessed content of a file comes from
+#[derive(Debug, Clone)]
+pub enum Origin {
+  /// The code is copied from the original content, from the offset
+  Original(usize),
+
+  /// The code replaces the code at the range of the original content, like
+  /// the code a `@registry` emits
+  Generated(Range<usize>),
+
+  /// The code comes from a macro expansion, it replaces the call
+  Expansion(Rc<Expansion>)
+}
+
+/// The origins of the parts of the preprocessed content of a file. The ranges
+/// are sorted and never overlap, and they cover the whole content once every
+/// step of the preprocessor built its content with the map.
+#[derive(Debug, Default)]
+pub struct OriginMap {
+  segments: Vec<(Range<usize>, Origin)>
+}
+
+impl OriginMap {
+  /// Returns the map of a content that was not preprocessed yet.
+  pub fn new(length: usize) -> Self {
+    let mut map = Self::default();
+    map.push(0..length, Origin::Original(0));
+
+    map
+  }
+
+  fn segment_at(&self, index: usize) -> Option<&(Range<usize>, Origin)> {
+    let position = self
+      .segments
+      .partition_point(|(range, _)| range.end <= index);
+
+    self
+      .segments
+      .get(position)
+      .filter(|(range, _)| range.start <= index)
+  }
+
+  /// Returns the expansion the code at the index comes from, if any.
+  pub fn expansion_at(&self, index: usize) -> Option<&Rc<Expansion>> {
+    match self.segment_at(index) {
+      Some((_, Origin::Expansion(expansion))) => Some(expansion),
+      _ => None
+    }
+  }
+
+  /// Returns the range of the original content the range of the content comes
+  /// from. The code of an expansion comes from its call, and the end of the
+  /// content is the end of the code before it.
+  pub fn original_range(&self, range: Range<usize>) -> Range<usize> {
+    let start = match self.segment_at(range.start) {
+      Some((segment, origin)) => original_start(segment, origin, range.start),
+      None => match self.segments.last() {
+        Some((segment, origin)) => original_end(segment, origin, segment.end),
+        None => return 0..0
+      }
+    };
+
+    let end = match range.is_empty() {
+      true => None,
+      false => self
+        .segment_at(range.end - 1)
+        .map(|(segment, origin)| original_end(segment, origin, range.end))
+    };
+
+    start..end.unwrap_or(start).max(start)
+  }
+
+  /// Returns the offset in the content of the offset of the original content,
+  /// the reverse of [OriginMap::original_range]. An offset in the code that
+  /// was replaced, like a macro call, is at the start of the code that
+  /// replaces it.
+  pub fn content_offset(&self, original: usize) -> usize {
+    let mut closest: Option<(usize, usize)> = None;
+
+    for (segment, origin) in &self.segments {
+      let start = original_start(segment, origin, segment.start);
+
+      if start > original || closest.is_some_and(|(closest_start, _)| closest_start >= start) {
+        continue;
+      }
+
+      let offset = match origin {
+        Origin::Original(_) => segment.start + (original - start).min(segment.len()),
+        Origin::Generated(_) | Origin::Expansion(_) => segment.start
+      };
+
+      closest = Some((start, offset));
+    }
+
+    closest.map_or(0, |(_, offset)| offset)
+  }
+
+  /// Returns the origin of the code that replaces the range of the content.
+  pub fn origin_of(&self, range: Range<usize>) -> Origin {
+    match self.expansion_at(range.start) {
+      Some(expansion) => Origin::Expansion(expansion.clone()),
+      None => Origin::Generated(self.original_range(range))
+    }
+  }
+
+  /// Adds the origins of the range of the other content, the code of the range
+  /// was copied at the offset.
+  pub fn copy_from(&mut self, other: &OriginMap, range: Range<usize>, offset: usize) {
+    let first = other
+      .segments
+      .partition_point(|(segment, _)| segment.end <= range.start);
+
+    for (segment, origin) in &other.segments[first..] {
+      if segment.start >= range.end {
+        break;
+      }
+
+      let start = segment.start.max(range.start);
+      let end = segment.end.min(range.end);
+
+      let origin = match origin {
+        Origin::Original(original) => Origin::Original(original + start - segment.start),
+        origin => origin.clone()
+      };
+
+      self.push(
+        start - range.start + offset..end - range.start + offset,
+        origin
+      );
+    }
+  }
+
+  /// Adds the origin of the code at the range, after the other segments. The
+  /// copies of adjacent original code are merged.
+  pub fn push(&mut self, range: Range<usize>, origin: Origin) {
+    if range.is_empty() {
+      return;
+    }
+
+    if let (Some((last, Origin::Original(last_original))), Origin::Original(original)) =
+      (self.segments.last_mut(), &origin)
+    {
+      if last.end == range.start && *last_original + last.len() == *original {
+        last.end = range.end;
+
+        return;
+      }
+    }
+
+    self.segments.push((range, origin));
+  }
+
+  /// Removes the segments past the length, for when the content is truncated.
+  pub fn truncate(&mut self, length: usize) {
+    while self
+      .segments
+      .last()
+      .is_some_and(|(range, _)| range.start >= length)
+    {
+      self.segments.pop();
+    }
+
+    if let Some((range, _)) = self.segments.last_mut() {
+      range.end = range.end.min(length);
+    }
+  }
+}
+
+/// Copies the range of the input to the output, with the origins of its code.
+pub fn copy_with_origins(
+  input: &str, input_origins: &OriginMap, range: Range<usize>, output: &mut String,
+  origins: &mut OriginMap
+) {
+  origins.copy_from(input_origins, range.clone(), output.len());
+  output.push_str(&input[range]);
+}
+
+fn original_start(segment: &Range<usize>, origin: &Origin, index: usize) -> usize {
+  match origin {
+    Origin::Original(original) => original + index - segment.start,
+    Origin::Generated(range) => range.start,
+    Origin::Expansion(expansion) => expansion.call_site.start
+  }
+}
+
+fn original_end(segment: &Range<usize>, origin: &Origin, index: usize) -> usize {
+  match origin {
+    Origin::Original(original) => original + index - segment.start,
+    Origin::Generated(range) => range.end,
+    Origin::Expansion(expansion) => expansion.call_site.end
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::preprocessor::types::PreprocessorOptions;
+  use crate::test_utils::{preprocess_source, processed_file};
+
+  fn expansion(macro_name: &str, call_site: Range<usize>) -> Rc<Expansion> {
+    Rc::new(Expansion {
+      macro_name: macro_name.to_string(),
+      package: None,
+      parent: None,
+      call_site,
+      depth: 1
+    })
+  }
+
+  /// The map of `ab` + `XYZ` + `cd`, from `ab M!() cd` where the call at 3..6
+  /// expands to `XYZ`.
+  fn expanded_map() -> OriginMap {
+    let mut map = OriginMap::default();
+    map.push(0..2, Origin::Original(0));
+    map.push(2..5, Origin::Expansion(expansion("M", 3..6)));
+    map.push(5..7, Origin::Original(8));
+
+    map
+  }
+
+  #[test]
+  fn original_code_keeps_its_offsets() {
+    let map = expanded_map();
+
+    assert_eq!(map.original_range(0..2), 0..2);
+    assert_eq!(map.original_range(6..7), 9..10);
+    assert!(map.expansion_at(1).is_none());
+  }
+
+  #[test]
+  fn expanded_code_comes_from_the_call() {
+    let map = expanded_map();
+
+    assert_eq!(map.original_range(3..4), 3..6);
+    assert_eq!(map.original_range(1..6), 1..9);
+    assert_eq!(map.expansion_at(4).unwrap().macro_name, "M");
+  }
+
+  #[test]
+  fn end_of_the_content_is_the_end_of_the_code_before_it() {
+    let map = expanded_map();
+
+    assert_eq!(map.original_range(7..7), 10..10);
+    assert_eq!(OriginMap::default().original_range(0..0), 0..0);
+  }
+
+  #[test]
+  fn generated_code_replaces_its_range() {
+    let mut map = OriginMap::new(4);
+    map.push(4..10, Origin::Generated(4..20));
+
+    assert_eq!(map.original_range(5..6), 4..20);
+    assert!(matches!(map.origin_of(5..6), Origin::Generated(range) if range == (4..20)));
+    assert!(matches!(
+      expanded_map().origin_of(2..3),
+      Origin::Expansion(expansion) if expansion.macro_name == "M"
+    ));
+  }
+
+  #[test]
+  fn original_offsets_point_to_the_content() {
+    let map = expanded_map();
+
+    assert_eq!(map.content_offset(1), 1);
+    assert_eq!(map.content_offset(9), 6);
+    assert_eq!(map.content_offset(10), 7);
+
+    // the call and its arguments are replaced by the expansion
+    assert_eq!(map.content_offset(3), 2);
+    assert_eq!(map.content_offset(7), 2);
+  }
+
+  #[test]
+  fn adjacent_copies_are_merged() {
+    let mut map = OriginMap::default();
+    map.push(0..2, Origin::Original(0));
+    map.push(2..4, Origin::Original(2));
+    map.push(4..6, Origin::Original(10));
+    map.push(6..6, Origin::Original(12));
+
+    assert_eq!(map.segments.len(), 2);
+    assert_eq!(map.original_range(3..5), 3..11);
+  }
+
+  #[test]
+  fn copies_are_moved_to_their_offset() {
+    let mut map = OriginMap::default();
+    map.push(0..1, Origin::Generated(0..0));
+    map.copy_from(&expanded_map(), 1..6, 1);
+
+    assert_eq!(map.original_range(1..2), 1..2);
+    assert_eq!(map.original_range(2..5), 3..6);
+    assert_eq!(map.original_range(5..6), 8..9);
+    assert!(map.segment_at(6).is_none());
+  }
+
+  #[test]
+  fn truncated_maps_end_at_the_length() {
+    let mut map = expanded_map();
+    map.truncate(3);
+
+    assert_eq!(map.segments.len(), 2);
+    assert!(map.segment_at(3).is_none());
+    assert_eq!(map.original_range(3..3), 6..6);
+  }
+
+  #[test]
+  fn preprocessed_code_points_to_the_original_content() {
+    let source = "#define function TWICE(x) {\n  x; x;\n};\n\nfunction main() {\n  TWICE!(print(1))\n  print(2);\n}\n";
+    let output = preprocess_source(source, &PreprocessorOptions::default());
+    let file = processed_file(&output, "main.wss");
+    let content = file.content.borrow();
+    let origins = file.origins.borrow();
+
+    let written = content.find("print(2)").unwrap();
+    let original = origins.original_range(written..written + "print(2)".len());
+    assert_eq!(&file.original_content[original], "print(2)");
+
+    let expanded = content.find("print(1)").unwrap();
+    let call_site = origins.original_range(expanded..expanded + 1);
+    assert_eq!(&file.original_content[call_site], "TWICE");
+    assert_eq!(origins.expansion_at(expanded).unwrap().macro_name, "TWICE");
+  }
+}
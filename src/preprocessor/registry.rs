@@ -1,4 +1,6 @@
-use std::collections::{HashMap, HashSet};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Range;
 
 pub use nom::bytes::complete::{tag, take_until1};
 pub use nom::character::complete::char;
@@ -6,152 +8,400 @@ pub use nom::sequence::delimited;
 pub use nom::IResult;
 use nom::Offset;
 
-use super::PreprocessorOutput;
+use super::origins::{copy_with_origins, OriginMap};
+use super::tokens::{TokenKind, Tokens};
+use super::types::{FileName, PreprocessorDiagnostic};
+use super::{package_files, PreprocessorOutput};
+use crate::ast::DiagnosticKind;
 
+/// Collects the @register of the sources and the dependencies, then emits them
+/// in the @registry with their names.
 pub fn handle_registers(output: &mut PreprocessorOutput) {
-  let registers = collect_registers(output);
-  emit_registers(output, &registers);
+  let mut diagnostics = Vec::new();
+
+  let registers = collect_registers(output, &mut diagnostics);
+  emit_registers(output, &registers, &mut diagnostics);
+
+  output.diagnostics.append(&mut diagnostics);
+}
+
+/// The entries of a register and where it was first defined, the origin is
+/// the file and the range of the first @register in its original content.
+struct CollectedRegister {
+  entries: Vec<RegisterEntry>,
+  origin: (FileName, Range<usize>)
 }
 
-fn collect_registers(output: &mut PreprocessorOutput) -> HashMap<String, Vec<String>> {
-  use nom::Offset;
+/// A @register, its value replaces the `REGISTER` of the @registry and its
+/// named arguments replace the `{name}` placeholders.
+struct RegisterEntry {
+  value: String,
+  arguments: BTreeMap<String, String>,
+  priority: i64,
+  origin: (FileName, Range<usize>)
+}
 
+/// Removes the @register from the files, in the order of the packages so the
+/// entries of a priority are emitted in the same order in every build.
+fn collect_registers(
+  output: &PreprocessorOutput, diagnostics: &mut Vec<PreprocessorDiagnostic>
+) -> HashMap<String, CollectedRegister> {
   let mut registers = HashMap::new();
 
-  'fileloop: for (_filename, file) in &output.source_files_content {
-    loop {
-      let content = file.content.borrow();
-      let content_ref = content.as_str();
+  for (_, filename, file) in package_files(output) {
+    let content = file.content.borrow();
+    let content_ref = content.as_str();
+    let origins = file.origins.take();
 
-      let Ok((start, _)) = Register::parse_find_register(content_ref) else {
-        // no @register was found
-        continue 'fileloop;
-      };
+    // the content without the @register, it is built as the file is read
+    let mut new_content = String::with_capacity(content_ref.len());
+    let mut new_origins = OriginMap::default();
+    let mut cursor = 0;
 
+    while let Some(start) = Register::parse_find_register(&content_ref[cursor..]) {
       let start_idx = content_ref.offset(start);
+      copy_with_origins(
+        content_ref,
+        &origins,
+        cursor..start_idx,
+        &mut new_content,
+        &mut new_origins
+      );
+      cursor = start_idx;
 
       match Register::parse(start) {
         Ok((new_i, register)) => {
-          registers
-            .entry(register.name.to_owned())
-            .or_insert(Vec::new())
-            .push(register.body.to_owned());
-
           let end_idx = content_ref.offset(new_i);
-          std::mem::drop(content);
+          let origin = (filename.clone(), origins.original_range(start_idx..end_idx));
 
-          // remove the register code from the file
-          file
-            .content
-            .borrow_mut()
-            .replace_range(start_idx..end_idx, "");
+          registers
+            .entry(register.name.to_owned())
+            .or_insert_with(|| CollectedRegister {
+              entries: Vec::new(),
+              origin: origin.clone()
+            })
+            .entries
+            .push(RegisterEntry {
+              value: register.value.to_owned(),
+              arguments: register
+                .arguments
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+              priority: register.priority,
+              origin
+            });
+
+          cursor = end_idx;
         }
         Err(e) => {
-          println!("ERROR parsing @register: {e}");
-          continue 'fileloop;
+          diagnostics.push(PreprocessorDiagnostic {
+            kind: DiagnosticKind::Error,
+            code: "invalid-register",
+            filename: filename.clone(),
+            range: origins.original_range(start_idx..start_idx + first_line(start).len()),
+            message: "Invalid @register".to_string(),
+            label: format!("Could not parse the @register: {e}"),
+            help: None
+          });
+
+          break;
         }
       };
     }
+
+    copy_with_origins(
+      content_ref,
+      &origins,
+      cursor..content_ref.len(),
+      &mut new_content,
+      &mut new_origins
+    );
+
+    std::mem::drop(content);
+    file.content.replace(new_content);
+    file.origins.replace(new_origins);
+  }
+
+  // the highest priorities first, the sort is stable so the entries of a
+  // priority stay in the order they were found
+  for register in registers.values_mut() {
+    register
+      .entries
+      .sort_by_key(|entry| Reverse(entry.priority));
   }
 
   registers
 }
 
-fn emit_registers(output: &mut PreprocessorOutput, registers: &HashMap<String, Vec<String>>) {
+fn emit_registers(
+  output: &PreprocessorOutput, registers: &HashMap<String, CollectedRegister>,
+  diagnostics: &mut Vec<PreprocessorDiagnostic>
+) {
   // memorize which register was used to emit a log about the unused ones
   let mut used_registers: HashSet<String> = HashSet::new();
 
-  for (_filename, file) in &output.source_files_content {
-    'parsing_loop: loop {
-      let content = file.content.borrow();
-      let content_ref = content.as_str();
+  for (_, filename, file) in package_files(output) {
+    let content = file.content.borrow();
+    let content_ref = content.as_str();
+    let origins = file.origins.take();
 
-      let Ok((start, _)) = RegisterEmitter::parse_find_register(content_ref) else {
-        // no @registry was found
-        break 'parsing_loop;
-      };
+    // the content with the code the @registry emit, it is built as the file is
+    // read
+    let mut new_content = String::with_capacity(content_ref.len());
+    let mut new_origins = OriginMap::default();
+    let mut cursor = 0;
 
+    while let Some(start) = RegisterEmitter::parse_find_register(&content_ref[cursor..]) {
       let start_idx = content_ref.offset(start);
+      copy_with_origins(
+        content_ref,
+        &origins,
+        cursor..start_idx,
+        &mut new_content,
+        &mut new_origins
+      );
+      cursor = start_idx;
 
       match RegisterEmitter::parse(start) {
         Ok((new_i, register_emitter)) => {
+          // replace the registerEmitter's code with the emitted code, it comes
+          // from the @registry
           let end_idx = content_ref.offset(new_i);
-
-          // replace the registerEmitter's code with the emitted code
-          let output = register_emitter.emit(registers, &mut used_registers);
-
-          std::mem::drop(content);
-          file
-            .content
-            .borrow_mut()
-            .replace_range(start_idx..end_idx, &output);
+          let emitted = register_emitter.emit(registers, &mut used_registers, diagnostics);
+
+          if emitted.is_empty() && register_emitter.is_required {
+            diagnostics.push(PreprocessorDiagnostic {
+              kind: DiagnosticKind::Error,
+              code: "empty-registry",
+              filename: filename.clone(),
+              range: origins.original_range(start_idx..end_idx),
+              message: format!("Registry `{}` has no registers", register_emitter.name),
+              label: format!(
+                "The @registry is required but no @register('{}', ...) was found",
+                register_emitter.name
+              ),
+              help: None
+            });
+          }
+
+          new_origins.push(
+            new_content.len()..new_content.len() + emitted.len(),
+            origins.origin_of(start_idx..end_idx)
+          );
+          new_content.push_str(&emitted);
+          cursor = end_idx;
         }
         Err(e) => {
-          println!("ERROR parsing @register: {e}");
-          break 'parsing_loop;
+          diagnostics.push(PreprocessorDiagnostic {
+            kind: DiagnosticKind::Error,
+            code: "invalid-registry",
+            filename: filename.clone(),
+            range: origins.original_range(start_idx..start_idx + first_line(start).len()),
+            message: "Invalid @registry".to_string(),
+            label: format!("Could not parse the @registry: {e}"),
+            help: None
+          });
+
+          break;
         }
       };
     }
-  }
 
-  for register in registers.keys() {
-    if !used_registers.contains(register) {
-      println!("register [{register}] defined but unused, no matching @registry found.");
-
-      if let Some(values) = registers.get(register) {
-        println!("  values:");
+    copy_with_origins(
+      content_ref,
+      &origins,
+      cursor..content_ref.len(),
+      &mut new_content,
+      &mut new_origins
+    );
+
+    std::mem::drop(content);
+    file.content.replace(new_content);
+    file.origins.replace(new_origins);
+  }
 
-        for value in values {
-          println!("  - {value}");
-        }
-      }
-    }
+  // sorted so the diagnostics are always reported in the same order
+  let mut unused_registers: Vec<_> = registers
+    .iter()
+    .filter(|(name, _)| !used_registers.contains(*name))
+    .collect();
+  unused_registers.sort_by(|a, b| a.0.cmp(b.0));
+
+  for (name, register) in unused_registers {
+    let (filename, range) = &register.origin;
+
+    diagnostics.push(PreprocessorDiagnostic {
+      kind: DiagnosticKind::Warning,
+      code: "unused-register",
+      filename: filename.clone(),
+      range: range.clone(),
+      message: format!("Register `{name}` is defined but unused"),
+      label: "No matching @registry was found".to_string(),
+      help: None
+    });
   }
 }
 
+fn first_line(code: &str) -> &str {
+  code.lines().next().unwrap_or(code)
+}
+
 trait RegisterParser<'a>
 where
   Self: Sized
 {
   fn prefix() -> &'static str;
-  fn from_name_and_body(name: &'a str, body: &'a str) -> Self;
+  fn from_name_and_arguments(name: &'a str, arguments: Arguments<'a>) -> Result<Self, String>;
+
+  fn parse(start: &'a str) -> Result<(&'a str, Self), String> {
+    let (i, name) = Self::parse_name(start).map_err(|e| e.to_string())?;
+    let (i, arguments) = parse_arguments(i)?;
 
-  fn parse(start: &'a str) -> IResult<&'a str, Self> {
+    Ok((i, Self::from_name_and_arguments(name, arguments)?))
+  }
+
+  fn parse_name(start: &'a str) -> IResult<&'a str, &'a str> {
     let (i, _) = tag(Self::prefix())(start)?;
     let (i, _) = char('(')(i)?;
     let (i, name) = delimited(tag("'"), take_until1("'"), tag("'"))(i.trim_start())?;
     let (i, _) = char(',')(i.trim_start())?;
-    let (i, body) = Self::parse_body(i.trim_start())?;
 
-    Ok((i, Self::from_name_and_body(name, body)))
+    Ok((i, name))
   }
 
-  fn parse_find_register(i: &'a str) -> IResult<&'a str, ()> {
-    let (i, _) = take_until1(Self::prefix())(i)?;
-
-    Ok((i, ()))
+  /// Returns the code from the next directive, the ones in the comments and
+  /// the literals are skipped.
+  fn parse_find_register(i: &'a str) -> Option<&'a str> {
+    let name = &Self::prefix()[1..];
+
+    Tokens::new(i, 0)
+      .filter(|token| token.kind == TokenKind::Symbol && token.text(i) == "@")
+      .find(|token| {
+        Tokens::new(i, token.range.end)
+          .next()
+          .is_some_and(|next| next.kind == TokenKind::Identifier && next.text(i) == name)
+      })
+      .map(|token| &i[token.range.start..])
   }
+}
 
-  fn parse_body(i: &'a str) -> IResult<&'a str, &'a str> {
-    if i.starts_with("{{") {
-      let (i, _) = tag("{{")(i)?;
-      let (i, body) = take_until1("}}")(i)?;
-      let (i, _) = tag("}}")(i)?;
-      let (i, _) = tag(")")(i.trim())?;
+/// The arguments that follow the name, the value then the named arguments as
+/// in `@register('events', value, name = hunt, priority = 10)`.
+struct Arguments<'a> {
+  value: Option<&'a str>,
+  named: Vec<(&'a str, &'a str)>
+}
 
-      Ok((i, body))
+/// Parses the arguments up to the closing parenthesis. The commas inside
+/// parentheses, brackets, braces and literals don't separate the arguments,
+/// and a `{{ ... }}` argument is taken as it is.
+fn parse_arguments(i: &str) -> Result<(&str, Arguments<'_>), String> {
+  let mut arguments = Arguments {
+    value: None,
+    named: Vec::new()
+  };
+
+  // the value spans every argument before the first named one, so a value can
+  // contain commas
+  let mut value_start = None;
+  let mut cursor = 0;
+
+  loop {
+    cursor += i[cursor..].len() - i[cursor..].trim_start().len();
+    let start = cursor;
+
+    let argument = if i[start..].starts_with("{{") {
+      let end = closing_braces(i, start + 2).ok_or("the `{{` is never closed by a `}}`")?;
+      cursor = end + 2;
+
+      &i[start + 2..end]
     } else {
-      let (i, body) = take_until1(")")(i)?;
-      let (i, _) = tag(")")(i)?;
+      cursor = argument_end(i, start).ok_or("the arguments are never closed by a `)`")?;
+
+      i[start..cursor].trim_end()
+    };
+
+    match named_argument(argument) {
+      Some(named) => arguments.named.push(named),
+      None if arguments.named.is_empty() => {
+        let value_start = *value_start.get_or_insert(start);
 
-      Ok((i, body))
+        arguments.value = match value_start == start {
+          true => Some(argument),
+          false => Some(i[value_start..cursor].trim_end())
+        };
+      }
+      None => {
+        return Err(format!(
+          "`{argument}` must be a named argument like `name = value`, the value comes first"
+        ))
+      }
+    }
+
+    cursor += i[cursor..].len() - i[cursor..].trim_start().len();
+    match i[cursor..].chars().next() {
+      Some(',') => cursor += 1,
+      Some(')') => return Ok((&i[cursor + 1..], arguments)),
+      _ => return Err("the arguments must be separated by commas and closed by a `)`".to_string())
     }
   }
 }
 
+/// Returns the index of the `}}` that closes the code starting at the index,
+/// the ones in the comments and the literals are skipped.
+fn closing_braces(i: &str, start: usize) -> Option<usize> {
+  Tokens::new(i, start)
+    .find(|token| token.kind == TokenKind::Symbol && i[token.range.start..].starts_with("}}"))
+    .map(|token| token.range.start)
+}
+
+/// Returns the index of the comma or the parenthesis that ends the argument
+/// starting at the index.
+fn argument_end(i: &str, start: usize) -> Option<usize> {
+  let mut depth = 0;
+  let mut quote = None;
+
+  for (index, character) in i[start..].char_indices() {
+    match (quote, character) {
+      (Some(opening), _) if character == opening => quote = None,
+      (Some(_), _) => {}
+      (None, '"' | '\'') => quote = Some(character),
+      (None, '(' | '[' | '{') => depth += 1,
+      (None, ',' | ')') if depth == 0 => return Some(start + index),
+      (None, ')' | ']' | '}') => depth -= 1,
+      _ => {}
+    }
+  }
+
+  None
+}
+
+/// Returns the name and the value of a `name = value` argument.
+fn named_argument(argument: &str) -> Option<(&str, &str)> {
+  let (name, value) = argument.split_once('=')?;
+  let name = name.trim();
+
+  let is_identifier = name
+    .chars()
+    .next()
+    .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+    && name
+      .chars()
+      .all(|character| character.is_ascii_alphanumeric() || character == '_');
+
+  // `a == b` is a value
+  match is_identifier && !value.starts_with('=') {
+    true => Some((name, value.trim())),
+    false => None
+  }
+}
+
 struct Register<'a> {
   pub name: &'a str,
-  pub body: &'a str
+  pub value: &'a str,
+  pub arguments: Vec<(&'a str, &'a str)>,
+  pub priority: i64
 }
 
 impl<'a> RegisterParser<'a> for Register<'a> {
@@ -159,14 +409,29 @@ impl<'a> RegisterParser<'a> for Register<'a> {
     "@register"
   }
 
-  fn from_name_and_body(name: &'a str, body: &'a str) -> Self {
-    Self { name, body }
+  fn from_name_and_arguments(name: &'a str, arguments: Arguments<'a>) -> Result<Self, String> {
+    let priority = match arguments.named.iter().find(|(name, _)| *name == "priority") {
+      Some((_, priority)) => priority
+        .parse()
+        .map_err(|_| format!("the priority must be an integer, not `{priority}`"))?,
+      None => 0
+    };
+
+    Ok(Self {
+      name,
+      value: arguments.value.unwrap_or_default(),
+      arguments: arguments.named,
+      priority
+    })
   }
 }
 
 struct RegisterEmitter<'a> {
   pub name: &'a str,
-  pub body: &'a str
+  pub body: &'a str,
+
+  /// Whether the @registry is reported when no @register has its name
+  pub is_required: bool
 }
 
 impl<'a> RegisterParser<'a> for RegisterEmitter<'a> {
@@ -174,27 +439,192 @@ impl<'a> RegisterParser<'a> for RegisterEmitter<'a> {
     "@registry"
   }
 
-  fn from_name_and_body(name: &'a str, body: &'a str) -> Self {
-    Self { name, body }
+  fn from_name_and_arguments(name: &'a str, arguments: Arguments<'a>) -> Result<Self, String> {
+    let body = arguments.value.ok_or("the code to emit is missing")?;
+    let mut is_required = false;
+
+    for (argument, value) in arguments.named {
+      is_required = match (argument, value) {
+        ("required", "true") => true,
+        ("required", "false") => false,
+        ("required", value) => {
+          return Err(format!("required must be `true` or `false`, not `{value}`"))
+        }
+        (argument, _) => return Err(format!("`{argument}` is not an argument of a @registry"))
+      };
+    }
+
+    Ok(Self {
+      name,
+      body,
+      is_required
+    })
   }
 }
 
 impl<'a> RegisterEmitter<'a> {
+  /// Returns the body for every entry of the register, a placeholder that an
+  /// entry doesn't fill is reported at its @register.
   pub fn emit(
-    &self, registers: &HashMap<String, Vec<String>>, used_register: &mut HashSet<String>
+    &self, registers: &HashMap<String, CollectedRegister>, used_register: &mut HashSet<String>,
+    diagnostics: &mut Vec<PreprocessorDiagnostic>
   ) -> String {
-    let Some(values) = registers.get(self.name) else {
+    let Some(register) = registers.get(self.name) else {
       return String::new();
     };
 
     let mut output = String::new();
 
-    for value in values {
-      output.push_str(&self.body.replace("REGISTER", value));
+    for entry in &register.entries {
+      output.push_str(&self.fill(entry, register, diagnostics));
     }
 
     used_register.insert(self.name.to_owned());
 
     output
   }
+
+  /// Replaces the `REGISTER` of the body with the value of the entry, and the
+  /// `{name}` placeholders with its named arguments. The braces around a name
+  /// that no entry has are left as they are, they are code.
+  fn fill(
+    &self, entry: &RegisterEntry, register: &CollectedRegister,
+    diagnostics: &mut Vec<PreprocessorDiagnostic>
+  ) -> String {
+    let mut output = String::with_capacity(self.body.len());
+    let mut rest = self.body;
+
+    while let Some(index) = rest.find(['R', '{']) {
+      output.push_str(&rest[..index]);
+      rest = &rest[index..];
+
+      if let Some(after) = rest.strip_prefix("REGISTER") {
+        output.push_str(&entry.value);
+        rest = after;
+
+        continue;
+      }
+
+      let placeholder = rest
+        .strip_prefix('{')
+        .and_then(|after| after.split_once('}'))
+        .map(|(name, _)| name)
+        .filter(|name| {
+          register
+            .entries
+            .iter()
+            .any(|entry| entry.arguments.contains_key(*name))
+        });
+
+      match placeholder {
+        Some(name) => {
+          match entry.arguments.get(name) {
+            Some(value) => output.push_str(value),
+            None => {
+              let (filename, range) = &entry.origin;
+
+              diagnostics.push(PreprocessorDiagnostic {
+                kind: DiagnosticKind::Error,
+                code: "missing-register-argument",
+                filename: filename.clone(),
+                range: range.clone(),
+                message: format!("Missing argument `{name}`"),
+                label: format!(
+                  "The @registry('{}', ...) uses `{{{name}}}` but the @register has no `{name}`",
+                  self.name
+                ),
+                help: Some(format!(
+                  "Add `{name} = value` to the arguments of the @register."
+                ))
+              });
+            }
+          }
+
+          rest = &rest[name.len() + 2..];
+        }
+        None => {
+          output.push_str(&rest[..1]);
+          rest = &rest[1..];
+        }
+      }
+    }
+
+    output.push_str(rest);
+    output
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::preprocessor::types::PreprocessorOptions;
+  use crate::test_utils::{normalize, preprocess_source, processed_file};
+
+  /// Returns the preprocessed content of the file, and the codes of the
+  /// diagnostics.
+  fn preprocess(source: &str) -> (String, Vec<&'static str>) {
+    let output = preprocess_source(source, &PreprocessorOptions::default());
+    let content = normalize(&processed_file(&output, "main.wss").content.borrow());
+
+    (
+      content,
+      output
+        .diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic.code)
+        .collect()
+    )
+  }
+
+  #[test]
+  fn registers_in_comments_and_literals_are_ignored() {
+    let (content, codes) = preprocess(
+      "// @register('comment', a)\n/* @register('block', b) */\n@register('names', c)\n\nfunction main() {\n  print(\"@register('string', d)\");\n  @registry('names', {{\n    // @registry('names', {{ REGISTER }})\n    print(REGISTER);\n  }})\n}\n"
+    );
+
+    assert!(codes.is_empty(), "{codes:?}");
+    assert!(content.contains("// @register('comment', a)"));
+    assert!(content.contains("print(\"@register('string', d)\");"));
+    assert_eq!(content.matches("print(c);").count(), 1);
+  }
+
+  #[test]
+  fn named_arguments_fill_the_placeholders() {
+    let (content, codes) = preprocess(
+      "@register('channels', f(a, b), name = Combat, level = 2)\n@register('channels', g(), name = Debug, level = 0, priority = 5)\n\nfunction main() {\n  @registry('channels', {{\n    add(REGISTER, '{name}', {level}, {other});\n  }})\n}\n"
+    );
+
+    assert!(codes.is_empty(), "{codes:?}");
+    assert!(content.contains("add(g(), 'Debug', 0, {other});\nadd(f(a, b), 'Combat', 2, {other});"));
+  }
+
+  #[test]
+  fn missing_arguments_are_reported() {
+    let (_, codes) = preprocess(
+      "@register('channels', a, name = Combat)\n@register('channels', b)\n\nfunction main() {\n  @registry('channels', {{ add('{name}'); }})\n}\n"
+    );
+
+    assert_eq!(codes, vec!["missing-register-argument"]);
+  }
+
+  #[test]
+  fn invalid_arguments_are_reported() {
+    let (_, codes) = preprocess("@register('channels', a, priority = high)\n");
+    assert_eq!(codes, vec!["invalid-register"]);
+
+    let (_, codes) = preprocess("@register('channels', name = a, b)\n");
+    assert_eq!(codes, vec!["invalid-register"]);
+
+    let (_, codes) =
+      preprocess("function main() {\n  @registry('channels', {{ a }}, other = b)\n}\n");
+    assert_eq!(codes, vec!["invalid-registry"]);
+  }
+
+  #[test]
+  fn unused_registers_and_empty_registries_are_reported() {
+    let (_, codes) = preprocess(
+      "@register('unused', a)\n\nfunction main() {\n  @registry('empty', {{ REGISTER; }}, required = true)\n  @registry('optional', {{ REGISTER; }})\n}\n"
+    );
+
+    assert_eq!(codes, vec!["empty-registry", "unused-register"]);
+  }
 }
@@ -0,0 +1,99 @@
+use super::tokens::{Token, TokenKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScopeKind {
+  /// A function or an event
+  Function,
+
+  /// A class, a state or a struct
+  Class
+}
+
+/// Follows the declarations the tokens of a file enter and leave, so the
+/// builtin macros know the function and the class they are called in. The
+/// tokens are read in order, and the blocks the preprocessor skips must be
+/// balanced.
+#[derive(Default)]
+pub struct ScopeTracker {
+  /// The kind of the declaration whose keyword was just read, its name is the
+  /// next token
+  keyword: Option<ScopeKind>,
+
+  /// The declaration whose name was read, it is entered at its `{`
+  pending: Option<(ScopeKind, String)>,
+
+  /// The number of `{` that are not closed yet
+  depth: usize,
+  scopes: Vec<Scope>
+}
+
+struct Scope {
+  kind: ScopeKind,
+  name: String,
+
+  /// The depth inside the `{` of the declaration
+  depth: usize
+}
+
+impl ScopeTracker {
+  pub fn observe(&mut self, token: &Token, content: &str) {
+    let text = token.text(content);
+
+    match token.kind {
+      TokenKind::Whitespace | TokenKind::Comment | TokenKind::Pragma => return,
+      TokenKind::Identifier => {
+        if let Some(kind) = self.keyword.take() {
+          self.pending = Some((kind, text.to_string()));
+        } else {
+          self.keyword = match text {
+            "function" | "event" => Some(ScopeKind::Function),
+            "class" | "state" | "struct" => Some(ScopeKind::Class),
+            _ => None
+          };
+        }
+
+        return;
+      }
+      _ => self.keyword = None
+    }
+
+    match text {
+      "{" => {
+        self.depth += 1;
+
+        if let Some((kind, name)) = self.pending.take() {
+          self.scopes.push(Scope {
+            kind,
+            name,
+            depth: self.depth
+          });
+        }
+      }
+      "}" => {
+        if self
+          .scopes
+          .last()
+          .is_some_and(|scope| scope.depth == self.depth)
+        {
+          self.scopes.pop();
+        }
+
+        self.depth = self.depth.saturating_sub(1);
+      }
+      // a declaration without a body, like an imported function
+      ";" => self.pending = None,
+      _ => {}
+    }
+  }
+
+  /// Returns the name of the innermost declaration of the kind the tokens are
+  /// in, if any.
+  pub fn enclosing(&self, kind: ScopeKind) -> Option<&str> {
+    self
+      .scopes
+      .iter()
+      .rev()
+      .find(|scope| scope.kind == kind)
+      .map(|scope| scope.name.as_str())
+  }
+}
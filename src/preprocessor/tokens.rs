@@ -0,0 +1,175 @@
+use std::iter::Peekable;
+use std::ops::Range;
+use std::str::CharIndices;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+  Identifier,
+
+  /// A `"..."` literal
+  StringLiteral,
+
+  /// A `'...'` literal
+  NameLiteral,
+  Comment,
+
+  /// A `#pragma` line, up to the end of the line
+  Pragma,
+  Whitespace,
+
+  /// Any other character
+  Symbol
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+  pub kind: TokenKind,
+
+  /// The byte range of the token in the tokenized text
+  pub range: Range<usize>
+}
+
+impl Token {
+  pub fn text<'a>(&self, content: &'a str) -> &'a str {
+    &content[self.range.clone()]
+  }
+}
+
+/// Splits the content in tokens, it only knows what the preprocessor needs so
+/// every character that is not part of an identifier, a literal or a comment
+/// is a token of its own. The tokens cover the whole content.
+pub fn tokenize(content: &str) -> Vec<Token> {
+  Tokens::new(content, 0).collect()
+}
+
+/// The tokens of the content from the given byte offset, their ranges are in
+/// the whole content.
+pub struct Tokens<'a> {
+  content: &'a str,
+  chars: Peekable<CharIndices<'a>>,
+  offset: usize
+}
+
+impl<'a> Tokens<'a> {
+  pub fn new(content: &'a str, offset: usize) -> Self {
+    Self {
+      content,
+      chars: content[offset..].char_indices().peekable(),
+      offset
+    }
+  }
+
+  /// Returns the next token that is not whitespace or a comment.
+  pub fn next_significant(&mut self) -> Option<Token> {
+    self.find(|token| !matches!(token.kind, TokenKind::Whitespace | TokenKind::Comment))
+  }
+}
+
+impl Iterator for Tokens<'_> {
+  type Item = Token;
+
+  fn next(&mut self) -> Option<Token> {
+    let (start, c) = self.chars.next()?;
+    let content = &self.content[self.offset..];
+
+    let kind = match c {
+      c if is_identifier_start(c) => {
+        while self
+          .chars
+          .next_if(|(_, c)| is_identifier_char(*c))
+          .is_some()
+        {}
+
+        TokenKind::Identifier
+      }
+      c if c.is_whitespace() => {
+        while self.chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+
+        TokenKind::Whitespace
+      }
+      '"' | '\'' => {
+        while let Some((_, next)) = self.chars.next() {
+          match next {
+            '\\' => {
+              self.chars.next();
+            }
+            // an unterminated literal ends with its line
+            '\n' => break,
+            next if next == c => break,
+            _ => {}
+          }
+        }
+
+        match c {
+          '"' => TokenKind::StringLiteral,
+          _ => TokenKind::NameLiteral
+        }
+      }
+      '/' if content[start..].starts_with("//") => {
+        while self.chars.next_if(|(_, c)| *c != '\n').is_some() {}
+
+        TokenKind::Comment
+      }
+      '/' if content[start..].starts_with("/*") => {
+        let end = match content[start + 2..].find("*/") {
+          Some(index) => start + 2 + index + 2,
+          None => content.len()
+        };
+
+        while self.chars.next_if(|(index, _)| *index < end).is_some() {}
+
+        TokenKind::Comment
+      }
+      '#' if content[start..].starts_with("#pragma ") => {
+        while self.chars.next_if(|(_, c)| *c != '\n').is_some() {}
+
+        TokenKind::Pragma
+      }
+      _ => TokenKind::Symbol
+    };
+
+    let end = self
+      .chars
+      .peek()
+      .map(|(index, _)| *index)
+      .unwrap_or(content.len());
+
+    Some(Token {
+      kind,
+      range: self.offset + start..self.offset + end
+    })
+  }
+}
+
+pub fn is_identifier_start(c: char) -> bool {
+  c.is_alphabetic() || c == '_'
+}
+
+pub fn is_identifier_char(c: char) -> bool {
+  c.is_alphanumeric() || c == '_'
+}
+
+/// Returns the name of the directive whose `#` is at the given index.
+pub fn directive_name(content: &str, start: usize) -> Option<&str> {
+  Tokens::new(content, start + 1)
+    .next()
+    .filter(|token| token.kind == TokenKind::Identifier)
+    .map(|token| token.text(content))
+}
+
+/// Returns the range of the `}` that closes the `{` at the given index, the
+/// braces in the literals and the comments are ignored.
+pub fn matching_brace(content: &str, opening_brace: usize) -> Option<Range<usize>> {
+  let mut depth = 0;
+
+  for token in Tokens::new(content, opening_brace) {
+    match token.text(content) {
+      "{" => depth += 1,
+      "}" if depth == 1 => return Some(token.range),
+      "}" => depth -= 1,
+      _ => {}
+    }
+  }
+
+  None
+}
@@ -1,25 +1,144 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
 use std::path::PathBuf;
 
-use regex::Regex;
+use crate::ast::DiagnosticKind;
+use crate::timings::Timings;
+
+use super::origins::OriginMap;
 
 pub type FileName = String;
 pub type DependencyName = String;
 
 pub struct ProcessedFile {
   pub content: RefCell<String>,
-  pub path: PathBuf
+
+  /// The content of the file as it was read, the diagnostics of the
+  /// preprocessor point to it.
+  pub original_content: String,
+
+  /// Where the parts of the content come from in the original content, it is
+  /// kept up to date by every step of the preprocessor.
+  pub origins: RefCell<OriginMap>,
+  pub path: PathBuf,
+
+  /// The path of the file in its source directory, with forward slashes
+  pub relative_path: String
 }
 
+/// The files are stored in ordered maps so they are always processed, and
+/// their code emitted, in the same order.
 pub struct PreprocessorOutput {
-  pub source_files_content: HashMap<FileName, ProcessedFile>,
+  pub source_files_content: BTreeMap<FileName, ProcessedFile>,
+
+  pub dependencies_files_content: BTreeMap<DependencyName, BTreeMap<FileName, ProcessedFile>>,
+
+  pub diagnostics: Vec<PreprocessorDiagnostic>,
+
+  /// The time spent in the phases of the preprocessor
+  pub timings: Timings
+}
+
+impl PreprocessorOutput {
+  /// Returns the file with the given name, from the sources or the
+  /// dependencies.
+  pub fn get_file(&self, filename: &str) -> Option<&ProcessedFile> {
+    self.source_files_content.get(filename).or_else(|| {
+      self
+        .dependencies_files_content
+        .values()
+        .find_map(|files| files.get(filename))
+    })
+  }
+}
+
+/// A problem found by the preprocessor. The range is a byte range in the
+/// original content of the file, the code that comes from an expansion is at
+/// the call written in the file.
+pub struct PreprocessorDiagnostic {
+  pub kind: DiagnosticKind,
+  pub code: &'static str,
+  pub filename: FileName,
+  pub range: Range<usize>,
+  pub message: String,
+  pub label: String,
+  pub help: Option<String>
+}
+
+/// The default maximum number of nested macro expansions
+pub const DEFAULT_EXPANSION_DEPTH_LIMIT: usize = 64;
+
+/// The default maximum size of a file once its macros are expanded, 16MiB
+pub const DEFAULT_EXPANSION_SIZE_LIMIT: usize = 16 * 1024 * 1024;
+
+/// The settings of the preprocessor, they come from the configuration of the
+/// package.
+pub struct PreprocessorOptions {
+  /// The maximum number of nested macro expansions, the deeper expansions are
+  /// reported and removed so recursive macros end.
+  pub expansion_depth_limit: usize,
 
-  pub dependencies_files_content: HashMap<DependencyName, HashMap<FileName, ProcessedFile>>
+  /// The maximum size in bytes of a file once its macros are expanded, the
+  /// expansion of the file stops once it grows past it.
+  pub expansion_size_limit: usize,
+
+  /// The macro constants defined before the files are read, with their values
+  pub defines: BTreeMap<String, String>,
+
+  /// The macro constants whose `#define` are ignored, they stay undefined
+  pub undefines: BTreeSet<String>,
+
+  /// The macro constants the libraries set for their own files, by
+  /// dependency
+  pub library_defines: BTreeMap<DependencyName, LibraryDefines>,
+
+  /// The dependencies every package declares, `None` for the sources. A
+  /// package only sees the macros its own dependencies export.
+  pub package_dependencies: BTreeMap<Option<DependencyName>, BTreeSet<DependencyName>>
+}
+
+/// The macro constants a library sets in its `cahirc.toml` file, the constants
+/// of the options win over them.
+#[derive(Default)]
+pub struct LibraryDefines {
+  /// The constants the files of the library see, with their values
+  pub defines: BTreeMap<String, String>,
+
+  /// The constants whose `#define` are ignored in the files of the library
+  pub undefines: BTreeSet<String>
+}
+
+impl PreprocessorOptions {
+  /// Returns whether the constant is set by the options or by the library of
+  /// the package, the `#define` of the files are then ignored.
+  pub fn is_set(&self, dependency: Option<&str>, name: &str) -> bool {
+    let library_defines = dependency.and_then(|dependency| self.library_defines.get(dependency));
+
+    self.defines.contains_key(name)
+      || self.undefines.contains(name)
+      || library_defines.is_some_and(|library| {
+        library.defines.contains_key(name) || library.undefines.contains(name)
+      })
+  }
+}
+
+impl Default for PreprocessorOptions {
+  fn default() -> Self {
+    Self {
+      expansion_depth_limit: DEFAULT_EXPANSION_DEPTH_LIMIT,
+      expansion_size_limit: DEFAULT_EXPANSION_SIZE_LIMIT,
+      defines: BTreeMap::new(),
+      undefines: BTreeSet::new(),
+      library_defines: BTreeMap::new(),
+      package_dependencies: BTreeMap::new()
+    }
+  }
 }
 
 #[derive(Debug)]
 pub struct MacroFunction {
+  pub name: String,
   pub parameters: Vec<String>,
   pub body: String
 }
@@ -35,12 +154,3 @@ pub enum MacroDefinition {
   Function(MacroFunction),
   Constant(MacroConstant)
 }
-
-pub struct RegexCollection {
-  pub macro_const: Regex,
-  pub macro_const_value: Regex,
-  pub macro_function: Regex,
-  pub macro_call: Regex,
-  pub macro_ifdef: Regex,
-  pub macro_ifndef: Regex
-}
@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::ast::codegen::{SPAN_MARKER_END, SPAN_MARKER_START};
+use crate::ast::Span;
+use crate::compiler::{Compilation, StrippedLines};
+
+/// The origin of every line of an output file, it is written next to the
+/// output with the `.map` extension. The files are relative to the directory of
+/// the project, so the maps do not depend on where the project is.
+#[derive(Serialize, Deserialize, Default)]
+pub struct SourceMap {
+  /// The first location is the origin of the first line of the output, a line
+  /// that doesn't come from a source file has no location.
+  pub lines: Vec<Option<SourceLocation>>
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct SourceLocation {
+  pub file: String,
+
+  /// The line and the column in the `.wss` file, starting at 1
+  pub line: usize,
+  pub column: usize,
+
+  /// The chain of macros the code comes from when it is the expansion of a
+  /// macro call, from the call written in the `.wss` file. The location is the
+  /// location of the call.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub macros: Vec<String>
+}
+
+/// Builds the source maps of the outputs from the span markers in the emitted
+/// code.
+pub struct SourceMapBuilder<'a> {
+  compilation: &'a Compilation,
+
+  /// The directory of the project
+  root: PathBuf,
+
+  /// The path of every source relative to the directory of the project
+  relative_paths: HashMap<String, String>,
+
+  /// The lines of the parsed sources and of their preprocessed content, by
+  /// source path
+  stripped_lines: HashMap<String, StrippedLines>
+}
+
+impl<'a> SourceMapBuilder<'a> {
+  pub fn new(compilation: &'a Compilation, root: &Path) -> Self {
+    Self {
+      compilation,
+      root: dunce::canonicalize(root).unwrap_or_else(|_| root.to_path_buf()),
+      relative_paths: HashMap::new(),
+      stripped_lines: HashMap::new()
+    }
+  }
+
+  /// Removes the span markers from the emitted code, and returns the code with
+  /// the source map of its lines. A line without a marker has the location of
+  /// the last marker before it.
+  pub fn take_span_markers(&mut self, code: &str) -> (String, SourceMap) {
+    let mut output = String::with_capacity(code.len());
+    let mut source_map = SourceMap::default();
+    let mut previous_location = None;
+    let mut line_location = None;
+    let mut chars = code.chars();
+
+    while let Some(c) = chars.next() {
+      match c {
+        SPAN_MARKER_START => {
+          let id: String = chars
+            .by_ref()
+            .take_while(|c| *c != SPAN_MARKER_END)
+            .collect();
+
+          if line_location.is_none() {
+            line_location = id.parse().ok().and_then(|id| self.span_location(Span(id)));
+          }
+        }
+        '\n' => {
+          output.push(c);
+
+          if line_location.is_some() {
+            previous_location = line_location.take();
+          }
+
+          source_map.lines.push(previous_location.clone());
+        }
+        _ => output.push(c)
+      };
+    }
+
+    if !output.ends_with('\n') {
+      source_map.lines.push(line_location.or(previous_location));
+    }
+
+    (output, source_map)
+  }
+
+  fn span_location(&mut self, span: Span) -> Option<SourceLocation> {
+    let span_manager = &self.compilation.span_manager;
+    let path = span_manager.get_source(&span);
+    let content = span_manager.get_source_content(&span);
+
+    if path.is_empty() {
+      return None;
+    }
+
+    let offset = span_manager.get_left(span).min(content.len());
+    let root = &self.root;
+    let file_path = self
+      .relative_paths
+      .entry(path.clone())
+      .or_insert_with(|| relative_path(Path::new(path), root))
+      .clone();
+
+    let Some(file) = self.compilation.preprocessed_content.get_file(path) else {
+      let (line, column) = line_and_column(content, offset);
+
+      return Some(SourceLocation {
+        file: file_path,
+        line,
+        column,
+        macros: Vec::new()
+      });
+    };
+
+    // the parsed content is the preprocessed content without its pragmas
+    let offset = self
+      .stripped_lines
+      .entry(path.clone())
+      .or_insert_with(|| StrippedLines::new(content, &file.content.borrow()))
+      .preprocessed_offset(offset);
+
+    let origins = file.origins.borrow();
+    let original_offset = origins.original_range(offset..offset).start;
+    let (line, column) = line_and_column(&file.original_content, original_offset);
+
+    Some(SourceLocation {
+      file: file_path,
+      line,
+      column,
+      macros: origins
+        .expansion_at(offset)
+        .map(|expansion| expansion.chain().into_iter().map(String::from).collect())
+        .unwrap_or_default()
+    })
+  }
+}
+
+/// Returns the path relative to the root with forward slashes, the files out
+/// of the root like the ones of the dependencies start with `..`.
+fn relative_path(path: &Path, root: &Path) -> String {
+  let path = dunce::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+  let path_components: Vec<_> = path.components().collect();
+  let root_components: Vec<_> = root.components().collect();
+
+  let common = path_components
+    .iter()
+    .zip(&root_components)
+    .take_while(|(a, b)| a == b)
+    .count();
+
+  let mut relative = PathBuf::new();
+  for _ in common..root_components.len() {
+    relative.push("..");
+  }
+  relative.extend(&path_components[common..]);
+
+  relative.to_str().unwrap_or_default().replace('\\', "/")
+}
+
+/// Returns the line and the column of the offset in the content, starting at 1.
+fn line_and_column(content: &str, offset: usize) -> (usize, usize) {
+  let before = &content[..offset.min(content.len())];
+  let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+
+  (
+    before.matches('\n').count() + 1,
+    before[line_start..].chars().count() + 1
+  )
+}
+
+impl SourceMap {
+  pub fn to_json(&self) -> String {
+    serde_json::to_string(self).unwrap_or_default()
+  }
+}
+
+/// Returns the path of the source map of an output file.
+pub fn source_map_path(output_path: &Path) -> PathBuf {
+  let mut path = output_path.as_os_str().to_owned();
+  path.push(".map");
+
+  PathBuf::from(path)
+}
+
+/// Prints the location in the `.wss` files of a line of an output file, the
+/// argument is the path of the output and the line as in `dist/foo.ws:812`.
+pub fn print_origin(argument: &str) -> Result<(), String> {
+  let Some((output_path, line)) = argument.rsplit_once(':') else {
+    return Err(format!(
+      "expected a path and a line as in `dist/foo.ws:812`, got `{argument}`"
+    ));
+  };
+
+  let line: usize = match line.parse() {
+    Ok(line) if line > 0 => line,
+    _ => return Err(format!("invalid line `{line}`, the lines start at 1"))
+  };
+
+  let map_path = source_map_path(Path::new(output_path));
+  let content = std::fs::read_to_string(&map_path)
+    .map_err(|error| format!("could not read {}: {error}", map_path.display()))?;
+
+  let source_map: SourceMap = serde_json::from_str(&content)
+    .map_err(|error| format!("invalid source map {}: {error}", map_path.display()))?;
+
+  let Some(Some(location)) = source_map.lines.get(line - 1) else {
+    return Err(format!(
+      "the line {line} of {output_path} has no known origin"
+    ));
+  };
+
+  println!("{}:{}:{}", location.file, location.line, location.column);
+
+  for macro_name in &location.macros {
+    println!("  in the expansion of {macro_name}!");
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_utils::compile_source;
+
+  const SOURCE: &str = r#"#define function ASSIGN(value) {
+  x = value;
+};
+
+#define function IF_DEBUG(code) {
+  if (true) {
+    code
+  }
+};
+
+#define function PRINT(message) {
+  IF_DEBUG!({{
+    print(message);
+  }})
+};
+
+function main() {
+  var x: string;
+
+  PRINT!("debug")
+  ASSIGN!("1.2")
+  #pragma cahirc-allow(unused)
+  x = "written";
+}
+"#;
+
+  /// Returns the location of the first line of the output that contains the
+  /// text.
+  fn location_of(text: &str) -> SourceLocation {
+    let project = compile_source(SOURCE, false);
+    let output = project.output("main.ws");
+    let source_map: SourceMap = serde_json::from_str(project.output("main.ws.map")).unwrap();
+
+    let index = output
+      .lines()
+      .position(|line| line.contains(text))
+      .unwrap_or_else(|| panic!("no line with `{text}` in:\n{output}"));
+
+    source_map.lines[index]
+      .clone()
+      .unwrap_or_else(|| panic!("the line with `{text}` has no location"))
+  }
+
+  #[test]
+  fn written_code_keeps_its_location() {
+    let location = location_of("\"written\"");
+
+    assert_eq!((location.line, location.column), (23, 3));
+    assert!(location.macros.is_empty());
+  }
+
+  #[test]
+  fn expanded_code_has_the_location_of_the_call() {
+    let location = location_of("\"1.2\"");
+
+    assert_eq!((location.line, location.column), (21, 3));
+    assert_eq!(location.macros, vec!["ASSIGN"]);
+  }
+
+  #[test]
+  fn nested_expansions_keep_their_chain() {
+    let location = location_of("print(\"debug\")");
+
+    assert_eq!((location.line, location.column), (20, 3));
+    assert_eq!(location.macros, vec!["PRINT", "IF_DEBUG"]);
+  }
+
+  #[test]
+  fn hoisted_variables_point_to_their_declaration() {
+    let location = location_of("var x: string;");
+
+    assert_eq!((location.line, location.column), (18, 7));
+    assert!(location.macros.is_empty());
+  }
+
+  #[test]
+  fn files_are_relative_to_the_project() {
+    assert_eq!(location_of("\"written\"").file, "src/main.wss");
+    assert_eq!(
+      relative_path(Path::new("/project/../lib/main.wss"), Path::new("/project")),
+      "../lib/main.wss"
+    );
+  }
+
+  #[test]
+  fn lines_are_counted_in_characters() {
+    assert_eq!(line_and_column("a\néé b", 6), (2, 3));
+    assert_eq!(line_and_column("", 0), (1, 1));
+  }
+}
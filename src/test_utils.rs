@@ -0,0 +1,226 @@
+//! Helpers for the tests that compile small projects written in a temporary
+//! directory.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::ast::DiagnosticKind;
+use crate::compiler;
+use crate::config::{read_config_from, Config};
+use crate::preprocessor::preprocess;
+use crate::preprocessor::types::{PreprocessorOptions, PreprocessorOutput, ProcessedFile};
+
+static PROJECT_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// A directory in the temporary directory, removed once dropped.
+pub struct TestDirectory {
+  pub path: PathBuf
+}
+
+impl TestDirectory {
+  pub fn new(name: &str) -> Self {
+    let path = std::env::temp_dir().join(format!(
+      "cahirc-test-{name}-{}-{}",
+      std::process::id(),
+      PROJECT_COUNT.fetch_add(1, Ordering::SeqCst)
+    ));
+
+    let _ = std::fs::remove_dir_all(&path);
+    std::fs::create_dir_all(&path).expect("could not create the test directory");
+
+    Self { path }
+  }
+
+  /// Writes the file at the path relative to the directory, with its parent
+  /// directories.
+  pub fn file(&self, path: &str, content: &str) -> &Self {
+    let path = self.path.join(path);
+
+    if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent).expect("could not create the test directories");
+    }
+
+    std::fs::write(path, content).expect("could not write the test file");
+
+    self
+  }
+
+  /// Runs git in the directory and returns its output, for the tests of the
+  /// git dependencies.
+  pub fn git(&self, args: &[&str]) -> String {
+    let output = std::process::Command::new("git")
+      .args([
+        "-c",
+        "user.name=cahirc",
+        "-c",
+        "user.email=cahirc@localhost",
+        "-c",
+        "commit.gpgsign=false",
+        "-c",
+        "init.defaultBranch=main"
+      ])
+      .args(args)
+      .current_dir(&self.path)
+      .output()
+      .expect("could not run git");
+
+    assert!(
+      output.status.success(),
+      "git {args:?} failed: {}",
+      String::from_utf8_lossy(&output.stderr)
+    );
+
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+  }
+
+  /// Returns the `file://` url of the directory.
+  pub fn url(&self) -> String {
+    format!("file://{}", self.path.to_str().unwrap().replace('\\', "/"))
+  }
+}
+
+impl Drop for TestDirectory {
+  fn drop(&mut self) {
+    let _ = std::fs::remove_dir_all(&self.path);
+  }
+}
+
+/// The outputs of a compiled project, by their path relative to the dist
+/// directory, and the codes of the diagnostics.
+pub struct CompiledProject {
+  pub outputs: BTreeMap<String, String>,
+  pub errors: Vec<String>,
+  pub warnings: Vec<String>
+}
+
+impl CompiledProject {
+  /// Returns the code emitted for the file, panics if there is none.
+  pub fn output(&self, path: &str) -> &str {
+    match self.outputs.get(path) {
+      Some(output) => output,
+      None => panic!(
+        "no output {path}, the outputs are {:?} and the errors {:?}",
+        self.outputs.keys().collect::<Vec<_>>(),
+        self.errors
+      )
+    }
+  }
+}
+
+/// Reads the project in the directory and compiles it.
+pub fn compile_project(directory: &TestDirectory) -> CompiledProject {
+  let config = read_config_from(&directory.path).expect("could not read the test project");
+
+  compile_config(&config)
+}
+
+pub fn compile_config(config: &Config) -> CompiledProject {
+  let compilation = compiler::analyse(config, config.package.static_analysis.unwrap_or(false))
+    .expect("could not analyse the test project");
+
+  let codes = |kind: DiagnosticKind| -> Vec<String> {
+    compilation
+      .report_manager
+      .diagnostics()
+      .iter()
+      .filter(|diagnostic| diagnostic.kind == kind)
+      .map(|diagnostic| diagnostic.code.unwrap_or_default().to_string())
+      .collect()
+  };
+
+  let errors = codes(DiagnosticKind::Error);
+  let warnings = codes(DiagnosticKind::Warning);
+
+  let outputs = compiler::generate(&compilation, config)
+    .into_iter()
+    .map(|(path, content)| {
+      let relative = path.strip_prefix(&config.package.dist).unwrap_or(&path);
+
+      (relative.to_str().unwrap().replace('\\', "/"), content)
+    })
+    .collect();
+
+  CompiledProject {
+    outputs,
+    errors,
+    warnings
+  }
+}
+
+/// Compiles a project made of a single `main.wss` file, and returns its
+/// diagnostics and outputs, the code of the file is in `main.ws`.
+pub fn compile_source(source: &str, static_analysis: bool) -> CompiledProject {
+  let directory = TestDirectory::new("source");
+
+  directory
+    .file(
+      "cahirc.toml",
+      &format!(
+        "[package]\nname = \"test\"\nsrc = \"src\"\ndist = \"dist\"\nstatic_analysis = {static_analysis}\n"
+      )
+    )
+    .file("src/main.wss", source);
+
+  compile_project(&directory)
+}
+
+/// Returns the code without its indentation and empty lines, to compare the
+/// emitted code with the expected one.
+pub fn normalize(code: &str) -> String {
+  code
+    .lines()
+    .map(str::trim)
+    .filter(|line| !line.is_empty())
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
+/// Preprocesses the files, the paths in `src/` are the sources and the ones in
+/// `<dependency>/` are the files of the dependency.
+pub fn preprocess_files(
+  files: &[(&str, &str)], options: &PreprocessorOptions
+) -> PreprocessorOutput {
+  let directory = TestDirectory::new("preprocessor");
+  std::fs::create_dir_all(directory.path.join("src")).unwrap();
+
+  let mut dependencies = HashMap::new();
+  for (path, content) in files {
+    directory.file(path, content);
+
+    if let Some((dependency, _)) = path.split_once('/').filter(|(root, _)| *root != "src") {
+      dependencies.insert(
+        dependency.to_string(),
+        directory
+          .path
+          .join(dependency)
+          .to_str()
+          .unwrap()
+          .to_string()
+      );
+    }
+  }
+
+  preprocess(
+    directory.path.join("src").to_str().unwrap(),
+    &dependencies,
+    options
+  )
+  .expect("could not preprocess the test files")
+}
+
+/// Preprocesses a project made of a single `main.wss` file.
+pub fn preprocess_source(source: &str, options: &PreprocessorOptions) -> PreprocessorOutput {
+  preprocess_files(&[("src/main.wss", source)], options)
+}
+
+/// Returns the file of the sources whose path ends with the name.
+pub fn processed_file<'a>(output: &'a PreprocessorOutput, name: &str) -> &'a ProcessedFile {
+  output
+    .source_files_content
+    .iter()
+    .chain(output.dependencies_files_content.values().flatten())
+    .find(|(filename, _)| filename.ends_with(name))
+    .map(|(_, file)| file)
+    .unwrap_or_else(|| panic!("no file {name}"))
+}
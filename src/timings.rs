@@ -0,0 +1,69 @@
+use std::time::{Duration, Instant};
+
+/// The time spent in the phases of the compilation, they are printed with the
+/// `--timings` option.
+#[derive(Default, Debug)]
+pub struct Timings {
+  phases: Vec<(String, Duration)>
+}
+
+impl Timings {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Runs the phase and records the time it took.
+  pub fn measure<T>(&mut self, phase: &str, run: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let output = run();
+
+    self.record(phase, start.elapsed());
+
+    output
+  }
+
+  pub fn record(&mut self, phase: &str, duration: Duration) {
+    self.phases.push((phase.to_string(), duration));
+  }
+
+  /// Moves the phases of the other timings at the end of these ones.
+  pub fn append(&mut self, other: &mut Timings) {
+    self.phases.append(&mut other.phases);
+  }
+
+  pub fn total(&self) -> Duration {
+    self.phases.iter().map(|(_, duration)| *duration).sum()
+  }
+
+  /// Returns the report of the phases, one per line with the total at the end.
+  pub fn report(&self) -> String {
+    let width = self
+      .phases
+      .iter()
+      .map(|(phase, _)| phase.len())
+      .chain(std::iter::once("total".len()))
+      .max()
+      .unwrap_or_default();
+
+    let mut output = String::from("timings:\n");
+
+    for (phase, duration) in &self.phases {
+      output.push_str(&format!(
+        "  {phase:width$}  {}\n",
+        format_duration(*duration)
+      ));
+    }
+
+    output.push_str(&format!(
+      "  {:width$}  {}",
+      "total",
+      format_duration(self.total())
+    ));
+
+    output
+  }
+}
+
+fn format_duration(duration: Duration) -> String {
+  format!("{:>9.2}ms", duration.as_secs_f64() * 1000.0)
+}
@@ -3,3 +3,9 @@ pub use strip_pragmas::strip_pragmas;
 
 mod convert_line_endings;
 pub use convert_line_endings::*;
+
+mod stable_hash;
+pub use stable_hash::*;
+
+mod read_script_file;
+pub use read_script_file::*;
@@ -0,0 +1,32 @@
+use std::path::Path;
+
+use super::convert_line_endings;
+
+/// Reads a witcherscript file, the vanilla scripts are sometimes encoded in
+/// UTF-16 so the BOM is used to know how to decode the content.
+pub fn read_script_file(path: &Path) -> std::io::Result<String> {
+  let bytes = std::fs::read(path)?;
+
+  let content = match bytes.as_slice() {
+    [0xff, 0xfe, rest @ ..] => {
+      let units: Vec<u16> = rest
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+
+      String::from_utf16_lossy(&units)
+    }
+    [0xfe, 0xff, rest @ ..] => {
+      let units: Vec<u16> = rest
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect();
+
+      String::from_utf16_lossy(&units)
+    }
+    [0xef, 0xbb, 0xbf, rest @ ..] => String::from_utf8_lossy(rest).to_string(),
+    _ => String::from_utf8_lossy(&bytes).to_string()
+  };
+
+  Ok(convert_line_endings(content))
+}
@@ -0,0 +1,69 @@
+use std::path::Path;
+
+/// Hashes the given parts into a short hexadecimal string that stays the same
+/// from one compilation to another, and from one machine to another. It is
+/// used to generate the mangled names so identical inputs always yield the same
+/// output.
+pub fn stable_hash(parts: &[&str]) -> String {
+  // FNV-1a, 64 bits
+  const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+  const PRIME: u64 = 0x100000001b3;
+
+  let mut hash = OFFSET_BASIS;
+
+  for part in parts {
+    // the separator avoids collisions between `["ab", "c"]` and `["a", "bc"]`
+    for byte in part.bytes().chain(std::iter::once(0xff)) {
+      hash ^= byte as u64;
+      hash = hash.wrapping_mul(PRIME);
+    }
+  }
+
+  format!("{hash:016x}")
+}
+
+/// Returns the path relative to the root with forward slashes, so the hashes
+/// made out of it do not depend on where the project is located or on the
+/// platform.
+pub fn stable_path(path: &Path, root: &str) -> String {
+  path
+    .strip_prefix(root)
+    .unwrap_or(path)
+    .components()
+    .map(|component| component.as_os_str().to_string_lossy())
+    .collect::<Vec<_>>()
+    .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::Path;
+
+  use super::*;
+
+  #[test]
+  fn hashes_are_stable() {
+    assert_eq!(
+      stable_hash(&["package", "file"]),
+      stable_hash(&["package", "file"])
+    );
+    assert_eq!(stable_hash(&[]), "cbf29ce484222325");
+  }
+
+  #[test]
+  fn parts_are_separated() {
+    assert_ne!(stable_hash(&["ab", "c"]), stable_hash(&["a", "bc"]));
+    assert_ne!(stable_hash(&["a"]), stable_hash(&["a", ""]));
+  }
+
+  #[test]
+  fn paths_are_relative_to_the_root() {
+    let root = Path::new("project").join("src");
+    let path = root.join("nested").join("file.wss");
+
+    assert_eq!(
+      stable_path(&path, root.to_str().unwrap()),
+      "nested/file.wss"
+    );
+  }
+}
@@ -0,0 +1,394 @@
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+mod parser;
+mod tokenizer;
+pub mod types;
+
+use crate::ast::codegen::context::{Context, ContextType};
+use crate::ast::codegen::type_inference::{
+  FunctionInferedParameterType, InferedType, TypeInferenceStore
+};
+use crate::ast::{Span, SpanMaker, SpanManager};
+use crate::utils::{read_script_file, stable_hash, stable_path};
+
+use self::parser::DeclarationParser;
+use self::tokenizer::tokenize;
+use self::types::*;
+
+/// The global variables the game provides to the scripts, they are not declared
+/// anywhere in the vanilla scripts.
+const BUILTIN_GLOBALS: [(&str, &str); 8] = [
+  ("theGame", "CR4Game"),
+  ("thePlayer", "CR4Player"),
+  ("theServer", "CServerInterface"),
+  ("theSound", "CScriptSoundSystem"),
+  ("theInput", "CInputManager"),
+  ("theTelemetry", "CR4TelemetryScriptProxy"),
+  ("theDebug", "CDebugAttributesManager"),
+  ("theTimer", "CTimerScriptKeyword")
+];
+
+/// The methods the game provides on the arrays, written like the vanilla
+/// declarations. `T` is the type of the items, it is replaced by the actual
+/// type of the items where the methods are called.
+const BUILTIN_ARRAY: &str = "
+class array {
+  function Clear();
+  function Size(): int;
+  function PushBack(element: T);
+  function PopBack(): T;
+  function Resize(newSize: int);
+  function Remove(element: T): bool;
+  function Contains(element: T): bool;
+  function FindFirst(element: T): int;
+  function FindLast(element: T): int;
+  function Grow(numElements: int): int;
+  function Erase(index: int);
+  function EraseFast(index: int);
+  function Insert(index: int, element: T);
+  function Last(): T;
+}
+";
+
+/// Registers what the game provides without declaring it in the vanilla
+/// scripts, the global variables like `thePlayer` and the methods of the
+/// arrays, so they are known to the static analysis.
+pub fn register_builtins(
+  global_context: &Rc<RefCell<Context>>, inference_store: &mut TypeInferenceStore,
+  span_manager: &mut SpanManager
+) {
+  {
+    let mut global_context = global_context.borrow_mut();
+
+    for (name, type_name) in BUILTIN_GLOBALS {
+      global_context
+        .local_variables_inference
+        .insert(name.to_string(), type_name.to_string());
+    }
+  }
+
+  let builtin_context = Rc::new(RefCell::new(Context::new(
+    "file: builtin",
+    None,
+    ContextType::Global
+  )));
+
+  Context::set_parent_context(&builtin_context, global_context);
+
+  let tokens = tokenize(BUILTIN_ARRAY);
+  let declarations = DeclarationParser::new(&tokens).parse();
+  let mut span_maker = span_manager.add_source(
+    "builtin: array".to_string(),
+    BUILTIN_ARRAY.to_string(),
+    stable_hash(&["builtin", "array"])
+  );
+
+  for declaration in declarations {
+    register_declaration(
+      declaration,
+      global_context,
+      &builtin_context,
+      inference_store,
+      &mut span_maker
+    );
+  }
+}
+
+/// Reads the declarations from the vanilla `content/scripts` directory and
+/// registers them in the inference store, so the calls to the game's API can be
+/// type checked like any other call.
+///
+/// The classes and structs also get a context in the global context, under a
+/// `file: vanilla` context, that holds the types of their properties.
+pub fn load_vanilla_scripts(
+  directory: &str, global_context: &Rc<RefCell<Context>>, inference_store: &mut TypeInferenceStore,
+  span_manager: &mut SpanManager
+) -> std::io::Result<()> {
+  let vanilla_context = Rc::new(RefCell::new(Context::new(
+    "file: vanilla",
+    None,
+    ContextType::Global
+  )));
+
+  Context::set_parent_context(&vanilla_context, global_context);
+
+  let files = walkdir::WalkDir::new(directory)
+    .sort_by_file_name()
+    .into_iter()
+    .filter_map(Result::ok)
+    .filter(|file| {
+      file
+        .path()
+        .extension()
+        .map(|extension| extension == "ws")
+        .unwrap_or(false)
+    });
+
+  for file in files {
+    let content = read_script_file(file.path())?;
+    let tokens = tokenize(&content);
+    let declarations = DeclarationParser::new(&tokens).parse();
+
+    let seed = stable_hash(&["vanilla", &stable_path(file.path(), directory)]);
+    let mut span_maker =
+      span_manager.add_source(path_to_string(file.path()), content.clone(), seed);
+
+    for declaration in declarations {
+      register_declaration(
+        declaration,
+        global_context,
+        &vanilla_context,
+        inference_store,
+        &mut span_maker
+      );
+    }
+  }
+
+  Ok(())
+}
+
+/// Registers the declaration in the inference store, and its properties in a
+/// context under `vanilla_context`.
+fn register_declaration(
+  declaration: VanillaDeclaration, global_context: &Rc<RefCell<Context>>,
+  vanilla_context: &Rc<RefCell<Context>>, inference_store: &mut TypeInferenceStore,
+  span_maker: &mut SpanMaker
+) {
+  // the vanilla scripts are trusted, a declaration that was already registered
+  // is simply ignored.
+  match declaration {
+    VanillaDeclaration::Class {
+      name,
+      extends,
+      fields,
+      methods
+    } => {
+      let _ = inference_store.register_compound(name.clone(), extends);
+
+      register_compound_context(&format!("class: {name}"), fields, vanilla_context);
+
+      for method in methods {
+        let (method_name, parameters, return_type, span) = function_signature(method, span_maker);
+
+        let _ =
+          inference_store.register_method(name.clone(), method_name, parameters, return_type, span);
+      }
+    }
+    VanillaDeclaration::Struct { name, fields } => {
+      let _ = inference_store.register_compound(name.clone(), None);
+
+      register_compound_context(&format!("struct: {name}"), fields, vanilla_context);
+    }
+    VanillaDeclaration::Enum { name, variants } => {
+      inference_store
+        .types
+        .entry(name.clone())
+        .or_insert_with(|| Rc::new(InferedType::Scalar));
+
+      // the enum variants are global constants
+      let mut global_context = global_context.borrow_mut();
+      for variant in variants {
+        global_context
+          .local_variables_inference
+          .insert(variant, name.clone());
+      }
+    }
+    VanillaDeclaration::Function(function) => {
+      let (function_name, parameters, return_type, span) = function_signature(function, span_maker);
+
+      let _ = inference_store.register_function(function_name, parameters, return_type, span);
+    }
+  };
+}
+
+/// Creates the context of a vanilla class or struct, with the types of its
+/// properties.
+fn register_compound_context(
+  context_name: &str, fields: Vec<VanillaField>, vanilla_context: &Rc<RefCell<Context>>
+) {
+  let context = Rc::new(RefCell::new(Context::new(
+    context_name,
+    None,
+    ContextType::ClassOrStruct
+  )));
+
+  for field in fields {
+    context
+      .borrow_mut()
+      .local_variables_inference
+      .insert(field.name, field.type_name);
+  }
+
+  Context::set_parent_context(&context, vanilla_context);
+}
+
+/// Turns the signature of a vanilla function into the form the inference store
+/// expects.
+fn function_signature(
+  function: VanillaFunction, span_maker: &mut SpanMaker
+) -> (
+  String,
+  Vec<FunctionInferedParameterType>,
+  Option<String>,
+  Span
+) {
+  let parameters = function
+    .parameters
+    .into_iter()
+    .map(|parameter| FunctionInferedParameterType {
+      parameter_type: parameter.parameter_type,
+      infered_type: parameter.type_name,
+      span: span_maker.span(parameter.left, parameter.right, "vanilla parameter")
+    })
+    .collect();
+
+  let span = span_maker.span(function.left, function.right, "vanilla function");
+
+  (function.name, parameters, function.return_type, span)
+}
+
+fn path_to_string(path: &Path) -> String {
+  path.to_string_lossy().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_utils::{compile_project, compile_source, CompiledProject, TestDirectory};
+
+  /// A few files written like the ones of the vanilla `content/scripts`
+  /// directory, with the constructs the parser skips.
+  const VANILLA_FILES: [(&str, &str); 4] = [
+    (
+      "vanilla/engine/gameplayEntity.ws",
+      "import class CGameplayEntity extends CEntity\n{\n  import var inv : CInventoryComponent;\n  editable var focusModeVisibility : EFocusModeVisibility;\n\n  default focusModeVisibility = FMV_None;\n\n  import final function GetInventory() : CInventoryComponent;\n}\n"
+    ),
+    (
+      "vanilla/game/player/r4Player.ws",
+      "statemachine abstract import class CR4Player extends CPlayer\n{\n  protected saved var isInCombat : bool;\n\n  public function IsInCombat() : bool\n  {\n    return isInCombat;\n  }\n\n  defaults\n  {\n    isInCombat = false;\n  }\n}\n\nimport class CPlayer extends CActor {}\nimport class CActor extends CGameplayEntity {}\n\nstate Exploration in CR4Player\n{\n  event OnEnterState( prevStateName : name )\n  {\n    parent.OnEnterState(prevStateName);\n  }\n\n  function CanSprint( speed : float ) : bool\n  {\n    return speed > 1.0;\n  }\n}\n"
+    ),
+    (
+      "vanilla/game/components/inventoryComponent.ws",
+      "import struct SItemUniqueId {}\n\nimport class CInventoryComponent extends CComponent\n{\n  import final function GetAllItems( out items : array< SItemUniqueId > );\n  import final function GetItemName( itemId : SItemUniqueId ) : name;\n}\n\nenum EFocusModeVisibility\n{\n  FMV_None,\n  FMV_Interactive,\n  FMV_Clue = 2\n}\n"
+    ),
+    (
+      "vanilla/game/globals.ws",
+      "function GetWitcherPlayer() : CR4Player\n{\n  return (CR4Player)thePlayer;\n}\n"
+    )
+  ];
+
+  /// Compiles the main file with the static analysis and the sample vanilla
+  /// scripts.
+  fn compile_with_vanilla(main: &str) -> CompiledProject {
+    let directory = TestDirectory::new("vanilla");
+
+    directory
+      .file(
+        "cahirc.toml",
+        "[package]\nname = \"test\"\nsrc = \"src\"\ndist = \"dist\"\nstatic_analysis = true\nvanilla_scripts = \"vanilla\"\n"
+      )
+      .file("src/main.wss", main);
+
+    for (path, content) in VANILLA_FILES {
+      directory.file(path, content);
+    }
+
+    compile_project(&directory)
+  }
+
+  #[test]
+  fn array_methods_are_declared() {
+    let tokens = tokenize(BUILTIN_ARRAY);
+    let declarations = DeclarationParser::new(&tokens).parse();
+
+    match declarations.as_slice() {
+      [VanillaDeclaration::Class { name, methods, .. }] => {
+        assert_eq!(name, "array");
+        assert_eq!(methods.len(), 14);
+      }
+      _ => panic!("unexpected declarations {declarations:?}")
+    };
+  }
+
+  #[test]
+  fn array_methods_are_known() {
+    let project = compile_source(
+      "function f(numbers: array<int>) {\n  var count: int = numbers.Size();\n  var last: int;\n\n  numbers.PushBack(5);\n  last = numbers.Last();\n}\n",
+      true
+    );
+
+    assert!(project.errors.is_empty(), "{:?}", project.errors);
+    assert!(project.warnings.is_empty(), "{:?}", project.warnings);
+  }
+
+  #[test]
+  fn array_items_are_type_checked() {
+    let project = compile_source(
+      "function f(numbers: array<int>) {\n  numbers.PushBack(\"five\");\n}\n",
+      true
+    );
+
+    assert_eq!(project.errors, vec!["parameter-type-mismatch"]);
+  }
+
+  #[test]
+  fn vanilla_declarations_are_read() {
+    let (_, content) = VANILLA_FILES[1];
+    let tokens = tokenize(content);
+    let declarations = DeclarationParser::new(&tokens).parse();
+
+    let names: Vec<_> = declarations
+      .iter()
+      .map(|declaration| match declaration {
+        VanillaDeclaration::Class { name, extends, .. } => {
+          format!("{name}: {}", extends.as_deref().unwrap_or_default())
+        }
+        declaration => panic!("unexpected declaration {declaration:?}")
+      })
+      .collect();
+
+    assert_eq!(
+      names,
+      vec![
+        "CR4Player: CPlayer",
+        "CPlayer: CActor",
+        "CActor: CGameplayEntity",
+        "CR4PlayerStateExploration: CScriptableState"
+      ]
+    );
+  }
+
+  #[test]
+  fn calls_to_the_vanilla_classes_are_type_checked() {
+    let project = compile_with_vanilla(
+      "function main(id: SItemUniqueId) {\n  var items: array<SItemUniqueId>;\n  var item_name: name;\n\n  thePlayer.inv.GetAllItems(items);\n  item_name = thePlayer.GetInventory().GetItemName(id);\n}\n"
+    );
+
+    assert!(project.errors.is_empty(), "{:?}", project.errors);
+
+    let project = compile_with_vanilla("function main() {\n  thePlayer.inv.GetAllItems(5);\n}\n");
+
+    assert_eq!(project.errors, vec!["parameter-type-mismatch"]);
+  }
+
+  #[test]
+  fn vanilla_globals_and_enums_are_known() {
+    let project = compile_with_vanilla(
+      "function main(): bool {\n  var visibility: EFocusModeVisibility = FMV_Clue;\n\n  return GetWitcherPlayer().IsInCombat();\n}\n"
+    );
+
+    assert!(project.errors.is_empty(), "{:?}", project.errors);
+    assert!(project.warnings.is_empty(), "{:?}", project.warnings);
+  }
+
+  #[test]
+  fn vanilla_return_types_are_type_checked() {
+    let project = compile_with_vanilla(
+      "function main() {\n  thePlayer.inv.GetItemName(GetWitcherPlayer());\n}\n"
+    );
+
+    assert_eq!(project.errors, vec!["parameter-type-mismatch"]);
+  }
+}
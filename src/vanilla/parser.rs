@@ -0,0 +1,515 @@
+use std::cell::RefCell;
+
+use super::tokenizer::{Token, TokenKind};
+use super::types::*;
+use crate::ast::{ParameterType, TypeDeclaration};
+
+/// The keywords that can be placed in front of a declaration and that do not
+/// change its signature.
+const DECLARATION_MODIFIERS: [&str; 20] = [
+  "import",
+  "abstract",
+  "statemachine",
+  "final",
+  "latent",
+  "entry",
+  "exec",
+  "quest",
+  "timer",
+  "storyscene",
+  "cleanup",
+  "reward",
+  "native",
+  "private",
+  "protected",
+  "public",
+  "saved",
+  "editable",
+  "inlined",
+  "const"
+];
+
+/// A tolerant parser for the vanilla scripts, it only reads the signatures of
+/// the declarations and skips the function bodies. Whenever it meets something
+/// it does not understand it skips the statement and continues, so a single
+/// unsupported construct never prevents the rest of the file from being read.
+///
+/// The LALRPOP grammar is not reused here: it stops at the first error, and the
+/// vanilla scripts use constructs it does not have (`state X in Y`, `defaults`
+/// blocks, `autobind`, `import` declarations, etc...) inside bodies this
+/// parser does not need to read anyway.
+pub struct DeclarationParser<'a> {
+  tokens: &'a [Token],
+  cursor: usize
+}
+
+impl<'a> DeclarationParser<'a> {
+  pub fn new(tokens: &'a [Token]) -> Self {
+    Self { tokens, cursor: 0 }
+  }
+
+  pub fn parse(mut self) -> Vec<VanillaDeclaration> {
+    let mut output = Vec::new();
+
+    while self.peek().is_some() {
+      let start = self.cursor;
+
+      self.skip_modifiers();
+
+      let declaration = match self.peek_identifier() {
+        Some("class") => {
+          self.advance();
+          self.parse_class()
+        }
+        Some("state") => {
+          self.advance();
+          self.parse_state()
+        }
+        Some("struct") => {
+          self.advance();
+          self.parse_struct()
+        }
+        Some("enum") => {
+          self.advance();
+          self.parse_enum()
+        }
+        Some("function") | Some("event") => {
+          self.advance();
+          self.parse_function().map(VanillaDeclaration::Function)
+        }
+        _ => {
+          self.skip_statement();
+          None
+        }
+      };
+
+      if let Some(declaration) = declaration {
+        output.push(declaration);
+      }
+
+      // a stray closing brace or any token the statement skipping stopped at
+      if self.cursor == start {
+        self.advance();
+      }
+    }
+
+    output
+  }
+
+  fn parse_class(&mut self) -> Option<VanillaDeclaration> {
+    let name = self.expect_identifier()?;
+    let extends = if self.eat_keyword("extends") {
+      self.expect_identifier()
+    } else {
+      None
+    };
+
+    let (fields, methods) = self.parse_compound_body()?;
+
+    Some(VanillaDeclaration::Class {
+      name,
+      extends,
+      fields,
+      methods
+    })
+  }
+
+  /// `state Name in ParentClass extends Base {}`, the game names the resulting
+  /// class `ParentClassStateName`.
+  fn parse_state(&mut self) -> Option<VanillaDeclaration> {
+    let name = self.expect_identifier()?;
+
+    if !self.eat_keyword("in") {
+      return None;
+    }
+
+    let parent_class_name = self.expect_identifier()?;
+    let extends = if self.eat_keyword("extends") {
+      self
+        .expect_identifier()
+        .map(|base| format!("{parent_class_name}State{base}"))
+    } else {
+      Some("CScriptableState".to_string())
+    };
+
+    let (fields, methods) = self.parse_compound_body()?;
+
+    Some(VanillaDeclaration::Class {
+      name: format!("{parent_class_name}State{name}"),
+      extends,
+      fields,
+      methods
+    })
+  }
+
+  fn parse_struct(&mut self) -> Option<VanillaDeclaration> {
+    let name = self.expect_identifier()?;
+    let (fields, _) = self.parse_compound_body()?;
+
+    Some(VanillaDeclaration::Struct { name, fields })
+  }
+
+  fn parse_enum(&mut self) -> Option<VanillaDeclaration> {
+    let name = self.expect_identifier()?;
+
+    if !self.eat_symbol('{') {
+      return None;
+    }
+
+    let mut variants = Vec::new();
+
+    loop {
+      match self.peek().map(|token| &token.kind) {
+        None => break,
+        Some(TokenKind::Symbol('}')) => {
+          self.advance();
+          break;
+        }
+        Some(TokenKind::Identifier(variant)) => {
+          variants.push(variant.clone());
+          self.advance();
+        }
+        _ => {}
+      };
+
+      // skip the optional value of the variant
+      while let Some(token) = self.peek() {
+        match token.kind {
+          TokenKind::Symbol(',') => {
+            self.advance();
+            break;
+          }
+          TokenKind::Symbol('}') => break,
+          _ => self.advance()
+        };
+      }
+    }
+
+    Some(VanillaDeclaration::Enum { name, variants })
+  }
+
+  /// Parses the body of a class, a state or a struct and returns its fields and
+  /// its methods.
+  fn parse_compound_body(&mut self) -> Option<(Vec<VanillaField>, Vec<VanillaFunction>)> {
+    if !self.eat_symbol('{') {
+      return None;
+    }
+
+    let mut fields = Vec::new();
+    let mut methods = Vec::new();
+
+    loop {
+      match self.peek().map(|token| &token.kind) {
+        None => break,
+        Some(TokenKind::Symbol('}')) => {
+          self.advance();
+          break;
+        }
+        _ => {}
+      };
+
+      let start = self.cursor;
+
+      self.skip_modifiers();
+
+      match self.peek_identifier() {
+        Some("var") => {
+          self.advance();
+          fields.extend(self.parse_variable_declaration());
+        }
+        Some("autobind") => {
+          self.advance();
+          fields.extend(self.parse_variable_declaration());
+        }
+        Some("function") | Some("event") => {
+          self.advance();
+
+          if let Some(method) = self.parse_function() {
+            methods.push(method);
+          }
+        }
+        _ => self.skip_statement()
+      };
+
+      if self.cursor == start {
+        self.advance();
+      }
+    }
+
+    Some((fields, methods))
+  }
+
+  /// `var a, b: Type = value;`
+  fn parse_variable_declaration(&mut self) -> Vec<VanillaField> {
+    let mut names = Vec::new();
+
+    while let Some(name) = self.expect_identifier() {
+      names.push(name);
+
+      if !self.eat_symbol(',') {
+        break;
+      }
+    }
+
+    let type_name = if self.eat_symbol(':') {
+      self.parse_type().map(|t| t.to_string())
+    } else {
+      None
+    };
+
+    self.skip_statement();
+
+    match type_name {
+      Some(type_name) => names
+        .into_iter()
+        .map(|name| VanillaField {
+          name,
+          type_name: type_name.clone()
+        })
+        .collect(),
+      None => Vec::new()
+    }
+  }
+
+  fn parse_function(&mut self) -> Option<VanillaFunction> {
+    let (left, right) = self.peek().map(|token| (token.left, token.right))?;
+    let name = self.expect_identifier()?;
+
+    if !self.eat_symbol('(') {
+      return None;
+    }
+
+    let parameters = self.parse_parameters();
+    let return_type = if self.eat_symbol(':') {
+      self.parse_type().map(|t| t.to_string())
+    } else {
+      None
+    };
+
+    match self.peek().map(|token| &token.kind) {
+      Some(TokenKind::Symbol('{')) => self.skip_block(),
+      Some(TokenKind::Symbol(';')) => self.advance(),
+      _ => {}
+    };
+
+    Some(VanillaFunction {
+      name,
+      parameters,
+      return_type,
+      left,
+      right
+    })
+  }
+
+  /// `(optional a, b: int, out c: string)`, the opening parenthesis is expected
+  /// to be consumed already.
+  fn parse_parameters(&mut self) -> Vec<VanillaParameter> {
+    let mut parameters = Vec::new();
+
+    loop {
+      if self.peek().is_none() || self.eat_symbol(')') {
+        break;
+      }
+
+      let mut parameter_type = ParameterType::Copy;
+
+      while let Some(modifier) = self.peek_identifier() {
+        match modifier {
+          "optional" => parameter_type = ParameterType::Optional,
+          "out" => parameter_type = ParameterType::Reference,
+          _ => break
+        };
+
+        self.advance();
+      }
+
+      let mut names = Vec::new();
+
+      while let Some(token) = self.peek() {
+        let (left, right) = (token.left, token.right);
+
+        if self.expect_identifier().is_none() {
+          break;
+        }
+
+        names.push((left, right));
+
+        if !self.eat_symbol(',') {
+          break;
+        }
+      }
+
+      let type_name = if self.eat_symbol(':') {
+        self.parse_type().map(|t| t.to_string())
+      } else {
+        None
+      };
+
+      let Some(type_name) = type_name else {
+        self.skip_parenthesis();
+        break;
+      };
+
+      for (left, right) in names {
+        parameters.push(VanillaParameter {
+          parameter_type,
+          type_name: type_name.clone(),
+          left,
+          right
+        });
+      }
+
+      if !self.eat_symbol(',') {
+        if !self.eat_symbol(')') {
+          self.skip_parenthesis();
+        }
+
+        break;
+      }
+    }
+
+    parameters
+  }
+
+  /// `Type` or `Type<Generic, ...>`
+  fn parse_type(&mut self) -> Option<TypeDeclaration> {
+    let type_name = self.expect_identifier()?;
+    let generic_type_assignment = if self.eat_symbol('<') {
+      let mut types = Vec::new();
+
+      while let Some(generic_type) = self.parse_type() {
+        types.push(generic_type);
+
+        if !self.eat_symbol(',') {
+          break;
+        }
+      }
+
+      self.eat_symbol('>');
+
+      Some(types)
+    } else {
+      None
+    };
+
+    Some(TypeDeclaration::Regular {
+      type_name,
+      generic_type_assignment,
+      mangled_accessor: RefCell::new(None)
+    })
+  }
+
+  fn peek(&self) -> Option<&Token> {
+    self.tokens.get(self.cursor)
+  }
+
+  fn peek_identifier(&self) -> Option<&str> {
+    match self.peek().map(|token| &token.kind) {
+      Some(TokenKind::Identifier(identifier)) => Some(identifier.as_str()),
+      _ => None
+    }
+  }
+
+  fn advance(&mut self) {
+    self.cursor += 1;
+  }
+
+  fn expect_identifier(&mut self) -> Option<String> {
+    let identifier = self.peek_identifier()?.to_string();
+    self.advance();
+
+    Some(identifier)
+  }
+
+  fn eat_symbol(&mut self, symbol: char) -> bool {
+    let is_symbol = matches!(
+      self.peek().map(|token| &token.kind),
+      Some(TokenKind::Symbol(c)) if *c == symbol
+    );
+
+    if is_symbol {
+      self.advance();
+    }
+
+    is_symbol
+  }
+
+  fn eat_keyword(&mut self, keyword: &str) -> bool {
+    let is_keyword = self.peek_identifier() == Some(keyword);
+
+    if is_keyword {
+      self.advance();
+    }
+
+    is_keyword
+  }
+
+  fn skip_modifiers(&mut self) {
+    while let Some(identifier) = self.peek_identifier() {
+      if !DECLARATION_MODIFIERS.contains(&identifier) {
+        break;
+      }
+
+      self.advance();
+    }
+  }
+
+  /// Skips a `{ ... }` block, the cursor is expected to be on the opening brace.
+  fn skip_block(&mut self) {
+    let mut depth = 0;
+
+    while let Some(token) = self.peek() {
+      match token.kind {
+        TokenKind::Symbol('{') => depth += 1,
+        TokenKind::Symbol('}') => depth -= 1,
+        _ => {}
+      };
+
+      self.advance();
+
+      if depth <= 0 {
+        break;
+      }
+    }
+  }
+
+  /// Skips the tokens until the closing parenthesis of the current list.
+  fn skip_parenthesis(&mut self) {
+    let mut depth = 1;
+
+    while let Some(token) = self.peek() {
+      match token.kind {
+        TokenKind::Symbol('(') => depth += 1,
+        TokenKind::Symbol(')') => depth -= 1,
+        // a missing parenthesis, leave the body to the caller
+        TokenKind::Symbol('{') | TokenKind::Symbol(';') if depth == 1 => break,
+        _ => {}
+      };
+
+      self.advance();
+
+      if depth == 0 {
+        break;
+      }
+    }
+  }
+
+  /// Skips the current statement, up to and including its semicolon or its
+  /// block. It stops before a closing brace as it belongs to the parent.
+  fn skip_statement(&mut self) {
+    while let Some(token) = self.peek() {
+      match token.kind {
+        TokenKind::Symbol(';') => {
+          self.advance();
+          break;
+        }
+        TokenKind::Symbol('{') => {
+          self.skip_block();
+          break;
+        }
+        TokenKind::Symbol('}') => break,
+        _ => self.advance()
+      };
+    }
+  }
+}
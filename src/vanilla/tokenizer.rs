@@ -0,0 +1,101 @@
+/// A token from a vanilla script, only what is needed to read the declarations
+/// is kept. Literals are not interpreted since the bodies are skipped.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+  Identifier(String),
+  Literal,
+  Symbol(char)
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+  pub kind: TokenKind,
+
+  /// The byte offsets of the token in the source
+  pub left: usize,
+  pub right: usize
+}
+
+/// Splits the content of a vanilla script into tokens, comments and whitespaces
+/// are ignored. The tokenizer never fails, unknown characters are emitted as
+/// symbols and unterminated literals end with the file.
+pub fn tokenize(content: &str) -> Vec<Token> {
+  let bytes = content.as_bytes();
+  let mut tokens = Vec::new();
+  let mut i = 0;
+
+  while i < bytes.len() {
+    let byte = bytes[i];
+
+    if byte.is_ascii_whitespace() {
+      i += 1;
+      continue;
+    }
+
+    // comments
+    if byte == b'/' && bytes.get(i + 1) == Some(&b'/') {
+      while i < bytes.len() && bytes[i] != b'\n' {
+        i += 1;
+      }
+
+      continue;
+    }
+
+    if byte == b'/' && bytes.get(i + 1) == Some(&b'*') {
+      i += 2;
+
+      while i < bytes.len() && !(bytes[i] == b'*' && bytes.get(i + 1) == Some(&b'/')) {
+        i += 1;
+      }
+
+      i = (i + 2).min(bytes.len());
+      continue;
+    }
+
+    let left = i;
+
+    let kind = if byte.is_ascii_alphabetic() || byte == b'_' {
+      while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+        i += 1;
+      }
+
+      TokenKind::Identifier(content[left..i].to_string())
+    } else if byte.is_ascii_digit() {
+      // covers floats, hexadecimal values and suffixes like `1.0f`
+      while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'.') {
+        i += 1;
+      }
+
+      TokenKind::Literal
+    } else if byte == b'"' || byte == b'\'' {
+      i += 1;
+
+      while i < bytes.len() && bytes[i] != byte {
+        if bytes[i] == b'\\' {
+          i += 1;
+        }
+
+        i += 1;
+      }
+
+      i = (i + 1).min(bytes.len());
+
+      TokenKind::Literal
+    } else {
+      // multi-bytes characters are kept whole to not split the source in the
+      // middle of a character
+      let character = content[i..].chars().next().unwrap_or(' ');
+      i += character.len_utf8();
+
+      TokenKind::Symbol(character)
+    };
+
+    tokens.push(Token {
+      kind,
+      left,
+      right: i
+    });
+  }
+
+  tokens
+}
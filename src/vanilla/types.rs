@@ -0,0 +1,53 @@
+use crate::ast::ParameterType;
+
+/// The declarations read from the vanilla scripts, only the signatures are
+/// kept as the bodies are not needed for the static analysis.
+#[derive(Debug)]
+pub enum VanillaDeclaration {
+  /// Classes and states, states are stored under the name the game gives them:
+  /// `ParentClassStateName`
+  Class {
+    name: String,
+    extends: Option<String>,
+    fields: Vec<VanillaField>,
+    methods: Vec<VanillaFunction>
+  },
+  Struct {
+    name: String,
+    fields: Vec<VanillaField>
+  },
+  Enum {
+    name: String,
+    variants: Vec<String>
+  },
+  Function(VanillaFunction)
+}
+
+#[derive(Debug)]
+pub struct VanillaField {
+  pub name: String,
+
+  /// The stringified type, in the same form as `TypeDeclaration::to_string()`
+  pub type_name: String
+}
+
+#[derive(Debug)]
+pub struct VanillaFunction {
+  pub name: String,
+  pub parameters: Vec<VanillaParameter>,
+  pub return_type: Option<String>,
+
+  /// The byte offsets of the function's name in the source
+  pub left: usize,
+  pub right: usize
+}
+
+#[derive(Debug)]
+pub struct VanillaParameter {
+  pub parameter_type: ParameterType,
+  pub type_name: String,
+
+  /// The byte offsets of the parameter in the source
+  pub left: usize,
+  pub right: usize
+}
@@ -0,0 +1,627 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::ast::codegen::context::Context;
+use crate::ast::visitor::{Visited, Visitor, VisitorType};
+use crate::ast::{
+  ClassDeclaration, EnumDeclaration, FunctionDeclaration, Lambda, LambdaDeclaration,
+  StructDeclaration
+};
+use crate::compiler::{self, Compilation, ParsedFile};
+use crate::config::Config;
+use crate::preprocessor::tokens::{TokenKind, Tokens};
+use crate::preprocessor::types::{FileName, PreprocessorOutput, ProcessedFile};
+use crate::utils::stable_hash;
+
+/// What the output of a file is made from, besides the code of the file
+/// itself.
+#[derive(Clone)]
+struct FileDependencies {
+  /// The hash of the content once preprocessed, it changes with the macros and
+  /// the registers of the other files.
+  content_hash: String,
+
+  /// The generic variants emitted for the declarations of the file, they come
+  /// from the calls in the other files.
+  generic_variants: BTreeSet<String>,
+
+  /// The names of the functions, methods, classes, structs, enums and
+  /// namespaces the file declares.
+  declarations: BTreeSet<String>,
+
+  /// The names of the generic functions and classes the file declares, the
+  /// files that use them add variants to their outputs.
+  generic_declarations: BTreeSet<String>,
+
+  /// Whether the file has lambdas, their code goes in the output of the
+  /// libraries.
+  has_lambdas: bool,
+
+  /// The identifiers the file uses, the file depends on the files that declare
+  /// them.
+  references: BTreeSet<String>,
+
+  /// The path of the output of the file, none for the files of the
+  /// dependencies whose code goes in the output of the libraries.
+  output: Option<PathBuf>
+}
+
+/// The dependencies of the files of a compilation, compared to the ones of
+/// the previous compilation to find the outputs to generate again.
+pub struct DependencyGraph {
+  files: BTreeMap<FileName, FileDependencies>
+}
+
+impl DependencyGraph {
+  /// Builds the graph of the analysed files, the source files that were not
+  /// analysed keep their dependencies from the previous graph.
+  pub fn new(
+    compilation: &Compilation, config: &Config, previous: Option<&DependencyGraph>
+  ) -> Self {
+    let mut files = BTreeMap::new();
+    let preprocessed_content = &compilation.preprocessed_content;
+
+    for parsed_file in &compilation.ast_list {
+      if let Some(file) = preprocessed_content.get_file(&parsed_file.filename) {
+        let output = compiler::output_path(&parsed_file.file_path, config);
+
+        files.insert(
+          parsed_file.filename.clone(),
+          file_dependencies(parsed_file, file, Some(output))
+        );
+      }
+    }
+
+    for parsed_file in &compilation.dependency_ast_list {
+      if let Some(file) = preprocessed_content.get_file(&parsed_file.filename) {
+        files.insert(
+          parsed_file.filename.clone(),
+          file_dependencies(parsed_file, file, None)
+        );
+      }
+    }
+
+    if let Some(previous) = previous {
+      for filename in preprocessed_content.source_files_content.keys() {
+        if let Some(dependencies) = previous.files.get(filename) {
+          if !files.contains_key(filename) {
+            files.insert(filename.clone(), dependencies.clone());
+          }
+        }
+      }
+    }
+
+    Self { files }
+  }
+
+  /// Returns the source files to analyse again since the graph was built: the
+  /// files the changes may affect, the files that add generic variants or
+  /// lambdas to the output of the libraries, and the files that declare what
+  /// they use. The other source files do not need to be parsed.
+  ///
+  /// The names the changed files declare are only known once they are parsed,
+  /// the files that use them are among the affected files of the next graph.
+  pub fn files_to_analyse(&self, preprocessed_content: &PreprocessorOutput) -> BTreeSet<FileName> {
+    let sources = &preprocessed_content.source_files_content;
+
+    let changed_files: BTreeSet<&FileName> = preprocessed_content
+      .source_files_content
+      .iter()
+      .chain(
+        preprocessed_content
+          .dependencies_files_content
+          .values()
+          .flatten()
+      )
+      .filter(|(filename, file)| {
+        self
+          .files
+          .get(*filename)
+          .is_none_or(|dependencies| dependencies.content_hash != content_hash(file))
+      })
+      .map(|(filename, _)| filename)
+      .collect();
+
+    // the names the changed files and the removed ones declared
+    let mut changed_names: BTreeSet<&String> = self
+      .files
+      .iter()
+      .filter(|(filename, _)| {
+        changed_files.contains(filename) || preprocessed_content.get_file(filename).is_none()
+      })
+      .flat_map(|(_, dependencies)| &dependencies.declarations)
+      .collect();
+
+    let generic_names: BTreeSet<&String> = self
+      .files
+      .values()
+      .flat_map(|dependencies| &dependencies.generic_declarations)
+      .collect();
+
+    // the references of the changed files are read from their new content
+    let changed_references: BTreeMap<&FileName, BTreeSet<String>> = sources
+      .iter()
+      .filter(|(filename, _)| changed_files.contains(filename))
+      .map(|(filename, file)| (filename, references(&file.content.borrow())))
+      .collect();
+
+    let references = |filename: &FileName| -> Option<&BTreeSet<String>> {
+      changed_references.get(filename).or_else(|| {
+        self
+          .files
+          .get(filename)
+          .map(|dependencies| &dependencies.references)
+      })
+    };
+
+    let mut selected: BTreeSet<&FileName> = sources
+      .keys()
+      .filter(|filename| {
+        changed_files.contains(filename)
+          || self.files.get(*filename).is_some_and(|dependencies| {
+            dependencies.has_lambdas
+              || dependencies
+                .references
+                .iter()
+                .any(|reference| generic_names.contains(reference))
+          })
+      })
+      .collect();
+
+    // the files that use the changed declarations, as in `affected_files`
+    loop {
+      let newly_selected: Vec<&FileName> = sources
+        .keys()
+        .filter(|filename| {
+          !selected.contains(filename)
+            && references(filename)
+              .is_some_and(|references| references.iter().any(|name| changed_names.contains(name)))
+        })
+        .collect();
+
+      if newly_selected.is_empty() {
+        break;
+      }
+
+      for filename in newly_selected {
+        if let Some(dependencies) = self.files.get(filename) {
+          changed_names.extend(&dependencies.declarations);
+        }
+
+        selected.insert(filename);
+      }
+    }
+
+    // then the files that declare the names the selected files use
+    let mut pending: Vec<&FileName> = selected.iter().copied().collect();
+    while let Some(filename) = pending.pop() {
+      let Some(used_names) = references(filename) else {
+        continue;
+      };
+
+      for (declaring_filename, dependencies) in &self.files {
+        let Some(declaring_filename) = sources
+          .get_key_value(declaring_filename)
+          .map(|(key, _)| key)
+        else {
+          continue;
+        };
+
+        if !selected.contains(declaring_filename)
+          && dependencies
+            .declarations
+            .iter()
+            .any(|name| used_names.contains(name))
+        {
+          selected.insert(declaring_filename);
+          pending.push(declaring_filename);
+        }
+      }
+    }
+
+    selected.into_iter().cloned().collect()
+  }
+
+  /// Returns the files whose outputs must be generated again since the
+  /// previous graph was built: the files that changed, the files whose macros,
+  /// registers or generic variants changed, then the files that use the
+  /// declarations of any of them. The files of the dependencies are in the
+  /// output of the libraries.
+  pub fn affected_files(&self, previous: &DependencyGraph) -> BTreeSet<FileName> {
+    let mut affected: BTreeSet<&FileName> = self
+      .files
+      .iter()
+      .filter(|(filename, dependencies)| {
+        previous
+          .files
+          .get(*filename)
+          .is_none_or(|previous_dependencies| {
+            previous_dependencies.content_hash != dependencies.content_hash
+              || previous_dependencies.generic_variants != dependencies.generic_variants
+          })
+      })
+      .map(|(filename, _)| filename)
+      .collect();
+
+    // the names declared by the changed files, and by the removed ones
+    let mut changed_names: BTreeSet<&String> = BTreeSet::new();
+    for (filename, dependencies) in &previous.files {
+      if affected.contains(filename) || !self.files.contains_key(filename) {
+        changed_names.extend(&dependencies.declarations);
+      }
+    }
+
+    for filename in &affected {
+      changed_names.extend(&self.files[*filename].declarations);
+    }
+
+    // a file that uses a changed declaration may change the way it uses its
+    // own declarations too, like the type of an implicit variable.
+    loop {
+      let newly_affected: Vec<&FileName> = self
+        .files
+        .iter()
+        .filter(|(filename, dependencies)| {
+          !affected.contains(filename)
+            && dependencies
+              .references
+              .iter()
+              .any(|reference| changed_names.contains(reference))
+        })
+        .map(|(filename, _)| filename)
+        .collect();
+
+      if newly_affected.is_empty() {
+        break;
+      }
+
+      for filename in newly_affected {
+        changed_names.extend(&self.files[filename].declarations);
+        affected.insert(filename);
+      }
+    }
+
+    affected.into_iter().cloned().collect()
+  }
+
+  /// Returns the outputs of the source files that are no longer in the
+  /// program since the previous graph was built.
+  pub fn removed_outputs<'a>(
+    &'a self, previous: &'a DependencyGraph
+  ) -> impl Iterator<Item = &'a PathBuf> {
+    previous
+      .files
+      .iter()
+      .filter(|(filename, _)| !self.files.contains_key(*filename))
+      .filter_map(|(_, dependencies)| dependencies.output.as_ref())
+  }
+
+  /// Returns the path of the output of the source file.
+  pub fn output(&self, filename: &str) -> Option<&PathBuf> {
+    self
+      .files
+      .get(filename)
+      .and_then(|dependencies| dependencies.output.as_ref())
+  }
+}
+
+fn file_dependencies(
+  parsed_file: &ParsedFile, file: &ProcessedFile, output: Option<PathBuf>
+) -> FileDependencies {
+  let content = file.content.borrow();
+
+  let mut declarations_visitor = DeclarationsVisitor::default();
+  parsed_file.ast.accept(&mut declarations_visitor);
+
+  let mut generic_variants = BTreeSet::new();
+  if let Some(file_context) = parsed_file.file_context.borrow().as_ref() {
+    collect_generic_variants(file_context, &mut generic_variants);
+  }
+
+  FileDependencies {
+    content_hash: content_hash(file),
+    generic_variants,
+    declarations: declarations_visitor.declarations,
+    generic_declarations: declarations_visitor.generic_declarations,
+    has_lambdas: declarations_visitor.has_lambdas,
+    references: references(&content),
+    output
+  }
+}
+
+fn content_hash(file: &ProcessedFile) -> String {
+  stable_hash(&[&file.content.borrow()])
+}
+
+/// Returns the identifiers the content uses.
+fn references(content: &str) -> BTreeSet<String> {
+  Tokens::new(content, 0)
+    .filter(|token| token.kind == TokenKind::Identifier)
+    .map(|token| token.text(content).to_string())
+    .collect()
+}
+
+/// Collects the variants of the generic declarations in the context and its
+/// children, prefixed with the name of their context.
+fn collect_generic_variants(context: &Rc<RefCell<Context>>, variants: &mut BTreeSet<String>) {
+  let context = Context::get_ref(context);
+
+  if let Some(generic_context) = &context.generic_context {
+    for variant in generic_context.translation_variants.keys() {
+      variants.insert(format!("{}{variant}", context.name));
+    }
+  }
+
+  for child in &context.children_contexts {
+    collect_generic_variants(child, variants);
+  }
+}
+
+/// Collects the names of the declarations of a file.
+#[derive(Default)]
+struct DeclarationsVisitor {
+  declarations: BTreeSet<String>,
+  generic_declarations: BTreeSet<String>,
+  has_lambdas: bool
+}
+
+impl Visitor for DeclarationsVisitor {
+  fn visitor_type(&self) -> VisitorType {
+    VisitorType::DeclarationsVisitor
+  }
+
+  fn visit_function_declaration(&mut self, node: &FunctionDeclaration) {
+    self.declarations.insert(node.name.clone());
+
+    if node.generic_types.is_some() {
+      self.generic_declarations.insert(node.name.clone());
+    }
+  }
+
+  fn visit_class_declaration(&mut self, node: &ClassDeclaration) {
+    self.declarations.insert(node.name.clone());
+
+    if node.generic_types.is_some() {
+      self.generic_declarations.insert(node.name.clone());
+    }
+  }
+
+  fn visit_struct_declaration(&mut self, node: &StructDeclaration) {
+    self.declarations.insert(node.name.clone());
+  }
+
+  fn visit_enum_declaration(&mut self, node: &EnumDeclaration) {
+    self.declarations.insert(node.name.clone());
+
+    // the variants are used without the name of the enum
+    for variant in &node.body_statements {
+      self.declarations.insert(variant.name.clone());
+    }
+  }
+
+  fn visit_namespace_declaration(&mut self, node: &crate::ast::NamespaceDeclaration) {
+    self.declarations.extend(node.path.iter().cloned());
+  }
+
+  fn visit_lambda(&mut self, _: &Lambda) {
+    self.has_lambdas = true;
+  }
+
+  fn visit_lambda_declaration(&mut self, _: &LambdaDeclaration) {
+    self.has_lambdas = true;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::Path;
+
+  use super::*;
+  use crate::config::read_config_from;
+  use crate::test_utils::TestDirectory;
+
+  fn project(files: &[(&str, &str)]) -> TestDirectory {
+    let directory = TestDirectory::new("watch");
+
+    directory.file(
+      "cahirc.toml",
+      "[package]\nname = \"watch\"\nsrc = \"src\"\ndist = \"dist\"\n"
+    );
+
+    for (path, content) in files {
+      directory.file(&format!("src/{path}"), content);
+    }
+
+    directory
+  }
+
+  fn dependency_graph(directory: &TestDirectory) -> DependencyGraph {
+    let config = read_config_from(&directory.path).unwrap();
+    let compilation = compiler::analyse(&config, false).unwrap();
+
+    assert!(
+      !compilation.report_manager.has_failed(false),
+      "the test project does not compile"
+    );
+
+    DependencyGraph::new(&compilation, &config, None)
+  }
+
+  /// Returns the names of the files affected by the changes, without their
+  /// directories.
+  fn affected_files(directory: &TestDirectory, previous: &DependencyGraph) -> Vec<String> {
+    dependency_graph(directory)
+      .affected_files(previous)
+      .iter()
+      .map(|filename| {
+        Path::new(filename)
+          .file_name()
+          .unwrap()
+          .to_string_lossy()
+          .to_string()
+      })
+      .collect()
+  }
+
+  #[test]
+  fn nothing_is_affected_without_changes() {
+    let directory = project(&[("a.wss", "function a() {}\n")]);
+    let previous = dependency_graph(&directory);
+
+    assert!(affected_files(&directory, &previous).is_empty());
+  }
+
+  #[test]
+  fn files_using_changed_declarations_are_affected() {
+    let directory = project(&[
+      ("helper.wss", "function helper(): int {\n  return 1;\n}\n"),
+      (
+        "caller.wss",
+        "function caller() {\n  var x: int = helper();\n}\n"
+      ),
+      ("indirect.wss", "function indirect() {\n  caller();\n}\n"),
+      ("other.wss", "function other() {}\n")
+    ]);
+    let previous = dependency_graph(&directory);
+
+    directory.file(
+      "src/helper.wss",
+      "function helper(): float {\n  return 1.5;\n}\n"
+    );
+
+    assert_eq!(
+      affected_files(&directory, &previous),
+      vec!["caller.wss", "helper.wss", "indirect.wss"]
+    );
+  }
+
+  #[test]
+  fn files_using_changed_macros_are_affected() {
+    let directory = project(&[
+      ("macros.wssh", "#define const GREETING = \"hello\";\n"),
+      (
+        "greet.wss",
+        "#include \"macros.wssh\";\n\nfunction greet(): string {\n  return GREETING!;\n}\n"
+      ),
+      ("other.wss", "function other() {}\n")
+    ]);
+    let previous = dependency_graph(&directory);
+
+    directory.file("src/macros.wssh", "#define const GREETING = \"hi\";\n");
+
+    assert_eq!(affected_files(&directory, &previous), vec!["greet.wss"]);
+  }
+
+  #[test]
+  fn registries_are_affected_by_their_registers() {
+    let directory = project(&[
+      ("register.wss", "@register('names', first)\n"),
+      (
+        "registry.wss",
+        "function names() {\n  @registry('names', {{\n    LogChannel('REGISTER', \"\");\n  }})\n}\n"
+      )
+    ]);
+    let previous = dependency_graph(&directory);
+
+    directory.file("src/register.wss", "@register('names', second)\n");
+
+    // the register itself is removed from its file
+    assert_eq!(affected_files(&directory, &previous), vec!["registry.wss"]);
+  }
+
+  #[test]
+  fn generic_declarations_are_affected_by_new_variants() {
+    let directory = project(&[
+      (
+        "generic.wss",
+        "function identity<T>(x: T): T {\n  return x;\n}\n"
+      ),
+      ("caller.wss", "function caller() {}\n")
+    ]);
+    let previous = dependency_graph(&directory);
+
+    directory.file(
+      "src/caller.wss",
+      "function caller() {\n  identity::<int>(1);\n}\n"
+    );
+
+    assert_eq!(
+      affected_files(&directory, &previous),
+      vec!["caller.wss", "generic.wss"]
+    );
+  }
+
+  /// Returns the names of the files to analyse again, without their
+  /// directories.
+  fn files_to_analyse(directory: &TestDirectory, previous: &DependencyGraph) -> Vec<String> {
+    let config = read_config_from(&directory.path).unwrap();
+    let preprocessed_content = compiler::preprocess(&config).unwrap();
+
+    previous
+      .files_to_analyse(&preprocessed_content)
+      .iter()
+      .map(|filename| {
+        Path::new(filename)
+          .file_name()
+          .unwrap()
+          .to_string_lossy()
+          .to_string()
+      })
+      .collect()
+  }
+
+  #[test]
+  fn files_declaring_the_used_names_are_analysed() {
+    let directory = project(&[
+      ("point.wss", "struct Point {\n  var x: int;\n}\n"),
+      (
+        "distance.wss",
+        "function distance(point: Point): int {\n  return point.x;\n}\n"
+      ),
+      (
+        "caller.wss",
+        "function caller(point: Point) {\n  distance(point);\n}\n"
+      ),
+      ("other.wss", "function other() {}\n")
+    ]);
+    let previous = dependency_graph(&directory);
+
+    assert!(files_to_analyse(&directory, &previous).is_empty());
+
+    directory.file(
+      "src/caller.wss",
+      "function caller(point: Point) {\n  var d: int = distance(point);\n}\n"
+    );
+
+    assert_eq!(
+      files_to_analyse(&directory, &previous),
+      vec!["caller.wss", "distance.wss", "point.wss"]
+    );
+  }
+
+  #[test]
+  fn files_adding_to_the_libraries_output_are_analysed() {
+    let directory = project(&[
+      (
+        "generic.wss",
+        "function identity<T>(x: T): T {\n  return x;\n}\n"
+      ),
+      ("caller.wss", "function caller() {\n  identity::<int>(1);\n}\n"),
+      (
+        "lambda.wss",
+        "function lambda() {\n  var double: fn(x: int): int;\n\n  double = |x: int| {\n    return x * 2 as int;\n  };\n}\n"
+      ),
+      ("other.wss", "function other() {}\n")
+    ]);
+    let previous = dependency_graph(&directory);
+
+    directory.file("src/other.wss", "function other() {\n  other();\n}\n");
+
+    assert_eq!(
+      files_to_analyse(&directory, &previous),
+      vec!["caller.wss", "generic.wss", "lambda.wss", "other.wss"]
+    );
+  }
+}
@@ -0,0 +1,493 @@
+use std::collections::BTreeMap;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+mod dependencies;
+
+use crate::compiler;
+use crate::config::{read_config_from, CommandOptions, Config};
+use crate::dependencies::lockfile::LOCKFILE_NAME;
+use crate::source_map::source_map_path;
+
+use self::dependencies::DependencyGraph;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+const CONFIG_FILENAME: &str = "cahirc.toml";
+
+/// The modification date and the size of the files the compilation depends on,
+/// a different snapshot means something changed since the last compilation.
+type Snapshot = BTreeMap<PathBuf, (SystemTime, u64)>;
+
+/// The state the watcher keeps between two compilations.
+#[derive(Default)]
+struct WatchState {
+  /// The dependencies of the files as they were when the outputs were last
+  /// written, none until a compilation succeeds.
+  dependency_graph: Option<DependencyGraph>,
+
+  /// The content of the outputs as they were written by the compilations
+  written_outputs: BTreeMap<PathBuf, String>
+}
+
+/// Compiles the project, then keeps polling its files and compiles it again
+/// every time one of them changes.
+///
+/// Every file is preprocessed again as the macros and the registers go from a
+/// file to another, but only the files that changed, the files that depend on
+/// their macros, registers, generic variants or declarations, and the files
+/// that declare what they use are parsed and analysed again. The diagnostics
+/// of the other files are not repeated. The code is only generated for the
+/// affected files, then only the output files whose content changed are
+/// written, so the game's script compiler and the merger tools don't see
+/// spurious changes.
+pub fn watch(project_directory: &Path, options: CommandOptions) -> std::io::Result<()> {
+  let mut snapshot: Option<Snapshot> = None;
+  let mut last_config_error = None;
+  let mut state = WatchState::default();
+
+  loop {
+    let config = read_config_from(project_directory)
+      .map_err(|error| error.to_string())
+      .and_then(|mut config| {
+        config.apply_command_options(&options)?;
+
+        Ok(config)
+      });
+
+    match config {
+      Ok(config) => {
+        last_config_error = None;
+
+        let new_snapshot = take_snapshot(project_directory, &config);
+
+        if snapshot.as_ref() != Some(&new_snapshot) {
+          // the settings and the dependencies may change every output
+          if let Some(snapshot) = &snapshot {
+            if configuration_changed(project_directory, snapshot, &new_snapshot) {
+              state.dependency_graph = None;
+            }
+          }
+
+          snapshot = Some(new_snapshot);
+          recompile(&config, &options, &mut state)?;
+        }
+      }
+      Err(error) => {
+        if last_config_error.as_ref() != Some(&error) {
+          eprintln!("could not read the cahirc.toml file: {error}");
+          last_config_error = Some(error);
+          snapshot = None;
+          state.dependency_graph = None;
+        }
+      }
+    };
+
+    std::thread::sleep(POLL_INTERVAL);
+  }
+}
+
+/// Returns whether the configuration files changed between the snapshots.
+fn configuration_changed(
+  project_directory: &Path, previous: &Snapshot, current: &Snapshot
+) -> bool {
+  [CONFIG_FILENAME, LOCKFILE_NAME]
+    .into_iter()
+    .any(|filename| {
+      let path = project_directory.join(filename);
+
+      previous.get(&path) != current.get(&path)
+    })
+}
+
+fn take_snapshot(project_directory: &Path, config: &Config) -> Snapshot {
+  let mut snapshot = Snapshot::new();
+  let directories = std::iter::once(&config.package.src).chain(config.dependencies.values());
+
+  for directory in directories {
+    let files = walkdir::WalkDir::new(directory)
+      .into_iter()
+      .filter_map(Result::ok)
+      .filter(|file| {
+        file
+          .path()
+          .extension()
+          .map(|extension| extension == "wss" || extension == "wssh")
+          .unwrap_or(false)
+      });
+
+    for file in files {
+      if let Ok(metadata) = file.metadata() {
+        if let Ok(modified) = metadata.modified() {
+          snapshot.insert(file.path().to_path_buf(), (modified, metadata.len()));
+        }
+      }
+    }
+  }
+
+  for filename in [CONFIG_FILENAME, LOCKFILE_NAME] {
+    let path = project_directory.join(filename);
+
+    if let Ok(metadata) = std::fs::metadata(&path) {
+      if let Ok(modified) = metadata.modified() {
+        snapshot.insert(path, (modified, metadata.len()));
+      }
+    }
+  }
+
+  snapshot
+}
+
+/// Compiles the project and writes the outputs that changed since the last
+/// compilation. Nothing is written if the compilation fails, the previous
+/// outputs are left as they are.
+fn recompile(
+  config: &Config, options: &CommandOptions, state: &mut WatchState
+) -> std::io::Result<()> {
+  let start = Instant::now();
+
+  let result = std::panic::catch_unwind(AssertUnwindSafe(
+    || -> std::io::Result<Option<GeneratedOutputs>> {
+      let (mut compilation, dependency_graph) =
+        analyse_affected_files(config, state.dependency_graph.as_ref())?;
+
+      let summary = compilation.report_manager.summary();
+      let has_failed = compilation.report_manager.has_failed(options.deny_warnings);
+
+      compilation
+        .report_manager
+        .consume(&compilation.span_manager, options.message_format);
+
+      eprintln!("{summary}");
+
+      let outputs = match has_failed {
+        true => None,
+        false => {
+          let generation_start = Instant::now();
+          let outputs = generate_affected_outputs(
+            &compilation,
+            config,
+            dependency_graph,
+            state.dependency_graph.as_ref()
+          );
+          compilation
+            .timings
+            .record("code generation", generation_start.elapsed());
+
+          Some(outputs)
+        }
+      };
+
+      if options.timings {
+        eprintln!("{}", compilation.timings.report());
+      }
+
+      Ok(outputs)
+    }
+  ));
+
+  let generated = match result {
+    Ok(Ok(Some(generated))) => generated,
+    Ok(Ok(None)) => {
+      eprintln!("the compilation failed, the outputs were left untouched");
+
+      return Ok(());
+    }
+    Ok(Err(error)) => {
+      eprintln!("could not compile the project: {error}");
+
+      return Ok(());
+    }
+    Err(_) => {
+      eprintln!("the compilation was interrupted, the outputs were left untouched");
+
+      return Ok(());
+    }
+  };
+
+  let written_outputs = &mut state.written_outputs;
+
+  let mut written = 0;
+  for (path, content) in &generated.outputs {
+    let is_unchanged = match written_outputs.get(path) {
+      Some(previous_content) => previous_content == content && path.exists(),
+      // the outputs from a previous run of the compiler
+      None => std::fs::read_to_string(path)
+        .map(|previous_content| &previous_content == content)
+        .unwrap_or(false)
+    };
+
+    if !is_unchanged {
+      compiler::write_output(path, content)?;
+      written += 1;
+    }
+  }
+
+  // the outputs that were generated again but are now empty, or whose source
+  // was removed
+  let mut removed = 0;
+  let removed_paths: Vec<PathBuf> = written_outputs
+    .keys()
+    .filter(|path| !generated.outputs.contains_key(*path))
+    .filter(|path| match &generated.regenerated_paths {
+      Some(regenerated_paths) => regenerated_paths.contains(*path),
+      None => true
+    })
+    .cloned()
+    .collect();
+
+  for path in removed_paths {
+    if std::fs::remove_file(&path).is_ok() {
+      removed += 1;
+    }
+
+    written_outputs.remove(&path);
+  }
+
+  let analysed_files = generated.analysed_files;
+  let generated_files = generated.outputs.len();
+  written_outputs.extend(generated.outputs);
+  state.dependency_graph = Some(generated.dependency_graph);
+
+  eprintln!(
+    "compiled in {:.2?}, {analysed_files} file(s) analysed, {generated_files} file(s) generated, {written} file(s) written, {removed} file(s) removed",
+    start.elapsed()
+  );
+
+  Ok(())
+}
+
+/// Analyses the files affected by the changes since the previous graph was
+/// built and the files they need, or every file without a previous graph.
+/// Returns the compilation with the graph of its files.
+fn analyse_affected_files(
+  config: &Config, previous: Option<&DependencyGraph>
+) -> std::io::Result<(compiler::Compilation, DependencyGraph)> {
+  let static_analysis = config.package.static_analysis.unwrap_or(false);
+  let preprocessed_content = compiler::preprocess(config)?;
+
+  if let Some(previous) = previous {
+    let selected_files = previous.files_to_analyse(&preprocessed_content);
+    let compilation =
+      compiler::analyse_files(config, static_analysis, preprocessed_content, |filename| {
+        selected_files.contains(filename)
+      })?;
+
+    let dependency_graph = DependencyGraph::new(&compilation, config, Some(previous));
+
+    // the files that use the names the changed files declare for the first
+    // time were not selected, the whole project is analysed in this case
+    if dependency_graph
+      .affected_files(previous)
+      .is_subset(&selected_files)
+    {
+      return Ok((compilation, dependency_graph));
+    }
+  }
+
+  let compilation = compiler::analyse(config, static_analysis)?;
+  let dependency_graph = DependencyGraph::new(&compilation, config, None);
+
+  Ok((compilation, dependency_graph))
+}
+
+/// The outputs generated by a compilation in watch mode.
+struct GeneratedOutputs {
+  outputs: BTreeMap<PathBuf, String>,
+
+  /// The number of source files that were parsed and analysed
+  analysed_files: usize,
+
+  /// The paths of the outputs that were generated again, an output that is not
+  /// in `outputs` was emptied. None when every output was generated.
+  regenerated_paths: Option<Vec<PathBuf>>,
+
+  dependency_graph: DependencyGraph
+}
+
+/// Generates the outputs of the files affected by the changes since the
+/// previous graph was built, or every output without a previous graph.
+fn generate_affected_outputs(
+  compilation: &compiler::Compilation, config: &Config, dependency_graph: DependencyGraph,
+  previous: Option<&DependencyGraph>
+) -> GeneratedOutputs {
+  let analysed_files = compilation.ast_list.len();
+
+  let Some(previous) = previous else {
+    return GeneratedOutputs {
+      outputs: compiler::generate(compilation, config),
+      analysed_files,
+      regenerated_paths: None,
+      dependency_graph
+    };
+  };
+
+  let affected_files = dependency_graph.affected_files(previous);
+  let removed_outputs: Vec<&PathBuf> = dependency_graph.removed_outputs(previous).collect();
+
+  if affected_files.is_empty() && removed_outputs.is_empty() {
+    return GeneratedOutputs {
+      outputs: BTreeMap::new(),
+      analysed_files,
+      regenerated_paths: Some(Vec::new()),
+      dependency_graph
+    };
+  }
+
+  // the output of the libraries also holds the lambdas of every file, it is
+  // generated again with any of them.
+  let regenerated_paths = affected_files
+    .iter()
+    .filter_map(|filename| dependency_graph.output(filename))
+    .chain(removed_outputs)
+    .cloned()
+    .chain(std::iter::once(compiler::library_output_path(config)))
+    .flat_map(|path| [source_map_path(&path), path])
+    .collect();
+
+  GeneratedOutputs {
+    outputs: compiler::generate_files(compilation, config, |filename| {
+      affected_files.contains(filename)
+    }),
+    analysed_files,
+    regenerated_paths: Some(regenerated_paths),
+    dependency_graph
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ast::MessageFormat;
+  use crate::test_utils::TestDirectory;
+
+  fn project() -> TestDirectory {
+    let directory = TestDirectory::new("watch");
+
+    directory
+      .file(
+        "cahirc.toml",
+        "[package]\nname = \"watch\"\nsrc = \"src\"\ndist = \"dist\"\n"
+      )
+      .file(
+        "src/helper.wss",
+        "function helper(): int {\n  return 1;\n}\n"
+      )
+      .file(
+        "src/caller.wss",
+        "function caller() {\n  var x: int = helper();\n}\n"
+      )
+      .file(
+        "src/other.wss",
+        "function other() {\n  LogChannel('other', \"ok\");\n}\n"
+      );
+
+    directory
+  }
+
+  /// Returns the names of the analysed source files, without their
+  /// directories.
+  fn analysed_files(compilation: &compiler::Compilation) -> Vec<String> {
+    let mut filenames: Vec<String> = compilation
+      .ast_list
+      .iter()
+      .map(|parsed_file| {
+        parsed_file
+          .file_path
+          .file_name()
+          .unwrap()
+          .to_string_lossy()
+          .to_string()
+      })
+      .collect();
+
+    filenames.sort();
+    filenames
+  }
+
+  #[test]
+  fn only_the_affected_files_are_analysed() {
+    let directory = project();
+    let config = read_config_from(&directory.path).unwrap();
+    let (_, previous) = analyse_affected_files(&config, None).unwrap();
+
+    directory.file(
+      "src/caller.wss",
+      "function caller() {\n  var y: int = helper();\n}\n"
+    );
+
+    let (compilation, dependency_graph) = analyse_affected_files(&config, Some(&previous)).unwrap();
+
+    // the caller needs the declaration of the helper
+    assert_eq!(
+      analysed_files(&compilation),
+      vec!["caller.wss", "helper.wss"]
+    );
+
+    let outputs =
+      generate_affected_outputs(&compilation, &config, dependency_graph, Some(&previous));
+    let caller_output = Path::new(&config.package.dist).join("caller.ws");
+    let full_outputs = compiler::generate(&compiler::analyse(&config, false).unwrap(), &config);
+
+    assert_eq!(
+      outputs.outputs.get(&caller_output),
+      full_outputs.get(&caller_output)
+    );
+    assert!(!outputs
+      .outputs
+      .contains_key(&Path::new(&config.package.dist).join("other.ws")));
+  }
+
+  #[test]
+  fn new_declarations_are_checked_against_every_file() {
+    let directory = project();
+    let config = read_config_from(&directory.path).unwrap();
+    let (_, previous) = analyse_affected_files(&config, None).unwrap();
+
+    // the other file now calls the function of the project instead of the
+    // vanilla one
+    directory.file(
+      "src/helper.wss",
+      "function helper(): int {\n  return 1;\n}\n\nfunction LogChannel(channel: name, message: string) {}\n"
+    );
+
+    let (compilation, _) = analyse_affected_files(&config, Some(&previous)).unwrap();
+
+    assert_eq!(
+      analysed_files(&compilation),
+      vec!["caller.wss", "helper.wss", "other.wss"]
+    );
+  }
+
+  #[test]
+  fn unaffected_outputs_are_not_written_again() {
+    let directory = project();
+    let config = read_config_from(&directory.path).unwrap();
+    let options = CommandOptions {
+      message_format: MessageFormat::Human,
+      deny_warnings: false,
+      timings: false,
+      profile: None,
+      defines: Vec::new()
+    };
+    let mut state = WatchState::default();
+
+    recompile(&config, &options, &mut state).unwrap();
+
+    let dist = Path::new(&config.package.dist);
+    std::fs::remove_file(dist.join("other.ws")).unwrap();
+
+    directory.file(
+      "src/helper.wss",
+      "function helper(): int {\n  return 2;\n}\n"
+    );
+    recompile(&config, &options, &mut state).unwrap();
+
+    let helper_output = std::fs::read_to_string(dist.join("helper.ws")).unwrap();
+
+    assert!(helper_output.contains("return 2;"), "{helper_output}");
+    assert!(dist.join("caller.ws").exists());
+    assert!(!dist.join("other.ws").exists());
+  }
+}